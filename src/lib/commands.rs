@@ -1,8 +1,26 @@
-use configparser::ini::Ini;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+use crate::lib::clean_unc;
 use crate::lib::objects::git_repository::*;
-use crate::lib::objects::git_object::{GitObject,ObjectError};
-use crate::lib::parsing::decoding::{GitNameFormat};
+use crate::lib::objects::git_attributes::{is_valid_attr_name, AttrSource, AttributeRules};
+use crate::lib::objects::git_config::{canonical_key, global_config_paths, ConfigError, ConfigFile, ConfigScope, ConfigType, ConfigValue, GitConfig};
+use crate::lib::objects::git_checkout::{checkout_tree, restore_paths, CheckoutBase, CheckoutError, RestoreTargets};
+use crate::lib::objects::git_commit::{cleanup_message, Commit};
+use crate::lib::objects::git_convert::{ConvertError, Converter};
+use crate::lib::objects::git_hash::ObjectFormat;
+use crate::lib::objects::git_ident::{identity, identity_date, parse_date, parse_name_email, reflog_identity, IdentError, IdentRole, Signature};
+use crate::lib::objects::git_ignore::IgnoreRules;
+use crate::lib::objects::git_index::{GitIndex, IndexEntry, IndexError, StatData};
+use crate::lib::objects::git_object::{GitObject,ObjectError,ObjectType};
+use crate::lib::objects::git_pathspec::{normalize, relative_path, Pathspec, PathspecError};
+use crate::lib::objects::git_refs::{is_valid_branch_name, read_ref, resolve_ref, set_head, update_ref, write_ref, RefValue};
+use crate::lib::objects::git_status::{branch_status, repo_status, BranchStatus, RepoStatus, ScanOptions, StatusEntry, StatusError, UntrackedMode};
+use crate::lib::objects::git_tree::{flatten_tree, peel_to_commit, peel_to_tree, write_index_tree};
+use crate::lib::objects::git_workdir::{hash_blob, mode_from_metadata, read_worktree_file, remove_worktree_file, untracked_paths, worktree_change, StatOptions, WorktreeChange, MODE_SYMLINK};
+use crate::lib::objects::git_worktree::{link_worktree, list_worktrees, prune_reason, unique_admin_dir};
+use crate::lib::parsing::decoding::{abbreviate, bytes_to_tree, find_object, GitNameFormat, ObjectParseError};
+use crate::lib::parsing::encoding::quote_path;
+use regex::Regex;
+use std::collections::{HashMap, HashSet};
 use std::rc::Rc;
 
 #[derive(Debug)]
@@ -10,6 +28,27 @@ pub enum CommandError {
     Init(String),
     Repo(RepositoryError),
     Object(ObjectError),
+    Io(std::io::Error),
+    Worktree(String),
+    CatFile(String),
+    Config(ConfigError),
+    Index(IndexError),
+    Pathspec(PathspecError),
+    LsFiles(String),
+    CheckIgnore(String),
+    CheckAttr(String),
+    Convert(ConvertError),
+    Add(String),
+    Rm(String),
+    Ident(IdentError),
+    WriteTree(String),
+    Commit(String),
+    Status(StatusError),
+    Checkout(String),
+    CheckoutConflict(CheckoutError),
+    Switch(String),
+    Restore(String),
+    Reset(String),
 }
 
 impl From<RepositoryError> for CommandError {
@@ -18,70 +57,2243 @@ impl From<RepositoryError> for CommandError {
     }
 }
 
+impl From<ConvertError> for CommandError {
+    fn from(convert_err: ConvertError) -> Self {
+        CommandError::Convert(convert_err)
+    }
+}
+
 impl From<ObjectError> for CommandError {
     fn from(obj_err: ObjectError) -> Self {
         CommandError::Object(obj_err)
     } 
 }
 
-fn default_config() -> Ini {
-    let mut config = Ini::new();
-    config.set("core", "repositoryformatversion", Some("0".to_owned()));
-    config.set("core", "filemode", Some("false".to_owned()));
-    config.set("core", "bare", Some("false".to_owned()));
-    config
+impl From<ObjectParseError> for CommandError {
+    fn from(parse_err: ObjectParseError) -> Self {
+        CommandError::Object(ObjectError::FileParse(parse_err))
+    }
+}
+
+impl From<ConfigError> for CommandError {
+    fn from(config_err: ConfigError) -> Self {
+        CommandError::Config(config_err)
+    }
+}
+
+impl From<IndexError> for CommandError {
+    fn from(index_err: IndexError) -> Self {
+        CommandError::Index(index_err)
+    }
+}
+
+impl From<IdentError> for CommandError {
+    fn from(ident_err: IdentError) -> Self {
+        CommandError::Ident(ident_err)
+    }
+}
+
+impl From<StatusError> for CommandError {
+    fn from(status_err: StatusError) -> Self {
+        CommandError::Status(status_err)
+    }
+}
+
+impl From<CheckoutError> for CommandError {
+    fn from(checkout_err: CheckoutError) -> Self {
+        CommandError::CheckoutConflict(checkout_err)
+    }
+}
+
+impl From<PathspecError> for CommandError {
+    fn from(pathspec_err: PathspecError) -> Self {
+        CommandError::Pathspec(pathspec_err)
+    }
+}
+
+impl From<std::io::Error> for CommandError {
+    fn from(io_err: std::io::Error) -> Self {
+        CommandError::Io(io_err)
+    }
+}
+
+const DEFAULT_BRANCH: &str = "master";
+
+const DEFAULT_DESCRIPTION: &str =
+    "Unnamed repository; edit this file 'description' to name the repository.\n";
+
+const DEFAULT_EXCLUDE: &str = "# git ls-files --others --exclude-from=.git/info/exclude
+# Lines that start with '#' are comments.
+# For a project mostly in C, the following would be a good set of
+# exclude patterns (uncomment them if you want to use them):
+# *.[oa]
+# *~
+";
+
+///Options for laying out a new (or re-initialized) repository
+#[derive(Default)]
+pub struct InitOptions {
+    pub bare: bool,
+    pub initial_branch: Option<String>,
+    pub template: Option<PathBuf>,
+    pub object_format: Option<ObjectFormat>,
+}
+
+///Fill in any core settings missing from the given config
+fn default_config(config: &mut ConfigFile, bare: bool, object_format: ObjectFormat) -> Result<(), ConfigError> {
+    let mut set_missing = |key: &str, value: &str| -> Result<(), ConfigError> {
+        if config.get(key).is_none() {
+            config.set(key, value)?;
+        }
+        Ok(())
+    };
+    set_missing("core.repositoryformatversion", "0")?;
+    set_missing("core.filemode", if cfg!(unix) { "true" } else { "false" })?;
+    set_missing("core.bare", if bare { "true" } else { "false" })?;
+    if !bare {
+        set_missing("core.logallrefupdates", "true")?;
+    }
+    if object_format != ObjectFormat::Sha1 {
+        config.set("core.repositoryformatversion", "1")?;
+        config.set("extensions.objectformat", &object_format.to_string())?;
+    }
+    Ok(())
+}
+
+///Create a repository at the given path, or fill in the missing pieces of an existing one
+pub fn init<P: Into<PathBuf>>(path: P, options: &InitOptions) -> Result<(), CommandError> {
+    let path: PathBuf = path.into();
+    let git_dir = if options.bare { path.clone() } else { path.join(".git") };
+
+    if let Some(branch) = &options.initial_branch {
+        if !is_valid_branch_name(branch) {
+            return Err(CommandError::Init(format!("invalid initial branch name: '{}'", branch)));
+        }
+    }
+
+    let reinit = git_dir.join("HEAD").is_file() || git_dir.join("config").is_file();
+    std::fs::create_dir_all(&git_dir).map_err(|err| {
+        CommandError::Init(format!("Cannot create directory {}: {}", git_dir.to_string_lossy(), err))
+    })?;
+    let repo = GitRepository::new(path, git_dir, ConfigFile::new());
+
+    let template = options
+        .template
+        .clone()
+        .or_else(|| std::env::var_os("GIT_TEMPLATE_DIR").map(PathBuf::from));
+    if let Some(template) = template {
+        copy_template(&template, repo.gitdir())?;
+    }
+
+    for dir in ["objects/info", "objects/pack", "refs/heads", "refs/tags", "info"].iter() {
+        repo_dir(&repo, dir, true)?;
+    }
+    write_if_missing(&repo, "description", DEFAULT_DESCRIPTION)?;
+    write_if_missing(&repo, "info/exclude", DEFAULT_EXCLUDE)?;
+
+    let branch = options.initial_branch.as_deref().unwrap_or(DEFAULT_BRANCH);
+    if reinit && options.initial_branch.is_some() {
+        eprintln!("warning: re-init: ignored --initial-branch={}", branch);
+    }
+    write_if_missing(&repo, "HEAD", &format!("ref: refs/heads/{}\n", branch))?;
+
+    let config_path = repo_path(&repo, "config");
+    let mut config = if config_path.is_file() {
+        ConfigFile::load(&config_path).map_err(RepositoryError::from)?
+    } else {
+        ConfigFile::new()
+    };
+    let existing_format = ObjectFormat::from_config(&config);
+    let object_format = match options.object_format {
+        Some(format) if reinit && format != existing_format => {
+            return Err(CommandError::Init(
+                "attempt to reinitialize repository with different hash".to_owned(),
+            ))
+        }
+        Some(format) => format,
+        None if reinit => existing_format,
+        None => default_object_format()?,
+    };
+    default_config(&mut config, options.bare, object_format)
+        .and_then(|_| config.save(&config_path))
+        .map_err(RepositoryError::from)?;
+
+    let status = if reinit { "Reinitialized existing" } else { "Initialized empty" };
+    let shown_dir = clean_unc(repo.gitdir().canonicalize()?);
+    println!("{} Git repository in {}", status, shown_dir.join("").to_string_lossy());
+    Ok(())
+}
+
+///The object format for new repositories, from GIT_DEFAULT_HASH or SHA-1
+fn default_object_format() -> Result<ObjectFormat, CommandError> {
+    match std::env::var("GIT_DEFAULT_HASH") {
+        Ok(name) if !name.is_empty() => name.parse().map_err(CommandError::Init),
+        _ => Ok(ObjectFormat::Sha1),
+    }
+}
+
+///Write a file inside the git directory unless it is already there
+fn write_if_missing(repo: &GitRepository, path: &str, contents: &str) -> Result<(), CommandError> {
+    let target = repo_path(repo, path);
+    if !target.exists() {
+        repo_file(repo, path, true)?;
+        std::fs::write(target, contents)?;
+    }
+    Ok(())
+}
+
+///Recursively copy a template directory into the git directory without overwriting anything
+fn copy_template(template: &Path, target: &Path) -> Result<(), CommandError> {
+    if !template.is_dir() {
+        eprintln!("warning: templates not found in {}", template.to_string_lossy());
+        return Ok(());
+    }
+    for entry in std::fs::read_dir(template)? {
+        let entry = entry?;
+        let destination = target.join(entry.file_name());
+        if entry.file_type()?.is_dir() {
+            std::fs::create_dir_all(&destination)?;
+            copy_template(&entry.path(), &destination)?;
+        } else if !destination.exists() {
+            std::fs::copy(entry.path(), &destination)?;
+        }
+    }
+    Ok(())
+}
+
+pub fn cat_file<P: Into<PathBuf>>(git_dir_path: P, type_str: &str, target: &str) -> Result<(), CommandError> {
+    let repo = GitRepository::along_path(git_dir_path.into(), false)?;
+    let repo = Rc::new(repo);
+    let object = GitObject::from_internal_name(&repo, target, &GitNameFormat::Placeholder,true)?;
+    if object.kind().to_string() != type_str {
+        return Err(CommandError::CatFile(format!("{} is a {}, not a {}", target, object.kind(), type_str)));
+    }
+    if *object.kind() == ObjectType::Tree {
+        for entry in bytes_to_tree(object.serialize(), repo.object_format())? {
+            println!("{:06o} {} {}\t{}", entry.mode, entry.kind_name(), entry.sha, entry.name);
+        }
+    } else {
+        println!("{}",String::from_utf8_lossy(object.serialize()));
+    }
+    Ok(())
+}
+
+///Creates hash for the given file and possibly adds it to a repo
+pub fn hash_object(_object_type: &str, file: &str, repo: &Rc<GitRepository>, write:bool) -> Result<(), CommandError> {
+    let mut blob = GitObject::from_external_file(file, repo)?;
+    //A file in the worktree is converted the way `add` would, checked only when it is written
+    if let Some(path) = worktree_relative(repo, Path::new(file)) {
+        let mut convert = Converter::for_index(repo, &GitIndex::load(repo)?, AttrSource::WorktreeFirst)?;
+        let content = blob.serialize().to_vec();
+        let converted = if write { convert.clean_checked(&path, content) } else { convert.clean(&path, content) };
+        show_warnings(&convert.warnings);
+        blob = GitObject::new(ObjectType::Blob, converted?, repo);
+    }
+    if write {
+        blob.write_to_repo()?;
+    }
+    println!("{}", blob.get_hash());
+    Ok(())
+}
+
+///A file's path relative to the top of the worktree, None for one outside it or in a bare repository
+fn worktree_relative(repo: &GitRepository, file: &Path) -> Option<String> {
+    let worktree = clean_unc(repo.worktree().ok()?.canonicalize().ok()?);
+    let file = clean_unc(file.canonicalize().ok()?);
+    let relative = file.strip_prefix(&worktree).ok()?;
+    Some(relative.components().map(|part| part.as_os_str().to_string_lossy()).collect::<Vec<_>>().join("/"))
+}
+
+///Show the warnings converting content gave, once each, on stderr the way git does
+fn show_warnings(warnings: &[String]) {
+    let mut shown = HashSet::new();
+    for warning in warnings.iter().filter(|warning| shown.insert(warning.as_str())) {
+        eprintln!("warning: {}", warning);
+    }
+}
+
+///Options for `worktree add`
+#[derive(Default)]
+pub struct WorktreeAddOptions {
+    pub new_branch: Option<String>,
+    pub detach: bool,
+    pub force: bool,
+    pub no_checkout: bool,
+}
+
+///Create a linked worktree at the given path checked out to a branch or detached commit
+pub fn worktree_add<P: AsRef<Path>>(repo: &GitRepository, path: P, commit_ish: Option<&str>, options: &WorktreeAddOptions) -> Result<(), CommandError> {
+    let path = path.as_ref();
+    if path.exists() && std::fs::read_dir(path)?.next().is_some() {
+        return Err(CommandError::Worktree(format!("'{}' already exists", path.to_string_lossy())));
+    }
+
+    let find_commit = |name: Option<&str>| find_object(repo, name.unwrap_or("HEAD"), &GitNameFormat::Placeholder, true);
+    let checked_out = |branch: &str| -> Result<bool, CommandError> {
+        let worktrees = list_worktrees(repo)?;
+        Ok(worktrees.iter().any(|tree| tree.branch.as_deref() == Some(branch)))
+    };
+
+    //Without a branch or commit, git names a branch after the worktree directory
+    let dwim_branch = match (&options.new_branch, commit_ish, options.detach) {
+        (None, None, false) => path.file_name().map(|name| name.to_string_lossy().into_owned()),
+        _ => None,
+    };
+    let existing_branch = commit_ish
+        .filter(|_| options.new_branch.is_none() && !options.detach)
+        .map(str::to_owned)
+        .or_else(|| dwim_branch.clone())
+        .map(|name| format!("refs/heads/{}", name))
+        .filter(|branch| matches!(resolve_ref(repo, branch), Ok(Some(_))));
+    let new_branch = options.new_branch.clone().or(if existing_branch.is_none() { dwim_branch } else { None });
+
+    let (head, description) = if let Some(branch) = existing_branch {
+        if !options.force && checked_out(&branch)? {
+            return Err(CommandError::Worktree(format!("'{}' is already checked out", branch)));
+        }
+        let short = branch.trim_start_matches("refs/heads/").to_owned();
+        (RefValue::Symbolic(branch), format!("checking out '{}'", short))
+    } else if let Some(branch) = new_branch {
+        let full_name = format!("refs/heads/{}", branch);
+        if !is_valid_branch_name(&branch) {
+            return Err(CommandError::Worktree(format!("'{}' is not a valid branch name", branch)));
+        }
+        if resolve_ref(repo, &full_name)?.is_some() {
+            return Err(CommandError::Worktree(format!("a branch named '{}' already exists", branch)));
+        }
+        let commit = find_commit(commit_ish)?;
+        write_ref(repo, &full_name, &RefValue::Direct(commit))?;
+        (RefValue::Symbolic(full_name), format!("new branch '{}'", branch))
+    } else {
+        let commit = find_commit(commit_ish)?;
+        let description = format!("detached HEAD {}", abbreviate(repo, &commit)?);
+        (RefValue::Direct(commit), description)
+    };
+
+    println!("Preparing worktree ({})", description);
+    let admin_dir = unique_admin_dir(repo, path);
+    link_worktree(path, &admin_dir, &head)?;
+    if !options.no_checkout {
+        let linked = Rc::new(GitRepository::at_path(path, false)?);
+        if let Some(commit) = resolve_ref(&linked, "HEAD")? {
+            let lock = GitIndex::lock(&linked)?;
+            let mut index = GitIndex::load(&linked)?;
+            let mut warnings = Vec::new();
+            checkout_tree(&linked, &mut index, CheckoutBase::Tree(None), Some(&peel_to_tree(&linked, &commit)?), false, &mut warnings)?;
+            show_warnings(&warnings);
+            index.save_locked(&linked, lock)?;
+            let subject = Commit::parse(GitObject::from_internal_file(&commit, &linked)?.serialize())?.subject();
+            println!("HEAD is now at {} {}", abbreviate(&linked, &commit)?, subject);
+        }
+    }
+    Ok(())
+}
+
+///Print every worktree with its checked out commit and branch
+pub fn worktree_list(repo: &GitRepository, porcelain: bool) -> Result<(), CommandError> {
+    let worktrees = list_worktrees(repo)?;
+    let width = worktrees
+        .iter()
+        .map(|tree| tree.path.to_string_lossy().len() + 1)
+        .max()
+        .unwrap_or(0);
+    let null_id = repo.object_format().null_id();
+    for tree in worktrees {
+        let path = tree.path.to_string_lossy();
+        if porcelain {
+            println!("worktree {}", path);
+            if tree.bare {
+                println!("bare");
+            } else {
+                println!("HEAD {}", tree.head.as_deref().unwrap_or(&null_id));
+                match &tree.branch {
+                    Some(branch) => println!("branch {}", branch),
+                    None => println!("detached"),
+                }
+            }
+            if let Some(reason) = &tree.locked {
+                println!("locked {}", reason);
+            }
+            if let Some(reason) = &tree.prunable {
+                println!("prunable {}", reason);
+            }
+            println!();
+        } else if tree.bare {
+            println!("{:width$} (bare)", path, width = width);
+        } else {
+            let sha = match tree.head.as_deref() {
+                Some(sha) => abbreviate(repo, sha)?,
+                None => null_id[..7].to_owned(),
+            };
+            let branch = match &tree.branch {
+                Some(branch) => format!("[{}]", branch.trim_start_matches("refs/heads/")),
+                None => "(detached HEAD)".to_owned(),
+            };
+            let mut line = format!("{:width$} {} {}", path, sha, branch, width = width);
+            if tree.locked.is_some() {
+                line.push_str(" locked");
+            }
+            if tree.prunable.is_some() {
+                line.push_str(" prunable");
+            }
+            println!("{}", line);
+        }
+    }
+    Ok(())
+}
+
+///Delete a linked worktree and its administrative files
+pub fn worktree_remove<P: AsRef<Path>>(repo: &GitRepository, path: P, force: bool) -> Result<(), CommandError> {
+    let target = clean_unc(path.as_ref().canonicalize()?);
+    let worktrees = list_worktrees(repo)?;
+    let tree = worktrees
+        .iter()
+        .find(|tree| tree.path.canonicalize().map(clean_unc).ok().as_ref() == Some(&target))
+        .ok_or_else(|| CommandError::Worktree(format!("'{}' is not a working tree", target.to_string_lossy())))?;
+    let admin_dir = tree
+        .admin_dir
+        .as_ref()
+        .ok_or_else(|| CommandError::Worktree(format!("'{}' is a main working tree", target.to_string_lossy())))?;
+    if tree.locked.is_some() && !force {
+        return Err(CommandError::Worktree("cannot remove a locked working tree, use --force".to_owned()));
+    }
+    let dirty = || -> Result<bool, CommandError> {
+        let linked = Rc::new(GitRepository::at_path(&target, false)?);
+        let scan = ScanOptions {
+            untracked: UntrackedMode::Normal,
+            ignored: false,
+            renames: false,
+        };
+        let status = repo_status(&linked, &Pathspec::default(), &scan)?;
+        show_warnings(&status.warnings);
+        Ok(!status.entries.is_empty() || !status.untracked.is_empty())
+    };
+    if !force && dirty()? {
+        return Err(CommandError::Worktree(format!(
+            "'{}' contains modified or untracked files, use --force to delete it",
+            target.to_string_lossy()
+        )));
+    }
+    std::fs::remove_dir_all(&target)?;
+    std::fs::remove_dir_all(admin_dir)?;
+    Ok(())
+}
+
+///Remove administrative files for worktrees whose directories have gone away
+pub fn worktree_prune(repo: &GitRepository, dry_run: bool, verbose: bool) -> Result<(), CommandError> {
+    let admin_root = repo_path(repo, "worktrees");
+    if !admin_root.is_dir() {
+        return Ok(());
+    }
+    for entry in std::fs::read_dir(&admin_root)? {
+        let admin_dir = entry?.path();
+        if let Some(reason) = prune_reason(&admin_dir) {
+            if dry_run || verbose {
+                println!(
+                    "Removing worktrees/{}: {}",
+                    admin_dir.file_name().unwrap_or_default().to_string_lossy(),
+                    reason
+                );
+            }
+            if !dry_run {
+                std::fs::remove_dir_all(&admin_dir)?;
+            }
+        }
+    }
+    if !dry_run && std::fs::read_dir(&admin_root)?.next().is_none() {
+        std::fs::remove_dir(&admin_root)?;
+    }
+    Ok(())
+}
+
+///What `config` does with its name, value and value pattern arguments
+pub enum ConfigAction {
+    Get { name: String, value_pattern: Option<String> },
+    GetAll { name: String, value_pattern: Option<String> },
+    GetRegexp { name_pattern: String, value_pattern: Option<String> },
+    List,
+    Set { name: String, value: String },
+    Add { name: String, value: String },
+    Unset { name: String, value_pattern: Option<String> },
+    UnsetAll { name: String, value_pattern: Option<String> },
+    RenameSection { old_name: String, new_name: String },
+    RemoveSection { name: String },
+}
+
+///Which file `config` works on and how it shows values
+#[derive(Default)]
+pub struct ConfigOptions {
+    pub file: Option<PathBuf>,
+    pub global: bool,
+    pub local: bool,
+    pub value_type: Option<ConfigType>,
+    pub show_origin: bool,
+}
+
+///Query or edit configuration. Reads see every layer unless a file or scope is chosen,
+///writes go to the repository's config unless --global or --file says otherwise
+pub fn config(action: &ConfigAction, options: &ConfigOptions) -> Result<(), CommandError> {
+    let repo = GitRepository::along_path(std::env::current_dir()?, false);
+    match action {
+        ConfigAction::Get { .. } | ConfigAction::GetAll { .. } | ConfigAction::GetRegexp { .. } | ConfigAction::List => {
+            let values = config_to_read(repo, options)?;
+            print_config(&values, action, options)
+        }
+        _ => {
+            let path = config_to_write(repo, options)?;
+            let mut file = if path.is_file() { ConfigFile::load(&path)? } else { ConfigFile::new() };
+            edit_config(&mut file, action, options)?;
+            file.save(&path)?;
+            Ok(())
+        }
+    }
+}
+
+fn config_to_read(repo: Result<GitRepository, RepositoryError>, options: &ConfigOptions) -> Result<GitConfig, CommandError> {
+    if let Some(file) = &options.file {
+        return Ok(GitConfig::load_file(ConfigScope::Command, file)?);
+    }
+    if options.global {
+        return Ok(GitConfig::without_repository()?.in_scopes(&[ConfigScope::Global]));
+    }
+    match repo {
+        Ok(repo) if options.local => Ok(repo.config().in_scopes(&[ConfigScope::Local])),
+        Ok(repo) => Ok(repo.config().clone()),
+        Err(RepositoryError::NoGitDirectory()) if !options.local => Ok(GitConfig::without_repository()?),
+        Err(err) => Err(err.into()),
+    }
+}
+
+///The file a write goes to: --file, the global file that already exists (~/.gitconfig
+///when neither does) or the repository's shared config
+fn config_to_write(repo: Result<GitRepository, RepositoryError>, options: &ConfigOptions) -> Result<PathBuf, CommandError> {
+    if let Some(file) = &options.file {
+        return Ok(file.clone());
+    }
+    if options.global {
+        let paths = global_config_paths();
+        return paths
+            .iter()
+            .rev()
+            .find(|path| path.is_file())
+            .or_else(|| paths.last())
+            .cloned()
+            .ok_or_else(|| CommandError::Config(ConfigError::BadEnvironment("$HOME not set".to_owned())));
+    }
+    Ok(repo_path(&repo?, "config"))
+}
+
+///A filter for values matching a regex, or not matching it when the pattern starts with `!`
+fn value_filter(pattern: &Option<String>) -> Result<impl Fn(Option<&str>) -> bool, CommandError> {
+    let (negated, regex) = match pattern.as_deref() {
+        Some(pattern) => {
+            let (negated, pattern) = match pattern.strip_prefix('!') {
+                Some(rest) => (true, rest),
+                None => (false, pattern),
+            };
+            let regex = Regex::new(pattern).map_err(|err| ConfigError::InvalidPattern(err.to_string()))?;
+            (negated, Some(regex))
+        }
+        None => (false, None),
+    };
+    Ok(move |value: Option<&str>| match &regex {
+        Some(regex) => regex.is_match(value.unwrap_or("")) != negated,
+        None => true,
+    })
+}
+
+fn print_config(config: &GitConfig, action: &ConfigAction, options: &ConfigOptions) -> Result<(), CommandError> {
+    let origin = |value: &ConfigValue| if options.show_origin { format!("{}\t", value.origin) } else { String::new() };
+    let shown = |value: &ConfigValue| -> Result<String, CommandError> {
+        match options.value_type {
+            Some(value_type) => Ok(value_type.display(&value.name, value.value.as_deref())?),
+            None => Ok(value.value.clone().unwrap_or_default()),
+        }
+    };
+
+    match action {
+        ConfigAction::Get { name, value_pattern } | ConfigAction::GetAll { name, value_pattern } => {
+            let key = canonical_key(name)?;
+            let filter = value_filter(value_pattern)?;
+            let matches: Vec<&ConfigValue> = config
+                .values()
+                .iter()
+                .filter(|value| value.name == key && filter(value.value.as_deref()))
+                .collect();
+            let shown_matches = match action {
+                ConfigAction::Get { .. } => &matches[matches.len().saturating_sub(1)..],
+                _ => &matches[..],
+            };
+            if shown_matches.is_empty() {
+                return Err(ConfigError::MissingKey(name.clone()).into());
+            }
+            for value in shown_matches {
+                println!("{}{}", origin(value), shown(value)?);
+            }
+        }
+        ConfigAction::GetRegexp { name_pattern, value_pattern } => {
+            let name_regex = Regex::new(name_pattern).map_err(|err| ConfigError::InvalidPattern(err.to_string()))?;
+            let filter = value_filter(value_pattern)?;
+            let mut found = false;
+            for value in config.values() {
+                if !name_regex.is_match(&value.name) || !filter(value.value.as_deref()) {
+                    continue;
+                }
+                found = true;
+                match (&value.value, options.value_type) {
+                    (None, None) => println!("{}{}", origin(value), value.name),
+                    _ => println!("{}{} {}", origin(value), value.name, shown(value)?),
+                }
+            }
+            if !found {
+                return Err(ConfigError::MissingKey(name_pattern.clone()).into());
+            }
+        }
+        _ => {
+            for value in config.values() {
+                match &value.value {
+                    Some(text) => println!("{}{}={}", origin(value), value.name, text),
+                    None => println!("{}{}", origin(value), value.name),
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+fn edit_config(file: &mut ConfigFile, action: &ConfigAction, options: &ConfigOptions) -> Result<(), CommandError> {
+    let normalized = |name: &str, value: &str| -> Result<String, ConfigError> {
+        match options.value_type {
+            Some(value_type) => value_type.normalize(name, value),
+            None => Ok(value.to_owned()),
+        }
+    };
+    match action {
+        ConfigAction::Set { name, value } => file.set(name, &normalized(name, value)?)?,
+        ConfigAction::Add { name, value } => file.add(name, &normalized(name, value)?)?,
+        ConfigAction::Unset { name, value_pattern } | ConfigAction::UnsetAll { name, value_pattern } => {
+            let all = matches!(action, ConfigAction::UnsetAll { .. });
+            if file.unset_matching(name, all, value_filter(value_pattern)?)? == 0 {
+                return Err(ConfigError::MissingKey(name.clone()).into());
+            }
+        }
+        ConfigAction::RenameSection { old_name, new_name } => {
+            let renamed = file.rename_section(old_name, new_name)?;
+            if !renamed {
+                return Err(ConfigError::NoSuchSection(old_name.clone()).into());
+            }
+        }
+        ConfigAction::RemoveSection { name } => {
+            let removed = file.remove_section(name)?;
+            if !removed {
+                return Err(ConfigError::NoSuchSection(name.clone()).into());
+            }
+        }
+        _ => (),
+    }
+    Ok(())
+}
+
+///Which index and worktree paths `ls-files` shows
+#[derive(Default)]
+pub struct LsFilesOptions {
+    pub cached: bool,
+    pub stage: bool,
+    pub deleted: bool,
+    pub modified: bool,
+    pub others: bool,
+    pub ignored: bool,
+    pub exclude_standard: bool,
+    pub unmerged: bool,
+    pub null_terminated: bool,
+}
+
+///List files in the index and worktree, relative to the current directory
+pub fn ls_files(repo: &Rc<GitRepository>, pathspecs: &[&str], options: &LsFilesOptions) -> Result<(), CommandError> {
+    let terminator = if options.null_terminated { '\0' } else { '\n' };
+    for line in ls_files_lines(repo, &std::env::current_dir()?, pathspecs, options)? {
+        print!("{}{}", line, terminator);
+    }
+    Ok(())
+}
+
+fn ls_files_lines(repo: &Rc<GitRepository>, cwd: &Path, pathspecs: &[&str], options: &LsFilesOptions) -> Result<Vec<String>, CommandError> {
+    if options.ignored && !options.others && !options.cached {
+        return Err(CommandError::LsFiles("ls-files -i must be used with either -o or -c".to_owned()));
+    }
+    if options.ignored && !options.exclude_standard {
+        return Err(CommandError::LsFiles("ls-files --ignored needs some exclude pattern".to_owned()));
+    }
+    let worktree = repo.worktree()?;
+    let prefix = repo.prefix_of(cwd)?;
+    //From a subdirectory only the files below it are listed
+    let pathspec = if pathspecs.is_empty() && !prefix.is_empty() {
+        Pathspec::parse(&["."], &prefix)?
+    } else {
+        Pathspec::parse(pathspecs, &prefix)?
+    };
+    let index = GitIndex::load(repo)?;
+    let mut ignore = if options.exclude_standard {
+        IgnoreRules::for_repository(repo)?
+    } else {
+        IgnoreRules::new(worktree)
+    };
+    let show_stage = options.stage || options.unmerged;
+    let show_cached = options.cached || show_stage || !(options.deleted || options.modified || options.others);
+    let quote_high = !matches!(repo.config().get_bool("core.quotepath"), Ok(Some(false)));
+    let show_path = |path: &str| {
+        let relative = relative_path(path, &prefix);
+        if options.null_terminated {
+            relative
+        } else {
+            quote_path(&relative, quote_high)
+        }
+    };
+
+    let stat_options = StatOptions::from_repo(repo);
+    let mut convert = Converter::for_index(repo, &index, AttrSource::WorktreeFirst)?;
+    let mut lines = Vec::new();
+    if options.others {
+        for untracked in untracked_paths(worktree, &index, &mut ignore, options.ignored)? {
+            if untracked.ignored == options.ignored && pathspec.matches(&untracked.path, |path, name| convert.attribute(path, name)) {
+                lines.push(show_path(&untracked.path));
+            }
+        }
+    }
+    for entry in index.entries() {
+        if !pathspec.matches(&entry.path, |path, name| convert.attribute(path, name)) || (options.unmerged && entry.stage == 0) {
+            continue;
+        }
+        if options.ignored && !ignore.is_ignored(&entry.path, false) {
+            continue;
+        }
+        let line = if show_stage {
+            format!("{:06o} {} {}\t{}", entry.mode, entry.sha, entry.stage, show_path(&entry.path))
+        } else {
+            show_path(&entry.path)
+        };
+        if show_cached {
+            lines.push(line.clone());
+        }
+        //Each entry is checked on disk once and reported under every listing it falls in
+        if (options.deleted || options.modified) && !entry.skip_worktree {
+            let change = worktree_change(worktree, entry, repo.object_format(), stat_options, index.timestamp(), &mut convert)?;
+            if options.deleted && change == Some(WorktreeChange::Deleted) {
+                lines.push(line.clone());
+            }
+            if options.modified && change.is_some() {
+                lines.push(line);
+            }
+        }
+    }
+    show_warnings(&convert.warnings);
+    Ok(lines)
+}
+
+///How `check-ignore` reports paths
+#[derive(Default)]
+pub struct CheckIgnoreOptions {
+    ///Show the file, line and pattern deciding each path, negated patterns included
+    pub verbose: bool,
+    ///With `verbose`, also list paths no pattern matched
+    pub non_matching: bool,
+    ///Check tracked files too instead of treating them as never ignored
+    pub no_index: bool,
+}
+
+///Print the paths that are ignored, or with `verbose` the rule deciding each one
+pub fn check_ignore(repo: &GitRepository, paths: &[&str], options: &CheckIgnoreOptions) -> Result<(), CommandError> {
+    for line in check_ignore_lines(repo, &std::env::current_dir()?, paths, options)? {
+        println!("{}", line);
+    }
+    Ok(())
+}
+
+fn check_ignore_lines(repo: &GitRepository, cwd: &Path, paths: &[&str], options: &CheckIgnoreOptions) -> Result<Vec<String>, CommandError> {
+    if options.non_matching && !options.verbose {
+        return Err(CommandError::CheckIgnore("--non-matching is only valid with --verbose".to_owned()));
+    }
+    if paths.is_empty() {
+        return Err(CommandError::CheckIgnore("no path specified".to_owned()));
+    }
+    let worktree = repo.worktree()?;
+    let prefix = repo.prefix_of(cwd)?;
+    let index = if options.no_index { GitIndex::new() } else { GitIndex::load(repo)? };
+    let mut rules = IgnoreRules::for_repository(repo)?;
+    let quote_high = !matches!(repo.config().get_bool("core.quotepath"), Ok(Some(false)));
+
+    let mut lines = Vec::new();
+    for arg in paths {
+        if arg.is_empty() {
+            return Err(CommandError::CheckIgnore("empty string is not a valid pathspec".to_owned()));
+        }
+        let path = normalize(&prefix, arg)?;
+        let path = path.trim_end_matches('/');
+        let is_dir = arg.ends_with('/') || std::fs::symlink_metadata(worktree.join(path)).is_ok_and(|meta| meta.is_dir());
+        //Tracked files are never ignored, so no pattern is looked up for them
+        let tracked = index.entries().iter().any(|entry| entry.path == path);
+        let pattern = if tracked || path.is_empty() { None } else { rules.matching(path, is_dir) };
+        let shown = quote_path(arg, quote_high);
+        match pattern {
+            Some(pattern) if options.verbose => lines.push(format!("{}:{}:{}\t{}", quote_path(&pattern.source, quote_high), pattern.line, pattern.text, shown)),
+            Some(pattern) if !pattern.negated => lines.push(shown),
+            None if options.non_matching => lines.push(format!("::\t{}", shown)),
+            _ => {}
+        }
+    }
+    Ok(lines)
+}
+
+///How `check-attr` looks attributes up
+#[derive(Default)]
+pub struct CheckAttrOptions {
+    ///Report every attribute given to each path instead of the named ones
+    pub all: bool,
+    ///Read `.gitattributes` files from the index only
+    pub cached: bool,
+}
+
+///Print the state of attributes for each path
+pub fn check_attr(repo: &Rc<GitRepository>, attrs: &[&str], paths: &[&str], options: &CheckAttrOptions) -> Result<(), CommandError> {
+    for line in check_attr_lines(repo, &std::env::current_dir()?, attrs, paths, options)? {
+        println!("{}", line);
+    }
+    Ok(())
+}
+
+fn check_attr_lines(repo: &Rc<GitRepository>, cwd: &Path, attrs: &[&str], paths: &[&str], options: &CheckAttrOptions) -> Result<Vec<String>, CommandError> {
+    if options.all && !attrs.is_empty() {
+        return Err(CommandError::CheckAttr("Attributes and --all both specified".to_owned()));
+    }
+    if !options.all && attrs.is_empty() {
+        return Err(CommandError::CheckAttr("No attribute specified".to_owned()));
+    }
+    if let Some(invalid) = attrs.iter().find(|attr| !is_valid_attr_name(attr)) {
+        return Err(CommandError::CheckAttr(format!("{}: not a valid attribute name", invalid)));
+    }
+    let prefix = repo.prefix_of(cwd)?;
+    let index = GitIndex::load(repo)?;
+    let staged = index.entries().iter().filter(|entry| entry.stage == 0).map(|entry| (entry.path.clone(), entry.sha.clone()));
+    let source = if options.cached { AttrSource::IndexOnly } else { AttrSource::WorktreeFirst };
+    let mut rules = AttributeRules::for_repository(repo, staged, source)?;
+    let quote_high = !matches!(repo.config().get_bool("core.quotepath"), Ok(Some(false)));
+
+    let mut lines = Vec::new();
+    for arg in paths {
+        let path = normalize(&prefix, arg)?;
+        let states = if options.all {
+            rules.all(&path)
+        } else {
+            attrs.iter().map(|attr| (attr.to_string(), rules.get(&path, attr))).collect()
+        };
+        for (name, value) in states {
+            lines.push(format!("{}: {}: {}", quote_path(arg, quote_high), name, value.describe()));
+        }
+    }
+    Ok(lines)
+}
+
+///Options for `add`
+#[derive(Default)]
+pub struct AddOptions {
+    pub all: bool,
+    pub update: bool,
+    pub force: bool,
+    pub dry_run: bool,
+    pub intent_to_add: bool,
+    pub verbose: bool,
+}
+
+///Stage new, modified and deleted files matching the pathspecs
+pub fn add(repo: &Rc<GitRepository>, pathspecs: &[&str], options: &AddOptions) -> Result<(), CommandError> {
+    for line in add_paths(repo, &std::env::current_dir()?, pathspecs, options)? {
+        println!("{}", line);
+    }
+    Ok(())
+}
+
+fn add_paths(repo: &Rc<GitRepository>, cwd: &Path, pathspecs: &[&str], options: &AddOptions) -> Result<Vec<String>, CommandError> {
+    if pathspecs.is_empty() && !options.all && !options.update {
+        return Ok(vec![
+            "Nothing specified, nothing added.".to_owned(),
+            "hint: Maybe you wanted to say 'git add .'?".to_owned(),
+        ]);
+    }
+    let lock = GitIndex::lock(repo)?;
+    let mut index = GitIndex::load(repo)?;
+    let (lines, ignored_named) = stage_paths(repo, cwd, &mut index, pathspecs, options)?;
+    if !options.dry_run {
+        index.save_locked(repo, lock)?;
+    }
+
+    if !ignored_named.is_empty() {
+        let mut message = vec!["The following paths are ignored by one of your .gitignore files:".to_owned()];
+        message.extend(ignored_named);
+        message.push("hint: Use -f if you really want to add them.".to_owned());
+        return Err(CommandError::Add(message.join("\n")));
+    }
+    Ok(lines)
+}
+
+///Stage the files matching the pathspecs into `index`, returning the lines to print and the
+///ignored paths that were named but left out
+fn stage_paths(repo: &Rc<GitRepository>, cwd: &Path, index: &mut GitIndex, pathspecs: &[&str], options: &AddOptions) -> Result<(Vec<String>, Vec<String>), CommandError> {
+    let worktree = repo.worktree()?;
+    let pathspec = Pathspec::parse(pathspecs, &repo.prefix_of(cwd)?)?;
+    let stat_options = StatOptions::from_repo(repo);
+    let format = repo.object_format();
+    let mut convert = Converter::for_index(repo, index, AttrSource::WorktreeFirst)?;
+
+    let mut additions = Vec::new();
+    let mut removals = Vec::new();
+    //Tracked files: -N only records new paths, so it leaves these alone
+    if !options.intent_to_add {
+        let mut seen = HashSet::new();
+        for entry in index.entries() {
+            if entry.skip_worktree || !pathspec.matches(&entry.path, |path, name| convert.attribute(path, name)) || !seen.insert(entry.path.as_str()) {
+                continue;
+            }
+            match worktree_change(worktree, entry, format, stat_options, index.timestamp(), &mut convert)? {
+                Some(WorktreeChange::Deleted) => removals.push(entry.path.clone()),
+                Some(_) => additions.push(entry.path.clone()),
+                None if entry.stage != 0 || entry.intent_to_add => additions.push(entry.path.clone()),
+                None => {}
+            }
+        }
+    }
+
+    let mut ignored_named = Vec::new();
+    if !options.update {
+        let mut ignore = IgnoreRules::for_repository(repo)?;
+        for untracked in untracked_paths(worktree, index, &mut ignore, true)? {
+            //Nested repositories would need submodule support
+            if untracked.path.ends_with('/') || !pathspec.matches(&untracked.path, |path, name| convert.attribute(path, name)) {
+                continue;
+            }
+            if !untracked.ignored || options.force {
+                additions.push(untracked.path);
+            } else if pathspec.names(&untracked.path) {
+                ignored_named.push(untracked.path);
+            }
+        }
+    }
+
+    //Ignored files only count as a match when named outright, so `add '*.log'` still fails
+    let candidates = index
+        .entries()
+        .iter()
+        .map(|entry| entry.path.as_str())
+        .chain(additions.iter().map(String::as_str))
+        .chain(ignored_named.iter().map(String::as_str));
+    if let Some(unmatched) = pathspec.unmatched(candidates, |path, name| convert.attribute(path, name)).first() {
+        return Err(CommandError::Add(format!("pathspec '{}' did not match any files", unmatched)));
+    }
+
+    let mut lines = Vec::new();
+    let mut changes: Vec<(String, bool)> = additions.into_iter().map(|path| (path, true)).collect();
+    changes.extend(removals.into_iter().map(|path| (path, false)));
+    changes.sort_by(|a, b| a.0.as_bytes().cmp(b.0.as_bytes()));
+    for (path, is_addition) in changes {
+        if options.dry_run || options.verbose {
+            lines.push(format!("{} '{}'", if is_addition { "add" } else { "remove" }, path));
+        }
+        if options.dry_run {
+            continue;
+        }
+        if !is_addition {
+            index.remove(&path);
+            continue;
+        }
+        let file = worktree.join(&path);
+        let meta = std::fs::symlink_metadata(&file)?;
+        let old_mode = index.get(&path, 0).map(|entry| entry.mode);
+        let mode = mode_from_metadata(&meta, old_mode, stat_options.filemode);
+        let mut entry = if options.intent_to_add {
+            let mut entry = IndexEntry::new(&path, &hash_blob(b"", format), mode, StatData::from_metadata(&meta));
+            entry.intent_to_add = true;
+            entry
+        } else {
+            let mut content = read_worktree_file(&file, &meta)?;
+            if mode != MODE_SYMLINK {
+                content = convert.clean_checked(&path, content)?;
+            }
+            let sha = GitObject::new(ObjectType::Blob, content, repo).write_to_repo()?;
+            IndexEntry::new(&path, &sha, mode, StatData::from_metadata(&meta))
+        };
+        if let Some(old) = index.get(&path, 0) {
+            entry.assume_valid = old.assume_valid;
+        }
+        index.add_replacing(entry);
+    }
+    show_warnings(&convert.warnings);
+    Ok((lines, ignored_named))
+}
+
+///Options for `rm`
+#[derive(Default)]
+pub struct RmOptions {
+    pub cached: bool,
+    pub recursive: bool,
+    pub force: bool,
+    pub dry_run: bool,
+    pub quiet: bool,
+}
+
+///Remove files from the index, and from the worktree unless `--cached`
+pub fn rm(repo: &Rc<GitRepository>, pathspecs: &[&str], options: &RmOptions) -> Result<(), CommandError> {
+    for line in rm_paths(repo, &std::env::current_dir()?, pathspecs, options)? {
+        println!("{}", line);
+    }
+    Ok(())
+}
+
+fn rm_paths(repo: &Rc<GitRepository>, cwd: &Path, pathspecs: &[&str], options: &RmOptions) -> Result<Vec<String>, CommandError> {
+    if pathspecs.is_empty() {
+        return Err(CommandError::Rm("No pathspec was given. Which files should I remove?".to_owned()));
+    }
+    let worktree = repo.worktree()?;
+    let pathspec = Pathspec::parse(pathspecs, &repo.prefix_of(cwd)?)?;
+    let lock = GitIndex::lock(repo)?;
+    let mut index = GitIndex::load(repo)?;
+    let staged = index.entries().iter().filter(|entry| entry.stage == 0).map(|entry| (entry.path.clone(), entry.sha.clone()));
+    let mut attributes = AttributeRules::for_repository(repo, staged, AttrSource::WorktreeFirst)?;
+
+    if let Some(unmatched) = pathspec.unmatched(index.entries().iter().map(|entry| entry.path.as_str()), |path, name| attributes.get(path, name)).first() {
+        return Err(CommandError::Rm(format!("pathspec '{}' did not match any files", unmatched)));
+    }
+    let mut targets: Vec<&IndexEntry> = Vec::new();
+    for entry in index.entries().iter().filter(|entry| pathspec.matches(&entry.path, |path, name| attributes.get(path, name))) {
+        if !options.recursive {
+            if let Some(dir) = pathspec.matching_directory(&entry.path) {
+                return Err(CommandError::Rm(format!("not removing '{}' recursively without -r", dir)));
+            }
+        }
+        if targets.last().is_none_or(|last| last.path != entry.path) {
+            targets.push(entry);
+        }
+    }
+
+    if !options.force {
+        let mut convert = Converter::for_index(repo, &index, AttrSource::WorktreeFirst)?;
+        let removable = check_removable(repo, worktree, &targets, index.timestamp(), options.cached, &mut convert);
+        show_warnings(&convert.warnings);
+        removable?;
+    }
+
+    let paths: Vec<String> = targets.iter().map(|entry| entry.path.clone()).collect();
+    let mut lines = Vec::new();
+    for path in paths {
+        if !options.quiet {
+            lines.push(format!("rm '{}'", path));
+        }
+        if options.dry_run {
+            continue;
+        }
+        index.remove(&path);
+        if !options.cached {
+            remove_worktree_file(worktree, &path)?;
+        }
+    }
+    if !options.dry_run {
+        index.save_locked(repo, lock)?;
+    }
+    Ok(lines)
+}
+
+///Refuse to lose work: staged content that matches neither HEAD nor the file is always protected,
+///staged or local changes unless only the index entry goes away
+fn check_removable(repo: &Rc<GitRepository>, worktree: &Path, targets: &[&IndexEntry], index_time: Option<(u32, u32)>, cached: bool, convert: &mut Converter) -> Result<(), CommandError> {
+    let head: HashMap<String, (u32, String)> = match resolve_ref(repo, "HEAD")? {
+        Some(commit) => flatten_tree(repo, &peel_to_tree(repo, &commit)?)?
+            .into_iter()
+            .map(|entry| (entry.name, (entry.mode, entry.sha)))
+            .collect(),
+        None => HashMap::new(),
+    };
+    let stat_options = StatOptions::from_repo(repo);
+    let mut both = Vec::new();
+    let mut staged = Vec::new();
+    let mut local = Vec::new();
+    for entry in targets.iter().filter(|entry| entry.stage == 0) {
+        let staged_change = !entry.intent_to_add
+            && head.get(&entry.path).is_none_or(|(mode, sha)| *mode != entry.mode || *sha != entry.sha);
+        let local_change = matches!(
+            worktree_change(worktree, entry, repo.object_format(), stat_options, index_time, convert)?,
+            Some(WorktreeChange::Modified) | Some(WorktreeChange::TypeChanged)
+        );
+        if staged_change && local_change {
+            both.push(entry.path.as_str());
+        } else if !cached && staged_change {
+            staged.push(entry.path.as_str());
+        } else if !cached && local_change {
+            local.push(entry.path.as_str());
+        }
+    }
+
+    let keep_hint = "(use --cached to keep the file, or -f to force removal)";
+    let mut messages = Vec::new();
+    for (paths, singular, plural, hint) in [
+        (&both, "the following file has staged content different from both the\nfile and the HEAD:", "the following files have staged content different from both the\nfile and the HEAD:", "(use -f to force removal)"),
+        (&staged, "the following file has changes staged in the index:", "the following files have changes staged in the index:", keep_hint),
+        (&local, "the following file has local modifications:", "the following files have local modifications:", keep_hint),
+    ] {
+        if paths.is_empty() {
+            continue;
+        }
+        let mut message = vec![if paths.len() == 1 { singular } else { plural }.to_owned()];
+        message.extend(paths.iter().map(|path| format!("    {}", path)));
+        message.push(hint.to_owned());
+        messages.push(message.join("\n"));
+    }
+    if messages.is_empty() {
+        Ok(())
+    } else {
+        Err(CommandError::Rm(messages.join("\n")))
+    }
+}
+
+///Write the index as a tree, or the subtree at `prefix`, and print its id
+pub fn write_tree(repo: &Rc<GitRepository>, prefix: Option<&str>) -> Result<(), CommandError> {
+    println!("{}", write_tree_object(repo, prefix)?);
+    Ok(())
+}
+
+fn write_tree_object(repo: &Rc<GitRepository>, prefix: Option<&str>) -> Result<String, CommandError> {
+    let lock = GitIndex::lock(repo)?;
+    let mut index = GitIndex::load(repo)?;
+    let unmerged: Vec<String> = index
+        .entries()
+        .iter()
+        .filter(|entry| entry.stage != 0)
+        .map(|entry| format!("error: {}: unmerged ({})", entry.path, entry.sha))
+        .collect();
+    if !unmerged.is_empty() {
+        return Err(CommandError::WriteTree(format!("{}\ngit-write-tree: error building trees", unmerged.join("\n"))));
+    }
+    let root = write_index_tree(repo, &mut index)?;
+    //Saving keeps the fresh tree ids for the next write
+    index.save_locked(repo, lock)?;
+    let prefix = match prefix {
+        Some(prefix) => prefix,
+        None => return Ok(root),
+    };
+    let mut node = index.cache_tree();
+    for name in prefix.split('/').filter(|name| !name.is_empty()) {
+        node = node.and_then(|node| node.children.iter().find(|child| child.name == name));
+    }
+    node.and_then(|node| node.sha.clone())
+        .ok_or_else(|| CommandError::WriteTree(format!("git-write-tree: prefix {} not found", prefix)))
+}
+
+///Where part of a commit message comes from, in the order given on the command line
+#[derive(Clone, Debug, PartialEq)]
+pub enum MessageSource {
+    Text(String),
+    ///A file to read, `-` for standard input
+    File(String),
+}
+
+///Join message parts into paragraphs separated by blank lines, each ending in a newline.
+///No parts at all means the message is read from standard input
+fn read_message(sources: &[MessageSource]) -> Result<String, CommandError> {
+    let read_stdin = || -> Result<String, CommandError> {
+        let mut text = String::new();
+        std::io::Read::read_to_string(&mut std::io::stdin(), &mut text)?;
+        Ok(text)
+    };
+    if sources.is_empty() {
+        return read_stdin();
+    }
+    let mut message = String::new();
+    for source in sources {
+        if !message.is_empty() {
+            message.push('\n');
+        }
+        match source {
+            MessageSource::Text(text) => message.push_str(text),
+            MessageSource::File(path) if path == "-" => message.push_str(&read_stdin()?),
+            MessageSource::File(path) => message.push_str(&std::fs::read_to_string(path)?),
+        }
+        if !message.is_empty() && !message.ends_with('\n') {
+            message.push('\n');
+        }
+    }
+    Ok(message)
+}
+
+///Id of a named object, which must be of the given type
+fn resolve_typed(repo: &Rc<GitRepository>, name: &str, kind: ObjectType) -> Result<String, CommandError> {
+    let sha = find_object(repo, name, &GitNameFormat::Placeholder, true)?;
+    if *GitObject::from_internal_file(&sha, repo)?.kind() != kind {
+        return Err(ObjectError::WrongType(sha, if kind == ObjectType::Tree { "tree" } else { "commit" }).into());
+    }
+    Ok(sha)
+}
+
+///Write a commit of a tree with the given parents and print its id
+pub fn commit_tree(repo: &Rc<GitRepository>, tree: &str, parents: &[&str], message: &[MessageSource]) -> Result<(), CommandError> {
+    println!("{}", commit_tree_object(repo, tree, parents, message)?);
+    Ok(())
+}
+
+fn commit_tree_object(repo: &Rc<GitRepository>, tree: &str, parents: &[&str], message: &[MessageSource]) -> Result<String, CommandError> {
+    let tree = resolve_typed(repo, tree, ObjectType::Tree)?;
+    let mut parent_ids: Vec<String> = Vec::new();
+    for parent in parents {
+        let sha = resolve_typed(repo, parent, ObjectType::Commit)?;
+        if parent_ids.contains(&sha) {
+            eprintln!("error: duplicate parent {} ignored", sha);
+        } else {
+            parent_ids.push(sha);
+        }
+    }
+    let author = identity(repo.config(), IdentRole::Author)?;
+    let committer = identity(repo.config(), IdentRole::Committer)?;
+    let commit = Commit::new(&tree, &parent_ids, &author, &committer, &read_message(message)?);
+    Ok(GitObject::new(ObjectType::Commit, commit.serialize(), repo).write_to_repo()?)
+}
+
+///Options for `commit`
+#[derive(Default)]
+pub struct CommitOptions {
+    ///-m and -F paragraphs; none means the message is written in the editor
+    pub message: Vec<MessageSource>,
+    pub all: bool,
+    pub amend: bool,
+    pub allow_empty: bool,
+    pub author: Option<String>,
+    pub date: Option<String>,
+}
+
+const EDITOR_HELP: &str = "\n# Please enter the commit message for your changes. Lines starting\n\
+                           # with '#' will be ignored, and an empty message aborts the commit.\n#\n";
+
+///Record the index as a new commit on the current branch and print a summary of it
+pub fn commit(repo: &Rc<GitRepository>, options: &CommitOptions) -> Result<(), CommandError> {
+    println!("{}", commit_index(repo, options)?);
+    Ok(())
+}
+
+fn commit_index(repo: &Rc<GitRepository>, options: &CommitOptions) -> Result<String, CommandError> {
+    let worktree = repo.worktree()?;
+    let merge_head_file = repo_path(repo, "MERGE_HEAD");
+    let merge_heads: Vec<String> = match std::fs::read_to_string(&merge_head_file) {
+        Ok(text) => text.lines().map(str::to_owned).collect(),
+        Err(_) => Vec::new(),
+    };
+    if options.amend && !merge_heads.is_empty() {
+        return Err(CommandError::Commit("You are in the middle of a merge -- cannot amend.".to_owned()));
+    }
+    //With -a the files are staged into the locked index in memory, which is only written once
+    //the commit is recorded, so an aborted commit leaves the index as it was
+    let lock = GitIndex::lock(repo)?;
+    let mut index = GitIndex::load(repo)?;
+    if options.all {
+        stage_paths(repo, worktree, &mut index, &[], &AddOptions { update: true, ..AddOptions::default() })?;
+    }
+    if index.entries().iter().any(|entry| entry.stage != 0) {
+        return Err(CommandError::Commit("Committing is not possible because you have unmerged files.".to_owned()));
+    }
+
+    let head = resolve_ref(repo, "HEAD")?;
+    let amended = match (&head, options.amend) {
+        (Some(head), true) => Some(Commit::parse(GitObject::from_internal_file(head, repo)?.serialize())?),
+        (None, true) => return Err(CommandError::Commit("You have nothing to amend.".to_owned())),
+        (_, false) => None,
+    };
+    let parents: Vec<String> = match &amended {
+        Some(old) => old.parents().into_iter().map(str::to_owned).collect(),
+        None => head.iter().cloned().chain(merge_heads.iter().cloned()).collect(),
+    };
+
+    let tree = write_index_tree(repo, &mut index)?;
+    if !options.allow_empty && amended.is_none() && merge_heads.is_empty() {
+        let parent_tree = match &head {
+            Some(head) => peel_to_tree(repo, head)?,
+            None => GitObject::new(ObjectType::Tree, Vec::new(), repo).get_hash(),
+        };
+        if parent_tree == tree {
+            return Err(CommandError::Commit("nothing to commit (use --allow-empty to record a commit without changes)".to_owned()));
+        }
+    }
+
+    let message = commit_message(repo, options, amended.as_ref())?;
+    let mut author = match (&amended, &options.author) {
+        (Some(old), _) => old.author().ok_or(ObjectParseError::MalformedCommit())?,
+        (None, Some(_)) => {
+            let (time, offset) = identity_date(IdentRole::Author)?;
+            Signature { name: String::new(), email: String::new(), time, offset }
+        }
+        (None, None) => identity(repo.config(), IdentRole::Author)?,
+    };
+    if let Some(given) = &options.author {
+        let (name, email) = parse_name_email(given).ok_or_else(|| {
+            CommandError::Commit(format!("--author '{}' is not 'Name <email>' and matches no existing author", given))
+        })?;
+        author.name = name;
+        author.email = email;
+    }
+    if let Some(date) = &options.date {
+        let (time, offset) = parse_date(date).ok_or_else(|| IdentError::BadDate(date.clone()))?;
+        author.time = time;
+        author.offset = offset;
+    }
+    let committer = identity(repo.config(), IdentRole::Committer)?;
+
+    let new_commit = Commit::new(&tree, &parents, &author, &committer, &message);
+    let sha = GitObject::new(ObjectType::Commit, new_commit.serialize(), repo).write_to_repo()?;
+    let kind = if amended.is_some() {
+        " (amend)"
+    } else if parents.is_empty() {
+        " (initial)"
+    } else if !merge_heads.is_empty() {
+        " (merge)"
+    } else {
+        ""
+    };
+    let first_line = message.lines().next().unwrap_or("");
+    update_ref(repo, "HEAD", &sha, head.as_deref(), &committer, &format!("commit{}: {}", kind, first_line))?;
+    index.save_locked(repo, lock)?;
+    for state in ["MERGE_HEAD", "MERGE_MSG", "MERGE_MODE", "SQUASH_MSG"] {
+        let _ = std::fs::remove_file(repo_path(repo, state));
+    }
+
+    let branch = match read_ref(repo, "HEAD")? {
+        Some(RefValue::Symbolic(target)) => target.strip_prefix("refs/heads/").unwrap_or(&target).to_owned(),
+        _ => "detached HEAD".to_owned(),
+    };
+    let root = if parents.is_empty() { " (root-commit)" } else { "" };
+    Ok(format!("[{}{} {}] {}", branch, root, abbreviate(repo, &sha)?, new_commit.subject()))
+}
+
+///The message from -m/-F, or from the editor started on the merge or amended message
+fn commit_message(repo: &Rc<GitRepository>, options: &CommitOptions, amended: Option<&Commit>) -> Result<String, CommandError> {
+    let edit_file = repo_path(repo, "COMMIT_EDITMSG");
+    let message = if !options.message.is_empty() {
+        let message = cleanup_message(&read_message(&options.message)?, false);
+        std::fs::write(&edit_file, &message)?;
+        message
+    } else {
+        let initial = match (amended, std::fs::read_to_string(repo_path(repo, "MERGE_MSG"))) {
+            (Some(old), _) => old.message().to_owned(),
+            (None, Ok(merge_message)) => merge_message,
+            (None, Err(_)) => String::new(),
+        };
+        std::fs::write(&edit_file, format!("{}{}", initial, EDITOR_HELP))?;
+        launch_editor(repo, &edit_file)?;
+        cleanup_message(&std::fs::read_to_string(&edit_file)?, true)
+    };
+    if message.is_empty() {
+        return Err(CommandError::Commit("Aborting commit due to empty commit message.".to_owned()));
+    }
+    Ok(message)
+}
+
+///Run the user's editor on a file: GIT_EDITOR, core.editor, VISUAL, EDITOR, then vi
+fn launch_editor(repo: &GitRepository, file: &Path) -> Result<(), CommandError> {
+    let editor = std::env::var("GIT_EDITOR")
+        .ok()
+        .or_else(|| repo.config().get("core.editor").map(str::to_owned))
+        .or_else(|| std::env::var("VISUAL").ok())
+        .or_else(|| std::env::var("EDITOR").ok())
+        .unwrap_or_else(|| "vi".to_owned());
+    //":" is the conventional editor that leaves the file as it is
+    if editor == ":" {
+        return Ok(());
+    }
+    //Through the shell so editors configured with arguments work
+    let status = std::process::Command::new("sh")
+        .arg("-c")
+        .arg(format!("{} \"$@\"", editor))
+        .arg(&editor)
+        .arg(file)
+        .status()?;
+    if !status.success() {
+        return Err(CommandError::Commit(format!("There was a problem with the editor '{}'.", editor)));
+    }
+    Ok(())
+}
+
+///Output layouts of `status`
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum StatusFormat {
+    #[default]
+    Long,
+    Short,
+    PorcelainV1,
+    PorcelainV2,
+}
+
+///Options for `status`
+#[derive(Default)]
+pub struct StatusOptions {
+    pub format: StatusFormat,
+    pub branch: bool,
+    pub null_terminated: bool,
+    ///Overrides status.showUntrackedFiles
+    pub untracked: Option<UntrackedMode>,
+    pub ignored: bool,
+}
+
+///Show how HEAD, the index and the worktree differ
+pub fn status(repo: &Rc<GitRepository>, pathspecs: &[&str], options: &StatusOptions) -> Result<(), CommandError> {
+    print!("{}", status_output(repo, &std::env::current_dir()?, pathspecs, options)?);
+    Ok(())
 }
 
-pub fn init<P: Into<PathBuf>>(path: P) -> Result<(), CommandError> {
-    let path: PathBuf = path.into();
-    let git_dir = path.join(".git");
-    if git_dir.exists() {
-        Err(CommandError::Init("Already a git directory".to_owned()))
+fn status_output(repo: &Rc<GitRepository>, cwd: &Path, pathspecs: &[&str], options: &StatusOptions) -> Result<String, CommandError> {
+    let prefix = repo.prefix_of(cwd)?;
+    let config = repo.config();
+    let untracked = options.untracked.unwrap_or(match config.get("status.showuntrackedfiles") {
+        Some("no") => UntrackedMode::No,
+        Some("all") => UntrackedMode::All,
+        _ => UntrackedMode::Normal,
+    });
+    let scan = ScanOptions {
+        untracked,
+        ignored: options.ignored,
+        renames: !matches!(config.get_bool("status.renames"), Ok(Some(false))),
+    };
+    let status = repo_status(repo, &Pathspec::parse(pathspecs, &prefix)?, &scan)?;
+    show_warnings(&status.warnings);
+
+    //-z implies porcelain output unless a format was asked for
+    let format = match options.format {
+        StatusFormat::Long if options.null_terminated => StatusFormat::PorcelainV1,
+        format => format,
+    };
+    let quote_high = !matches!(config.get_bool("core.quotepath"), Ok(Some(false)));
+    let relative = format != StatusFormat::PorcelainV1 && format != StatusFormat::PorcelainV2;
+    let show = |path: &str| -> String {
+        let path = match relative_path(path, &prefix) {
+            //The directory we are in, shown as untracked from inside it
+            shown if shown.is_empty() && relative => "./".to_owned(),
+            shown if relative => shown,
+            _ => path.to_owned(),
+        };
+        if options.null_terminated { path } else { quote_path(&path, quote_high) }
+    };
+    let end = if options.null_terminated { '\0' } else { '\n' };
+
+    let mut out = String::new();
+    match format {
+        StatusFormat::Long => return long_status(repo, &status, untracked, &show),
+        StatusFormat::Short | StatusFormat::PorcelainV1 => {
+            if options.branch {
+                out.push_str(&format!("## {}{}", short_branch_line(&status.branch), end));
+            }
+            for entry in &status.entries {
+                let code = match entry.unmerged_code() {
+                    Some(code) => code.to_owned(),
+                    None => format!("{}{}", entry.staged, entry.unstaged),
+                };
+                match &entry.renamed_from {
+                    Some((from, _)) if options.null_terminated => out.push_str(&format!("{} {}\0{}\0", code, show(&entry.path), show(from))),
+                    Some((from, _)) => out.push_str(&format!("{} {} -> {}\n", code, show(from), show(&entry.path))),
+                    None => out.push_str(&format!("{} {}{}", code, show(&entry.path), end)),
+                }
+            }
+            for path in &status.untracked {
+                out.push_str(&format!("?? {}{}", show(path), end));
+            }
+            for path in &status.ignored {
+                out.push_str(&format!("!! {}{}", show(path), end));
+            }
+        }
+        StatusFormat::PorcelainV2 => {
+            if options.branch {
+                let branch = &status.branch;
+                out.push_str(&format!("# branch.oid {}{}", branch.head.as_deref().unwrap_or("(initial)"), end));
+                out.push_str(&format!("# branch.head {}{}", branch.branch.as_deref().unwrap_or("(detached)"), end));
+                if let Some(upstream) = &branch.upstream {
+                    out.push_str(&format!("# branch.upstream {}{}", upstream, end));
+                    if let Some((ahead, behind)) = branch.ahead_behind {
+                        out.push_str(&format!("# branch.ab +{} -{}{}", ahead, behind, end));
+                    }
+                }
+            }
+            let null_id = repo.object_format().null_id();
+            let mode_and_id = |blob: &Option<(u32, String)>| match blob {
+                Some((mode, sha)) => (format!("{:06o}", mode), sha.clone()),
+                None => ("000000".to_owned(), null_id.clone()),
+            };
+            //Unmerged paths come after all ordinary changes
+            let (unmerged, changed): (Vec<&StatusEntry>, Vec<&StatusEntry>) = status.entries.iter().partition(|entry| entry.stages.is_some());
+            for entry in changed.into_iter().chain(unmerged) {
+                let submodule = if entry.index.as_ref().or(entry.head.as_ref()).is_some_and(|(mode, _)| *mode == 0o160000) { "S..." } else { "N..." };
+                let (head_mode, head_id) = mode_and_id(&entry.head);
+                let (index_mode, index_id) = mode_and_id(&entry.index);
+                let letter = |letter: char| if letter == ' ' { '.' } else { letter };
+                if let (Some(stages), Some(code)) = (&entry.stages, entry.unmerged_code()) {
+                    let stages: Vec<(String, String)> = stages.iter().map(&mode_and_id).collect();
+                    out.push_str(&format!(
+                        "u {} {} {} {} {} {:06o} {} {} {} {}{}",
+                        code, submodule, stages[0].0, stages[1].0, stages[2].0, entry.worktree_mode,
+                        stages[0].1, stages[1].1, stages[2].1, show(&entry.path), end
+                    ));
+                    continue;
+                }
+                let fields = format!(
+                    "{}{} {} {} {} {:06o} {} {}",
+                    letter(entry.staged), letter(entry.unstaged), submodule, head_mode, index_mode, entry.worktree_mode, head_id, index_id
+                );
+                match &entry.renamed_from {
+                    Some((from, score)) => {
+                        let separator = if options.null_terminated { '\0' } else { '\t' };
+                        out.push_str(&format!("2 {} R{} {}{}{}{}", fields, score, show(&entry.path), separator, show(from), end));
+                    }
+                    None => out.push_str(&format!("1 {} {}{}", fields, show(&entry.path), end)),
+                }
+            }
+            for path in &status.untracked {
+                out.push_str(&format!("? {}{}", show(path), end));
+            }
+            for path in &status.ignored {
+                out.push_str(&format!("! {}{}", show(path), end));
+            }
+        }
+    }
+    Ok(out)
+}
+
+///The `## ...` line of short status: the branch, its upstream and how far apart they are
+fn short_branch_line(branch: &BranchStatus) -> String {
+    let name = match (&branch.branch, &branch.head) {
+        (Some(name), None) => return format!("No commits yet on {}", name),
+        (Some(name), Some(_)) => name.clone(),
+        (None, _) => return "HEAD (no branch)".to_owned(),
+    };
+    let upstream = match &branch.upstream {
+        Some(upstream) => upstream,
+        None => return name,
+    };
+    let tracking = match branch.ahead_behind {
+        None => " [gone]".to_owned(),
+        Some((0, 0)) => String::new(),
+        Some((ahead, 0)) => format!(" [ahead {}]", ahead),
+        Some((0, behind)) => format!(" [behind {}]", behind),
+        Some((ahead, behind)) => format!(" [ahead {}, behind {}]", ahead, behind),
+    };
+    format!("{}...{}{}", name, upstream, tracking)
+}
+
+///How a branch compares with its upstream, as `status` and `checkout` describe it
+fn tracking_info(branch: &BranchStatus) -> Option<String> {
+    let upstream = branch.upstream.as_ref().filter(|_| branch.head.is_some())?;
+    let plural = |count: usize| if count == 1 { "commit" } else { "commits" };
+    Some(match branch.ahead_behind {
+        None => format!("Your branch is based on '{}', but the upstream is gone.\n  (use \"git branch --unset-upstream\" to fixup)\n", upstream),
+        Some((0, 0)) => format!("Your branch is up to date with '{}'.\n", upstream),
+        Some((ahead, 0)) => format!("Your branch is ahead of '{}' by {} {}.\n  (use \"git push\" to publish your local commits)\n", upstream, ahead, plural(ahead)),
+        Some((0, behind)) => format!("Your branch is behind '{}' by {} {}, and can be fast-forwarded.\n  (use \"git pull\" to update your local branch)\n", upstream, behind, plural(behind)),
+        Some((ahead, behind)) => format!("Your branch and '{}' have diverged,\nand have {} and {} different commits each, respectively.\n  (use \"git pull\" to merge the remote branch into yours)\n", upstream, ahead, behind),
+    })
+}
+
+///Human readable status with hints, like plain `git status`
+fn long_status<F: Fn(&str) -> String>(repo: &GitRepository, status: &RepoStatus, untracked: UntrackedMode, show: &F) -> Result<String, CommandError> {
+    let mut out = String::new();
+    let branch = &status.branch;
+    match (&branch.branch, &branch.head) {
+        (Some(name), _) => out.push_str(&format!("On branch {}\n", name)),
+        (None, Some(head)) => out.push_str(&format!("HEAD detached at {}\n", abbreviate(repo, head)?)),
+        (None, None) => out.push_str("Not currently on any branch.\n"),
+    }
+    if let Some(tracking) = tracking_info(branch) {
+        out.push_str(&tracking);
+        out.push('\n');
+    }
+    if branch.head.is_none() {
+        out.push_str("\nNo commits yet\n\n");
+    }
+
+    let unmerged: Vec<&StatusEntry> = status.entries.iter().filter(|entry| entry.stages.is_some()).collect();
+    let staged: Vec<&StatusEntry> = status.entries.iter().filter(|entry| entry.stages.is_none() && entry.staged != ' ').collect();
+    let unstaged: Vec<&StatusEntry> = status.entries.iter().filter(|entry| entry.stages.is_none() && entry.unstaged != ' ').collect();
+    if status.merging {
+        if unmerged.is_empty() {
+            out.push_str("All conflicts fixed but you are still merging.\n  (use \"git commit\" to conclude merge)\n\n");
+        } else {
+            out.push_str("You have unmerged paths.\n  (fix conflicts and run \"git commit\")\n  (use \"git merge --abort\" to abort the merge)\n\n");
+        }
+    }
+
+    let change_label = |letter: char| match letter {
+        'A' => "new file:",
+        'D' => "deleted:",
+        'R' => "renamed:",
+        'T' => "typechange:",
+        _ => "modified:",
+    };
+    let restore_staged = if branch.head.is_some() { "  (use \"git restore --staged <file>...\" to unstage)\n" } else { "  (use \"git rm --cached <file>...\" to unstage)\n" };
+    if !staged.is_empty() {
+        out.push_str("Changes to be committed:\n");
+        if !status.merging {
+            out.push_str(restore_staged);
+        }
+        for entry in &staged {
+            let path = match &entry.renamed_from {
+                Some((from, _)) => format!("{} -> {}", show(from), show(&entry.path)),
+                None => show(&entry.path),
+            };
+            out.push_str(&format!("\t{:<12}{}\n", change_label(entry.staged), path));
+        }
+        out.push('\n');
+    }
+    if !unmerged.is_empty() {
+        out.push_str("Unmerged paths:\n");
+        if !status.merging {
+            out.push_str(restore_staged);
+        }
+        let deletions = unmerged.iter().any(|entry| entry.unmerged_code().is_some_and(|code| code.contains('D')));
+        out.push_str(if deletions { "  (use \"git add/rm <file>...\" as appropriate to mark resolution)\n" } else { "  (use \"git add <file>...\" to mark resolution)\n" });
+        for entry in &unmerged {
+            let label = match entry.unmerged_code() {
+                Some("DD") => "both deleted:",
+                Some("AU") => "added by us:",
+                Some("UD") => "deleted by them:",
+                Some("UA") => "added by them:",
+                Some("DU") => "deleted by us:",
+                Some("AA") => "both added:",
+                _ => "both modified:",
+            };
+            out.push_str(&format!("\t{:<17}{}\n", label, show(&entry.path)));
+        }
+        out.push('\n');
+    }
+    if !unstaged.is_empty() {
+        out.push_str("Changes not staged for commit:\n");
+        let deletions = unstaged.iter().any(|entry| entry.unstaged == 'D');
+        out.push_str(if deletions { "  (use \"git add/rm <file>...\" to update what will be committed)\n" } else { "  (use \"git add <file>...\" to update what will be committed)\n" });
+        out.push_str("  (use \"git restore <file>...\" to discard changes in working directory)\n");
+        for entry in &unstaged {
+            out.push_str(&format!("\t{:<12}{}\n", change_label(entry.unstaged), show(&entry.path)));
+        }
+        out.push('\n');
+    }
+    for (title, hint, paths) in [
+        ("Untracked files:", "  (use \"git add <file>...\" to include in what will be committed)", &status.untracked),
+        ("Ignored files:", "  (use \"git add -f <file>...\" to include in what will be committed)", &status.ignored),
+    ] {
+        if paths.is_empty() {
+            continue;
+        }
+        out.push_str(&format!("{}\n{}\n", title, hint));
+        for path in paths {
+            out.push_str(&format!("\t{}\n", show(path)));
+        }
+        out.push('\n');
+    }
+
+    if !staged.is_empty() || !unmerged.is_empty() {
+        if untracked == UntrackedMode::No {
+            out.push_str("Untracked files not listed (use -u option to show untracked files)\n");
+        }
+    } else if !unstaged.is_empty() {
+        out.push_str("no changes added to commit (use \"git add\" and/or \"git commit -a\")\n");
+    } else if !status.untracked.is_empty() {
+        out.push_str("nothing added to commit but untracked files present (use \"git add\" to track)\n");
+    } else if branch.head.is_none() {
+        out.push_str("nothing to commit (create/copy files and use \"git add\" to track)\n");
+    } else if untracked == UntrackedMode::No {
+        out.push_str("nothing to commit (use -u to show untracked files)\n");
     } else {
-        std::fs::create_dir_all(&git_dir).or(Err(CommandError::Init("Cannot create .git dirctory".to_owned())));
-        let config = default_config();
-        config.write(git_dir.join("config").to_str().unwrap());
-        Ok(())
+        out.push_str("nothing to commit, working tree clean\n");
     }
+    Ok(out)
 }
 
-pub fn cat_file<P: Into<PathBuf>>(git_dir_path: P, type_str: &str, target: &str) -> Result<(), CommandError> {
-    let repo = GitRepository::along_path(git_dir_path.into(), false)?;
-    let repo = Rc::new(repo);
-    let object = GitObject::from_internal_name(&repo, target, &GitNameFormat::Placeholder,true)?;
-    println!("{}",String::from_utf8_lossy(object.serialize()));
+///Options for `checkout`
+#[derive(Default)]
+pub struct CheckoutOptions {
+    pub force: bool,
+    pub detach: bool,
+}
+
+const DETACHED_ADVICE: &str = "You are in 'detached HEAD' state. You can look around, make experimental
+changes and commit them, and you can discard any commits you make in this
+state without impacting any branches by switching back to a branch.
+
+If you want to create a new branch to retain commits you create, you may
+do so (now or later) by using -c with the switch command. Example:
+
+  git switch -c <new-branch-name>
+
+Or undo this operation with:
+
+  git switch -
+
+Turn off this advice by setting config variable advice.detachedHead to false
+";
+
+///Switch to a branch or detached commit, bringing the index and worktree along, or with paths
+///copy just those paths out of the index or the given tree-ish
+pub fn checkout(repo: &Rc<GitRepository>, target: Option<&str>, pathspecs: &[&str], options: &CheckoutOptions) -> Result<(), CommandError> {
+    for line in checkout_lines(repo, &std::env::current_dir()?, target, pathspecs, options)? {
+        println!("{}", line);
+    }
     Ok(())
 }
 
-///Creates hash for the given file and possibly adds it to a repo
-pub fn hash_object(object_type: &str, file: &str, repo: &Rc<GitRepository>, write:bool) -> Result<(), CommandError> {
-    let blob = GitObject::from_external_file(file, repo)?;
-    if write {
-        blob.write_to_repo()?;
+fn checkout_lines(repo: &Rc<GitRepository>, cwd: &Path, target: Option<&str>, pathspecs: &[&str], options: &CheckoutOptions) -> Result<Vec<String>, CommandError> {
+    if !pathspecs.is_empty() {
+        let source = target.map(|name| resolve_tree(repo, name)).transpose()?;
+        let targets = RestoreTargets {
+            index: source.is_some(),
+            worktree: true,
+            overlay: true,
+        };
+        let base = match &source {
+            Some(tree) => CheckoutBase::Tree(Some(tree)),
+            None => CheckoutBase::Index,
+        };
+        let mut warnings = Vec::new();
+        let restored = restore_in(repo, cwd, base, pathspecs, targets, &mut warnings);
+        show_warnings(&warnings);
+        let count = restored?;
+        let from = match &source {
+            Some(tree) => abbreviate(repo, tree)?,
+            None => "the index".to_owned(),
+        };
+        return Ok(vec![format!("Updated {} path{} from {}", count, if count == 1 { "" } else { "s" }, from)]);
+    }
+
+    let target = target.unwrap_or("HEAD");
+    if target == "HEAD" && !options.detach {
+        //Forcing a checkout of HEAD throws the local changes away
+        let mut warnings = Vec::new();
+        if let Some(head) = resolve_ref(repo, "HEAD")?.filter(|_| options.force) {
+            let tree = peel_to_tree(repo, &head)?;
+            let lock = GitIndex::lock(repo)?;
+            let mut index = GitIndex::load(repo)?;
+            checkout_tree(repo, &mut index, CheckoutBase::Tree(Some(&tree)), Some(&tree), true, &mut warnings)?;
+            index.save_locked(repo, lock)?;
+        }
+        let lines = local_change_lines(repo, &mut warnings);
+        show_warnings(&warnings);
+        return lines;
+    }
+    let on_branch = !options.detach && resolve_ref(repo, &format!("refs/heads/{}", target))?.is_some();
+    let found = find_object(repo, target, &GitNameFormat::Placeholder, true)
+        .map_err(|_| CommandError::Checkout(format!("pathspec '{}' did not match any file(s) known to git", target)))?;
+    let commit = peel_to_commit(repo, &found)
+        .map_err(|_| CommandError::Checkout(format!("Cannot switch branch to a non-commit '{}'", target)))?;
+    let head_move = if on_branch { HeadMove::Branch(target) } else { HeadMove::Detach { advise: !options.detach } };
+    switch_head(repo, target, &commit, head_move, options.force)
+}
+
+///Where `switch_head` leaves HEAD
+enum HeadMove<'a> {
+    Branch(&'a str),
+    ///A branch made at the commit, or with `reset` moved there if it exists
+    NewBranch { name: &'a str, reset: bool },
+    ///`advise` explains detached HEAD to anyone who did not ask for it
+    Detach { advise: bool },
+}
+
+///Check out a commit's tree over the current one, then point HEAD at it the way `head_move`
+///says. `label` is what the user called the commit, for messages and the reflog
+fn switch_head(repo: &Rc<GitRepository>, label: &str, commit: &str, head_move: HeadMove, force: bool) -> Result<Vec<String>, CommandError> {
+    let head = read_ref(repo, "HEAD")?;
+    let old_commit = resolve_ref(repo, "HEAD")?;
+    //Held until HEAD has moved, so the worktree is never switched under an index someone else holds
+    let lock = GitIndex::lock(repo)?;
+    let mut index = GitIndex::load(repo)?;
+    let from_tree = old_commit.as_deref().map(|sha| peel_to_tree(repo, sha)).transpose()?;
+    let mut warnings = Vec::new();
+    let checked_out = checkout_tree(repo, &mut index, CheckoutBase::Tree(from_tree.as_deref()), Some(&peel_to_tree(repo, commit)?), force, &mut warnings);
+    if checked_out.is_err() {
+        show_warnings(&warnings);
+    }
+    checked_out?;
+
+    let committer = reflog_identity(repo.config());
+    let describe = |sha: &str| -> Result<String, CommandError> {
+        let subject = Commit::parse(GitObject::from_internal_file(sha, repo)?.serialize())?.subject();
+        Ok(format!("{} {}", abbreviate(repo, sha)?, subject))
+    };
+    let mut messages = Vec::new();
+    if let Some(RefValue::Direct(old)) = head.as_ref().filter(|_| old_commit.as_deref() != Some(commit)) {
+        messages.push(format!("Previous HEAD position was {}", describe(old)?));
+    }
+    let new_head = match head_move {
+        HeadMove::Branch(name) => {
+            let branch = RefValue::Symbolic(format!("refs/heads/{}", name));
+            messages.push(if head.as_ref() == Some(&branch) {
+                format!("Already on '{}'", name)
+            } else {
+                format!("Switched to branch '{}'", name)
+            });
+            branch
+        }
+        HeadMove::NewBranch { name, reset } => {
+            let full_name = format!("refs/heads/{}", name);
+            let existing = resolve_ref(repo, &full_name)?;
+            let message = match &existing {
+                Some(_) if reset => format!("branch: Reset to {}", label),
+                _ => format!("branch: Created from {}", label),
+            };
+            update_ref(repo, &full_name, commit, existing.as_deref(), &committer, &message)?;
+            messages.push(match existing {
+                Some(_) => format!("Switched to and reset branch '{}'", name),
+                None => format!("Switched to a new branch '{}'", name),
+            });
+            RefValue::Symbolic(full_name)
+        }
+        HeadMove::Detach { advise } => {
+            if advise && matches!(head, Some(RefValue::Symbolic(_))) && !matches!(repo.config().get_bool("advice.detachedhead"), Ok(Some(false))) {
+                messages.push(format!("Note: switching to '{}'.\n\n{}", label, DETACHED_ADVICE));
+            }
+            messages.push(format!("HEAD is now at {}", describe(commit)?));
+            RefValue::Direct(commit.to_owned())
+        }
+    };
+    let moved_from = match &head {
+        Some(RefValue::Symbolic(name)) => name.trim_start_matches("refs/heads/").to_owned(),
+        Some(RefValue::Direct(sha)) => sha.clone(),
+        None => String::new(),
+    };
+    let moved_to = match &new_head {
+        RefValue::Symbolic(name) => name.trim_start_matches("refs/heads/"),
+        RefValue::Direct(_) => label,
+    };
+    set_head(repo, &new_head, &committer, &format!("checkout: moving from {} to {}", moved_from, moved_to))?;
+    index.save_locked(repo, lock)?;
+    if let RefValue::Symbolic(_) = new_head {
+        messages.extend(tracking_info(&branch_status(repo)?).map(|tracking| tracking.trim_end().to_owned()));
+    }
+    //Local changes are described against the new HEAD, ahead of the messages
+    let mut lines = local_change_lines(repo, &mut warnings)?;
+    show_warnings(&warnings);
+    lines.extend(messages);
+    Ok(lines)
+}
+
+///Options for `switch`
+#[derive(Default)]
+pub struct SwitchOptions {
+    ///`-c`: a branch to create at the start point
+    pub create: Option<String>,
+    ///`-C`: like `create`, resetting the branch if it exists
+    pub force_create: Option<String>,
+    pub detach: bool,
+    ///`--discard-changes`/`--force`: throw away local changes in the way
+    pub discard_changes: bool,
+}
+
+///Switch to a branch, a new branch, or with `--detach` any commit
+pub fn switch(repo: &Rc<GitRepository>, target: Option<&str>, options: &SwitchOptions) -> Result<(), CommandError> {
+    for line in switch_lines(repo, target, options)? {
+        println!("{}", line);
+    }
+    Ok(())
+}
+
+fn switch_lines(repo: &Rc<GitRepository>, target: Option<&str>, options: &SwitchOptions) -> Result<Vec<String>, CommandError> {
+    let new_branch = options.create.as_deref().map(|name| (name, false)).or(options.force_create.as_deref().map(|name| (name, true)));
+    if let Some((name, reset)) = new_branch {
+        if !is_valid_branch_name(name) {
+            return Err(CommandError::Switch(format!("'{}' is not a valid branch name", name)));
+        }
+        if !reset && resolve_ref(repo, &format!("refs/heads/{}", name))?.is_some() {
+            return Err(CommandError::Switch(format!("a branch named '{}' already exists", name)));
+        }
+        let start = target.unwrap_or("HEAD");
+        let commit = resolve_commit(repo, start)?;
+        return switch_head(repo, start, &commit, HeadMove::NewBranch { name, reset }, options.discard_changes);
+    }
+
+    let target = match (target, options.detach) {
+        (Some(target), _) => target,
+        (None, true) => "HEAD",
+        (None, false) => return Err(CommandError::Switch("missing branch or commit argument".to_owned())),
+    };
+    let is_branch = target != "HEAD" && resolve_ref(repo, &format!("refs/heads/{}", target))?.is_some();
+    let commit = resolve_commit(repo, target)?;
+    if options.detach {
+        return switch_head(repo, target, &commit, HeadMove::Detach { advise: false }, options.discard_changes);
+    }
+    if !is_branch {
+        return Err(CommandError::Switch(format!("a branch is expected, got '{}'", target)));
+    }
+    switch_head(repo, target, &commit, HeadMove::Branch(target), options.discard_changes)
+}
+
+///The commit a name refers to, peeling tags, with the errors `switch` gives
+fn resolve_commit(repo: &Rc<GitRepository>, name: &str) -> Result<String, CommandError> {
+    let found = find_object(repo, name, &GitNameFormat::Placeholder, true).map_err(|_| CommandError::Switch(format!("invalid reference: {}", name)))?;
+    peel_to_commit(repo, &found).map_err(|_| CommandError::Switch(format!("reference is not a commit: {}", name)))
+}
+
+///The tree a tree-ish names, for copying paths out of it
+fn resolve_tree(repo: &Rc<GitRepository>, name: &str) -> Result<String, CommandError> {
+    let found = find_object(repo, name, &GitNameFormat::Placeholder, true).map_err(|_| CommandError::Checkout(format!("invalid reference: {}", name)))?;
+    Ok(peel_to_tree(repo, &found)?)
+}
+
+///Options for `restore`
+#[derive(Default)]
+pub struct RestoreOptions {
+    pub source: Option<String>,
+    pub staged: bool,
+    pub worktree: bool,
+}
+
+///Put files back as they are in the index or, with `--staged` or `--source`, in a commit
+pub fn restore(repo: &Rc<GitRepository>, pathspecs: &[&str], options: &RestoreOptions) -> Result<(), CommandError> {
+    restore_files(repo, &std::env::current_dir()?, pathspecs, options)?;
+    Ok(())
+}
+
+fn restore_files(repo: &Rc<GitRepository>, cwd: &Path, pathspecs: &[&str], options: &RestoreOptions) -> Result<usize, CommandError> {
+    if pathspecs.is_empty() {
+        return Err(CommandError::Restore("you must specify path(s) to restore".to_owned()));
+    }
+    //With neither location given only the worktree is restored
+    let targets = RestoreTargets {
+        index: options.staged,
+        worktree: options.worktree || !options.staged,
+        overlay: false,
+    };
+    //The index only comes from a commit, HEAD unless told otherwise
+    let source = match (&options.source, targets.index) {
+        (Some(source), _) => Some(source.as_str()),
+        (None, true) => Some("HEAD"),
+        (None, false) => None,
+    };
+    let tree = match source {
+        Some("HEAD") if resolve_ref(repo, "HEAD")?.is_none() => None,
+        Some(name) => Some(resolve_tree(repo, name).map_err(|_| CommandError::Restore(format!("could not resolve {}", name)))?),
+        None => None,
+    };
+    let base = match source {
+        Some(_) => CheckoutBase::Tree(tree.as_deref()),
+        None => CheckoutBase::Index,
+    };
+    let mut warnings = Vec::new();
+    let restored = restore_in(repo, cwd, base, pathspecs, targets, &mut warnings);
+    show_warnings(&warnings);
+    restored
+}
+
+///Restore paths given relative to `cwd` under the index lock, collecting conversion warnings
+fn restore_in(repo: &Rc<GitRepository>, cwd: &Path, source: CheckoutBase, pathspecs: &[&str], targets: RestoreTargets, warnings: &mut Vec<String>) -> Result<usize, CommandError> {
+    let pathspec = Pathspec::parse(pathspecs, &repo.prefix_of(cwd)?)?;
+    let lock = GitIndex::lock(repo)?;
+    let mut index = GitIndex::load(repo)?;
+    let count = restore_paths(repo, &mut index, source, &pathspec, targets, warnings)?;
+    index.save_locked(repo, lock)?;
+    Ok(count)
+}
+
+///Paths whose local changes survived a checkout, as `git checkout` lists them: one letter for
+///how the file differs from the new HEAD, then the path
+fn local_change_lines(repo: &Rc<GitRepository>, warnings: &mut Vec<String>) -> Result<Vec<String>, CommandError> {
+    let scan = ScanOptions {
+        untracked: UntrackedMode::No,
+        ignored: false,
+        renames: false,
+    };
+    let mut status = repo_status(repo, &Pathspec::default(), &scan)?;
+    warnings.append(&mut status.warnings);
+    Ok(status
+        .entries
+        .iter()
+        .filter_map(|entry| {
+            let letter = match (entry.staged, entry.unstaged) {
+                _ if entry.stages.is_some() => 'U',
+                ('A', 'D') => return None,
+                ('A', _) => 'A',
+                ('D', _) | (_, 'D') => 'D',
+                _ => 'M',
+            };
+            Some(format!("{}\t{}", letter, entry.path))
+        })
+        .collect())
+}
+
+///How far `reset` goes besides moving HEAD
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum ResetMode {
+    ///Only move HEAD
+    Soft,
+    ///Also make the index match
+    #[default]
+    Mixed,
+    ///Also make the worktree match, throwing local changes away
+    Hard,
+    ///Update files that differ between HEAD and the target, refusing if they have local changes
+    Keep,
+    ///Update files that differ between the index and the target, refusing if they have unstaged changes
+    Merge,
+}
+
+impl ResetMode {
+    fn name(self) -> &'static str {
+        match self {
+            ResetMode::Soft => "soft",
+            ResetMode::Mixed => "mixed",
+            ResetMode::Hard => "hard",
+            ResetMode::Keep => "keep",
+            ResetMode::Merge => "merge",
+        }
+    }
+}
+
+///Move HEAD to a commit, resetting the index and worktree as far as `mode` says, or with paths
+///reset just their index entries
+pub fn reset(repo: &Rc<GitRepository>, target: Option<&str>, pathspecs: &[&str], mode: Option<ResetMode>) -> Result<(), CommandError> {
+    for line in reset_lines(repo, &std::env::current_dir()?, target, pathspecs, mode)? {
+        println!("{}", line);
     }
-    println!("{}", blob.get_hash());
     Ok(())
 }
 
+fn reset_lines(repo: &Rc<GitRepository>, cwd: &Path, target: Option<&str>, pathspecs: &[&str], mode: Option<ResetMode>) -> Result<Vec<String>, CommandError> {
+    let mut warnings = Vec::new();
+    let lines = reset_collecting(repo, cwd, target, pathspecs, mode, &mut warnings);
+    show_warnings(&warnings);
+    lines
+}
+
+fn reset_collecting(
+    repo: &Rc<GitRepository>,
+    cwd: &Path,
+    target: Option<&str>,
+    pathspecs: &[&str],
+    mode: Option<ResetMode>,
+    warnings: &mut Vec<String>,
+) -> Result<Vec<String>, CommandError> {
+    //A lone argument that names no commit but an existing file is taken as a path, as git does
+    let (target, pathspecs) = match target {
+        Some(name) if pathspecs.is_empty() && find_object(repo, name, &GitNameFormat::Placeholder, true).is_err() && cwd.join(name).exists() => (None, vec![name]),
+        _ => (target, pathspecs.to_vec()),
+    };
+    let target_name = target.unwrap_or("HEAD");
+    let old = resolve_ref(repo, "HEAD")?;
+    let commit = match (target_name, &old) {
+        ("HEAD", None) => None,
+        _ => Some(
+            find_object(repo, target_name, &GitNameFormat::Placeholder, true)
+                .ok()
+                .and_then(|found| peel_to_commit(repo, &found).ok())
+                .ok_or_else(|| CommandError::Reset(format!("Failed to resolve '{}' as a valid revision.", target_name)))?,
+        ),
+    };
+    //An unborn HEAD resets to the empty tree, which needs no object
+    let tree = commit.as_deref().map(|commit| peel_to_tree(repo, commit)).transpose()?;
+    let only_index = RestoreTargets {
+        index: true,
+        worktree: false,
+        overlay: false,
+    };
+
+    if !pathspecs.is_empty() {
+        if let Some(mode) = mode.filter(|mode| *mode != ResetMode::Mixed) {
+            return Err(CommandError::Reset(format!("Cannot do {} reset with paths.", mode.name())));
+        }
+        restore_in(repo, cwd, CheckoutBase::Tree(tree.as_deref()), &pathspecs, only_index, warnings)?;
+        return unstaged_after_reset(repo, warnings);
+    }
+
+    let mode = mode.unwrap_or_default();
+    if mode == ResetMode::Soft && repo_path(repo, "MERGE_HEAD").is_file() {
+        return Err(CommandError::Reset("Cannot do a soft reset in the middle of a merge.".to_owned()));
+    }
+    let old_tree = old.as_deref().map(|sha| peel_to_tree(repo, sha)).transpose()?;
+    //A soft reset leaves the index alone, every other mode holds its lock until it is written
+    let lock = if mode == ResetMode::Soft { None } else { Some(GitIndex::lock(repo)?) };
+    let mut index = GitIndex::load(repo)?;
+    match mode {
+        ResetMode::Soft => {}
+        ResetMode::Mixed => {
+            restore_paths(repo, &mut index, CheckoutBase::Tree(tree.as_deref()), &Pathspec::default(), only_index, warnings)?;
+        }
+        ResetMode::Hard => checkout_tree(repo, &mut index, CheckoutBase::Tree(old_tree.as_deref()), tree.as_deref(), true, warnings)?,
+        ResetMode::Keep => checkout_tree(repo, &mut index, CheckoutBase::Tree(old_tree.as_deref()), tree.as_deref(), false, warnings)?,
+        ResetMode::Merge => checkout_tree(repo, &mut index, CheckoutBase::Index, tree.as_deref(), false, warnings)?,
+    }
+    if let Some(lock) = lock {
+        index.save_locked(repo, lock)?;
+    }
+
+    if let Some(commit) = &commit {
+        if let Some(old) = &old {
+            write_ref(repo, "ORIG_HEAD", &RefValue::Direct(old.clone()))?;
+        }
+        let committer = reflog_identity(repo.config());
+        update_ref(repo, "HEAD", commit, old.as_deref(), &committer, &format!("reset: moving to {}", target_name))?;
+    }
+    for state in ["MERGE_HEAD", "MERGE_MSG", "MERGE_MODE", "SQUASH_MSG", "CHERRY_PICK_HEAD", "REVERT_HEAD"] {
+        let _ = std::fs::remove_file(repo_path(repo, state));
+    }
+
+    match (mode, &commit) {
+        (ResetMode::Hard, Some(commit)) => {
+            let subject = Commit::parse(GitObject::from_internal_file(commit, repo)?.serialize())?.subject();
+            Ok(vec![format!("HEAD is now at {} {}", abbreviate(repo, commit)?, subject)])
+        }
+        (ResetMode::Mixed, _) => unstaged_after_reset(repo, warnings),
+        _ => Ok(Vec::new()),
+    }
+}
+
+///Files that still differ from the index after a reset, as `reset` lists them
+fn unstaged_after_reset(repo: &Rc<GitRepository>, warnings: &mut Vec<String>) -> Result<Vec<String>, CommandError> {
+    let scan = ScanOptions {
+        untracked: UntrackedMode::No,
+        ignored: false,
+        renames: false,
+    };
+    let mut status = repo_status(repo, &Pathspec::default(), &scan)?;
+    warnings.append(&mut status.warnings);
+    let changed: Vec<String> = status
+        .entries
+        .iter()
+        .filter(|entry| entry.stages.is_some() || entry.unstaged != ' ')
+        .map(|entry| format!("{}\t{}", if entry.stages.is_some() { 'U' } else { entry.unstaged }, entry.path))
+        .collect();
+    if changed.is_empty() {
+        return Ok(changed);
+    }
+    let mut lines = vec!["Unstaged changes after reset:".to_owned()];
+    lines.extend(changed);
+    Ok(lines)
+}
+
 #[cfg(test)]
 mod tests {
-    use super::{hash_object, init};
-    use crate::lib::get_test_dir;
-    use crate::lib::objects::git_repository::GitRepository;
+    use super::{add_paths, check_attr_lines, check_ignore_lines, checkout_lines, commit_index, config, hash_object, init, ls_files_lines, rm_paths, reset_lines, restore_files, status_output, switch_lines, worktree_add, worktree_prune, write_tree_object, AddOptions, CheckAttrOptions, CheckIgnoreOptions, CheckoutOptions, CommitOptions, ConfigAction, ConfigOptions, InitOptions, LsFilesOptions, MessageSource, ResetMode, RestoreOptions, RmOptions, StatusFormat, StatusOptions, SwitchOptions, WorktreeAddOptions};
+    use crate::lib::objects::git_commit::Commit;
+    use crate::lib::objects::git_index::{GitIndex, IndexEntry, StatData};
+    use crate::lib::objects::git_workdir::hash_blob;
+    use crate::lib::objects::git_config::{ConfigFile, ConfigType};
+    use crate::lib::objects::git_hash::ObjectFormat;
+    use crate::lib::objects::git_object::{GitObject, ObjectType};
+    use crate::lib::{configured_test_repo, get_test_dir, test_repo};
+    use crate::lib::objects::git_refs::{read_ref, resolve_ref, RefValue};
+    use crate::lib::objects::git_repository::{repo_path, GitRepository};
+    use std::path::PathBuf;
     use std::rc::Rc;
     #[test]
+    #[allow(clippy::assertions_on_constants, clippy::needless_borrows_for_generic_args)]
     fn create_default_repo() {
         let test_dir = get_test_dir("create_default_repo");
         if test_dir.join(".git").exists() {
             std::fs::remove_dir_all(&test_dir.join(".git")).expect("Error cleaning directory");
         }
-        match init(test_dir.to_str().unwrap()) {
+        match init(test_dir.to_str().unwrap(), &InitOptions::default()) {
             Ok(_) => assert!(true),
             Err(err) => assert!(false, "Error initializing repo: {:?}", err),
         }
     }
 
     #[test]
+    fn init_lays_out_repository() {
+        let test_dir = get_test_dir("init_lays_out_repository");
+        if test_dir.join(".git").exists() {
+            std::fs::remove_dir_all(test_dir.join(".git")).expect("Error cleaning directory");
+        }
+        init(&test_dir, &InitOptions::default()).expect("Error initializing repo");
+        let git_dir = test_dir.join(".git");
+        for dir in ["objects/info", "objects/pack", "refs/heads", "refs/tags"].iter() {
+            assert!(git_dir.join(dir).is_dir(), "missing directory {}", dir);
+        }
+        for file in ["config", "description", "info/exclude"].iter() {
+            assert!(git_dir.join(file).is_file(), "missing file {}", file);
+        }
+        let head = std::fs::read_to_string(git_dir.join("HEAD")).expect("Error reading HEAD");
+        assert!(head == "ref: refs/heads/master\n", "HEAD was {}", head);
+    }
+
+    #[test]
+    fn reinit_keeps_existing_repo() {
+        let test_dir = get_test_dir("reinit_keeps_existing_repo");
+        if test_dir.join(".git").exists() {
+            std::fs::remove_dir_all(test_dir.join(".git")).expect("Error cleaning directory");
+        }
+        let options = InitOptions {
+            initial_branch: Some("main".to_owned()),
+            ..InitOptions::default()
+        };
+        init(&test_dir, &options).expect("Error initializing repo");
+        let description = test_dir.join(".git").join("description");
+        std::fs::write(&description, "custom\n").expect("Error editing description");
+        std::fs::remove_dir_all(test_dir.join(".git").join("refs")).expect("Error removing refs");
+
+        init(&test_dir, &InitOptions::default()).expect("Error re-initializing repo");
+
+        let head = std::fs::read_to_string(test_dir.join(".git").join("HEAD")).expect("Error reading HEAD");
+        assert!(head == "ref: refs/heads/main\n", "HEAD was {}", head);
+        assert!(std::fs::read_to_string(&description).unwrap() == "custom\n");
+        assert!(test_dir.join(".git").join("refs").join("heads").is_dir());
+    }
+
+    #[test]
+    fn init_bare_with_template() {
+        let test_dir = get_test_dir("init_bare_with_template");
+        let repo_dir = test_dir.join("repo.git");
+        let template_dir = test_dir.join("template");
+        if test_dir.exists() {
+            std::fs::remove_dir_all(&test_dir).expect("Error cleaning directory");
+        }
+        std::fs::create_dir_all(template_dir.join("hooks")).expect("Error creating template");
+        std::fs::write(template_dir.join("hooks").join("pre-commit"), "#!/bin/sh\n").unwrap();
+        std::fs::write(template_dir.join("description"), "from template\n").unwrap();
+
+        let options = InitOptions {
+            bare: true,
+            template: Some(template_dir),
+            ..InitOptions::default()
+        };
+        init(&repo_dir, &options).expect("Error initializing bare repo");
+
+        assert!(!repo_dir.join(".git").exists());
+        assert!(repo_dir.join("HEAD").is_file());
+        assert!(repo_dir.join("hooks").join("pre-commit").is_file());
+        let description = std::fs::read_to_string(repo_dir.join("description")).unwrap();
+        assert!(description == "from template\n", "description was {}", description);
+        let config = std::fs::read_to_string(repo_dir.join("config")).unwrap();
+        assert!(config.contains("\tbare = true\n"), "config was {}", config);
+    }
+
+    #[test]
+    fn init_rejects_bad_branch() {
+        let test_dir = get_test_dir("init_rejects_bad_branch");
+        let options = InitOptions {
+            initial_branch: Some("bad..name".to_owned()),
+            ..InitOptions::default()
+        };
+        assert!(init(&test_dir, &options).is_err());
+    }
+
+    #[test]
+    fn init_sha256_repo() {
+        let test_dir = get_test_dir("init_sha256_repo");
+        if test_dir.exists() {
+            std::fs::remove_dir_all(&test_dir).expect("Error cleaning directory");
+        }
+        let options = InitOptions {
+            object_format: Some(ObjectFormat::Sha256),
+            ..InitOptions::default()
+        };
+        init(&test_dir, &options).expect("Error initializing sha256 repo");
+
+        let repo = Rc::new(GitRepository::at_path(&test_dir, false).expect("Error opening repo"));
+        assert!(repo.object_format() == ObjectFormat::Sha256);
+        let object = GitObject::new(ObjectType::Blob, b"what is up, doc?".to_vec(), &repo);
+        let sha = object.write_to_repo().expect("Error writing object");
+        assert!(sha.len() == 64, "id was {}", sha);
+
+        let sha1 = InitOptions {
+            object_format: Some(ObjectFormat::Sha1),
+            ..InitOptions::default()
+        };
+        assert!(init(&test_dir, &sha1).is_err());
+    }
+
+    #[test]
+    #[allow(clippy::assertions_on_constants, clippy::needless_borrows_for_generic_args)]
     fn hash_a_file() {
         let test_dir = get_test_dir("hash_a_file");
         if test_dir.join(".git").exists() {
@@ -89,13 +2301,538 @@ mod tests {
         }
         let test_file = std::env::current_dir()
             .expect("Unable to find test file")
-            .join(r"src\test\blob_test.txt");
+            .join(["src", "test", "blob_test.txt"].iter().collect::<PathBuf>());
 
-        init(&test_dir).expect("unable to create git dir at test dir");
+        init(&test_dir, &InitOptions::default()).expect("unable to create git dir at test dir");
         let repo = Rc::new(GitRepository::at_path( &test_dir, false).expect("Error opening repo"));
         match hash_object("blob", test_file.to_str().unwrap(), &repo,false) {
             Ok(_) => assert!(true),
             Err(err) => assert!(false, "Error hashing object: {:?}",err),
         }
     }
+
+    #[test]
+    fn add_linked_worktree() {
+        let test_dir = get_test_dir("add_linked_worktree");
+        let main_dir = test_dir.join("main");
+        let linked_dir = test_dir.join("linked");
+        if test_dir.exists() {
+            std::fs::remove_dir_all(&test_dir).expect("Error cleaning directory");
+        }
+        init(&main_dir, &InitOptions::default()).expect("Error initializing repo");
+        let sha = "4494953d947add7f87c652ad6bdf7243cc945041";
+        std::fs::write(main_dir.join(".git").join("refs").join("heads").join("master"), format!("{}\n", sha)).unwrap();
+        let repo = GitRepository::at_path(&main_dir, false).expect("Error opening repo");
+
+        let options = WorktreeAddOptions {
+            new_branch: Some("topic".to_owned()),
+            no_checkout: true,
+            ..WorktreeAddOptions::default()
+        };
+        worktree_add(&repo, &linked_dir, None, &options).expect("Error adding worktree");
+        assert!(worktree_add(&repo, &linked_dir, None, &options).is_err());
+
+        let linked = GitRepository::at_path(&linked_dir, false).expect("Error opening linked worktree");
+        assert!(linked.is_linked_worktree());
+        assert!(linked.worktree().unwrap() == linked_dir);
+        assert!(repo_path(&linked, "HEAD").starts_with(linked.commondir().join("worktrees")));
+        assert!(repo_path(&linked, "refs/heads/topic") == linked.commondir().join("refs/heads/topic"));
+        assert!(resolve_ref(&linked, "HEAD").unwrap() == Some(sha.to_owned()));
+
+        std::fs::remove_dir_all(&linked_dir).unwrap();
+        worktree_prune(&repo, false, false).expect("Error pruning worktrees");
+        assert!(!main_dir.join(".git").join("worktrees").exists());
+    }
+
+    #[test]
+    fn config_edits_a_file() {
+        let test_dir = get_test_dir("config_edits_a_file");
+        std::fs::create_dir_all(&test_dir).expect("Error creating directory");
+        let file = test_dir.join("config");
+        std::fs::write(&file, "# kept\n[core]\n\tbare = false\n").unwrap();
+        let options = ConfigOptions {
+            file: Some(file.clone()),
+            ..ConfigOptions::default()
+        };
+        let typed = ConfigOptions {
+            file: Some(file.clone()),
+            value_type: Some(ConfigType::Bool),
+            ..ConfigOptions::default()
+        };
+        let name = |name: &str| name.to_owned();
+
+        config(&ConfigAction::Set { name: name("core.bare"), value: name("yes") }, &typed).unwrap();
+        config(&ConfigAction::Add { name: name("remote.origin.fetch"), value: name("a") }, &options).unwrap();
+        config(&ConfigAction::Add { name: name("remote.origin.fetch"), value: name("b") }, &options).unwrap();
+        let unset = ConfigAction::Unset { name: name("remote.origin.fetch"), value_pattern: None };
+        assert!(config(&unset, &options).is_err());
+        let unset = ConfigAction::UnsetAll { name: name("remote.origin.fetch"), value_pattern: Some(name("^a$")) };
+        config(&unset, &options).unwrap();
+        let rename = ConfigAction::RenameSection { old_name: name("remote.origin"), new_name: name("remote.upstream") };
+        config(&rename, &options).unwrap();
+        let missing = ConfigAction::Get { name: name("core.missing"), value_pattern: None };
+        assert!(config(&missing, &options).is_err());
+
+        let text = std::fs::read_to_string(&file).unwrap();
+        assert!(text.starts_with("# kept\n[core]\n\tbare = true\n"), "config was {}", text);
+        let written = ConfigFile::parse(&text).unwrap();
+        assert!(written.get_all("remote.upstream.fetch") == vec![Some("b")], "config was {}", text);
+    }
+
+    #[test]
+    fn ls_files_lists_index_and_worktree() {
+        let (test_dir, repo) = test_repo("ls_files_lists_index_and_worktree");
+        std::fs::create_dir_all(test_dir.join("sub")).unwrap();
+        for (path, content) in [("a.txt", "a\n"), ("sub/b.txt", "b\n"), ("gone.txt", "g\n")] {
+            std::fs::write(test_dir.join(path), content).unwrap();
+        }
+        let mut index = GitIndex::new();
+        for path in ["a.txt", "sub/b.txt", "gone.txt"] {
+            let file = test_dir.join(path);
+            let stat = StatData::from_metadata(&std::fs::metadata(&file).unwrap());
+            let sha = hash_blob(&std::fs::read(&file).unwrap(), repo.object_format());
+            index.add(IndexEntry::new(path, &sha, 0o100644, stat));
+        }
+        index.save(&repo).expect("Error writing index");
+        std::fs::remove_file(test_dir.join("gone.txt")).unwrap();
+        std::fs::write(test_dir.join("a.txt"), "changed\n").unwrap();
+        std::fs::write(test_dir.join("new.log"), "").unwrap();
+        std::fs::write(test_dir.join("sub").join("new.txt"), "").unwrap();
+        std::fs::write(test_dir.join(".gitignore"), "*.log\n").unwrap();
+
+        let list = |cwd: &PathBuf, pathspecs: &[&str], options: &LsFilesOptions| ls_files_lines(&repo, cwd, pathspecs, options).unwrap();
+        let cached = list(&test_dir, &[], &LsFilesOptions::default());
+        assert!(cached == ["a.txt", "gone.txt", "sub/b.txt"], "cached was {:?}", cached);
+        let changed = list(&test_dir, &[], &LsFilesOptions { modified: true, deleted: true, ..LsFilesOptions::default() });
+        assert!(changed == ["a.txt", "gone.txt", "gone.txt"], "changed was {:?}", changed);
+        let others = list(&test_dir, &[], &LsFilesOptions { others: true, exclude_standard: true, ..LsFilesOptions::default() });
+        assert!(others == [".gitignore", "sub/new.txt"], "others were {:?}", others);
+        let ignored = LsFilesOptions { others: true, ignored: true, exclude_standard: true, ..LsFilesOptions::default() };
+        assert!(list(&test_dir, &[], &ignored) == ["new.log"]);
+        let from_sub = list(&test_dir.join("sub"), &["../a.txt", "b.txt"], &LsFilesOptions { stage: true, ..LsFilesOptions::default() });
+        assert!(from_sub.len() == 2 && from_sub[0].ends_with(" 0\t../a.txt"), "stage was {:?}", from_sub);
+        assert!(ls_files_lines(&repo, &test_dir, &[], &LsFilesOptions { ignored: true, ..LsFilesOptions::default() }).is_err());
+    }
+
+    #[test]
+    fn ls_files_matches_attr_pathspecs() {
+        let (test_dir, repo) = test_repo("ls_files_matches_attr_pathspecs");
+        std::fs::create_dir_all(test_dir.join("sub")).unwrap();
+        std::fs::write(test_dir.join(".gitattributes"), "*.txt text\n*.bin -text\n").unwrap();
+        std::fs::write(test_dir.join("sub").join(".gitattributes"), "b.txt -text\n").unwrap();
+        for path in ["a.txt", "sub/b.txt", "c.bin", "d.dat"] {
+            std::fs::write(test_dir.join(path), "x\n").unwrap();
+        }
+        add_paths(&repo, &test_dir, &["."], &AddOptions::default()).unwrap();
+
+        let list = |pathspecs: &[&str]| ls_files_lines(&repo, &test_dir, pathspecs, &LsFilesOptions::default());
+        assert!(list(&[":(attr:text)"]).unwrap() == ["a.txt"]);
+        assert!(list(&[":(attr:-text)"]).unwrap() == ["c.bin", "sub/b.txt"]);
+        assert!(list(&[":(attr:!text)"]).unwrap() == [".gitattributes", "d.dat", "sub/.gitattributes"]);
+        assert!(list(&[".", ":(exclude,attr:text)"]).unwrap() == [".gitattributes", "c.bin", "d.dat", "sub/.gitattributes", "sub/b.txt"]);
+    }
+
+    #[test]
+    fn add_and_rm_update_index() {
+        let (test_dir, repo) = test_repo("add_and_rm_update_index");
+        std::fs::create_dir_all(test_dir.join("sub")).unwrap();
+        std::fs::write(test_dir.join("sub").join("a.txt"), "a\n").unwrap();
+        std::fs::write(test_dir.join("sub").join("x.log"), "").unwrap();
+        std::fs::write(test_dir.join(".gitignore"), "*.log\n").unwrap();
+
+        let dry_run = AddOptions { dry_run: true, ..AddOptions::default() };
+        let lines = add_paths(&repo, &test_dir.join("sub"), &["."], &dry_run).unwrap();
+        assert!(lines == ["add 'sub/a.txt'"], "dry run was {:?}", lines);
+        assert!(GitIndex::load(&repo).unwrap().entries().is_empty());
+        assert!(add_paths(&repo, &test_dir, &["missing"], &AddOptions::default()).is_err());
+        assert!(add_paths(&repo, &test_dir, &["sub/x.log"], &AddOptions::default()).is_err(), "Ignored files need -f");
+
+        add_paths(&repo, &test_dir, &[], &AddOptions { all: true, ..AddOptions::default() }).unwrap();
+        let index = GitIndex::load(&repo).unwrap();
+        let paths: Vec<&str> = index.entries().iter().map(|entry| entry.path.as_str()).collect();
+        assert!(paths == [".gitignore", "sub/a.txt"], "index was {:?}", paths);
+        let sha = &index.get("sub/a.txt", 0).unwrap().sha;
+        assert!(GitObject::from_internal_file(sha, &repo).is_ok(), "Blob was not written");
+
+        assert!(rm_paths(&repo, &test_dir, &["sub"], &RmOptions::default()).is_err(), "Directories need -r");
+        assert!(rm_paths(&repo, &test_dir, &["sub/a.txt"], &RmOptions::default()).is_err(), "Staged changes are protected");
+        rm_paths(&repo, &test_dir, &["sub/a.txt"], &RmOptions { cached: true, ..RmOptions::default() }).unwrap();
+        assert!(GitIndex::load(&repo).unwrap().get("sub/a.txt", 0).is_none());
+        assert!(test_dir.join("sub").join("a.txt").exists(), "--cached keeps the file");
+    }
+
+    #[test]
+    fn commit_moves_branch_and_logs() {
+        let (test_dir, repo) = test_repo("commit_moves_branch_and_logs");
+        std::fs::write(test_dir.join("a.txt"), "a\n").unwrap();
+        add_paths(&repo, &test_dir, &["a.txt"], &AddOptions::default()).unwrap();
+
+        let message = |text: &str| CommitOptions { message: vec![MessageSource::Text(text.to_owned())], ..CommitOptions::default() };
+        let summary = commit_index(&repo, &message("first")).expect("Error committing");
+        assert!(summary.starts_with("[master (root-commit) ") && summary.ends_with("] first"), "Summary was {}", summary);
+        let first = resolve_ref(&repo, "refs/heads/master").unwrap().expect("Branch was not created");
+        assert!(commit_index(&repo, &message("again")).is_err(), "Nothing changed");
+
+        let empty = CommitOptions { allow_empty: true, author: Some("Other <other@example.com>".to_owned()), ..message("second") };
+        commit_index(&repo, &empty).expect("Error committing empty change");
+        let second = resolve_ref(&repo, "HEAD").unwrap().unwrap();
+        let commit = Commit::parse(GitObject::from_internal_file(&second, &repo).unwrap().serialize()).unwrap();
+        assert!(commit.parents() == [first.as_str()]);
+        assert!(commit.author().unwrap().name == "Other" && commit.committer().unwrap().name == "A U Thor");
+
+        let log = std::fs::read_to_string(test_dir.join(".git").join("logs").join("HEAD")).expect("Missing HEAD reflog");
+        assert!(log.lines().count() == 2 && log.contains("\tcommit (initial): first\n"), "Reflog was {}", log);
+    }
+
+    #[test]
+    fn write_tree_fails_while_index_is_locked() {
+        let (test_dir, repo) = test_repo("write_tree_fails_while_index_is_locked");
+        std::fs::write(test_dir.join("a.txt"), "a\n").unwrap();
+        add_paths(&repo, &test_dir, &["a.txt"], &AddOptions::default()).unwrap();
+        let lock = test_dir.join(".git").join("index.lock");
+        std::fs::write(&lock, "").unwrap();
+        assert!(write_tree_object(&repo, None).is_err(), "Wrote a tree while the index was locked");
+        std::fs::remove_file(&lock).unwrap();
+        write_tree_object(&repo, None).expect("Error writing tree");
+        assert!(GitIndex::load(&repo).unwrap().cache_tree().is_some(), "Tree ids were not saved");
+    }
+
+    #[test]
+    fn status_reports_staged_and_untracked() {
+        let (test_dir, repo) = test_repo("status_reports_staged_and_untracked");
+        std::fs::create_dir_all(test_dir.join("new")).unwrap();
+        for path in ["a.txt", "b.txt", "new/c.txt"] {
+            std::fs::write(test_dir.join(path), "content\n").unwrap();
+        }
+        add_paths(&repo, &test_dir, &["a.txt", "b.txt"], &AddOptions::default()).unwrap();
+        std::fs::write(test_dir.join("b.txt"), "changed\n").unwrap();
+
+        let short = StatusOptions { format: StatusFormat::Short, branch: true, ..StatusOptions::default() };
+        let output = status_output(&repo, &test_dir, &[], &short).expect("Error getting status");
+        assert!(output == "## No commits yet on master\nA  a.txt\nAM b.txt\n?? new/\n", "Short status was {:?}", output);
+        let from_sub = status_output(&repo, &test_dir.join("new"), &[], &short).unwrap();
+        assert!(from_sub.ends_with("AM ../b.txt\n?? ./\n"), "Status from a subdirectory was {:?}", from_sub);
+
+        let v2 = StatusOptions { format: StatusFormat::PorcelainV2, branch: true, null_terminated: true, ..StatusOptions::default() };
+        let output = status_output(&repo, &test_dir, &["a.txt"], &v2).unwrap();
+        let lines: Vec<&str> = output.split_terminator('\0').collect();
+        assert!(lines[0] == "# branch.oid (initial)" && lines[1] == "# branch.head master", "Headers were {:?}", lines);
+        assert!(lines.len() == 3 && lines[2].starts_with("1 A. N... 000000 100644 100644 "), "Entries were {:?}", lines);
+    }
+
+    #[test]
+    fn checkout_switches_trees_and_protects_changes() {
+        let (test_dir, repo) = test_repo("checkout_switches_trees_and_protects_changes");
+        let message = |text: &str| CommitOptions { message: vec![MessageSource::Text(text.to_owned())], ..CommitOptions::default() };
+        std::fs::create_dir_all(test_dir.join("dir")).unwrap();
+        std::fs::write(test_dir.join("a.txt"), "a\n").unwrap();
+        std::fs::write(test_dir.join("dir").join("b.txt"), "b\n").unwrap();
+        add_paths(&repo, &test_dir, &["."], &AddOptions::default()).unwrap();
+        commit_index(&repo, &message("first")).unwrap();
+        let first = resolve_ref(&repo, "HEAD").unwrap().unwrap();
+        std::fs::write(test_dir.join("a.txt"), "a2\n").unwrap();
+        rm_paths(&repo, &test_dir, &["dir/b.txt"], &RmOptions::default()).unwrap();
+        add_paths(&repo, &test_dir, &["a.txt"], &AddOptions::default()).unwrap();
+        commit_index(&repo, &message("second")).unwrap();
+
+        let options = CheckoutOptions::default();
+        let lines = checkout_lines(&repo, &test_dir, Some(&first), &[], &options).expect("Error checking out");
+        assert!(lines.last().is_some_and(|line| line.starts_with("HEAD is now at ")), "Output was {:?}", lines);
+        assert!(std::fs::read_to_string(test_dir.join("dir").join("b.txt")).unwrap() == "b\n");
+        assert!(read_ref(&repo, "HEAD").unwrap() == Some(RefValue::Direct(first.clone())));
+
+        std::fs::write(test_dir.join("a.txt"), "local\n").unwrap();
+        assert!(checkout_lines(&repo, &test_dir, Some("master"), &[], &options).is_err(), "Local changes are protected");
+        let lines = checkout_lines(&repo, &test_dir, Some("master"), &[], &CheckoutOptions { force: true, ..options }).unwrap();
+        assert!(lines.len() == 2 && lines[0].starts_with("Previous HEAD position was ") && lines[1] == "Switched to branch 'master'", "Output was {:?}", lines);
+        assert!(!test_dir.join("dir").exists(), "Removed files leave no empty directories");
+        assert!(std::fs::read_to_string(test_dir.join("a.txt")).unwrap() == "a2\n");
+
+        std::fs::write(test_dir.join("a.txt"), "local\n").unwrap();
+        let lines = checkout_lines(&repo, &test_dir, None, &["a.txt"], &options).unwrap();
+        assert!(lines == ["Updated 1 path from the index"], "Output was {:?}", lines);
+        assert!(std::fs::read_to_string(test_dir.join("a.txt")).unwrap() == "a2\n");
+        let log = std::fs::read_to_string(test_dir.join(".git").join("logs").join("HEAD")).unwrap();
+        assert!(log.contains("\tcheckout: moving from master to "), "Reflog was {}", log);
+    }
+
+    ///A repository with `a.txt` and `dir/b.txt` committed on master
+    fn committed_repo(name: &str) -> (PathBuf, Rc<GitRepository>) {
+        let (test_dir, repo) = test_repo(name);
+        std::fs::create_dir_all(test_dir.join("dir")).unwrap();
+        std::fs::write(test_dir.join("a.txt"), "a\n").unwrap();
+        std::fs::write(test_dir.join("dir").join("b.txt"), "b\n").unwrap();
+        add_paths(&repo, &test_dir, &["."], &AddOptions::default()).unwrap();
+        commit_index(&repo, &CommitOptions { message: vec![MessageSource::Text("first".to_owned())], ..CommitOptions::default() }).unwrap();
+        (test_dir, repo)
+    }
+
+    #[test]
+    fn aborted_commit_all_leaves_index_alone() {
+        let (test_dir, repo) = committed_repo("aborted_commit_all_leaves_index_alone");
+        std::fs::write(test_dir.join("a.txt"), "changed\n").unwrap();
+        let empty = CommitOptions { all: true, message: vec![MessageSource::Text(String::new())], ..CommitOptions::default() };
+        assert!(commit_index(&repo, &empty).is_err(), "An empty message aborts");
+        let short = StatusOptions { format: StatusFormat::Short, ..StatusOptions::default() };
+        let status = status_output(&repo, &test_dir, &[], &short).unwrap();
+        assert!(status == " M a.txt\n", "Status was {:?}", status);
+        assert!(!test_dir.join(".git").join("index.lock").exists());
+
+        let all = CommitOptions { all: true, message: vec![MessageSource::Text("second".to_owned())], ..CommitOptions::default() };
+        commit_index(&repo, &all).expect("Error committing");
+        assert!(status_output(&repo, &test_dir, &[], &short).unwrap().is_empty());
+    }
+
+    #[test]
+    fn forced_checkout_restores_deleted_file() {
+        let (test_dir, repo) = committed_repo("forced_checkout_restores_deleted_file");
+        std::fs::remove_file(test_dir.join("dir").join("b.txt")).unwrap();
+        checkout_lines(&repo, &test_dir, Some("master"), &[], &CheckoutOptions { force: true, ..CheckoutOptions::default() }).unwrap();
+        assert!(std::fs::read_to_string(test_dir.join("dir").join("b.txt")).unwrap() == "b\n", "Deleted file was not restored");
+        assert!(GitIndex::load(&repo).unwrap().get("dir/b.txt", 0).is_some());
+    }
+
+    #[test]
+    fn checkout_leaves_worktree_alone_while_index_is_locked() {
+        let (test_dir, repo) = committed_repo("checkout_leaves_worktree_alone_while_index_is_locked");
+        let first = resolve_ref(&repo, "HEAD").unwrap().unwrap();
+        std::fs::write(test_dir.join("a.txt"), "a2\n").unwrap();
+        let all = CommitOptions { all: true, message: vec![MessageSource::Text("second".to_owned())], ..CommitOptions::default() };
+        commit_index(&repo, &all).unwrap();
+        std::fs::write(test_dir.join(".git").join("index.lock"), "").unwrap();
+        assert!(checkout_lines(&repo, &test_dir, Some(&first), &[], &CheckoutOptions::default()).is_err());
+        assert!(std::fs::read_to_string(test_dir.join("a.txt")).unwrap() == "a2\n", "Worktree was switched");
+        assert!(read_ref(&repo, "HEAD").unwrap() == Some(RefValue::Symbolic("refs/heads/master".to_owned())));
+    }
+
+    #[test]
+    fn checkout_force_head_discards_local_edits() {
+        let (test_dir, repo) = committed_repo("checkout_force_head_discards_local_edits");
+        std::fs::write(test_dir.join("a.txt"), "local\n").unwrap();
+        std::fs::write(test_dir.join("new.txt"), "new\n").unwrap();
+        add_paths(&repo, &test_dir, &["new.txt"], &AddOptions::default()).unwrap();
+        let lines = checkout_lines(&repo, &test_dir, None, &[], &CheckoutOptions { force: true, ..CheckoutOptions::default() }).unwrap();
+        assert!(lines.is_empty(), "Output was {:?}", lines);
+        assert!(std::fs::read_to_string(test_dir.join("a.txt")).unwrap() == "a\n", "Local edit was kept");
+        assert!(!test_dir.join("new.txt").exists(), "Staged new file was kept");
+        assert!(GitIndex::load(&repo).unwrap().get("new.txt", 0).is_none());
+    }
+
+    #[test]
+    fn switch_and_restore_files() {
+        let (test_dir, repo) = test_repo("switch_and_restore_files");
+        std::fs::write(test_dir.join("a.txt"), "a\n").unwrap();
+        add_paths(&repo, &test_dir, &["a.txt"], &AddOptions::default()).unwrap();
+        commit_index(&repo, &CommitOptions { message: vec![MessageSource::Text("first".to_owned())], ..CommitOptions::default() }).unwrap();
+
+        std::fs::write(test_dir.join("a.txt"), "local\n").unwrap();
+        let create = SwitchOptions { create: Some("topic".to_owned()), ..SwitchOptions::default() };
+        let lines = switch_lines(&repo, None, &create).expect("Error switching");
+        assert!(lines == ["M\ta.txt", "Switched to a new branch 'topic'"], "Output was {:?}", lines);
+        assert!(switch_lines(&repo, None, &create).is_err(), "Branch exists");
+        assert!(switch_lines(&repo, Some("HEAD"), &SwitchOptions::default()).is_err(), "A branch is expected");
+        assert!(read_ref(&repo, "HEAD").unwrap() == Some(RefValue::Symbolic("refs/heads/topic".to_owned())));
+
+        add_paths(&repo, &test_dir, &["a.txt"], &AddOptions::default()).unwrap();
+        let staged = RestoreOptions { staged: true, ..RestoreOptions::default() };
+        assert!(restore_files(&repo, &test_dir, &["a.txt"], &staged).unwrap() == 1);
+        let status = status_output(&repo, &test_dir, &[], &StatusOptions { format: StatusFormat::Short, ..StatusOptions::default() }).unwrap();
+        assert!(status == " M a.txt\n", "Status was {:?}", status);
+        restore_files(&repo, &test_dir, &["a.txt"], &RestoreOptions::default()).unwrap();
+        assert!(std::fs::read_to_string(test_dir.join("a.txt")).unwrap() == "a\n");
+        assert!(restore_files(&repo, &test_dir, &["missing"], &RestoreOptions::default()).is_err());
+    }
+
+    #[test]
+    fn reset_modes_and_paths() {
+        let (test_dir, repo) = test_repo("reset_modes_and_paths");
+        std::fs::write(test_dir.join("a.txt"), "a\n").unwrap();
+        add_paths(&repo, &test_dir, &["a.txt"], &AddOptions::default()).unwrap();
+        commit_index(&repo, &CommitOptions { message: vec![MessageSource::Text("first".to_owned())], ..CommitOptions::default() }).unwrap();
+        let first = resolve_ref(&repo, "HEAD").unwrap().unwrap();
+        std::fs::write(test_dir.join("a.txt"), "b\n").unwrap();
+        add_paths(&repo, &test_dir, &["a.txt"], &AddOptions::default()).unwrap();
+        commit_index(&repo, &CommitOptions { message: vec![MessageSource::Text("second".to_owned())], ..CommitOptions::default() }).unwrap();
+        let second = resolve_ref(&repo, "HEAD").unwrap().unwrap();
+        let short = StatusOptions { format: StatusFormat::Short, ..StatusOptions::default() };
+
+        let lines = reset_lines(&repo, &test_dir, Some(&first), &[], None).expect("Error resetting");
+        assert!(lines == ["Unstaged changes after reset:", "M\ta.txt"], "Output was {:?}", lines);
+        assert!(resolve_ref(&repo, "ORIG_HEAD").unwrap() == Some(second.clone()));
+        reset_lines(&repo, &test_dir, Some(&second), &[], Some(ResetMode::Soft)).unwrap();
+        let status = status_output(&repo, &test_dir, &[], &short).unwrap();
+        assert!(status == "MM a.txt\n", "Status was {:?}", status);
+
+        let lines = reset_lines(&repo, &test_dir, Some(&first), &["a.txt"], None).unwrap();
+        assert!(lines.len() == 2 && resolve_ref(&repo, "HEAD").unwrap() == Some(second.clone()), "Paths leave HEAD alone");
+        assert!(reset_lines(&repo, &test_dir, None, &["a.txt"], Some(ResetMode::Hard)).is_err());
+        reset_lines(&repo, &test_dir, None, &["a.txt"], None).unwrap();
+        std::fs::write(test_dir.join("a.txt"), "local\n").unwrap();
+        assert!(reset_lines(&repo, &test_dir, Some(&first), &[], Some(ResetMode::Keep)).is_err(), "Keep protects local changes");
+        let lines = reset_lines(&repo, &test_dir, Some(&first), &[], Some(ResetMode::Hard)).unwrap();
+        assert!(lines[0].starts_with("HEAD is now at ") && lines[0].ends_with(" first"), "Output was {:?}", lines);
+        assert!(std::fs::read_to_string(test_dir.join("a.txt")).unwrap() == "a\n");
+        std::fs::remove_file(test_dir.join("a.txt")).unwrap();
+        reset_lines(&repo, &test_dir, None, &[], Some(ResetMode::Hard)).unwrap();
+        assert!(test_dir.join("a.txt").exists(), "Hard reset restores deleted files");
+    }
+
+    #[test]
+    fn reset_and_restore_on_unborn_head() {
+        let (test_dir, repo) = test_repo("reset_and_restore_on_unborn_head");
+        let objects = test_dir.join(".git").join("objects");
+        let empty_tree = objects.join("4b").join("825dc642cb6eb9a060e54bf8d69288fbee4904");
+        std::fs::write(test_dir.join("a.txt"), "a\n").unwrap();
+        std::fs::write(test_dir.join("b.txt"), "b\n").unwrap();
+        add_paths(&repo, &test_dir, &["a.txt", "b.txt"], &AddOptions::default()).unwrap();
+
+        let staged = RestoreOptions { staged: true, ..RestoreOptions::default() };
+        assert!(restore_files(&repo, &test_dir, &["a.txt"], &staged).unwrap() == 1);
+        assert!(GitIndex::load(&repo).unwrap().get("a.txt", 0).is_none(), "Unborn HEAD restores to nothing");
+        reset_lines(&repo, &test_dir, None, &[], None).unwrap();
+        assert!(GitIndex::load(&repo).unwrap().entries().is_empty());
+        assert!(test_dir.join("b.txt").exists(), "Mixed reset leaves the worktree alone");
+        assert!(!empty_tree.exists(), "The empty tree was written");
+    }
+
+    #[test]
+    fn check_ignore_reports_matching_rule() {
+        let (test_dir, repo) = test_repo("check_ignore_reports_matching_rule");
+        std::fs::create_dir_all(test_dir.join("sub")).unwrap();
+        std::fs::write(test_dir.join(".gitignore"), "*.log\n").unwrap();
+        std::fs::write(test_dir.join("sub").join(".gitignore"), "!keep.log\n").unwrap();
+        std::fs::write(test_dir.join(".git").join("info").join("exclude"), "secret\n").unwrap();
+        std::fs::write(test_dir.join("tracked.log"), "").unwrap();
+        add_paths(&repo, &test_dir, &["tracked.log"], &AddOptions { force: true, ..AddOptions::default() }).unwrap();
+
+        let paths = ["a.log", "sub/keep.log", "secret", "tracked.log", "other"];
+        let lines = check_ignore_lines(&repo, &test_dir, &paths, &CheckIgnoreOptions::default()).unwrap();
+        assert!(lines == ["a.log", "secret"], "Output was {:?}", lines);
+        let verbose = CheckIgnoreOptions { verbose: true, non_matching: true, ..CheckIgnoreOptions::default() };
+        let lines = check_ignore_lines(&repo, &test_dir.join("sub"), &["../a.log", "keep.log", "../tracked.log"], &verbose).unwrap();
+        assert!(lines == [".gitignore:1:*.log\t../a.log", "sub/.gitignore:1:!keep.log\tkeep.log", "::\t../tracked.log"], "Output was {:?}", lines);
+        let no_index = CheckIgnoreOptions { no_index: true, ..CheckIgnoreOptions::default() };
+        assert!(check_ignore_lines(&repo, &test_dir, &["tracked.log"], &no_index).unwrap() == ["tracked.log"]);
+        assert!(check_ignore_lines(&repo, &test_dir, &["a.log"], &CheckIgnoreOptions { non_matching: true, ..CheckIgnoreOptions::default() }).is_err());
+    }
+
+    #[test]
+    fn check_ignore_negation_and_directories() {
+        let (test_dir, repo) = test_repo("check_ignore_negation_and_directories");
+        std::fs::create_dir_all(test_dir.join("out")).unwrap();
+        std::fs::write(test_dir.join(".gitignore"), "out/\n*.tmp\n!important.tmp\nbuild/\n").unwrap();
+        std::fs::write(test_dir.join("out").join("x"), "").unwrap();
+        std::fs::write(test_dir.join("tracked.tmp"), "").unwrap();
+        add_paths(&repo, &test_dir, &["tracked.tmp"], &AddOptions { force: true, ..AddOptions::default() }).unwrap();
+
+        let paths = ["out", "out/x", "important.tmp", "a.tmp", "build", "build/", "tracked.tmp"];
+        let lines = check_ignore_lines(&repo, &test_dir, &paths, &CheckIgnoreOptions::default()).unwrap();
+        assert!(lines == ["out", "out/x", "a.tmp", "build/"], "Output was {:?}", lines);
+        let verbose = CheckIgnoreOptions { verbose: true, non_matching: true, ..CheckIgnoreOptions::default() };
+        let lines = check_ignore_lines(&repo, &test_dir, &["important.tmp", "a.tmp", "plain", "tracked.tmp"], &verbose).unwrap();
+        assert!(
+            lines == [".gitignore:3:!important.tmp\timportant.tmp", ".gitignore:2:*.tmp\ta.tmp", "::\tplain", "::\ttracked.tmp"],
+            "Output was {:?}", lines
+        );
+        let verbose_no_index = CheckIgnoreOptions { verbose: true, no_index: true, ..CheckIgnoreOptions::default() };
+        let lines = check_ignore_lines(&repo, &test_dir, &["tracked.tmp", "plain"], &verbose_no_index).unwrap();
+        assert!(lines == [".gitignore:2:*.tmp\ttracked.tmp"], "Output was {:?}", lines);
+    }
+
+    #[test]
+    fn attributes_convert_line_endings() {
+        let (test_dir, repo) = test_repo("attributes_convert_line_endings");
+        std::fs::write(test_dir.join(".gitattributes"), "*.txt text\n*.bat eol=crlf\n*.bin binary\n").unwrap();
+        std::fs::write(test_dir.join("a.txt"), "a\r\nb\r\n").unwrap();
+        std::fs::write(test_dir.join("run.bat"), "x\ny\n").unwrap();
+
+        let lines = check_attr_lines(&repo, &test_dir, &["text", "eol"], &["a.txt", "run.bat"], &CheckAttrOptions::default()).unwrap();
+        assert!(lines == ["a.txt: text: set", "a.txt: eol: unspecified", "run.bat: text: unspecified", "run.bat: eol: crlf"], "Output was {:?}", lines);
+        let all = check_attr_lines(&repo, &test_dir, &[], &["x.bin"], &CheckAttrOptions { all: true, ..CheckAttrOptions::default() }).unwrap();
+        assert!(all == ["x.bin: binary: set", "x.bin: diff: unset", "x.bin: merge: unset", "x.bin: text: unset"], "Output was {:?}", all);
+        assert!(check_attr_lines(&repo, &test_dir, &[], &["a.txt"], &CheckAttrOptions::default()).is_err());
+
+        add_paths(&repo, &test_dir, &["."], &AddOptions::default()).unwrap();
+        let index = GitIndex::load(&repo).unwrap();
+        let stored = GitObject::from_internal_file(&index.get("a.txt", 0).unwrap().sha, &repo).unwrap();
+        assert!(stored.serialize() == b"a\nb\n", "CRLF is stored as LF");
+        let status = status_output(&repo, &test_dir, &["a.txt"], &StatusOptions { format: StatusFormat::Short, ..StatusOptions::default() }).unwrap();
+        assert!(status == "A  a.txt\n", "Status was {:?}", status);
+
+        std::fs::remove_file(test_dir.join("run.bat")).unwrap();
+        restore_files(&repo, &test_dir, &["run.bat"], &RestoreOptions::default()).unwrap();
+        assert!(std::fs::read(test_dir.join("run.bat")).unwrap() == b"x\r\ny\r\n");
+    }
+
+    ///A `filter.<name>.process` filter in shell: clean lowercases, smudge uppercases, and the first
+    ///smudge of delayed.txt is delayed when git allows it
+    const PROCESS_FILTER: &str = r#"tmp=.git/filter-tmp
+mkdir -p $tmp
+packet() { printf '%04x%s' $((${#1} + 5)) "$1
+"; }
+flush() { printf 0000; }
+read_packet() {
+    len=$(dd bs=1 count=4 2>/dev/null)
+    [ -n "$len" ] && [ "$len" != 0000 ] || return 1
+    dd bs=1 count=$((0x$len - 4)) of=$tmp/packet 2>/dev/null
+}
+text() { tr -d '\n' < $tmp/packet; }
+while read_packet; do :; done
+packet git-filter-server; packet version=2; flush
+while read_packet; do :; done
+packet capability=clean; packet capability=smudge; packet capability=delay; flush
+while read_packet; do
+    command=$(text); path=; delay=
+    while read_packet; do
+        case $(text) in
+        pathname=*) path=$(text | cut -c10-) ;;
+        can-delay=1) delay=1 ;;
+        esac
+    done
+    if [ "$command" = command=list_available_blobs ]; then
+        [ -f $tmp/delayed ] && mv $tmp/delayed $tmp/ready && packet pathname=delayed.txt
+        flush; packet status=success; flush
+        continue
+    fi
+    : > $tmp/in
+    while read_packet; do cat $tmp/packet >> $tmp/in; done
+    case $command in
+    command=clean) tr A-Z a-z < $tmp/in > $tmp/out ;;
+    *) tr a-z A-Z < $tmp/in > $tmp/out ;;
+    esac
+    if [ "$path" = delayed.txt ] && [ -n "$delay" ]; then
+        mv $tmp/out $tmp/delayed
+        packet status=delayed; flush
+        continue
+    fi
+    [ "$path" = delayed.txt ] && [ -f $tmp/ready ] && mv $tmp/ready $tmp/out
+    packet status=success; flush
+    size=$(wc -c < $tmp/out)
+    [ "$size" -gt 0 ] && printf '%04x' $((size + 4)) && cat $tmp/out
+    flush; flush
+done
+"#;
+
+    #[test]
+    fn filter_process_delays_checkout() {
+        let settings = [("filter.case.process", "sh .git/case-filter.sh"), ("filter.case.required", "true")];
+        let (test_dir, repo) = configured_test_repo("filter_process_delays_checkout", &settings);
+        std::fs::write(test_dir.join(".git").join("case-filter.sh"), PROCESS_FILTER).unwrap();
+        std::fs::write(test_dir.join(".gitattributes"), "*.txt filter=case\n").unwrap();
+        std::fs::write(test_dir.join("hello.txt"), "Hello\n").unwrap();
+        std::fs::write(test_dir.join("delayed.txt"), "Later\n").unwrap();
+
+        add_paths(&repo, &test_dir, &["."], &AddOptions::default()).unwrap();
+        let index = GitIndex::load(&repo).unwrap();
+        let stored = GitObject::from_internal_file(&index.get("hello.txt", 0).unwrap().sha, &repo).unwrap();
+        assert!(stored.serialize() == b"hello\n", "Stored {:?}", stored.serialize());
+        let status = status_output(&repo, &test_dir, &[], &StatusOptions { format: StatusFormat::Short, ..StatusOptions::default() }).unwrap();
+        assert!(status == "A  .gitattributes\nA  delayed.txt\nA  hello.txt\n", "Status was {:?}", status);
+
+        std::fs::remove_file(test_dir.join("hello.txt")).unwrap();
+        std::fs::remove_file(test_dir.join("delayed.txt")).unwrap();
+        restore_files(&repo, &test_dir, &["*.txt"], &RestoreOptions::default()).unwrap();
+        assert!(std::fs::read(test_dir.join("hello.txt")).unwrap() == b"HELLO\n");
+        assert!(std::fs::read(test_dir.join("delayed.txt")).unwrap() == b"LATER\n", "Delayed files are written once available");
+    }
 }
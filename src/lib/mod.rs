@@ -2,8 +2,9 @@
 use std::path::{PathBuf};
 
 pub mod commands;
-pub(crate) mod objects;
+pub mod objects;
 pub(crate) mod parsing;
+#[allow(dead_code)]
 pub(crate) mod sorted_dict;
 
 fn clean_unc(path: PathBuf) -> PathBuf {
@@ -16,8 +17,33 @@ fn clean_unc(path: PathBuf) -> PathBuf {
     }
 }
 
+#[cfg(test)]
 fn get_test_dir(sub_dir: &str) -> PathBuf {
     std::env::temp_dir().join("testing").join(sub_dir)
 }
 
+///A freshly initialized repository in its own test directory, with an identity to commit as
+#[cfg(test)]
+fn test_repo(name: &str) -> (PathBuf, std::rc::Rc<objects::git_repository::GitRepository>) {
+    configured_test_repo(name, &[])
+}
+
+///Like `test_repo`, with extra settings written to the local config before it is opened
+#[cfg(test)]
+fn configured_test_repo(name: &str, settings: &[(&str, &str)]) -> (PathBuf, std::rc::Rc<objects::git_repository::GitRepository>) {
+    let test_dir = get_test_dir(name);
+    let _ = std::fs::remove_dir_all(&test_dir);
+    commands::init(&test_dir, &commands::InitOptions::default()).expect("Error initializing repo");
+    let config_path = test_dir.join(".git").join("config");
+    let mut local = objects::git_config::ConfigFile::load(&config_path).unwrap();
+    local.set("user.name", "A U Thor").unwrap();
+    local.set("user.email", "author@example.com").unwrap();
+    for (key, value) in settings {
+        local.set(key, value).unwrap();
+    }
+    local.save(&config_path).unwrap();
+    let repo = objects::git_repository::GitRepository::at_path(&test_dir, false).expect("Error opening repo");
+    (test_dir, std::rc::Rc::new(repo))
+}
+
 
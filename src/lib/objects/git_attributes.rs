@@ -0,0 +1,255 @@
+use crate::lib::objects::git_config::{expand_path, xdg_config_path};
+use crate::lib::objects::git_ignore::IgnorePattern;
+use crate::lib::objects::git_object::GitObject;
+use crate::lib::objects::git_repository::{repo_path, GitRepository, RepositoryError};
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::rc::Rc;
+
+///An attribute's state for a path, or the state `:(attr:...)` asks for
+#[derive(Clone, Debug, PartialEq)]
+pub enum AttrValue {
+    Set,
+    Unset,
+    Unspecified,
+    Value(String),
+}
+
+impl AttrValue {
+    ///How `check-attr` shows the state
+    pub fn describe(&self) -> &str {
+        match self {
+            AttrValue::Set => "set",
+            AttrValue::Unset => "unset",
+            AttrValue::Unspecified => "unspecified",
+            AttrValue::Value(value) => value,
+        }
+    }
+}
+
+///Macros git defines before reading any file
+const BUILTIN_ATTRIBUTES: &str = "[attr]binary -diff -merge -text\n";
+
+///Where `.gitattributes` files are read from, git's attribute direction
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum AttrSource {
+    ///The worktree, then the index for files missing there: for content going into the repository
+    WorktreeFirst,
+    ///The index, then the worktree: for content being written out
+    IndexFirst,
+    ///Only the index, as `check-attr --cached`
+    IndexOnly,
+}
+
+///One line of an attributes file: a pattern and the attributes it assigns, in order
+#[derive(Clone, Debug)]
+struct AttrLine {
+    pattern: IgnorePattern,
+    assignments: Vec<(String, AttrValue)>,
+}
+
+///One attribute as written on a line: `name`, `-name`, `!name` or `name=value`
+fn parse_assignment(word: &str) -> Option<(String, AttrValue)> {
+    let (name, value) = if let Some(name) = word.strip_prefix('-') {
+        (name, AttrValue::Unset)
+    } else if let Some(name) = word.strip_prefix('!') {
+        (name, AttrValue::Unspecified)
+    } else if let Some((name, value)) = word.split_once('=') {
+        (name, AttrValue::Value(value.to_owned()))
+    } else {
+        (word, AttrValue::Set)
+    };
+    Some((name.to_owned(), value)).filter(|_| is_valid_attr_name(name))
+}
+
+pub(crate) fn is_valid_attr_name(name: &str) -> bool {
+    !name.is_empty() && !name.starts_with('-') && name.bytes().all(|byte| byte.is_ascii_alphanumeric() || b"-._".contains(&byte))
+}
+
+///Git's attribute rules for one worktree: `info/attributes`, then `.gitattributes` files from the
+///deepest directory up, then `core.attributesFile`, with `[attr]` macros from the top-level files
+pub struct AttributeRules {
+    repo: Rc<GitRepository>,
+    worktree: PathBuf,
+    source: AttrSource,
+    ///Blob ids of the index's (or a tree's) `.gitattributes` files by path
+    staged: HashMap<String, String>,
+    ignore_case: bool,
+    info: Vec<AttrLine>,
+    global: Vec<AttrLine>,
+    builtin: Vec<AttrLine>,
+    ///Each directory's lines, read the first time a path below it is looked up
+    per_directory: HashMap<String, Vec<AttrLine>>,
+    macros: HashMap<String, Vec<(String, AttrValue)>>,
+    ///Attribute names in the order first seen, the order `check-attr --all` reports them in
+    names: Vec<String>,
+}
+
+impl AttributeRules {
+    ///Rules for a repository given the blob ids of its staged files, only `.gitattributes` ones being read
+    pub fn for_repository<I: IntoIterator<Item = (String, String)>>(repo: &Rc<GitRepository>, staged: I, source: AttrSource) -> Result<AttributeRules, RepositoryError> {
+        let worktree = repo.worktree()?.to_owned();
+        let config = repo.config();
+        let mut rules = AttributeRules {
+            repo: Rc::clone(repo),
+            worktree,
+            source,
+            staged: staged.into_iter().filter(|(path, _)| path == ".gitattributes" || path.ends_with("/.gitattributes")).collect(),
+            ignore_case: matches!(config.get_bool("core.ignorecase"), Ok(Some(true))),
+            info: Vec::new(),
+            global: Vec::new(),
+            builtin: Vec::new(),
+            per_directory: HashMap::new(),
+            macros: HashMap::new(),
+            names: Vec::new(),
+        };
+        rules.builtin = rules.parse(BUILTIN_ATTRIBUTES, "", "[builtin]", true);
+        let attributes_file = match config.get("core.attributesfile") {
+            Some(path) => Some(expand_path(path)),
+            None => xdg_config_path("attributes"),
+        };
+        if let Some(text) = attributes_file.and_then(|path| std::fs::read_to_string(path).ok()) {
+            rules.global = rules.parse(&text, "", "", true);
+        }
+        rules.load_directory("");
+        if let Ok(text) = std::fs::read_to_string(repo_path(repo, "info/attributes")) {
+            rules.info = rules.parse(&text, "", "", true);
+        }
+        Ok(rules)
+    }
+
+    ///The state of one attribute for a path relative to the worktree
+    pub fn get(&mut self, path: &str, name: &str) -> AttrValue {
+        self.lookup(path).remove(name).unwrap_or(AttrValue::Unspecified)
+    }
+
+    ///Every attribute some rule gives a state other than unspecified, in the order first seen
+    pub fn all(&mut self, path: &str) -> Vec<(String, AttrValue)> {
+        let mut found = self.lookup(path);
+        self.names
+            .iter()
+            .filter_map(|name| found.remove(name).map(|value| (name.clone(), value)))
+            .filter(|(_, value)| *value != AttrValue::Unspecified)
+            .collect()
+    }
+
+    ///Walk the rules from the highest precedence down, the first state found for an attribute
+    ///winning. Later lines beat earlier ones, and a set macro sets what it stands for
+    fn lookup(&mut self, path: &str) -> HashMap<String, AttrValue> {
+        let bases: Vec<String> = std::iter::once(String::new())
+            .chain(path.match_indices('/').map(|(end, _)| format!("{}/", &path[..end])))
+            .collect();
+        for base in &bases {
+            self.load_directory(base);
+        }
+        let mut frames: Vec<&[AttrLine]> = vec![&self.info];
+        frames.extend(bases.iter().rev().map(|base| self.per_directory[base].as_slice()));
+        frames.push(&self.global);
+        frames.push(&self.builtin);
+
+        let mut found = HashMap::new();
+        for line in frames.into_iter().flat_map(|frame| frame.iter().rev()) {
+            if line.pattern.matches(path, false, self.ignore_case) {
+                self.fill(&line.assignments, &mut found, 0);
+            }
+        }
+        found
+    }
+
+    fn fill(&self, assignments: &[(String, AttrValue)], found: &mut HashMap<String, AttrValue>, depth: usize) {
+        for (name, value) in assignments.iter().rev() {
+            if found.contains_key(name) {
+                continue;
+            }
+            found.insert(name.clone(), value.clone());
+            match self.macros.get(name) {
+                Some(expansion) if *value == AttrValue::Set && depth < 16 => self.fill(expansion, found, depth + 1),
+                _ => {}
+            }
+        }
+    }
+
+    fn parse(&mut self, text: &str, base: &str, source: &str, allow_macros: bool) -> Vec<AttrLine> {
+        let mut lines = Vec::new();
+        for (number, line) in text.lines().enumerate() {
+            let line = line.trim_start();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let (pattern, rest) = match line.split_once([' ', '\t']) {
+                Some((pattern, rest)) => (pattern, rest),
+                None => (line, ""),
+            };
+            //Macros may only be defined where they apply to the whole worktree
+            let macro_name = pattern.strip_prefix("[attr]");
+            if macro_name.is_some_and(|name| !allow_macros || !is_valid_attr_name(name)) {
+                continue;
+            }
+            let assignments: Vec<(String, AttrValue)> = rest.split_whitespace().filter_map(parse_assignment).collect();
+            for name in macro_name.into_iter().chain(assignments.iter().map(|(name, _)| name.as_str())) {
+                if !self.names.iter().any(|known| known == name) {
+                    self.names.push(name.to_owned());
+                }
+            }
+            if let Some(name) = macro_name {
+                self.macros.insert(name.to_owned(), assignments);
+                continue;
+            }
+            if pattern.starts_with('!') {
+                continue;
+            }
+            if let Some(pattern) = IgnorePattern::parse(pattern, base, source, number + 1) {
+                lines.push(AttrLine { pattern, assignments });
+            }
+        }
+        lines
+    }
+
+    fn load_directory(&mut self, base: &str) {
+        if self.per_directory.contains_key(base) {
+            return;
+        }
+        let path = format!("{}.gitattributes", base);
+        let from_worktree = || std::fs::read_to_string(self.worktree.join(&path)).ok();
+        let from_index = || {
+            let sha = self.staged.get(&path)?;
+            let object = GitObject::from_internal_file(sha, &self.repo).ok()?;
+            Some(String::from_utf8_lossy(object.serialize()).into_owned())
+        };
+        let text = match self.source {
+            AttrSource::WorktreeFirst => from_worktree().or_else(from_index),
+            AttrSource::IndexFirst => from_index().or_else(from_worktree),
+            AttrSource::IndexOnly => from_index(),
+        };
+        let lines = match text {
+            Some(text) => self.parse(&text, base, &path, base.is_empty()),
+            None => Vec::new(),
+        };
+        self.per_directory.insert(base.to_owned(), lines);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lib::test_repo;
+
+    #[test]
+    fn resolves_attributes_by_precedence() {
+        let (test_dir, repo) = test_repo("resolves_attributes_by_precedence");
+        std::fs::create_dir_all(test_dir.join("sub")).unwrap();
+        std::fs::write(test_dir.join(".gitattributes"), "[attr]cfile text diff=c\n*.c cfile\n*.txt text eol=crlf\n*.bin binary\n").unwrap();
+        std::fs::write(test_dir.join("sub").join(".gitattributes"), "*.txt -text !eol\n[attr]ignored text\n").unwrap();
+        std::fs::write(test_dir.join(".git").join("info").join("attributes"), "special.txt whitespace\n").unwrap();
+
+        let mut rules = AttributeRules::for_repository(&repo, Vec::new(), AttrSource::WorktreeFirst).unwrap();
+        assert!(rules.get("a.txt", "eol") == AttrValue::Value("crlf".to_owned()));
+        assert!(rules.get("sub/a.txt", "text") == AttrValue::Unset, "Deeper files win");
+        assert!(rules.get("sub/a.txt", "eol") == AttrValue::Unspecified);
+        assert!(rules.get("x.c", "diff") == AttrValue::Value("c".to_owned()), "Macros expand when set");
+        let all = rules.all("x.bin");
+        assert!(all == [("binary".to_owned(), AttrValue::Set), ("diff".to_owned(), AttrValue::Unset), ("merge".to_owned(), AttrValue::Unset), ("text".to_owned(), AttrValue::Unset)], "Attributes were {:?}", all);
+        assert!(rules.get("special.txt", "whitespace") == AttrValue::Set);
+        assert!(rules.get("sub/x.c", "ignored") == AttrValue::Unspecified, "Macros are only defined at the top");
+    }
+}
@@ -0,0 +1,362 @@
+use crate::lib::objects::git_attributes::AttrSource;
+use crate::lib::objects::git_convert::{ConvertError, Converter};
+use crate::lib::objects::git_ignore::IgnoreRules;
+use crate::lib::objects::git_index::{GitIndex, IndexEntry, IndexError, StatData};
+use crate::lib::objects::git_object::{GitObject, ObjectError};
+use crate::lib::objects::git_pathspec::Pathspec;
+use crate::lib::objects::git_repository::{GitRepository, RepositoryError};
+use crate::lib::objects::git_status::Blob;
+use crate::lib::objects::git_tree::{flatten_tree, flatten_tree_matching};
+use crate::lib::objects::git_workdir::{remove_worktree_file, worktree_change, write_worktree_file, StatOptions, WorktreeChange, MODE_GITLINK, MODE_SYMLINK};
+use std::collections::{BTreeSet, HashMap};
+use std::fmt::{Display, Formatter};
+use std::path::Path;
+use std::rc::Rc;
+
+///What a checkout moves away from, or where `restore_paths` takes content from
+#[derive(Clone, Copy, Debug)]
+pub(crate) enum CheckoutBase<'a> {
+    ///The tree checked out so far, None when HEAD is unborn
+    Tree(Option<&'a str>),
+    ///The index itself, so that only unstaged changes are protected and unmerged paths are
+    ///resolved to the target, as `reset --merge` wants
+    Index,
+}
+
+///Move the index and worktree from `from` to tree `to`, the empty tree when None. Paths that
+///are the same in both keep whatever local changes they have, and so do paths whose index entry
+///already matches `to`. Everything else is only replaced when the index and the file still
+///match `from`, unless `force` throws local changes and untracked files away. Warnings from
+///converting content are added to `warnings` for the command to show
+pub(crate) fn checkout_tree(
+    repo: &Rc<GitRepository>,
+    index: &mut GitIndex,
+    from: CheckoutBase,
+    to: Option<&str>,
+    force: bool,
+    warnings: &mut Vec<String>,
+) -> Result<(), CheckoutError> {
+    let worktree = repo.worktree()?;
+    let format = repo.object_format();
+    let stat_options = StatOptions::from_repo(repo);
+    let old = match from {
+        CheckoutBase::Tree(tree) => {
+            let unmerged: Vec<String> = index.entries().iter().filter(|entry| entry.stage != 0).map(|entry| entry.path.clone()).collect();
+            if !force && !unmerged.is_empty() {
+                return Err(CheckoutError::Unmerged(unmerged));
+            }
+            tree_blobs(repo, tree)?
+        }
+        CheckoutBase::Index => index
+            .entries()
+            .iter()
+            .filter(|entry| entry.stage == 0 && !entry.intent_to_add)
+            .map(|entry| (entry.path.clone(), (entry.mode, entry.sha.clone())))
+            .collect(),
+    };
+    let new = tree_blobs(repo, to)?;
+    let mut paths: BTreeSet<&str> = old.keys().chain(new.keys()).map(String::as_str).collect();
+    paths.extend(index.entries().iter().map(|entry| entry.path.as_str()));
+
+    let mut rules = IgnoreRules::for_repository(repo)?;
+    let mut convert = Converter::for_index(repo, index, AttrSource::WorktreeFirst)?;
+    let mut updates: Vec<(String, Option<Blob>)> = Vec::new();
+    let mut local = Vec::new();
+    let mut untracked = Vec::new();
+    for path in paths {
+        let (before, after) = (old.get(path), new.get(path));
+        let entry = index.get(path, 0);
+        let staged = entry.filter(|entry| !entry.intent_to_add).map(|entry| (entry.mode, entry.sha.clone()));
+        let change = match entry {
+            Some(entry) => worktree_change(worktree, entry, format, stat_options, index.timestamp(), &mut convert)?,
+            None => None,
+        };
+        let clean = !matches!(change, Some(WorktreeChange::Modified) | Some(WorktreeChange::TypeChanged));
+        let unmerged = index.get(path, 1).is_some() || index.get(path, 2).is_some() || index.get(path, 3).is_some();
+        if force || unmerged {
+            //A forced checkout also brings back tracked files deleted from the worktree
+            let current = staged.as_ref() == after && change.is_none() && !unmerged;
+            let tracked = entry.is_some() || unmerged;
+            if !current && (after.is_some() || tracked) {
+                updates.push((path.to_owned(), after.cloned()));
+            }
+            continue;
+        }
+        //Local changes carry over when the checkout has nothing to say about the path
+        if before == after || (staged.as_ref() == after && entry.is_some() == after.is_some()) {
+            continue;
+        }
+        if staged.as_ref() != before || !clean {
+            local.push(path.to_owned());
+            continue;
+        }
+        if entry.is_none() && after.is_some() {
+            if let Some(blocker) = untracked_blocker(worktree, path, index, &mut rules)? {
+                untracked.push(blocker);
+                continue;
+            }
+        }
+        updates.push((path.to_owned(), after.cloned()));
+    }
+    warnings.append(&mut convert.warnings);
+    if !local.is_empty() || !untracked.is_empty() {
+        return Err(CheckoutError::WouldOverwrite(local, untracked));
+    }
+
+    //Removals go first so directories can give way to files and files to directories
+    for (path, _) in updates.iter().filter(|(_, blob)| blob.is_none()) {
+        remove_worktree_file(worktree, path)?;
+        index.remove(path);
+    }
+    let symlinks = !matches!(repo.config().get_bool("core.symlinks"), Ok(Some(false)));
+    //Files are written with the attributes they are checked out with
+    let checked_out = new.iter().map(|(path, (_, sha))| (path.clone(), sha.clone())).collect();
+    let mut convert = Converter::for_blobs(repo, checked_out, AttrSource::IndexFirst)?;
+    let mut delayed = HashMap::new();
+    for (path, blob) in &updates {
+        if let Some((mode, sha)) = blob {
+            let stat = write_blob(repo, worktree, path, *mode, sha, symlinks, &mut convert)?;
+            if stat.is_none() {
+                delayed.insert(path.clone(), (*mode, true));
+            }
+            index.remove(path);
+            index.add_replacing(IndexEntry::new(path, sha, *mode, stat.unwrap_or_default()));
+        }
+    }
+    let written = write_delayed(worktree, index, &mut convert, &delayed, symlinks);
+    warnings.append(&mut convert.warnings);
+    written
+}
+
+///What `restore_paths` writes: the index, the worktree or both. In overlay mode, as for
+///`checkout <tree-ish> -- <paths>`, tracked paths missing from the source are left alone;
+///otherwise they are removed to match it
+#[derive(Clone, Copy, Debug, Default)]
+pub(crate) struct RestoreTargets {
+    pub index: bool,
+    pub worktree: bool,
+    pub overlay: bool,
+}
+
+///Make the paths a pathspec selects match a tree, the empty one for an unborn HEAD, or the
+///index. Local changes to those paths are overwritten, as naming them is taken as asking for it.
+///Returns how many paths were restored, adding warnings from converting content to `warnings`
+pub(crate) fn restore_paths(
+    repo: &Rc<GitRepository>,
+    index: &mut GitIndex,
+    source: CheckoutBase,
+    pathspec: &Pathspec,
+    targets: RestoreTargets,
+    warnings: &mut Vec<String>,
+) -> Result<usize, CheckoutError> {
+    let worktree = repo.worktree()?;
+    let mut convert = Converter::for_index(repo, index, AttrSource::IndexFirst)?;
+    let selected: Vec<&IndexEntry> = index
+        .entries()
+        .iter()
+        .filter(|entry| pathspec.matches(&entry.path, |path, name| convert.attribute(path, name)))
+        .collect();
+    //Unmerged paths can be resolved by taking a tree's version, but the index has no single one
+    let unmerged: Vec<String> = selected.iter().filter(|entry| entry.stage != 0).map(|entry| entry.path.clone()).collect();
+    if matches!(source, CheckoutBase::Index) && !unmerged.is_empty() {
+        return Err(CheckoutError::Unmerged(unmerged));
+    }
+    let blobs: Vec<(String, Blob)> = match source {
+        CheckoutBase::Tree(Some(tree)) => flatten_tree_matching(repo, tree, pathspec, |path, name| convert.attribute(path, name))?
+            .into_iter()
+            .map(|entry| (entry.name, (entry.mode, entry.sha)))
+            .collect(),
+        CheckoutBase::Tree(None) => Vec::new(),
+        CheckoutBase::Index => selected.iter().map(|entry| (entry.path.clone(), (entry.mode, entry.sha.clone()))).collect(),
+    };
+    let known = blobs.iter().map(|(path, _)| path.as_str()).chain(selected.iter().map(|entry| entry.path.as_str()));
+    let unmatched = pathspec.unmatched(known, |path, name| convert.attribute(path, name));
+    if !unmatched.is_empty() {
+        return Err(CheckoutError::Unmatched(unmatched.iter().map(|arg| arg.to_string()).collect()));
+    }
+    let mut missing = BTreeSet::new();
+    if !targets.overlay {
+        let sourced: BTreeSet<&str> = blobs.iter().map(|(path, _)| path.as_str()).collect();
+        missing.extend(selected.iter().filter(|entry| !sourced.contains(entry.path.as_str())).map(|entry| entry.path.clone()));
+    }
+
+    for path in &missing {
+        if targets.worktree {
+            remove_worktree_file(worktree, path)?;
+        }
+        if targets.index {
+            index.remove(path);
+        }
+    }
+    let symlinks = !matches!(repo.config().get_bool("core.symlinks"), Ok(Some(false)));
+    let mut delayed = HashMap::new();
+    for (path, (mode, sha)) in &blobs {
+        let stat = if targets.worktree { write_blob(repo, worktree, path, *mode, sha, symlinks, &mut convert)? } else { None };
+        let unchanged = index.get(path, 0).is_some_and(|entry| entry.mode == *mode && entry.sha == *sha);
+        if targets.worktree && stat.is_none() {
+            delayed.insert(path.clone(), (*mode, targets.index || unchanged));
+        }
+        if targets.index && !unchanged {
+            //Without a freshly written file the stat data stays empty, so the file is compared by content
+            index.remove(path);
+            index.add_replacing(IndexEntry::new(path, sha, *mode, stat.unwrap_or_default()));
+        } else if let Some(stat) = stat.filter(|_| unchanged) {
+            index.refresh_stat(path, 0, stat);
+        }
+    }
+    let written = write_delayed(worktree, index, &mut convert, &delayed, symlinks);
+    warnings.append(&mut convert.warnings);
+    written?;
+    Ok(blobs.len() + missing.len())
+}
+
+///Every file in a tree by path, none for a missing tree
+fn tree_blobs(repo: &Rc<GitRepository>, tree: Option<&str>) -> Result<HashMap<String, Blob>, ObjectError> {
+    Ok(match tree {
+        Some(tree) => flatten_tree(repo, tree)?
+            .into_iter()
+            .map(|entry| (entry.name, (entry.mode, entry.sha)))
+            .collect(),
+        None => HashMap::new(),
+    })
+}
+
+///Write a blob to the worktree, returning the stat data to record. None when a filter delays
+///the file, in which case `write_delayed` writes it
+fn write_blob(repo: &Rc<GitRepository>, worktree: &Path, path: &str, mode: u32, sha: &str, symlinks: bool, convert: &mut Converter) -> Result<Option<StatData>, CheckoutError> {
+    let content = match mode {
+        MODE_GITLINK => Vec::new(),
+        MODE_SYMLINK => GitObject::from_internal_file(sha, repo)?.serialize().to_vec(),
+        _ => match convert.smudge(path, GitObject::from_internal_file(sha, repo)?.serialize().to_vec())? {
+            Some(content) => content,
+            None => return Ok(None),
+        },
+    };
+    Ok(Some(write_worktree_file(worktree, path, mode, &content, symlinks)?))
+}
+
+///Write files the filters delayed as they become available. `delayed` has each one's mode and
+///whether its index entry is the blob written, so it gets the file's stat data
+fn write_delayed(worktree: &Path, index: &mut GitIndex, convert: &mut Converter, delayed: &HashMap<String, (u32, bool)>, symlinks: bool) -> Result<(), CheckoutError> {
+    if delayed.is_empty() {
+        return Ok(());
+    }
+    loop {
+        let available = convert.available_delayed()?;
+        if available.is_empty() {
+            return Ok(());
+        }
+        for (path, content) in available {
+            let (mode, refresh) = delayed[&path];
+            let stat = write_worktree_file(worktree, &path, mode, &content, symlinks)?;
+            if refresh {
+                index.refresh_stat(&path, 0, stat);
+            }
+        }
+    }
+}
+
+///The untracked file writing a new path would destroy: the path itself, a file where one of its
+///directories belongs, or a file inside a directory standing in its place. Ignored files are
+///expendable, as they are to git
+fn untracked_blocker(worktree: &Path, path: &str, index: &GitIndex, rules: &mut IgnoreRules) -> Result<Option<String>, std::io::Error> {
+    for (end, _) in path.match_indices('/') {
+        let dir = &path[..end];
+        match std::fs::symlink_metadata(worktree.join(dir)) {
+            Ok(meta) if !meta.is_dir() && index.get(dir, 0).is_none() && !rules.is_ignored(dir, false) => return Ok(Some(dir.to_owned())),
+            Ok(meta) if meta.is_dir() => {}
+            _ => return Ok(None),
+        }
+    }
+    match std::fs::symlink_metadata(worktree.join(path)) {
+        Ok(meta) if meta.is_dir() => first_untracked(worktree, &format!("{}/", path), index, rules),
+        Ok(_) if !rules.is_ignored(path, false) => Ok(Some(path.to_owned())),
+        _ => Ok(None),
+    }
+}
+
+fn first_untracked(worktree: &Path, dir: &str, index: &GitIndex, rules: &mut IgnoreRules) -> Result<Option<String>, std::io::Error> {
+    let mut names: Vec<String> = std::fs::read_dir(worktree.join(dir))?
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.file_name().to_string_lossy().into_owned())
+        .collect();
+    names.sort();
+    for name in names {
+        let path = format!("{}{}", dir, name);
+        if std::fs::symlink_metadata(worktree.join(&path))?.is_dir() {
+            if let Some(found) = first_untracked(worktree, &format!("{}/", path), index, rules)? {
+                return Ok(Some(found));
+            }
+        } else if index.get(&path, 0).is_none() && !rules.is_ignored(&path, false) {
+            return Ok(Some(path));
+        }
+    }
+    Ok(None)
+}
+
+#[derive(Debug)]
+pub enum CheckoutError {
+    Repo(RepositoryError),
+    Index(IndexError),
+    Object(ObjectError),
+    Io(std::io::Error),
+    Convert(ConvertError),
+    ///Paths with local changes, then untracked files, that the checkout would overwrite
+    WouldOverwrite(Vec<String>, Vec<String>),
+    Unmerged(Vec<String>),
+    Unmatched(Vec<String>),
+}
+
+impl Display for CheckoutError {
+    fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
+        let list = |paths: &[String]| paths.iter().map(|path| format!("\t{}\n", path)).collect::<String>();
+        match self {
+            CheckoutError::Repo(err) => {write!(f, "{}", err)},
+            CheckoutError::Index(err) => {write!(f, "{}", err)},
+            CheckoutError::Object(err) => {write!(f, "{}", err)},
+            CheckoutError::Io(err) => {write!(f, "{}", err)},
+            CheckoutError::Convert(err) => {write!(f, "{}", err)},
+            CheckoutError::WouldOverwrite(local, untracked) => {
+                if !local.is_empty() {
+                    write!(f, "Your local changes to the following files would be overwritten by checkout:\n{}Please commit your changes or stash them before you switch branches.\n", list(local))?;
+                }
+                if !untracked.is_empty() {
+                    write!(f, "The following untracked working tree files would be overwritten by checkout:\n{}Please move or remove them before you switch branches.\n", list(untracked))?;
+                }
+                write!(f, "Aborting")
+            },
+            CheckoutError::Unmerged(paths) => {write!(f, "you need to resolve your current index first\n{}", paths.iter().map(|path| format!("{}: needs merge", path)).collect::<Vec<_>>().join("\n"))},
+            CheckoutError::Unmatched(args) => {write!(f, "{}", args.iter().map(|arg| format!("pathspec '{}' did not match any file(s) known to git", arg)).collect::<Vec<_>>().join("\n"))},
+        }
+    }
+}
+
+impl From<RepositoryError> for CheckoutError {
+    fn from(repo_err: RepositoryError) -> Self {
+        CheckoutError::Repo(repo_err)
+    }
+}
+
+impl From<IndexError> for CheckoutError {
+    fn from(index_err: IndexError) -> Self {
+        CheckoutError::Index(index_err)
+    }
+}
+
+impl From<ObjectError> for CheckoutError {
+    fn from(obj_err: ObjectError) -> Self {
+        CheckoutError::Object(obj_err)
+    }
+}
+
+impl From<std::io::Error> for CheckoutError {
+    fn from(io_err: std::io::Error) -> Self {
+        CheckoutError::Io(io_err)
+    }
+}
+
+impl From<ConvertError> for CheckoutError {
+    fn from(convert_err: ConvertError) -> Self {
+        CheckoutError::Convert(convert_err)
+    }
+}
@@ -0,0 +1,148 @@
+use crate::lib::objects::git_ident::Signature;
+use crate::lib::parsing::decoding::ObjectParseError;
+
+///A commit or tag: `key value` header lines, continued on lines starting with a space,
+///then a blank line and the message. Headers keep their order so objects re-serialize exactly
+#[derive(Clone, Debug, PartialEq)]
+pub struct Commit {
+    headers: Vec<(String, String)>,
+    message: String,
+}
+
+impl Commit {
+    pub fn new(tree: &str, parents: &[String], author: &Signature, committer: &Signature, message: &str) -> Commit {
+        let mut headers = vec![("tree".to_owned(), tree.to_owned())];
+        headers.extend(parents.iter().map(|parent| ("parent".to_owned(), parent.clone())));
+        headers.push(("author".to_owned(), author.to_string()));
+        headers.push(("committer".to_owned(), committer.to_string()));
+        Commit {
+            headers,
+            message: message.to_owned(),
+        }
+    }
+
+    pub fn parse(bytes: &[u8]) -> Result<Commit, ObjectParseError> {
+        let text = String::from_utf8_lossy(bytes);
+        let (head, message) = match text.find("\n\n") {
+            Some(end) => (&text[..end], &text[end + 2..]),
+            None => (text.trim_end_matches('\n'), ""),
+        };
+        let mut headers: Vec<(String, String)> = Vec::new();
+        for line in head.split('\n') {
+            if let Some(continued) = line.strip_prefix(' ') {
+                let (_, value) = headers.last_mut().ok_or(ObjectParseError::MalformedCommit())?;
+                value.push('\n');
+                value.push_str(continued);
+            } else {
+                let (key, value) = line.split_once(' ').ok_or(ObjectParseError::MalformedCommit())?;
+                headers.push((key.to_owned(), value.to_owned()));
+            }
+        }
+        Ok(Commit {
+            headers,
+            message: message.to_owned(),
+        })
+    }
+
+    ///The first value of a header
+    pub fn header(&self, key: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(name, _)| name == key)
+            .map(|(_, value)| value.as_str())
+    }
+
+    pub fn tree(&self) -> Option<&str> {
+        self.header("tree")
+    }
+
+    pub fn message(&self) -> &str {
+        &self.message
+    }
+
+    pub fn parents(&self) -> Vec<&str> {
+        self.headers
+            .iter()
+            .filter(|(name, _)| name == "parent")
+            .map(|(_, value)| value.as_str())
+            .collect()
+    }
+
+    pub fn author(&self) -> Option<Signature> {
+        self.header("author").and_then(Signature::parse)
+    }
+
+    pub fn committer(&self) -> Option<Signature> {
+        self.header("committer").and_then(Signature::parse)
+    }
+
+    ///The first paragraph of the message on one line, as shown in summaries
+    pub fn subject(&self) -> String {
+        let paragraph = self.message.trim_start_matches('\n').split("\n\n").next().unwrap_or("");
+        paragraph.split('\n').map(str::trim).filter(|line| !line.is_empty()).collect::<Vec<_>>().join(" ")
+    }
+
+    ///Object content: headers with continuation lines indented, a blank line, then the message
+    pub fn serialize(&self) -> Vec<u8> {
+        let mut text = String::new();
+        for (key, value) in &self.headers {
+            text.push_str(key);
+            text.push(' ');
+            text.push_str(&value.replace('\n', "\n "));
+            text.push('\n');
+        }
+        text.push('\n');
+        text.push_str(&self.message);
+        text.into_bytes()
+    }
+}
+
+///Tidy a commit message the way `git commit` does: trailing whitespace and runs of blank lines
+///go, as do `#` comment lines when the message came from the editor
+pub fn cleanup_message(text: &str, strip_comments: bool) -> String {
+    let mut cleaned = String::new();
+    let mut pending_blank = false;
+    for line in text.lines() {
+        let line = line.trim_end();
+        if strip_comments && line.starts_with('#') {
+            continue;
+        }
+        if line.is_empty() {
+            pending_blank = !cleaned.is_empty();
+            continue;
+        }
+        if pending_blank {
+            cleaned.push('\n');
+            pending_blank = false;
+        }
+        cleaned.push_str(line);
+        cleaned.push('\n');
+    }
+    cleaned
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_continued_headers() {
+        let text = "tree 4b825dc642cb6eb9a060e54bf8d69288fbee4904\n\
+                    parent 05f01ab76171493c8ab7dc46d0abdbc94ed85372\n\
+                    author A U Thor <a@example.com> 1600000000 +0000\n\
+                    gpgsig -----BEGIN PGP SIGNATURE-----\n \n line\n -----END PGP SIGNATURE-----\n\
+                    \n\
+                    Subject\n\nBody\n";
+        let commit = Commit::parse(text.as_bytes()).expect("Error parsing commit");
+        assert!(commit.tree() == Some("4b825dc642cb6eb9a060e54bf8d69288fbee4904"));
+        assert!(commit.header("gpgsig") == Some("-----BEGIN PGP SIGNATURE-----\n\nline\n-----END PGP SIGNATURE-----"));
+        assert!(commit.message() == "Subject\n\nBody\n");
+        assert!(commit.parents() == ["05f01ab76171493c8ab7dc46d0abdbc94ed85372"]);
+        assert!(commit.author().unwrap().time == 1600000000);
+        assert!(commit.serialize() == text.as_bytes(), "Serialized commit differs");
+        assert!(commit.subject() == "Subject");
+        assert!(cleanup_message("\n\nSubject  \n\n\n# comment\nBody\n\n", true) == "Subject\n\nBody\n");
+        assert!(cleanup_message("# kept\n", false) == "# kept\n");
+        assert!(Commit::parse(b"nonsense\n\nmessage").is_err());
+    }
+}
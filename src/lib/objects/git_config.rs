@@ -0,0 +1,1290 @@
+use crate::lib::clean_unc;
+use crate::lib::objects::git_lock::LockFile;
+use crate::lib::parsing::wildmatch::{wildmatch, WM_CASEFOLD, WM_PATHNAME};
+use std::fmt::{Display, Formatter};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+///How deep include.path may nest before git assumes a cycle
+const MAX_INCLUDE_DEPTH: usize = 10;
+
+///A section header, with the name lowercased and the subsection kept as written
+#[derive(Clone, Debug, PartialEq)]
+struct SectionName {
+    name: String,
+    subsection: Option<String>,
+}
+
+impl SectionName {
+    ///Parse `section` or `section.subsection` as used by --rename-section and --remove-section
+    fn parse(section: &str) -> Result<SectionName, ConfigError> {
+        let (name, subsection) = match section.split_once('.') {
+            Some((name, subsection)) => (name, Some(subsection.to_owned())),
+            None => (section, None),
+        };
+        if name.is_empty() || !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '-') {
+            return Err(ConfigError::InvalidSection(section.to_owned()));
+        }
+        Ok(SectionName {
+            name: name.to_lowercase(),
+            subsection,
+        })
+    }
+
+    fn header(&self) -> String {
+        match &self.subsection {
+            Some(subsection) => {
+                let escaped = subsection.replace('\\', "\\\\").replace('"', "\\\"");
+                format!("[{} \"{}\"]", self.name, escaped)
+            }
+            None => format!("[{}]", self.name),
+        }
+    }
+
+    fn full_key(&self, key: &str) -> String {
+        match &self.subsection {
+            Some(subsection) => format!("{}.{}.{}", self.name, subsection, key),
+            None => format!("{}.{}", self.name, key),
+        }
+    }
+}
+
+///One piece of a config file. Every item keeps the exact text it was parsed from so
+///rewriting a file only touches the lines that were edited
+#[derive(Clone, Debug)]
+enum ConfigItem {
+    Section {
+        raw: String,
+        section: SectionName,
+    },
+    Entry {
+        raw: String,
+        section: SectionName,
+        key: String,
+        value: Option<String>,
+    },
+    ///Blank lines and comments
+    Other(String),
+}
+
+impl ConfigItem {
+    fn raw(&self) -> &str {
+        match self {
+            ConfigItem::Section { raw, .. } | ConfigItem::Entry { raw, .. } | ConfigItem::Other(raw) => raw,
+        }
+    }
+
+    fn raw_mut(&mut self) -> &mut String {
+        match self {
+            ConfigItem::Section { raw, .. } | ConfigItem::Entry { raw, .. } | ConfigItem::Other(raw) => raw,
+        }
+    }
+
+    fn section(&self) -> Option<&SectionName> {
+        match self {
+            ConfigItem::Section { section, .. } | ConfigItem::Entry { section, .. } => Some(section),
+            ConfigItem::Other(_) => None,
+        }
+    }
+}
+
+///A single git config file, parsed with git's syntax rules and editable without
+///disturbing comments or formatting elsewhere in the file
+#[derive(Clone, Debug, Default)]
+pub struct ConfigFile {
+    items: Vec<ConfigItem>,
+    ///Set when the file on disk was not valid UTF-8, so writing it back would corrupt it
+    lossy: bool,
+}
+
+#[derive(Debug)]
+pub enum ConfigError {
+    Io(std::io::Error),
+    ///Source the text came from (empty when parsed from a string) and the 1-based line
+    BadLine(String, usize),
+    InvalidKey(String),
+    InvalidSection(String),
+    NotBoolean(String, String),
+    NotInteger(String, String),
+    MultipleValues(String),
+    MissingKey(String),
+    NoSuchSection(String),
+    NotColor(String, String),
+    InvalidPattern(String),
+    IncludeDepth(String),
+    BadEnvironment(String),
+    NotUtf8(String),
+}
+
+impl Display for ConfigError {
+    fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
+        match self {
+            ConfigError::Io(io_err) => {write!(f, "{}", io_err)},
+            ConfigError::BadLine(source, line) if source.is_empty() => {write!(f, "bad config line {}", line)},
+            ConfigError::BadLine(source, line) => {write!(f, "bad config line {} in file {}", line, source)},
+            ConfigError::InvalidKey(key) => {write!(f, "invalid key: {}", key)},
+            ConfigError::InvalidSection(section) => {write!(f, "invalid section name: {}", section)},
+            ConfigError::NotBoolean(key, value) => {write!(f, "bad boolean config value '{}' for '{}'", value, key)},
+            ConfigError::NotInteger(key, value) => {write!(f, "bad numeric config value '{}' for '{}'", value, key)},
+            ConfigError::MultipleValues(key) => {write!(f, "{} has multiple values", key)},
+            ConfigError::MissingKey(key) => {write!(f, "key not found: {}", key)},
+            ConfigError::NoSuchSection(section) => {write!(f, "no such section: {}", section)},
+            ConfigError::NotColor(key, value) => {write!(f, "invalid color value '{}' for '{}'", value, key)},
+            ConfigError::InvalidPattern(msg) => {write!(f, "invalid pattern: {}", msg)},
+            ConfigError::IncludeDepth(path) => {write!(f, "exceeded maximum include depth ({}) while including {}", MAX_INCLUDE_DEPTH, path)},
+            ConfigError::BadEnvironment(msg) => {write!(f, "{}", msg)},
+            ConfigError::NotUtf8(path) => {write!(f, "refusing to rewrite {}: it is not valid UTF-8", path)},
+        }
+    }
+}
+
+impl From<std::io::Error> for ConfigError {
+    fn from(io_err: std::io::Error) -> Self {
+        ConfigError::Io(io_err)
+    }
+}
+
+impl ConfigFile {
+    pub fn new() -> ConfigFile {
+        ConfigFile::default()
+    }
+
+    ///Read and parse a config file
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<ConfigFile, ConfigError> {
+        let path = path.as_ref();
+        let text = std::fs::read(path)?;
+        let mut file = ConfigFile::parse(&String::from_utf8_lossy(&text)).map_err(|err| match err {
+            ConfigError::BadLine(_, line) => ConfigError::BadLine(path.to_string_lossy().into_owned(), line),
+            err => err,
+        })?;
+        file.lossy = std::str::from_utf8(&text).is_err();
+        Ok(file)
+    }
+
+    ///Write the file through a `.lock` file so readers never see it half written. Files that
+    ///were loaded with invalid UTF-8 are refused rather than written back with replacement characters
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<(), ConfigError> {
+        if self.lossy {
+            return Err(ConfigError::NotUtf8(path.as_ref().to_string_lossy().into_owned()));
+        }
+        let mut lock = LockFile::acquire(path)?;
+        lock.write_all(self.to_string().as_bytes())?;
+        lock.commit()?;
+        Ok(())
+    }
+
+    pub fn parse(text: &str) -> Result<ConfigFile, ConfigError> {
+        let bytes = text.as_bytes();
+        let mut items = Vec::new();
+        let mut section: Option<SectionName> = None;
+        let mut pos = 0;
+        //Start of the text not yet owned by an item
+        let mut start = 0;
+        let mut line = 1;
+        while pos < bytes.len() {
+            match bytes[pos] {
+                b'\n' => {
+                    line += 1;
+                    pos += 1;
+                }
+                b'#' | b';' => pos = skip_line(bytes, pos, &mut line),
+                byte if byte.is_ascii_whitespace() => pos += 1,
+                byte => {
+                    //Items own their whole line, indentation included
+                    let item_start = text[start..pos].rfind('\n').map_or(start, |i| start + i + 1);
+                    if item_start > start {
+                        items.push(ConfigItem::Other(text[start..item_start].to_owned()));
+                    }
+                    let bad_line = || ConfigError::BadLine(String::new(), line);
+                    if byte == b'[' {
+                        let (name, header_end) = parse_section_header(bytes, pos).ok_or_else(bad_line)?;
+                        let end = end_of_header_line(bytes, header_end, &mut line);
+                        items.push(ConfigItem::Section {
+                            raw: text[item_start..end].to_owned(),
+                            section: name.clone(),
+                        });
+                        section = Some(name);
+                        pos = end;
+                    } else {
+                        let current = section.clone().ok_or_else(bad_line)?;
+                        let (key, value, end) = parse_entry(bytes, pos, &mut line)?;
+                        items.push(ConfigItem::Entry {
+                            raw: text[item_start..end].to_owned(),
+                            section: current,
+                            key,
+                            value,
+                        });
+                        pos = end;
+                    }
+                    start = pos;
+                }
+            }
+        }
+        if start < text.len() {
+            items.push(ConfigItem::Other(text[start..].to_owned()));
+        }
+        Ok(ConfigFile { items, lossy: false })
+    }
+
+    ///Every entry in file order as (section[.subsection].key, value). A value of None
+    ///is a key written without `=`, which reads as boolean true
+    pub fn entries(&self) -> impl Iterator<Item = (String, Option<&str>)> {
+        self.items.iter().filter_map(|item| match item {
+            ConfigItem::Entry { section, key, value, .. } => Some((section.full_key(key), value.as_deref())),
+            _ => None,
+        })
+    }
+
+    ///All values for a key, in file order
+    pub fn get_all(&self, name: &str) -> Vec<Option<&str>> {
+        let (section, key) = match split_key(name) {
+            Ok(parts) => parts,
+            Err(_) => return Vec::new(),
+        };
+        self.matching_entries(&section, &key)
+            .map(|i| match &self.items[i] {
+                ConfigItem::Entry { value, .. } => value.as_deref(),
+                _ => None,
+            })
+            .collect()
+    }
+
+    ///The last value for a key, which is the one git uses. Keys without a value read as ""
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.get_all(name).pop().map(|value| value.unwrap_or(""))
+    }
+
+    pub fn get_bool(&self, name: &str) -> Result<Option<bool>, ConfigError> {
+        last_as_bool(name, self.get_all(name).pop())
+    }
+
+    pub fn get_int(&self, name: &str) -> Result<Option<i64>, ConfigError> {
+        last_as_int(name, self.get(name))
+    }
+
+    ///Set a key, replacing its value. Keys with several values have to be changed with unset_all and add
+    pub fn set(&mut self, name: &str, value: &str) -> Result<(), ConfigError> {
+        let (section, key) = split_key(name)?;
+        let existing: Vec<usize> = self.matching_entries(&section, &key).collect();
+        match existing.as_slice() {
+            [] => self.add(name, value),
+            [i] => {
+                self.items[*i] = entry_item(section, key, value);
+                Ok(())
+            }
+            _ => Err(ConfigError::MultipleValues(name.to_owned())),
+        }
+    }
+
+    ///Add another value for a key after the last entry of its section, creating the section if needed
+    pub fn add(&mut self, name: &str, value: &str) -> Result<(), ConfigError> {
+        let (section, key) = split_key(name)?;
+        let insert_at = self
+            .items
+            .iter()
+            .rposition(|item| item.section() == Some(&section))
+            .map(|i| i + 1);
+        match insert_at {
+            Some(i) => {
+                end_with_newline(&mut self.items[i - 1]);
+                self.items.insert(i, entry_item(section, key, value));
+            }
+            None => {
+                if let Some(last) = self.items.last_mut() {
+                    end_with_newline(last);
+                }
+                self.items.push(ConfigItem::Section {
+                    raw: format!("{}\n", section.header()),
+                    section: section.clone(),
+                });
+                self.items.push(entry_item(section, key, value));
+            }
+        }
+        Ok(())
+    }
+
+    ///Remove a single valued key, returning whether it was there
+    pub fn unset(&mut self, name: &str) -> Result<bool, ConfigError> {
+        Ok(self.unset_matching(name, false, |_| true)? > 0)
+    }
+
+    ///Remove every value of a key, returning how many were removed
+    pub fn unset_all(&mut self, name: &str) -> Result<usize, ConfigError> {
+        self.unset_matching(name, true, |_| true)
+    }
+
+    ///Remove the values of a key accepted by the filter. Unless `all` is set, more than
+    ///one accepted value is an error
+    pub fn unset_matching<F: Fn(Option<&str>) -> bool>(&mut self, name: &str, all: bool, filter: F) -> Result<usize, ConfigError> {
+        let (section, key) = split_key(name)?;
+        let matches: Vec<usize> = self
+            .matching_entries(&section, &key)
+            .filter(|i| match &self.items[*i] {
+                ConfigItem::Entry { value, .. } => filter(value.as_deref()),
+                _ => false,
+            })
+            .collect();
+        if !all && matches.len() > 1 {
+            return Err(ConfigError::MultipleValues(name.to_owned()));
+        }
+        for i in matches.iter().rev() {
+            self.remove_item(*i);
+        }
+        Ok(matches.len())
+    }
+
+    ///Remove every copy of a section and its entries, keeping surrounding comments
+    pub fn remove_section(&mut self, name: &str) -> Result<bool, ConfigError> {
+        let section = SectionName::parse(name)?;
+        let matches: Vec<usize> = (0..self.items.len())
+            .filter(|i| self.items[*i].section() == Some(&section))
+            .collect();
+        for i in matches.iter().rev() {
+            self.remove_item(*i);
+        }
+        Ok(!matches.is_empty())
+    }
+
+    ///Rename every copy of a section, returning whether any existed
+    pub fn rename_section(&mut self, old_name: &str, new_name: &str) -> Result<bool, ConfigError> {
+        let old_section = SectionName::parse(old_name)?;
+        let new_section = SectionName::parse(new_name)?;
+        let mut found = false;
+        for item in self.items.iter_mut() {
+            match item {
+                ConfigItem::Section { raw, section } if *section == old_section => {
+                    let newline = if raw.ends_with('\n') { "\n" } else { "" };
+                    let indent: String = raw.chars().take_while(|c| c.is_whitespace()).collect();
+                    *raw = format!("{}{}{}", indent, new_section.header(), newline);
+                    *section = new_section.clone();
+                    found = true;
+                }
+                ConfigItem::Entry { section, .. } if *section == old_section => {
+                    *section = new_section.clone();
+                }
+                _ => (),
+            }
+        }
+        Ok(found)
+    }
+
+    fn matching_entries<'a>(&'a self, section: &'a SectionName, key: &'a str) -> impl Iterator<Item = usize> + 'a {
+        self.items.iter().enumerate().filter_map(move |(i, item)| match item {
+            ConfigItem::Entry { section: entry_section, key: entry_key, .. }
+                if entry_section == section && entry_key == key => Some(i),
+            _ => None,
+        })
+    }
+
+    fn remove_item(&mut self, i: usize) {
+        self.items.remove(i);
+        //An entry written on its header's line leaves the header without a newline
+        if i > 0 && i < self.items.len() {
+            end_with_newline(&mut self.items[i - 1]);
+        }
+    }
+}
+
+impl Display for ConfigFile {
+    fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
+        for item in self.items.iter() {
+            write!(f, "{}", item.raw())?;
+        }
+        Ok(())
+    }
+}
+
+///How a value is interpreted by `config --type`
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ConfigType {
+    Bool,
+    Int,
+    Path,
+    Color,
+}
+
+impl FromStr for ConfigType {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<ConfigType, String> {
+        match s {
+            "bool" => Ok(ConfigType::Bool),
+            "int" => Ok(ConfigType::Int),
+            "path" => Ok(ConfigType::Path),
+            "color" => Ok(ConfigType::Color),
+            _ => Err(format!("unrecognized --type argument, {}", s)),
+        }
+    }
+}
+
+impl ConfigType {
+    ///Check a value being written and put it in the form git stores
+    pub fn normalize(&self, name: &str, value: &str) -> Result<String, ConfigError> {
+        match self {
+            ConfigType::Bool => Ok(last_as_bool(name, Some(Some(value)))?.unwrap_or_default().to_string()),
+            ConfigType::Int => Ok(last_as_int(name, Some(value))?.unwrap_or_default().to_string()),
+            ConfigType::Path => Ok(value.to_owned()),
+            ConfigType::Color => parse_color(value)
+                .map(|_| value.to_owned())
+                .ok_or_else(|| ConfigError::NotColor(name.to_owned(), value.to_owned())),
+        }
+    }
+
+    ///The form a stored value is printed in: paths expanded and colors as escape sequences
+    pub fn display(&self, name: &str, value: Option<&str>) -> Result<String, ConfigError> {
+        match self {
+            ConfigType::Bool => Ok(last_as_bool(name, Some(value))?.unwrap_or_default().to_string()),
+            ConfigType::Int => Ok(last_as_int(name, Some(value.unwrap_or("")))?.unwrap_or_default().to_string()),
+            ConfigType::Path => Ok(expand_path(value.unwrap_or("")).to_string_lossy().into_owned()),
+            ConfigType::Color => parse_color(value.unwrap_or(""))
+                .ok_or_else(|| ConfigError::NotColor(name.to_owned(), value.unwrap_or("").to_owned())),
+        }
+    }
+}
+
+///Terminal attributes git accepts in color values, with their SGR codes
+const COLOR_ATTRIBUTES: [(&str, u8, u8); 7] = [
+    ("bold", 1, 22),
+    ("dim", 2, 22),
+    ("italic", 3, 23),
+    ("ul", 4, 24),
+    ("blink", 5, 25),
+    ("reverse", 7, 27),
+    ("strike", 9, 29),
+];
+
+const COLOR_NAMES: [&str; 8] = ["black", "red", "green", "yellow", "blue", "magenta", "cyan", "white"];
+
+///Turn a color value like `bold red ul` or `#ff0000 black` into its ANSI escape sequence.
+///The first color is the foreground and the second the background
+pub fn parse_color(value: &str) -> Option<String> {
+    let mut attributes: Vec<u8> = Vec::new();
+    let mut colors: Vec<String> = Vec::new();
+    for word in value.split_whitespace() {
+        let word = word.to_lowercase();
+        if word == "reset" {
+            attributes.push(0);
+            continue;
+        }
+        let attribute = COLOR_ATTRIBUTES.iter().find_map(|(name, on, off)| {
+            let negated = word.strip_prefix("no-").or_else(|| word.strip_prefix("no"));
+            if word == *name || (*name == "ul" && word == "underline") {
+                Some(*on)
+            } else if negated == Some(name) || (*name == "ul" && negated == Some("underline")) {
+                Some(*off)
+            } else {
+                None
+            }
+        });
+        if let Some(code) = attribute {
+            if !attributes.contains(&code) {
+                attributes.push(code);
+            }
+            continue;
+        }
+        if colors.len() == 2 {
+            return None;
+        }
+        //Foreground and background codes differ only in their first digit
+        let (base, bright_base) = if colors.is_empty() { (30, 90) } else { (40, 100) };
+        let color = if word == "normal" {
+            String::new()
+        } else if word == "default" {
+            (base + 9).to_string()
+        } else if let Some(i) = COLOR_NAMES.iter().position(|name| *name == word) {
+            (base + i).to_string()
+        } else if let Some(i) = word.strip_prefix("bright").and_then(|name| COLOR_NAMES.iter().position(|c| *c == name)) {
+            (bright_base + i).to_string()
+        } else if let Some(hex) = word.strip_prefix('#').filter(|hex| hex.len() == 6) {
+            let channel = |i: usize| u8::from_str_radix(&hex[i..i + 2], 16).ok();
+            format!("{};2;{};{};{}", base + 8, channel(0)?, channel(2)?, channel(4)?)
+        } else {
+            match word.parse::<i32>().ok()? {
+                -1 => String::new(),
+                num @ 0..=7 => (base + num as usize).to_string(),
+                num @ 8..=255 => format!("{};5;{}", base + 8, num),
+                _ => return None,
+            }
+        };
+        colors.push(color);
+    }
+    let mut codes: Vec<String> = attributes.iter().map(u8::to_string).collect();
+    codes.extend(colors.into_iter().filter(|color| !color.is_empty()));
+    if codes.is_empty() {
+        Some(String::new())
+    } else if codes == ["0"] {
+        Some("\x1b[m".to_owned())
+    } else {
+        Some(format!("\x1b[{}m", codes.join(";")))
+    }
+}
+
+///Where a config value came from, in increasing order of precedence
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ConfigScope {
+    System,
+    Global,
+    Local,
+    Worktree,
+    Command,
+}
+
+impl Display for ConfigScope {
+    fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
+        match self {
+            ConfigScope::System => write!(f, "system"),
+            ConfigScope::Global => write!(f, "global"),
+            ConfigScope::Local => write!(f, "local"),
+            ConfigScope::Worktree => write!(f, "worktree"),
+            ConfigScope::Command => write!(f, "command"),
+        }
+    }
+}
+
+///A single value of the layered configuration
+#[derive(Clone, Debug)]
+pub struct ConfigValue {
+    pub name: String,
+    pub value: Option<String>,
+    pub scope: ConfigScope,
+    ///`file:<path>` or `command line:`, as shown by --show-origin
+    pub origin: String,
+}
+
+///Every config layer git reads, flattened in precedence order with includes expanded
+///where they appear, so the last value for a key is the one that applies
+#[derive(Clone, Debug, Default)]
+pub struct GitConfig {
+    values: Vec<ConfigValue>,
+}
+
+///What includeIf conditions are tested against
+#[derive(Default)]
+struct IncludeContext {
+    gitdir: Option<PathBuf>,
+    branch: Option<String>,
+}
+
+impl IncludeContext {
+    fn for_gitdir(gitdir: &Path) -> IncludeContext {
+        let head = std::fs::read_to_string(gitdir.join("HEAD")).unwrap_or_default();
+        IncludeContext {
+            gitdir: Some(gitdir.canonicalize().map(clean_unc).unwrap_or_else(|_| gitdir.to_owned())),
+            branch: head
+                .trim_end()
+                .strip_prefix("ref: refs/heads/")
+                .map(str::to_owned),
+        }
+    }
+
+    ///Evaluate the condition of an `[includeIf "<condition>"]` section
+    fn matches(&self, condition: &str, config_path: &Path) -> bool {
+        if let Some(pattern) = condition.strip_prefix("gitdir:") {
+            self.gitdir_matches(pattern, config_path, 0)
+        } else if let Some(pattern) = condition.strip_prefix("gitdir/i:") {
+            self.gitdir_matches(pattern, config_path, WM_CASEFOLD)
+        } else if let Some(pattern) = condition.strip_prefix("onbranch:") {
+            let pattern = if pattern.ends_with('/') { format!("{}**", pattern) } else { pattern.to_owned() };
+            match &self.branch {
+                Some(branch) => wildmatch(&pattern, branch, WM_PATHNAME),
+                None => false,
+            }
+        } else {
+            false
+        }
+    }
+
+    fn gitdir_matches(&self, pattern: &str, config_path: &Path, flags: u32) -> bool {
+        let gitdir = match &self.gitdir {
+            Some(gitdir) => gitdir,
+            None => return false,
+        };
+        let mut pattern = match pattern.strip_prefix("./") {
+            Some(relative) => config_dir(config_path).join(relative).to_string_lossy().into_owned(),
+            None => expand_path(pattern).to_string_lossy().into_owned(),
+        };
+        if !Path::new(&pattern).is_absolute() {
+            pattern = format!("**/{}", pattern);
+        }
+        if pattern.ends_with('/') {
+            pattern.push_str("**");
+        }
+        wildmatch(&pattern, &gitdir.to_string_lossy(), WM_PATHNAME | flags)
+    }
+}
+
+impl GitConfig {
+    pub fn new() -> GitConfig {
+        GitConfig::default()
+    }
+
+    ///Take already parsed files as they are, without includes or the system, global and command line layers
+    pub(crate) fn from_files(files: &[(ConfigScope, &Path, &ConfigFile)]) -> GitConfig {
+        let mut config = GitConfig::new();
+        for (scope, path, file) in files {
+            for (name, value) in file.entries() {
+                config.push(*scope, file_origin(path), name, value);
+            }
+        }
+        config
+    }
+
+    ///A single file and whatever it includes, as read by `config --file`
+    pub fn load_file(scope: ConfigScope, path: &Path) -> Result<GitConfig, ConfigError> {
+        let mut config = GitConfig::new();
+        config.add_file(scope, path, &IncludeContext::default(), 0)?;
+        Ok(config)
+    }
+
+    ///The system, global and command line layers, for commands run outside a repository
+    pub fn without_repository() -> Result<GitConfig, ConfigError> {
+        let context = IncludeContext::default();
+        let mut config = GitConfig::new();
+        config.add_user_files(&context)?;
+        config.add_command_line()?;
+        Ok(config)
+    }
+
+    ///Every layer for a repository: system, global, the given repository files, then the command line
+    pub(crate) fn for_repository(gitdir: &Path, files: &[(ConfigScope, &Path, &ConfigFile)]) -> Result<GitConfig, ConfigError> {
+        let context = IncludeContext::for_gitdir(gitdir);
+        let mut config = GitConfig::new();
+        config.add_user_files(&context)?;
+        for (scope, path, file) in files {
+            config.add_parsed(*scope, path, file, &context, 0)?;
+        }
+        config.add_command_line()?;
+        Ok(config)
+    }
+
+    ///Every value in precedence order
+    pub fn values(&self) -> &[ConfigValue] {
+        &self.values
+    }
+
+    ///Only the values from the given scopes
+    pub fn in_scopes(&self, scopes: &[ConfigScope]) -> GitConfig {
+        GitConfig {
+            values: self
+                .values
+                .iter()
+                .filter(|value| scopes.contains(&value.scope))
+                .cloned()
+                .collect(),
+        }
+    }
+
+    ///All values for a key, lowest precedence first
+    pub fn get_all(&self, name: &str) -> Vec<Option<&str>> {
+        let name = match canonical_key(name) {
+            Ok(name) => name,
+            Err(_) => return Vec::new(),
+        };
+        self.values
+            .iter()
+            .filter(|value| value.name == name)
+            .map(|value| value.value.as_deref())
+            .collect()
+    }
+
+    ///The value that applies for a key. Keys without a value read as ""
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.get_all(name).pop().map(|value| value.unwrap_or(""))
+    }
+
+    pub fn get_bool(&self, name: &str) -> Result<Option<bool>, ConfigError> {
+        last_as_bool(name, self.get_all(name).pop())
+    }
+
+    pub fn get_int(&self, name: &str) -> Result<Option<i64>, ConfigError> {
+        last_as_int(name, self.get(name))
+    }
+
+    fn push(&mut self, scope: ConfigScope, origin: String, name: String, value: Option<&str>) {
+        self.values.push(ConfigValue {
+            name,
+            value: value.map(str::to_owned),
+            scope,
+            origin,
+        });
+    }
+
+    fn add_user_files(&mut self, context: &IncludeContext) -> Result<(), ConfigError> {
+        if let Some(path) = system_config_path() {
+            self.add_file(ConfigScope::System, &path, context, 0)?;
+        }
+        for path in global_config_paths() {
+            self.add_file(ConfigScope::Global, &path, context, 0)?;
+        }
+        Ok(())
+    }
+
+    ///Add a config file's values if the file exists
+    fn add_file(&mut self, scope: ConfigScope, path: &Path, context: &IncludeContext, depth: usize) -> Result<(), ConfigError> {
+        if !path.is_file() {
+            return Ok(());
+        }
+        let file = ConfigFile::load(path)?;
+        self.add_parsed(scope, path, &file, context, depth)
+    }
+
+    ///Add a file's values, following include.path and matching includeIf.<condition>.path
+    ///entries into the files they name
+    fn add_parsed(&mut self, scope: ConfigScope, path: &Path, file: &ConfigFile, context: &IncludeContext, depth: usize) -> Result<(), ConfigError> {
+        for (name, value) in file.entries() {
+            let included = match name
+                .strip_prefix("includeif.")
+                .and_then(|rest| rest.strip_suffix(".path"))
+            {
+                Some(condition) => value.filter(|_| context.matches(condition, path)),
+                None if name == "include.path" => value,
+                None => None,
+            };
+            self.push(scope, file_origin(path), name, value);
+
+            if let Some(target) = included {
+                if depth >= MAX_INCLUDE_DEPTH {
+                    return Err(ConfigError::IncludeDepth(target.to_owned()));
+                }
+                let target = config_dir(path).join(expand_path(target));
+                self.add_file(scope, &target, context, depth + 1)?;
+            }
+        }
+        Ok(())
+    }
+
+    ///Settings from `-c` (passed along in GIT_CONFIG_PARAMETERS) and GIT_CONFIG_COUNT/KEY_n/VALUE_n
+    fn add_command_line(&mut self) -> Result<(), ConfigError> {
+        let mut settings = Vec::new();
+        if let Ok(count) = std::env::var("GIT_CONFIG_COUNT") {
+            let count: usize = count
+                .parse()
+                .map_err(|_| ConfigError::BadEnvironment("bogus count in GIT_CONFIG_COUNT".to_owned()))?;
+            for i in 0..count {
+                let key = std::env::var(format!("GIT_CONFIG_KEY_{}", i))
+                    .map_err(|_| ConfigError::BadEnvironment(format!("missing config key GIT_CONFIG_KEY_{}", i)))?;
+                let value = std::env::var(format!("GIT_CONFIG_VALUE_{}", i))
+                    .map_err(|_| ConfigError::BadEnvironment(format!("missing config value GIT_CONFIG_VALUE_{}", i)))?;
+                settings.push((key, Some(value)));
+            }
+        }
+        if let Ok(parameters) = std::env::var("GIT_CONFIG_PARAMETERS") {
+            settings.extend(parse_parameters(&parameters)?);
+        }
+        for (key, value) in settings {
+            self.push(ConfigScope::Command, "command line:".to_owned(), canonical_key(&key)?, value.as_deref());
+        }
+        Ok(())
+    }
+}
+
+///Quote a `-c key[=value]` setting for GIT_CONFIG_PARAMETERS the way git does
+pub fn quote_parameter(setting: &str) -> String {
+    let quote = |text: &str| format!("'{}'", text.replace('\'', "'\\''"));
+    match setting.split_once('=') {
+        Some((key, value)) => format!("{}={}", quote(key), quote(value)),
+        None => quote(setting),
+    }
+}
+
+///Parse GIT_CONFIG_PARAMETERS: space separated, shell single quoted `'key'='value'`,
+///`'key'` or the older `'key=value'`
+fn parse_parameters(parameters: &str) -> Result<Vec<(String, Option<String>)>, ConfigError> {
+    let bogus = || ConfigError::BadEnvironment("bogus format in GIT_CONFIG_PARAMETERS".to_owned());
+    let mut chars = parameters.chars().peekable();
+    let read_quoted = |chars: &mut std::iter::Peekable<std::str::Chars>| -> Result<String, ConfigError> {
+        if chars.next() != Some('\'') {
+            return Err(bogus());
+        }
+        let mut text = String::new();
+        loop {
+            match chars.next().ok_or_else(bogus)? {
+                '\'' => {
+                    //'\'' ends the quote, adds a quote and starts quoting again
+                    let mut lookahead = chars.clone();
+                    if lookahead.next() == Some('\\') && lookahead.next() == Some('\'') && lookahead.next() == Some('\'') {
+                        text.push('\'');
+                        *chars = lookahead;
+                    } else {
+                        return Ok(text);
+                    }
+                }
+                c => text.push(c),
+            }
+        }
+    };
+
+    let mut settings = Vec::new();
+    loop {
+        while chars.peek().is_some_and(|c| c.is_whitespace()) {
+            chars.next();
+        }
+        if chars.peek().is_none() {
+            return Ok(settings);
+        }
+        let key = read_quoted(&mut chars)?;
+        if chars.peek() == Some(&'=') {
+            chars.next();
+            let value = if chars.peek() == Some(&'\'') { Some(read_quoted(&mut chars)?) } else { None };
+            settings.push((key, value));
+        } else {
+            match key.split_once('=') {
+                Some((key, value)) => settings.push((key.to_owned(), Some(value.to_owned()))),
+                None => settings.push((key, None)),
+            }
+        }
+    }
+}
+
+///The system config file, unless GIT_CONFIG_NOSYSTEM is set
+pub fn system_config_path() -> Option<PathBuf> {
+    if std::env::var("GIT_CONFIG_NOSYSTEM").is_ok_and(|value| parse_bool(Some(&value)) == Some(true)) {
+        return None;
+    }
+    Some(std::env::var_os("GIT_CONFIG_SYSTEM").map_or_else(|| PathBuf::from("/etc/gitconfig"), PathBuf::from))
+}
+
+///The global config files, lowest precedence first: the XDG one then ~/.gitconfig
+pub fn global_config_paths() -> Vec<PathBuf> {
+    if let Some(path) = std::env::var_os("GIT_CONFIG_GLOBAL") {
+        return vec![PathBuf::from(path)];
+    }
+    let mut paths: Vec<PathBuf> = xdg_config_path("config").into_iter().collect();
+    paths.extend(home_dir().map(|home| home.join(".gitconfig")));
+    paths
+}
+
+///A file in git's XDG config directory, `$XDG_CONFIG_HOME/git` or `~/.config/git`
+pub fn xdg_config_path(name: &str) -> Option<PathBuf> {
+    match std::env::var_os("XDG_CONFIG_HOME").filter(|dir| !dir.is_empty()) {
+        Some(dir) => Some(PathBuf::from(dir).join("git").join(name)),
+        None => home_dir().map(|home| home.join(".config").join("git").join(name)),
+    }
+}
+
+fn home_dir() -> Option<PathBuf> {
+    std::env::var_os("HOME").filter(|home| !home.is_empty()).map(PathBuf::from)
+}
+
+///Expand a leading `~/` to the home directory, as git does for path values
+pub fn expand_path(value: &str) -> PathBuf {
+    match (value.strip_prefix("~/"), home_dir()) {
+        (Some(rest), Some(home)) => home.join(rest),
+        _ if value == "~" => home_dir().unwrap_or_else(|| PathBuf::from(value)),
+        _ => PathBuf::from(value),
+    }
+}
+
+fn config_dir(config_path: &Path) -> &Path {
+    config_path.parent().unwrap_or_else(|| Path::new(""))
+}
+
+fn file_origin(path: &Path) -> String {
+    format!("file:{}", path.to_string_lossy())
+}
+
+///Split `section[.subsection].key` into a section and lowercased key, checking git's naming rules
+fn split_key(name: &str) -> Result<(SectionName, String), ConfigError> {
+    let invalid = || ConfigError::InvalidKey(name.to_owned());
+    let last_dot = name.rfind('.').ok_or_else(invalid)?;
+    let key = &name[last_dot + 1..];
+    let valid_key = key.starts_with(|c: char| c.is_ascii_alphabetic())
+        && key.chars().all(|c| c.is_ascii_alphanumeric() || c == '-');
+    if !valid_key {
+        return Err(invalid());
+    }
+    let section = SectionName::parse(&name[..last_dot]).map_err(|_| invalid())?;
+    Ok((section, key.to_lowercase()))
+}
+
+///The canonical form of a key: section and key lowercased, subsection as written
+pub fn canonical_key(name: &str) -> Result<String, ConfigError> {
+    let (section, key) = split_key(name)?;
+    Ok(section.full_key(&key))
+}
+
+fn last_as_bool(name: &str, last: Option<Option<&str>>) -> Result<Option<bool>, ConfigError> {
+    match last {
+        Some(value) => parse_bool(value)
+            .map(Some)
+            .ok_or_else(|| ConfigError::NotBoolean(name.to_owned(), value.unwrap_or("").to_owned())),
+        None => Ok(None),
+    }
+}
+
+fn last_as_int(name: &str, last: Option<&str>) -> Result<Option<i64>, ConfigError> {
+    match last {
+        Some(value) => parse_int(value)
+            .map(Some)
+            .ok_or_else(|| ConfigError::NotInteger(name.to_owned(), value.to_owned())),
+        None => Ok(None),
+    }
+}
+
+///Interpret a config value the way git's --type=bool does
+pub fn parse_bool(value: Option<&str>) -> Option<bool> {
+    let value = match value {
+        Some(value) => value.to_lowercase(),
+        None => return Some(true),
+    };
+    match value.as_str() {
+        "true" | "yes" | "on" => Some(true),
+        "false" | "no" | "off" | "" => Some(false),
+        _ => parse_int(&value).map(|num| num != 0),
+    }
+}
+
+///Interpret a config value as an integer, allowing git's k, m and g suffixes
+pub fn parse_int(value: &str) -> Option<i64> {
+    let value = value.trim();
+    let (number, scale) = match value.chars().last().map(|c| c.to_ascii_lowercase()) {
+        Some('k') => (&value[..value.len() - 1], 1 << 10),
+        Some('m') => (&value[..value.len() - 1], 1 << 20),
+        Some('g') => (&value[..value.len() - 1], 1 << 30),
+        _ => (value, 1),
+    };
+    number.parse::<i64>().ok()?.checked_mul(scale)
+}
+
+///Write a value so that parsing it back gives the same string
+fn quote_value(value: &str) -> String {
+    let needs_quotes = value.starts_with(char::is_whitespace)
+        || value.ends_with(char::is_whitespace)
+        || value.contains([';', '#']);
+    let mut quoted = String::new();
+    for c in value.chars() {
+        match c {
+            '\n' => quoted.push_str("\\n"),
+            '\t' => quoted.push_str("\\t"),
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            c => quoted.push(c),
+        }
+    }
+    if needs_quotes {
+        format!("\"{}\"", quoted)
+    } else {
+        quoted
+    }
+}
+
+fn entry_item(section: SectionName, key: String, value: &str) -> ConfigItem {
+    ConfigItem::Entry {
+        raw: format!("\t{} = {}\n", key, quote_value(value)),
+        section,
+        key,
+        value: Some(value.to_owned()),
+    }
+}
+
+fn end_with_newline(item: &mut ConfigItem) {
+    let raw = item.raw_mut();
+    if !raw.is_empty() && !raw.ends_with('\n') {
+        raw.push('\n');
+    }
+}
+
+///Position just past the end of the current line
+fn skip_line(bytes: &[u8], pos: usize, line: &mut usize) -> usize {
+    match bytes[pos..].iter().position(|b| *b == b'\n') {
+        Some(offset) => {
+            *line += 1;
+            pos + offset + 1
+        }
+        None => bytes.len(),
+    }
+}
+
+///Parse `[section]`, `[section "subsection"]` or the deprecated `[section.subsection]`,
+///returning the name and the position after the closing bracket
+fn parse_section_header(bytes: &[u8], pos: usize) -> Option<(SectionName, usize)> {
+    let mut pos = pos + 1;
+    let name_start = pos;
+    while pos < bytes.len() && (bytes[pos].is_ascii_alphanumeric() || bytes[pos] == b'-' || bytes[pos] == b'.') {
+        pos += 1;
+    }
+    let name = String::from_utf8_lossy(&bytes[name_start..pos]).to_lowercase();
+    if name.is_empty() {
+        return None;
+    }
+    if *bytes.get(pos)? == b']' {
+        let section = match name.split_once('.') {
+            Some((name, subsection)) => SectionName {
+                name: name.to_owned(),
+                subsection: Some(subsection.to_owned()),
+            },
+            None => SectionName { name, subsection: None },
+        };
+        return Some((section, pos + 1));
+    }
+
+    while pos < bytes.len() && (bytes[pos] == b' ' || bytes[pos] == b'\t') {
+        pos += 1;
+    }
+    if *bytes.get(pos)? != b'"' {
+        return None;
+    }
+    pos += 1;
+    let mut subsection = Vec::new();
+    loop {
+        match *bytes.get(pos)? {
+            b'"' => break,
+            b'\n' => return None,
+            b'\\' => {
+                pos += 1;
+                match *bytes.get(pos)? {
+                    b'\n' => return None,
+                    byte => subsection.push(byte),
+                }
+            }
+            byte => subsection.push(byte),
+        }
+        pos += 1;
+    }
+    if *bytes.get(pos + 1)? != b']' {
+        return None;
+    }
+    let section = SectionName {
+        name,
+        subsection: Some(String::from_utf8_lossy(&subsection).into_owned()),
+    };
+    Some((section, pos + 2))
+}
+
+///A header owns the rest of its line when only whitespace or a comment follows it,
+///otherwise an entry starts on the same line
+fn end_of_header_line(bytes: &[u8], pos: usize, line: &mut usize) -> usize {
+    let mut end = pos;
+    while end < bytes.len() && bytes[end] != b'\n' && bytes[end].is_ascii_whitespace() {
+        end += 1;
+    }
+    match bytes.get(end) {
+        None => end,
+        Some(b'\n') | Some(b'#') | Some(b';') => skip_line(bytes, end, line),
+        Some(_) => pos,
+    }
+}
+
+///Parse `key`, `key = value` or `key = value ; comment`, returning the lowercased key,
+///the unquoted value and the position after the entry's last line
+fn parse_entry(bytes: &[u8], pos: usize, line: &mut usize) -> Result<(String, Option<String>, usize), ConfigError> {
+    let bad_line = |line: usize| ConfigError::BadLine(String::new(), line);
+    if !bytes[pos].is_ascii_alphabetic() {
+        return Err(bad_line(*line));
+    }
+    let mut pos = pos;
+    let key_start = pos;
+    while pos < bytes.len() && (bytes[pos].is_ascii_alphanumeric() || bytes[pos] == b'-') {
+        pos += 1;
+    }
+    let key = String::from_utf8_lossy(&bytes[key_start..pos]).to_lowercase();
+    while pos < bytes.len() && bytes[pos] != b'\n' && bytes[pos].is_ascii_whitespace() {
+        pos += 1;
+    }
+    match bytes.get(pos) {
+        None => return Ok((key, None, pos)),
+        Some(b'\n') | Some(b'#') | Some(b';') => return Ok((key, None, skip_line(bytes, pos, line))),
+        Some(b'=') => pos += 1,
+        Some(_) => return Err(bad_line(*line)),
+    }
+
+    let mut value = Vec::new();
+    let mut quoted = false;
+    let mut spaces = 0;
+    while pos < bytes.len() {
+        let byte = bytes[pos];
+        pos += 1;
+        if byte == b'\n' {
+            if quoted {
+                return Err(bad_line(*line));
+            }
+            *line += 1;
+            return Ok((key, Some(String::from_utf8_lossy(&value).into_owned()), pos));
+        }
+        if !quoted && (byte == b'#' || byte == b';') {
+            let end = skip_line(bytes, pos - 1, line);
+            return Ok((key, Some(String::from_utf8_lossy(&value).into_owned()), end));
+        }
+        //Unquoted whitespace is dropped at either end; inside the value each whitespace
+        //character becomes a space
+        if !quoted && byte.is_ascii_whitespace() {
+            if !value.is_empty() {
+                spaces += 1;
+            }
+            continue;
+        }
+        value.extend(std::iter::repeat_n(b' ', spaces));
+        spaces = 0;
+        match byte {
+            b'\\' => {
+                let escaped = *bytes.get(pos).ok_or_else(|| bad_line(*line))?;
+                pos += 1;
+                match escaped {
+                    b'\n' => *line += 1,
+                    b'n' => value.push(b'\n'),
+                    b't' => value.push(b'\t'),
+                    b'b' => value.push(8),
+                    b'\\' | b'"' => value.push(escaped),
+                    _ => return Err(bad_line(*line)),
+                }
+            }
+            b'"' => quoted = !quoted,
+            byte => value.push(byte),
+        }
+    }
+    if quoted {
+        return Err(bad_line(*line));
+    }
+    Ok((key, Some(String::from_utf8_lossy(&value).into_owned()), pos))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lib::get_test_dir;
+
+    const SAMPLE: &str = "# leading comment\n\
+        [core]\n\
+        \trepositoryformatversion = 0\n\
+        \tbare ; no value means true\n\
+        [remote \"Origin\"]\n\
+        \turl = \"https://example.com/a b.git\"\n\
+        \tfetch = +refs/heads/*:refs/remotes/origin/*\n\
+        \tfetch = +refs/tags/*:refs/tags/*\n\
+        [Alias]\n\
+        \tlg = log \\\n\
+        \t  --oneline   # trailing comment\n\
+        \tquote = \"say \\\"hi\\\"\\tthere\"\n\
+        [branch.Main]\n\
+        \tremote = origin\n";
+
+    #[test]
+    fn parses_git_syntax() {
+        let config = ConfigFile::parse(SAMPLE).expect("Error parsing config");
+        assert!(config.to_string() == SAMPLE, "round trip was {}", config);
+        assert!(config.get_bool("core.bare").unwrap() == Some(true));
+        assert!(config.get_int("Core.RepositoryFormatVersion").unwrap() == Some(0));
+        assert!(config.get("remote.Origin.url") == Some("https://example.com/a b.git"));
+        assert!(config.get("remote.origin.url").is_none());
+        assert!(config.get_all("remote.Origin.fetch").len() == 2);
+        let lg = config.get("alias.lg");
+        assert!(lg == Some("log    --oneline"), "was {:?}", lg);
+        assert!(config.get("alias.quote") == Some("say \"hi\"\tthere"));
+        assert!(config.get("branch.main.remote") == Some("origin"));
+        assert!(ConfigFile::parse("[core]\n\tkey = \"unterminated\n").is_err());
+        assert!(ConfigFile::parse("key = value\n").is_err());
+        assert!(parse_int("2k") == Some(2048));
+    }
+
+    #[test]
+    fn edits_preserve_other_lines() {
+        let mut config = ConfigFile::parse(SAMPLE).expect("Error parsing config");
+        config.set("core.bare", "false").unwrap();
+        config.set("core.filemode", "true").unwrap();
+        config.add("user.name", " padded; name ").unwrap();
+        assert!(config.set("remote.Origin.fetch", "x").is_err());
+        let text = config.to_string();
+        assert!(text.starts_with("# leading comment\n[core]\n\trepositoryformatversion = 0\n\tbare = false\n\tfilemode = true\n"), "was {}", text);
+        assert!(text.contains("# trailing comment"));
+        assert!(text.ends_with("[user]\n\tname = \" padded; name \"\n"), "was {}", text);
+
+        let reparsed = ConfigFile::parse(&text).expect("Error parsing edited config");
+        assert!(reparsed.get("user.name") == Some(" padded; name "));
+        assert!(reparsed.get_bool("core.bare").unwrap() == Some(false));
+    }
+
+    #[test]
+    fn refuses_to_rewrite_invalid_utf8() {
+        let test_dir = get_test_dir("refuses_to_rewrite_invalid_utf8");
+        std::fs::create_dir_all(&test_dir).expect("Error creating directory");
+        let path = test_dir.join("config");
+        let original = b"[user]\n\tname = Andr\xe9\n".to_vec();
+        std::fs::write(&path, &original).unwrap();
+
+        let mut config = ConfigFile::load(&path).expect("Error loading config");
+        config.set("core.bare", "false").unwrap();
+        assert!(matches!(config.save(&path), Err(ConfigError::NotUtf8(_))));
+        assert!(std::fs::read(&path).unwrap() == original, "File was rewritten");
+    }
+
+    #[test]
+    fn removes_and_renames() {
+        let mut config = ConfigFile::parse("[core] bare = true\n\tfilemode = false\n[remote \"a\"]\n\turl = one\n").unwrap();
+        assert!(config.unset("core.bare").unwrap());
+        assert!(config.to_string().starts_with("[core]\n\tfilemode = false\n"), "was {}", config);
+        assert!(config.rename_section("remote.a", "remote.b").unwrap());
+        assert!(config.get("remote.b.url") == Some("one"));
+        assert!(config.to_string().contains("[remote \"b\"]\n"));
+        assert!(config.remove_section("remote.b").unwrap());
+        assert!(!config.to_string().contains("url"));
+        assert!(config.unset_all("core.missing").unwrap() == 0);
+    }
+
+    #[test]
+    fn layers_follow_includes() {
+        let test_dir = get_test_dir("layers_follow_includes");
+        if test_dir.exists() {
+            std::fs::remove_dir_all(&test_dir).expect("Error cleaning directory");
+        }
+        let gitdir = test_dir.join("work").join(".git");
+        std::fs::create_dir_all(&gitdir).unwrap();
+        std::fs::write(gitdir.join("HEAD"), "ref: refs/heads/topic/one\n").unwrap();
+        let gitdir_pattern = gitdir.canonicalize().unwrap().parent().unwrap().to_string_lossy().into_owned();
+        std::fs::write(
+            test_dir.join("global"),
+            format!(
+                "[user]\n\tname = Global\n\temail = g@example.com\n[include]\n\tpath = extra\n\
+                 [includeIf \"gitdir:{}/\"]\n\tpath = work.inc\n\
+                 [includeIf \"gitdir:/elsewhere/\"]\n\tpath = never.inc\n\
+                 [includeIf \"onbranch:topic/\"]\n\tpath = branch.inc\n",
+                gitdir_pattern
+            ),
+        )
+        .unwrap();
+        std::fs::write(test_dir.join("extra"), "[core]\n\teditor = vi\n").unwrap();
+        std::fs::write(test_dir.join("work.inc"), "[user]\n\temail = w@example.com\n").unwrap();
+        std::fs::write(test_dir.join("never.inc"), "[user]\n\temail = never@example.com\n").unwrap();
+        std::fs::write(test_dir.join("branch.inc"), "[topic]\n\tseen\n").unwrap();
+        let local = ConfigFile::parse("[user]\n\tname = Local\n").unwrap();
+
+        let context = IncludeContext::for_gitdir(&gitdir);
+        let mut config = GitConfig::new();
+        config.add_file(ConfigScope::Global, &test_dir.join("global"), &context, 0).unwrap();
+        config.add_parsed(ConfigScope::Local, &gitdir.join("config"), &local, &context, 0).unwrap();
+
+        assert!(config.get("user.name") == Some("Local"));
+        assert!(config.get("user.email") == Some("w@example.com"), "was {:?}", config.get("user.email"));
+        assert!(config.get("core.editor") == Some("vi"));
+        assert!(config.get_bool("topic.seen").unwrap() == Some(true));
+        let origin = &config.values().iter().find(|value| value.name == "core.editor").unwrap().origin;
+        assert!(origin.ends_with("extra"), "origin was {}", origin);
+        assert!(config.in_scopes(&[ConfigScope::Local]).get_all("user.email").is_empty());
+    }
+
+    #[test]
+    fn parses_command_line_parameters() {
+        let quoted: Vec<String> = ["core.bare=true", "user.name=it's me", "Remote.Origin.Prune"]
+            .iter()
+            .map(|setting| quote_parameter(setting))
+            .collect();
+        let parsed = parse_parameters(&format!("{} 'old.style=x'", quoted.join(" "))).expect("Error parsing parameters");
+        assert!(parsed[0] == ("core.bare".to_owned(), Some("true".to_owned())));
+        assert!(parsed[1] == ("user.name".to_owned(), Some("it's me".to_owned())), "was {:?}", parsed[1]);
+        assert!(parsed[2] == ("Remote.Origin.Prune".to_owned(), None));
+        assert!(parsed[3] == ("old.style".to_owned(), Some("x".to_owned())));
+        assert!(canonical_key("Remote.Origin.Prune").unwrap() == "remote.Origin.prune");
+        assert!(parse_parameters("'unterminated").is_err());
+    }
+
+    #[test]
+    fn converts_typed_values() {
+        assert!(parse_color("bold red").unwrap() == "\x1b[1;31m");
+        assert!(parse_color("brightblue ul 236").unwrap() == "\x1b[4;94;48;5;236m");
+        assert!(parse_color("normal").unwrap().is_empty());
+        assert!(parse_color("red green blue").is_none());
+        assert!(ConfigType::Bool.normalize("a.b", "on").unwrap() == "true");
+        assert!(ConfigType::Int.normalize("a.b", "1m").unwrap() == "1048576");
+        assert!(ConfigType::Int.normalize("a.b", "many").is_err());
+        assert!(ConfigType::Bool.display("a.b", None).unwrap() == "true");
+    }
+}
@@ -0,0 +1,424 @@
+use crate::lib::objects::git_attributes::{AttrSource, AttrValue, AttributeRules};
+use crate::lib::objects::git_filter::{FilterDriver, FilterKind, FilterRunner, Filtered};
+use crate::lib::objects::git_index::GitIndex;
+use crate::lib::objects::git_object::GitObject;
+use crate::lib::objects::git_repository::{GitRepository, RepositoryError};
+use std::collections::HashMap;
+use std::fmt::{Display, Formatter};
+use std::rc::Rc;
+
+///core.autocrlf
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum AutoCrlf {
+    False,
+    True,
+    Input,
+}
+
+///core.safecrlf: what to do when converting would not survive a round trip
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum SafeCrlf {
+    Off,
+    Warn,
+    Fail,
+}
+
+///How one path's line endings are converted, git's crlf_action
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum CrlfAction {
+    Binary,
+    ///Text with the configured line ending
+    Text,
+    TextInput,
+    TextCrlf,
+    ///Text unless the content looks binary
+    Auto,
+    AutoInput,
+    AutoCrlf,
+}
+
+impl CrlfAction {
+    fn is_auto(self) -> bool {
+        matches!(self, CrlfAction::Auto | CrlfAction::AutoInput | CrlfAction::AutoCrlf)
+    }
+}
+
+///`text`, or the older `crlf`, as a conversion
+fn crlf_from_attr(value: &AttrValue) -> Option<CrlfAction> {
+    match value {
+        AttrValue::Set => Some(CrlfAction::Text),
+        AttrValue::Unset => Some(CrlfAction::Binary),
+        AttrValue::Value(value) if value == "input" => Some(CrlfAction::TextInput),
+        AttrValue::Value(value) if value == "auto" => Some(CrlfAction::Auto),
+        _ => None,
+    }
+}
+
+///Line ending and character counts git bases its decisions on
+#[derive(Clone, Copy, Debug, Default)]
+struct TextStats {
+    nul: usize,
+    lone_cr: usize,
+    lone_lf: usize,
+    crlf: usize,
+    printable: usize,
+    nonprintable: usize,
+}
+
+impl TextStats {
+    fn gather(content: &[u8]) -> TextStats {
+        let mut stats = TextStats::default();
+        let mut i = 0;
+        while i < content.len() {
+            match content[i] {
+                b'\r' if content.get(i + 1) == Some(&b'\n') => {
+                    stats.crlf += 1;
+                    i += 1;
+                }
+                b'\r' => stats.lone_cr += 1,
+                b'\n' => stats.lone_lf += 1,
+                0x7f => stats.nonprintable += 1,
+                b'\x08' | b'\t' | b'\x1b' | b'\x0c' => stats.printable += 1,
+                0 => {
+                    stats.nul += 1;
+                    stats.nonprintable += 1;
+                }
+                byte if byte < 0x20 => stats.nonprintable += 1,
+                _ => stats.printable += 1,
+            }
+            i += 1;
+        }
+        //A DOS end-of-file marker does not make a file binary
+        if content.last() == Some(&0x1a) {
+            stats.nonprintable -= 1;
+        }
+        stats
+    }
+
+    ///Git's guess at binary content: NUL bytes, lone CRs, or many control characters
+    fn is_binary(&self) -> bool {
+        self.lone_cr > 0 || self.nul > 0 || (self.printable >> 7) < self.nonprintable
+    }
+}
+
+///Conversion between the worktree and the repository: the `filter` attribute's driver, and line
+///endings following `text`, `eol` and `crlf` attributes and core.autocrlf, core.eol and core.safecrlf
+pub(crate) struct Converter {
+    repo: Rc<GitRepository>,
+    attributes: AttributeRules,
+    autocrlf: AutoCrlf,
+    ///core.eol=crlf: text files get CRLF in the worktree
+    eol_crlf: bool,
+    safecrlf: SafeCrlf,
+    ///Staged blob ids, so files committed with CRLF are left alone by automatic conversion
+    staged: HashMap<String, String>,
+    filters: FilterRunner,
+    ///safecrlf warnings and failures of filters that are not required, for the command to show
+    pub warnings: Vec<String>,
+}
+
+impl Converter {
+    ///A converter reading `.gitattributes` from `source`, with the index's files as fallback or
+    ///in place of the worktree's
+    pub fn for_index(repo: &Rc<GitRepository>, index: &GitIndex, source: AttrSource) -> Result<Converter, RepositoryError> {
+        let staged: HashMap<String, String> = index
+            .entries()
+            .iter()
+            .filter(|entry| entry.stage == 0)
+            .map(|entry| (entry.path.clone(), entry.sha.clone()))
+            .collect();
+        Converter::for_blobs(repo, staged, source)
+    }
+
+    ///Like `for_index`, for a tree's blobs by path
+    pub fn for_blobs(repo: &Rc<GitRepository>, staged: HashMap<String, String>, source: AttrSource) -> Result<Converter, RepositoryError> {
+        let config = repo.config();
+        let autocrlf = match config.get("core.autocrlf") {
+            Some(value) if value.eq_ignore_ascii_case("input") => AutoCrlf::Input,
+            _ if matches!(config.get_bool("core.autocrlf"), Ok(Some(true))) => AutoCrlf::True,
+            _ => AutoCrlf::False,
+        };
+        let safecrlf = match config.get("core.safecrlf") {
+            Some(value) if value.eq_ignore_ascii_case("warn") => SafeCrlf::Warn,
+            Some(_) if matches!(config.get_bool("core.safecrlf"), Ok(Some(true))) => SafeCrlf::Fail,
+            Some(_) => SafeCrlf::Off,
+            None => SafeCrlf::Warn,
+        };
+        let attributes = AttributeRules::for_repository(repo, staged.iter().map(|(path, sha)| (path.clone(), sha.clone())), source)?;
+        Ok(Converter {
+            filters: FilterRunner::new(repo.worktree()?),
+            repo: Rc::clone(repo),
+            attributes,
+            autocrlf,
+            eol_crlf: config.get("core.eol").is_some_and(|value| value.eq_ignore_ascii_case("crlf")),
+            safecrlf,
+            staged,
+            warnings: Vec::new(),
+        })
+    }
+
+    ///An attribute's state for a path, for pathspec `attr:` magic
+    pub fn attribute(&mut self, path: &str, name: &str) -> AttrValue {
+        self.attributes.get(path, name)
+    }
+
+    fn crlf_action(&mut self, path: &str) -> CrlfAction {
+        let mut action = crlf_from_attr(&self.attributes.get(path, "text")).or_else(|| crlf_from_attr(&self.attributes.get(path, "crlf")));
+        //An `eol` attribute makes a path text unless it is explicitly binary
+        if action != Some(CrlfAction::Binary) {
+            action = match (action, self.attributes.get(path, "eol")) {
+                (Some(CrlfAction::Auto), AttrValue::Value(eol)) if eol == "lf" => Some(CrlfAction::AutoInput),
+                (Some(CrlfAction::Auto), AttrValue::Value(eol)) if eol == "crlf" => Some(CrlfAction::AutoCrlf),
+                (_, AttrValue::Value(eol)) if eol == "lf" => Some(CrlfAction::TextInput),
+                (_, AttrValue::Value(eol)) if eol == "crlf" => Some(CrlfAction::TextCrlf),
+                (action, _) => action,
+            };
+        }
+        action.unwrap_or(match self.autocrlf {
+            AutoCrlf::False => CrlfAction::Binary,
+            AutoCrlf::True => CrlfAction::AutoCrlf,
+            AutoCrlf::Input => CrlfAction::AutoInput,
+        })
+    }
+
+    ///Whether text gets CRLF line endings in the worktree
+    fn output_crlf(&self, action: CrlfAction) -> bool {
+        match action {
+            CrlfAction::Binary | CrlfAction::TextInput | CrlfAction::AutoInput => false,
+            CrlfAction::TextCrlf | CrlfAction::AutoCrlf => true,
+            CrlfAction::Text | CrlfAction::Auto => match self.autocrlf {
+                AutoCrlf::True => true,
+                AutoCrlf::Input => false,
+                AutoCrlf::False => self.eol_crlf,
+            },
+        }
+    }
+
+    fn will_add_cr(&self, stats: &TextStats, action: CrlfAction) -> bool {
+        if !self.output_crlf(action) || stats.lone_lf == 0 {
+            return false;
+        }
+        //Automatic conversion leaves files with any CR in them as they are
+        !action.is_auto() || (stats.lone_cr == 0 && stats.crlf == 0 && !stats.is_binary())
+    }
+
+    fn has_crlf_in_index(&self, path: &str) -> bool {
+        let blob = self.staged.get(path).and_then(|sha| GitObject::from_internal_file(sha, &self.repo).ok());
+        match blob {
+            Some(blob) if blob.serialize().contains(&b'\r') => {
+                let stats = TextStats::gather(blob.serialize());
+                !stats.is_binary() && stats.crlf > 0
+            }
+            _ => false,
+        }
+    }
+
+    ///Content of a worktree file as git stores it
+    pub fn clean(&mut self, path: &str, content: Vec<u8>) -> Result<Vec<u8>, ConvertError> {
+        self.convert_to_git(path, content, SafeCrlf::Off)
+    }
+
+    ///Like `clean` for content about to be added, warning or failing as core.safecrlf says when
+    ///checking the result out again would not give back the same file
+    pub fn clean_checked(&mut self, path: &str, content: Vec<u8>) -> Result<Vec<u8>, ConvertError> {
+        self.convert_to_git(path, content, self.safecrlf)
+    }
+
+    ///Like `clean_checked` for content only compared with what is staged, where git's diff
+    ///warns even when core.safecrlf asks to fail
+    pub fn clean_compared(&mut self, path: &str, content: Vec<u8>) -> Result<Vec<u8>, ConvertError> {
+        let safecrlf = if self.safecrlf == SafeCrlf::Off { SafeCrlf::Off } else { SafeCrlf::Warn };
+        self.convert_to_git(path, content, safecrlf)
+    }
+
+    fn convert_to_git(&mut self, path: &str, content: Vec<u8>, safecrlf: SafeCrlf) -> Result<Vec<u8>, ConvertError> {
+        let content = self.filter(path, FilterKind::Clean, content, false)?.expect("only smudging is delayed");
+        let action = self.crlf_action(path);
+        if action == CrlfAction::Binary || content.is_empty() {
+            return Ok(content);
+        }
+        let stats = TextStats::gather(&content);
+        let mut convert = true;
+        if action.is_auto() {
+            if stats.is_binary() {
+                return Ok(content);
+            }
+            convert = !self.has_crlf_in_index(path);
+        }
+        if safecrlf != SafeCrlf::Off {
+            //What adding then checking out would do to the file
+            let mut round_trip = stats;
+            if convert {
+                round_trip.lone_lf += round_trip.crlf;
+                round_trip.crlf = 0;
+            }
+            if self.will_add_cr(&round_trip, action) {
+                round_trip.crlf += round_trip.lone_lf;
+                round_trip.lone_lf = 0;
+            }
+            let lost = if stats.crlf > 0 && round_trip.crlf == 0 {
+                Some(("CRLF", "LF"))
+            } else if stats.lone_lf > 0 && round_trip.lone_lf == 0 {
+                Some(("LF", "CRLF"))
+            } else {
+                None
+            };
+            match (lost, safecrlf) {
+                (Some((from, to)), SafeCrlf::Fail) => return Err(ConvertError::SafeCrlf(from.to_owned(), to.to_owned(), path.to_owned())),
+                (Some((from, to)), _) => {
+                    //A file compared before it is added would otherwise be warned about twice
+                    let warning = format!("in the working copy of '{}', {} will be replaced by {} the next time Git touches it", path, from, to);
+                    if !self.warnings.contains(&warning) {
+                        self.warnings.push(warning);
+                    }
+                }
+                (None, _) => {}
+            }
+        }
+        if !convert || stats.crlf == 0 {
+            return Ok(content);
+        }
+        let mut converted = Vec::with_capacity(content.len() - stats.crlf);
+        for (i, byte) in content.iter().enumerate() {
+            if *byte != b'\r' || content.get(i + 1) != Some(&b'\n') {
+                converted.push(*byte);
+            }
+        }
+        Ok(converted)
+    }
+
+    ///Content of a blob as it is written to the worktree, None when a process filter delays it
+    ///until `available_delayed` hands it over
+    pub fn smudge(&mut self, path: &str, content: Vec<u8>) -> Result<Option<Vec<u8>>, ConvertError> {
+        let action = self.crlf_action(path);
+        let stats = TextStats::gather(&content);
+        if !self.will_add_cr(&stats, action) {
+            return self.filter(path, FilterKind::Smudge, content, true);
+        }
+        let mut converted = Vec::with_capacity(content.len() + stats.lone_lf);
+        for (i, byte) in content.iter().enumerate() {
+            if *byte == b'\n' && (i == 0 || content[i - 1] != b'\r') {
+                converted.push(b'\r');
+            }
+            converted.push(*byte);
+        }
+        self.filter(path, FilterKind::Smudge, converted, true)
+    }
+
+    ///Delayed paths ready to be written and their content, empty once none are left
+    pub fn available_delayed(&mut self) -> Result<Vec<(String, Vec<u8>)>, ConvertError> {
+        let available = self.filters.available_delayed();
+        self.warnings.append(&mut self.filters.warnings);
+        available
+    }
+
+    ///Run the path's filter driver, if it has one. Content a driver that is not required fails
+    ///on goes through as it is
+    fn filter(&mut self, path: &str, kind: FilterKind, content: Vec<u8>, can_delay: bool) -> Result<Option<Vec<u8>>, ConvertError> {
+        let driver = match self.attributes.get(path, "filter") {
+            AttrValue::Value(name) => FilterDriver::from_config(self.repo.config(), &name),
+            _ => None,
+        };
+        let driver = match driver {
+            Some(driver) => driver,
+            None => return Ok(Some(content)),
+        };
+        let filtered = self.filters.apply(&driver, kind, path, &content, can_delay);
+        self.warnings.append(&mut self.filters.warnings);
+        match filtered {
+            Filtered::Done(output) => Ok(Some(output)),
+            Filtered::Delayed => Ok(None),
+            Filtered::Failed if driver.required => Err(ConvertError::FilterFailed(path.to_owned(), kind, driver.name)),
+            Filtered::Failed => Ok(Some(content)),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum ConvertError {
+    Io(std::io::Error),
+    ///core.safecrlf=true refusing a conversion: the line ending lost, its replacement and the path
+    SafeCrlf(String, String, String),
+    ///A required filter failing: the path, which way and the driver's name
+    FilterFailed(String, FilterKind, String),
+    ///A process filter offering a path it never delayed: the command and the path
+    NotDelayed(String, String),
+    ///Delayed paths the filters never handed over
+    NotFiltered(Vec<String>),
+}
+
+impl Display for ConvertError {
+    fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
+        match self {
+            ConvertError::Io(err) => {write!(f, "{}", err)},
+            ConvertError::SafeCrlf(from, to, path) => {write!(f, "{} would be replaced by {} in {}", from, to, path)},
+            ConvertError::FilterFailed(path, FilterKind::Clean, driver) => {write!(f, "{}: clean filter '{}' failed", path, driver)},
+            ConvertError::FilterFailed(path, FilterKind::Smudge, driver) => {write!(f, "{}: smudge filter {} failed", path, driver)},
+            ConvertError::NotDelayed(command, path) => {write!(f, "external filter '{}' signaled that '{}' is now available although it has not been delayed earlier", command, path)},
+            ConvertError::NotFiltered(paths) => {write!(f, "{}", paths.iter().map(|path| format!("'{}' was not filtered properly", path)).collect::<Vec<_>>().join("\n"))},
+        }
+    }
+}
+
+impl From<std::io::Error> for ConvertError {
+    fn from(io_err: std::io::Error) -> Self {
+        ConvertError::Io(io_err)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lib::{configured_test_repo, test_repo};
+    use crate::lib::objects::git_config::ConfigFile;
+
+    #[test]
+    fn converts_line_endings() {
+        let (test_dir, repo) = test_repo("converts_line_endings");
+        std::fs::write(test_dir.join(".gitattributes"), "*.txt text\n*.bat eol=crlf\n*.sh eol=lf\n*.dat binary\n").unwrap();
+        let mut convert = Converter::for_index(&repo, &GitIndex::new(), AttrSource::WorktreeFirst).unwrap();
+        assert!(convert.clean("a.txt", b"a\r\nb\n".to_vec()).unwrap() == b"a\nb\n");
+        assert!(convert.clean("a.dat", b"a\r\n".to_vec()).unwrap() == b"a\r\n");
+        assert!(convert.clean("other", b"a\r\n".to_vec()).unwrap() == b"a\r\n", "Nothing happens without autocrlf");
+        assert!(convert.smudge("a.bat", b"a\nb\r\n".to_vec()).unwrap() == Some(b"a\r\nb\r\n".to_vec()));
+        assert!(convert.smudge("a.txt", b"a\n".to_vec()).unwrap() == Some(b"a\n".to_vec()), "Native line endings are LF");
+        assert!(convert.clean_checked("a.sh", b"a\r\n".to_vec()).unwrap() == b"a\n");
+        assert!(convert.warnings == ["in the working copy of 'a.sh', CRLF will be replaced by LF the next time Git touches it"], "Warnings were {:?}", convert.warnings);
+
+        let mut local = ConfigFile::load(test_dir.join(".git").join("config")).unwrap();
+        local.set("core.autocrlf", "true").unwrap();
+        local.set("core.safecrlf", "true").unwrap();
+        local.save(test_dir.join(".git").join("config")).unwrap();
+        let repo = Rc::new(GitRepository::at_path(&test_dir, false).expect("Error opening repo"));
+        let mut convert = Converter::for_index(&repo, &GitIndex::new(), AttrSource::WorktreeFirst).unwrap();
+        assert!(convert.smudge("other", b"a\nb\n".to_vec()).unwrap() == Some(b"a\r\nb\r\n".to_vec()));
+        assert!(convert.smudge("other", b"a\0\n".to_vec()).unwrap() == Some(b"a\0\n".to_vec()), "Binary content is left alone");
+        assert!(convert.clean_checked("other", b"a\r\nb\r\n".to_vec()).unwrap() == b"a\nb\n");
+        assert!(convert.clean_checked("other", b"a\r\nb\n".to_vec()).is_err(), "Mixed line endings would not round trip");
+    }
+
+    #[test]
+    fn runs_filter_drivers() {
+        let settings = [
+            ("filter.upper.clean", "tr A-Z a-z"),
+            ("filter.upper.smudge", "printf '%s:' %f; tr a-z A-Z"),
+            ("filter.broken.clean", "false"),
+            ("filter.broken.required", "true"),
+            ("filter.optional.clean", "false"),
+        ];
+        let (test_dir, repo) = configured_test_repo("runs_filter_drivers", &settings);
+        std::fs::write(test_dir.join(".gitattributes"), "*.up filter=upper\n*.req filter=broken\n*.opt filter=optional\n*.none filter=unknown\n").unwrap();
+        let mut convert = Converter::for_index(&repo, &GitIndex::new(), AttrSource::WorktreeFirst).unwrap();
+
+        assert!(convert.clean("a b.up", b"ABC\n".to_vec()).unwrap() == b"abc\n");
+        let smudged = convert.smudge("a b.up", b"abc\n".to_vec()).unwrap();
+        assert!(smudged == Some(b"a b.up:ABC\n".to_vec()), "Smudged to {:?}", smudged);
+        let err = convert.clean("x.req", b"x".to_vec()).unwrap_err();
+        assert!(err.to_string() == "x.req: clean filter 'broken' failed", "Error was {}", err);
+        assert!(convert.smudge("x.req", b"x".to_vec()).unwrap_err().to_string() == "x.req: smudge filter broken failed", "Required filters need both commands");
+        convert.warnings.clear();
+        assert!(convert.clean("x.opt", b"x".to_vec()).unwrap() == b"x", "Optional filters may fail");
+        assert!(convert.warnings == ["external filter 'false' failed 1"], "Warnings were {:?}", convert.warnings);
+        assert!(convert.clean("x.none", b"X".to_vec()).unwrap() == b"X");
+    }
+}
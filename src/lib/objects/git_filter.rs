@@ -0,0 +1,431 @@
+use crate::lib::objects::git_config::GitConfig;
+use crate::lib::objects::git_convert::ConvertError;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::io::{BufReader, BufWriter, ErrorKind, Read, Write};
+use std::path::{Path, PathBuf};
+use std::process::{Child, ChildStdin, ChildStdout, Command, Stdio};
+
+///Most a pkt-line can carry after its length
+const PACKET_DATA_MAX: usize = 65516;
+
+///Which way content goes through a filter
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum FilterKind {
+    ///Worktree to repository
+    Clean,
+    ///Repository to worktree
+    Smudge,
+}
+
+impl FilterKind {
+    ///The name in config keys and the process protocol
+    pub fn name(self) -> &'static str {
+        match self {
+            FilterKind::Clean => "clean",
+            FilterKind::Smudge => "smudge",
+        }
+    }
+}
+
+///A `filter.<name>` driver the `filter` attribute refers to
+#[derive(Clone, Debug)]
+pub(crate) struct FilterDriver {
+    pub name: String,
+    clean: Option<String>,
+    smudge: Option<String>,
+    process: Option<String>,
+    ///Failing to filter is an error instead of leaving the content as it is
+    pub required: bool,
+}
+
+impl FilterDriver {
+    ///The driver configured under a name, None when nothing is
+    pub fn from_config(config: &GitConfig, name: &str) -> Option<FilterDriver> {
+        let get = |key: &str| config.get(&format!("filter.{}.{}", name, key)).map(str::to_owned);
+        let required = get("required");
+        let driver = FilterDriver {
+            name: name.to_owned(),
+            clean: get("clean"),
+            smudge: get("smudge"),
+            process: get("process"),
+            required: matches!(config.get_bool(&format!("filter.{}.required", name)), Ok(Some(true))),
+        };
+        let configured = driver.clean.is_some() || driver.smudge.is_some() || driver.process.is_some() || required.is_some();
+        Some(driver).filter(|_| configured)
+    }
+}
+
+///What became of content sent through a filter
+pub(crate) enum Filtered {
+    Done(Vec<u8>),
+    ///A process filter will hand the content over later, see `FilterRunner::available_delayed`
+    Delayed,
+    Failed,
+}
+
+///Runs filter drivers from the top of the worktree, keeping `process` filters running from one
+///file to the next and tracking the paths they delay
+pub(crate) struct FilterRunner {
+    worktree: PathBuf,
+    ///Running process filters by command
+    processes: HashMap<String, FilterProcess>,
+    ///Paths each process filter has delayed
+    delayed: BTreeMap<String, BTreeSet<String>>,
+    ///Delayed paths whose filter gave up on them
+    unfinished: Vec<String>,
+    ///Failures of filters that are not required, for the command to show
+    pub warnings: Vec<String>,
+}
+
+impl FilterRunner {
+    pub fn new(worktree: &Path) -> FilterRunner {
+        FilterRunner {
+            worktree: worktree.to_owned(),
+            processes: HashMap::new(),
+            delayed: BTreeMap::new(),
+            unfinished: Vec::new(),
+            warnings: Vec::new(),
+        }
+    }
+
+    ///Send content through a driver. A `process` command takes the place of `clean` and
+    ///`smudge`, and can delay smudged content when `can_delay` allows it
+    pub fn apply(&mut self, driver: &FilterDriver, kind: FilterKind, path: &str, content: &[u8], can_delay: bool) -> Filtered {
+        let command = match kind {
+            FilterKind::Clean => driver.clean.as_deref(),
+            FilterKind::Smudge => driver.smudge.as_deref(),
+        };
+        match (driver.process.as_deref(), command) {
+            (None, Some(command)) if !command.is_empty() => self.apply_command(command, path, content),
+            (Some(process), _) if !process.is_empty() => self.apply_process(process, kind, path, content, can_delay),
+            _ => Filtered::Failed,
+        }
+    }
+
+    ///Run a one-shot filter with the content on its standard input, `%f` standing for the path
+    fn apply_command(&mut self, command: &str, path: &str, content: &[u8]) -> Filtered {
+        let command = expand_path_placeholder(command, path);
+        let mut child = match shell(&command, &self.worktree).spawn() {
+            Ok(child) => child,
+            Err(_) => {
+                self.warnings.push(format!("cannot fork to run external filter '{}'", command));
+                return Filtered::Failed;
+            }
+        };
+        //Fed from another thread so a filter writing before it has read everything cannot deadlock
+        let mut stdin = child.stdin.take().expect("stdin is piped");
+        let input = content.to_vec();
+        let writer = std::thread::spawn(move || stdin.write_all(&input));
+        let mut output = Vec::new();
+        let read = child.stdout.take().expect("stdout is piped").read_to_end(&mut output);
+        let written = writer.join().unwrap_or_else(|_| Err(ErrorKind::Other.into()));
+        let status = child.wait();
+        //Filters may stop reading once they have what they need
+        if written.is_err_and(|err| err.kind() != ErrorKind::BrokenPipe) {
+            self.warnings.push(format!("cannot feed the input to external filter '{}'", command));
+            return Filtered::Failed;
+        }
+        if read.is_err() {
+            self.warnings.push(format!("read from external filter '{}' failed", command));
+            return Filtered::Failed;
+        }
+        match status {
+            Ok(status) if status.success() => Filtered::Done(output),
+            Ok(status) => {
+                self.warnings.push(format!("external filter '{}' failed {}", command, status.code().unwrap_or(-1)));
+                Filtered::Failed
+            }
+            Err(_) => {
+                self.warnings.push(format!("external filter '{}' failed", command));
+                Filtered::Failed
+            }
+        }
+    }
+
+    fn apply_process(&mut self, command: &str, kind: FilterKind, path: &str, content: &[u8], can_delay: bool) -> Filtered {
+        let process = match self.process(command) {
+            Some(process) => process,
+            None => return Filtered::Failed,
+        };
+        if !process.supports(kind.name()) {
+            return Filtered::Failed;
+        }
+        let can_delay = can_delay && kind == FilterKind::Smudge && process.supports("delay");
+        match process.filter(kind, path, content, can_delay) {
+            Ok(Reply::Success(output)) => Filtered::Done(output),
+            Ok(Reply::Delayed) => {
+                self.delayed.entry(command.to_owned()).or_default().insert(path.to_owned());
+                Filtered::Delayed
+            }
+            Ok(Reply::Error) => Filtered::Failed,
+            //The filter wants nothing more of this kind for as long as it runs
+            Ok(Reply::Abort) => {
+                process.capabilities.retain(|capability| capability != kind.name());
+                Filtered::Failed
+            }
+            Err(_) => {
+                self.warnings.push(format!("external filter '{}' failed", command));
+                self.stop(command);
+                Filtered::Failed
+            }
+        }
+    }
+
+    ///The running process for a command, started on first use
+    fn process(&mut self, command: &str) -> Option<&mut FilterProcess> {
+        if !self.processes.contains_key(command) {
+            let mut child = match shell(command, &self.worktree).spawn() {
+                Ok(child) => child,
+                Err(_) => {
+                    self.warnings.push(format!("cannot fork to run subprocess '{}'", command));
+                    return None;
+                }
+            };
+            let mut process = FilterProcess {
+                input: Some(BufWriter::new(child.stdin.take().expect("stdin is piped"))),
+                output: BufReader::new(child.stdout.take().expect("stdout is piped")),
+                child,
+                capabilities: Vec::new(),
+            };
+            if process.handshake().is_err() {
+                self.warnings.push(format!("initialization for subprocess '{}' failed", command));
+                return None;
+            }
+            self.processes.insert(command.to_owned(), process);
+        }
+        self.processes.get_mut(command)
+    }
+
+    ///Shut a misbehaving process down; the paths it delayed will not be coming
+    fn stop(&mut self, command: &str) {
+        self.processes.remove(command);
+        if let Some(paths) = self.delayed.remove(command) {
+            self.unfinished.extend(paths);
+        }
+    }
+
+    ///Delayed paths the filters have finished, with their content. Empty once there is nothing
+    ///left to wait for, or an error naming the paths that never arrived
+    pub fn available_delayed(&mut self) -> Result<Vec<(String, Vec<u8>)>, ConvertError> {
+        let mut found = Vec::new();
+        let commands: Vec<String> = self.delayed.keys().cloned().collect();
+        for command in commands {
+            let available = match self.processes.get_mut(&command).map(FilterProcess::available_blobs) {
+                Some(Ok(paths)) => paths,
+                _ => Vec::new(),
+            };
+            //A filter listing nothing has nothing more to give
+            if available.is_empty() {
+                self.stop(&command);
+                continue;
+            }
+            for path in available {
+                let pending = self.delayed.get_mut(&command).map(|pending| pending.remove(&path));
+                if pending != Some(true) {
+                    return Err(ConvertError::NotDelayed(command, path));
+                }
+                let process = self.processes.get_mut(&command).expect("delaying filters are running");
+                match process.filter(FilterKind::Smudge, &path, &[], false) {
+                    Ok(Reply::Success(output)) => found.push((path, output)),
+                    Ok(_) => self.unfinished.push(path),
+                    Err(_) => {
+                        self.warnings.push(format!("external filter '{}' failed", command));
+                        self.unfinished.push(path);
+                        self.stop(&command);
+                        break;
+                    }
+                }
+            }
+        }
+        if found.is_empty() && !self.unfinished.is_empty() {
+            self.unfinished.sort();
+            return Err(ConvertError::NotFiltered(std::mem::take(&mut self.unfinished)));
+        }
+        Ok(found)
+    }
+}
+
+///How a process filter answered a request
+enum Reply {
+    Success(Vec<u8>),
+    Delayed,
+    ///Nothing came of this file
+    Error,
+    ///Nothing will come of any file of this kind
+    Abort,
+}
+
+///A `filter.<name>.process` command speaking git's long-running filter protocol in pkt-lines
+struct FilterProcess {
+    child: Child,
+    ///Taken on drop, as closing it is what tells the filter to finish
+    input: Option<BufWriter<ChildStdin>>,
+    output: BufReader<ChildStdout>,
+    capabilities: Vec<String>,
+}
+
+impl FilterProcess {
+    ///Agree on version 2 of the protocol and on the capabilities both sides know
+    fn handshake(&mut self) -> std::io::Result<()> {
+        let input = self.input.as_mut().expect("input is open");
+        write_text(input, "git-filter-client")?;
+        write_text(input, "version=2")?;
+        write_flush(input)?;
+        input.flush()?;
+        let welcome = read_text_list(&mut self.output)?;
+        if welcome.first().map(String::as_str) != Some("git-filter-server") || !welcome.iter().any(|line| line == "version=2") {
+            return Err(invalid("unexpected filter welcome"));
+        }
+        let wanted = ["clean", "smudge", "delay"];
+        for capability in wanted {
+            write_text(input, &format!("capability={}", capability))?;
+        }
+        write_flush(input)?;
+        input.flush()?;
+        self.capabilities = read_text_list(&mut self.output)?
+            .iter()
+            .filter_map(|line| line.strip_prefix("capability="))
+            .filter(|capability| wanted.contains(capability))
+            .map(str::to_owned)
+            .collect();
+        Ok(())
+    }
+
+    fn supports(&self, capability: &str) -> bool {
+        self.capabilities.iter().any(|known| known == capability)
+    }
+
+    ///One request: the command, the path and then the content, answered by a status, the
+    ///filtered content and a status that may overrule the first one
+    fn filter(&mut self, kind: FilterKind, path: &str, content: &[u8], can_delay: bool) -> std::io::Result<Reply> {
+        let input = self.input.as_mut().expect("input is open");
+        write_text(input, &format!("command={}", kind.name()))?;
+        write_text(input, &format!("pathname={}", path))?;
+        if can_delay {
+            write_text(input, "can-delay=1")?;
+        }
+        write_flush(input)?;
+        for chunk in content.chunks(PACKET_DATA_MAX) {
+            write_packet(input, chunk)?;
+        }
+        write_flush(input)?;
+        input.flush()?;
+        match last_status(read_text_list(&mut self.output)?).as_deref() {
+            Some("success") => {
+                let mut output = Vec::new();
+                while let Some(packet) = read_packet(&mut self.output)? {
+                    output.extend(packet);
+                }
+                match last_status(read_text_list(&mut self.output)?).as_deref() {
+                    None | Some("success") => Ok(Reply::Success(output)),
+                    Some("error") => Ok(Reply::Error),
+                    Some("abort") => Ok(Reply::Abort),
+                    Some(_) => Err(invalid("unexpected filter status")),
+                }
+            }
+            Some("delayed") if can_delay => Ok(Reply::Delayed),
+            Some("error") => Ok(Reply::Error),
+            Some("abort") => Ok(Reply::Abort),
+            _ => Err(invalid("unexpected filter status")),
+        }
+    }
+
+    ///The delayed paths that are ready to be asked for again
+    fn available_blobs(&mut self) -> std::io::Result<Vec<String>> {
+        let input = self.input.as_mut().expect("input is open");
+        write_text(input, "command=list_available_blobs")?;
+        write_flush(input)?;
+        input.flush()?;
+        let paths = read_text_list(&mut self.output)?
+            .iter()
+            .filter_map(|line| line.strip_prefix("pathname="))
+            .map(str::to_owned)
+            .collect();
+        match last_status(read_text_list(&mut self.output)?).as_deref() {
+            Some("success") => Ok(paths),
+            _ => Err(invalid("unexpected filter status")),
+        }
+    }
+}
+
+impl Drop for FilterProcess {
+    fn drop(&mut self) {
+        drop(self.input.take());
+        let _ = self.child.wait();
+    }
+}
+
+fn shell(command: &str, worktree: &Path) -> Command {
+    let mut shell = Command::new("sh");
+    shell.arg("-c").arg(command).current_dir(worktree).stdin(Stdio::piped()).stdout(Stdio::piped());
+    shell
+}
+
+///A filter command with `%f` replaced by the path, quoted for the shell
+fn expand_path_placeholder(command: &str, path: &str) -> String {
+    let mut expanded = String::new();
+    let mut chars = command.chars();
+    while let Some(c) = chars.next() {
+        match (c, chars.clone().next()) {
+            ('%', Some('f')) => expanded.push_str(&format!("'{}'", path.replace('\'', "'\\''").replace('!', "'\\!'"))),
+            ('%', Some('%')) => expanded.push('%'),
+            _ => {
+                expanded.push(c);
+                continue;
+            }
+        }
+        chars.next();
+    }
+    expanded
+}
+
+fn invalid(message: &str) -> std::io::Error {
+    std::io::Error::new(ErrorKind::InvalidData, message)
+}
+
+fn write_packet<W: Write>(out: &mut W, data: &[u8]) -> std::io::Result<()> {
+    write!(out, "{:04x}", data.len() + 4)?;
+    out.write_all(data)
+}
+
+fn write_text<W: Write>(out: &mut W, text: &str) -> std::io::Result<()> {
+    write_packet(out, format!("{}\n", text).as_bytes())
+}
+
+fn write_flush<W: Write>(out: &mut W) -> std::io::Result<()> {
+    out.write_all(b"0000")
+}
+
+///One pkt-line's payload, None for a flush packet
+fn read_packet<R: Read>(input: &mut R) -> std::io::Result<Option<Vec<u8>>> {
+    let mut length = [0; 4];
+    input.read_exact(&mut length)?;
+    let length = std::str::from_utf8(&length)
+        .ok()
+        .and_then(|length| usize::from_str_radix(length, 16).ok())
+        .ok_or_else(|| invalid("bad pkt-line length"))?;
+    match length {
+        0 => Ok(None),
+        1..=4 => Err(invalid("bad pkt-line length")),
+        _ => {
+            let mut data = vec![0; length - 4];
+            input.read_exact(&mut data)?;
+            Ok(Some(data))
+        }
+    }
+}
+
+///Text packets up to the next flush, without their line endings
+fn read_text_list<R: Read>(input: &mut R) -> std::io::Result<Vec<String>> {
+    let mut lines = Vec::new();
+    while let Some(packet) = read_packet(input)? {
+        let text = String::from_utf8_lossy(&packet);
+        lines.push(text.strip_suffix('\n').unwrap_or(&text).to_owned());
+    }
+    Ok(lines)
+}
+
+///The status a list ends on: later ones replace earlier ones
+fn last_status(lines: Vec<String>) -> Option<String> {
+    lines.into_iter().rev().find_map(|line| line.strip_prefix("status=").map(str::to_owned))
+}
@@ -0,0 +1,91 @@
+use crate::lib::objects::git_config::ConfigFile;
+use sha1::Sha1;
+use sha2::{Digest, Sha256};
+use std::fmt::{Display, Formatter};
+use std::str::FromStr;
+
+///Hash algorithm used to name objects in a repository, chosen by extensions.objectFormat
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum ObjectFormat {
+    #[default]
+    Sha1,
+    Sha256,
+}
+
+impl ObjectFormat {
+    ///Read the format from a repository config, extensions only count for version 1
+    pub fn from_config(config: &ConfigFile) -> ObjectFormat {
+        let version = config.get_int("core.repositoryformatversion");
+        match (version, config.get("extensions.objectformat")) {
+            (Ok(Some(1)), Some(name)) => name.parse().unwrap_or_default(),
+            _ => ObjectFormat::Sha1,
+        }
+    }
+
+    ///Length of an object id in bytes
+    pub fn raw_len(&self) -> usize {
+        match self {
+            ObjectFormat::Sha1 => 20,
+            ObjectFormat::Sha256 => 32,
+        }
+    }
+
+    ///Length of an object id written as hex characters
+    pub fn hex_len(&self) -> usize {
+        self.raw_len() * 2
+    }
+
+    ///The all zero id git uses for "no object"
+    pub fn null_id(&self) -> String {
+        "0".repeat(self.hex_len())
+    }
+
+    pub fn digest(&self, content: &[u8]) -> Vec<u8> {
+        match self {
+            ObjectFormat::Sha1 => Sha1::digest(content).to_vec(),
+            ObjectFormat::Sha256 => Sha256::digest(content).to_vec(),
+        }
+    }
+
+    ///Whether the string is a full object id in this format
+    pub fn is_object_id(&self, name: &str) -> bool {
+        name.len() == self.hex_len() && name.chars().all(|c| c.is_ascii_hexdigit())
+    }
+}
+
+impl Display for ObjectFormat {
+    fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
+        match self {
+            ObjectFormat::Sha1 => write!(f, "sha1"),
+            ObjectFormat::Sha256 => write!(f, "sha256"),
+        }
+    }
+}
+
+impl FromStr for ObjectFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<ObjectFormat, String> {
+        match s.to_lowercase().as_ref() {
+            "sha1" => Ok(ObjectFormat::Sha1),
+            "sha256" => Ok(ObjectFormat::Sha256),
+            _ => Err(format!("unknown object format '{}'", s)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lib::parsing::encoding::bytes_to_hex;
+
+    #[test]
+    fn digests_empty_input() {
+        assert!(bytes_to_hex(&ObjectFormat::Sha1.digest(b"")) == "da39a3ee5e6b4b0d3255bfef95601890afd80709");
+        assert!(
+            bytes_to_hex(&ObjectFormat::Sha256.digest(b""))
+                == "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855"
+        );
+        assert!(ObjectFormat::Sha256.null_id().len() == 64);
+    }
+}
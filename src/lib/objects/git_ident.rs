@@ -0,0 +1,297 @@
+use crate::lib::objects::git_config::GitConfig;
+use regex::Regex;
+use std::fmt::{Display, Formatter};
+use std::sync::OnceLock;
+
+///Who made a commit and when: `Name <email> 1600000000 +0200` in commit and tag headers
+#[derive(Clone, Debug, PartialEq)]
+pub struct Signature {
+    pub name: String,
+    pub email: String,
+    ///Seconds since the epoch
+    pub time: i64,
+    ///Offset from UTC in minutes
+    pub offset: i32,
+}
+
+impl Signature {
+    ///Read a signature from a commit header value
+    pub fn parse(value: &str) -> Option<Signature> {
+        let open = value.find('<')?;
+        let close = open + value[open..].find('>')?;
+        let (time, offset) = parse_raw_date(value[close + 1..].trim())?;
+        Some(Signature {
+            name: value[..open].trim_end().to_owned(),
+            email: value[open + 1..close].to_owned(),
+            time,
+            offset,
+        })
+    }
+}
+
+impl Display for Signature {
+    fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
+        write!(f, "{} <{}> {} {}", self.name, self.email, self.time, format_offset(self.offset))
+    }
+}
+
+///Which identity to look up: each has its own environment variables and config section
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum IdentRole {
+    Author,
+    Committer,
+}
+
+impl IdentRole {
+    fn env_prefix(self) -> &'static str {
+        match self {
+            IdentRole::Author => "GIT_AUTHOR",
+            IdentRole::Committer => "GIT_COMMITTER",
+        }
+    }
+
+    fn section(self) -> &'static str {
+        match self {
+            IdentRole::Author => "author",
+            IdentRole::Committer => "committer",
+        }
+    }
+}
+
+///The identity git would record, from `GIT_<ROLE>_NAME`/`_EMAIL`/`_DATE`, then `<role>.name`
+///and `<role>.email`, then `user.name` and `user.email`, with the current time as the date
+pub fn identity(config: &GitConfig, role: IdentRole) -> Result<Signature, IdentError> {
+    let prefix = role.env_prefix();
+    let lookup = |key: &str| {
+        std::env::var(format!("{}_{}", prefix, key.to_uppercase()))
+            .ok()
+            .or_else(|| config.get(&format!("{}.{}", role.section(), key)).map(str::to_owned))
+            .or_else(|| config.get(&format!("user.{}", key)).map(str::to_owned))
+    };
+    let email = lookup("email").or_else(|| std::env::var("EMAIL").ok());
+    let (name, email) = match (lookup("name"), email) {
+        (Some(name), Some(email)) => (sanitize(&name), sanitize(&email)),
+        _ => return Err(IdentError::Unknown(role)),
+    };
+    if name.is_empty() {
+        return Err(IdentError::EmptyName(email));
+    }
+    let (time, offset) = identity_date(role)?;
+    Ok(Signature { name, email, time, offset })
+}
+
+///The committer recorded in reflogs, which git never refuses to write: the configured identity
+///when there is one, otherwise the login name at this host
+pub fn reflog_identity(config: &GitConfig) -> Signature {
+    identity(config, IdentRole::Committer).unwrap_or_else(|_| {
+        let user = std::env::var("USER").or_else(|_| std::env::var("USERNAME")).unwrap_or_else(|_| "unknown".to_owned());
+        let host = std::fs::read_to_string("/etc/hostname")
+            .ok()
+            .map(|host| host.trim().to_owned())
+            .filter(|host| !host.is_empty())
+            .unwrap_or_else(|| "localhost".to_owned());
+        let (time, offset) = identity_date(IdentRole::Committer).unwrap_or_else(|_| now());
+        Signature {
+            email: format!("{}@{}", user, host),
+            name: user,
+            time,
+            offset,
+        }
+    })
+}
+
+///The date an identity gets: `GIT_<ROLE>_DATE` if set, otherwise now
+pub fn identity_date(role: IdentRole) -> Result<(i64, i32), IdentError> {
+    match std::env::var(format!("{}_DATE", role.env_prefix())) {
+        Ok(date) => parse_date(&date).ok_or(IdentError::BadDate(date)),
+        Err(_) => Ok(now()),
+    }
+}
+
+///Split `Name <email>` as given to `--author`
+pub fn parse_name_email(text: &str) -> Option<(String, String)> {
+    let open = text.find('<')?;
+    let email = text[open + 1..].trim_end().strip_suffix('>')?;
+    let name = sanitize(&text[..open]);
+    if name.is_empty() || email.contains(['<', '>']) {
+        return None;
+    }
+    Some((name, email.to_owned()))
+}
+
+///Drop characters that would break the header and the punctuation git trims from the ends
+fn sanitize(value: &str) -> String {
+    let cleaned: String = value.chars().filter(|c| !matches!(c, '<' | '>' | '\n')).collect();
+    cleaned
+        .trim_matches(|c: char| c.is_whitespace() || ".,:;\"'\\".contains(c))
+        .to_owned()
+}
+
+///The current time with the local offset
+pub fn now() -> (i64, i32) {
+    let time = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_secs() as i64)
+        .unwrap_or(0);
+    (time, local_offset(time))
+}
+
+#[cfg(unix)]
+fn local_offset(time: i64) -> i32 {
+    let time = time as libc::time_t;
+    let mut tm: libc::tm = unsafe { std::mem::zeroed() };
+    if unsafe { libc::localtime_r(&time, &mut tm) }.is_null() {
+        return 0;
+    }
+    (tm.tm_gmtoff / 60) as i32
+}
+
+#[cfg(not(unix))]
+fn local_offset(_time: i64) -> i32 {
+    0
+}
+
+///Format an offset in minutes as `+hhmm`
+pub fn format_offset(offset: i32) -> String {
+    let sign = if offset < 0 { '-' } else { '+' };
+    format!("{}{:02}{:02}", sign, offset.abs() / 60, offset.abs() % 60)
+}
+
+fn parse_offset(text: &str) -> Option<i32> {
+    if text == "Z" {
+        return Some(0);
+    }
+    let sign = match text.chars().next()? {
+        '+' => 1,
+        '-' => -1,
+        _ => return None,
+    };
+    let digits = text[1..].replace(':', "");
+    if digits.len() != 4 || !digits.chars().all(|c| c.is_ascii_digit()) {
+        return None;
+    }
+    let hours: i32 = digits[..2].parse().ok()?;
+    let minutes: i32 = digits[2..].parse().ok()?;
+    Some(sign * (hours * 60 + minutes))
+}
+
+///`1600000000 +0200`, optionally with a leading `@`, as stored in commits
+fn parse_raw_date(text: &str) -> Option<(i64, i32)> {
+    let text = text.strip_prefix('@').unwrap_or(text);
+    let mut parts = text.split_whitespace();
+    let time = parts.next()?.parse().ok()?;
+    let offset = match parts.next() {
+        Some(offset) => parse_offset(offset)?,
+        None => 0,
+    };
+    match parts.next() {
+        Some(_) => None,
+        None => Some((time, offset)),
+    }
+}
+
+///ISO 8601, RFC 2822 and `log` date patterns, compiled the first time a date is parsed
+static DATE_FORMATS: OnceLock<[Regex; 3]> = OnceLock::new();
+
+///Parse a date given to `--date` or `GIT_*_DATE`: raw `<seconds> <offset>`, ISO 8601, RFC 2822
+///or the format `log` prints. Times without an offset are taken as local time
+pub fn parse_date(text: &str) -> Option<(i64, i32)> {
+    let text = text.trim();
+    if let Some(raw) = parse_raw_date(text) {
+        return Some(raw);
+    }
+    let [iso, rfc, log] = DATE_FORMATS.get_or_init(|| {
+        [
+            r"^(\d{4})-(\d{2})-(\d{2})(?:[T ](\d{2}):(\d{2})(?::(\d{2}))?(?:\.\d+)?)?\s*(Z|[+-]\d{2}:?\d{2})?$",
+            r"^(?:[A-Za-z]{3},\s*)?(\d{1,2})\s+([A-Za-z]{3})\s+(\d{4})\s+(\d{2}):(\d{2})(?::(\d{2}))?\s*([+-]\d{4})?$",
+            r"^[A-Za-z]{3}\s+([A-Za-z]{3})\s+(\d{1,2})\s+(\d{2}):(\d{2}):(\d{2})\s+(\d{4})(?:\s+([+-]\d{4}))?$",
+        ]
+        .map(|pattern| Regex::new(pattern).unwrap())
+    });
+    let number = |capture: Option<regex::Match>| capture.map_or(Some(0), |value| value.as_str().parse::<i64>().ok());
+
+    let (year, month, day, hour, minute, second, offset) = if let Some(caps) = iso.captures(text) {
+        (number(caps.get(1))?, number(caps.get(2))?, number(caps.get(3))?, number(caps.get(4))?, number(caps.get(5))?, number(caps.get(6))?, caps.get(7))
+    } else if let Some(caps) = rfc.captures(text) {
+        (number(caps.get(3))?, month_number(&caps[2])?, number(caps.get(1))?, number(caps.get(4))?, number(caps.get(5))?, number(caps.get(6))?, caps.get(7))
+    } else if let Some(caps) = log.captures(text) {
+        (number(caps.get(6))?, month_number(&caps[1])?, number(caps.get(2))?, number(caps.get(3))?, number(caps.get(4))?, number(caps.get(5))?, caps.get(7))
+    } else {
+        return None;
+    };
+    if !(1..=12).contains(&month) || !(1..=31).contains(&day) || hour > 23 || minute > 59 || second > 60 {
+        return None;
+    }
+    let wall_clock = days_from_civil(year, month, day) * 86400 + hour * 3600 + minute * 60 + second;
+    match offset {
+        Some(offset) => {
+            let offset = parse_offset(offset.as_str())?;
+            Some((wall_clock - offset as i64 * 60, offset))
+        }
+        None => {
+            let guess = local_offset(wall_clock);
+            let offset = local_offset(wall_clock - guess as i64 * 60);
+            Some((wall_clock - offset as i64 * 60, offset))
+        }
+    }
+}
+
+fn month_number(name: &str) -> Option<i64> {
+    let months = ["jan", "feb", "mar", "apr", "may", "jun", "jul", "aug", "sep", "oct", "nov", "dec"];
+    months
+        .iter()
+        .position(|month| name.eq_ignore_ascii_case(month))
+        .map(|index| index as i64 + 1)
+}
+
+///Days between 1970-01-01 and a date in the proleptic Gregorian calendar
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146097 + day_of_era - 719468
+}
+
+#[derive(Debug)]
+pub enum IdentError {
+    Unknown(IdentRole),
+    EmptyName(String),
+    BadDate(String),
+}
+
+impl Display for IdentError {
+    fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
+        match self {
+            IdentError::Unknown(role) => {write!(f, "{} identity unknown\n\n*** Please tell me who you are.\n\nRun\n\n  git config --global user.email \"you@example.com\"\n  git config --global user.name \"Your Name\"\n\nto set your account's default identity.\nOmit --global to set the identity only in this repository.", if *role == IdentRole::Author { "Author" } else { "Committer" })},
+            IdentError::EmptyName(email) => {write!(f, "empty ident name (for <{}>) not allowed", email)},
+            IdentError::BadDate(date) => {write!(f, "invalid date format: {}", date)},
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_signatures_and_dates() {
+        let signature = Signature::parse("A U Thor <a@example.com> 1112911993 -0700").expect("Error parsing signature");
+        assert!(signature.name == "A U Thor" && signature.email == "a@example.com");
+        assert!(signature.time == 1112911993 && signature.offset == -420);
+        assert!(signature.to_string() == "A U Thor <a@example.com> 1112911993 -0700");
+
+        let expected = Some((1112911993, 120));
+        assert!(parse_date("@1112911993 +0200") == expected);
+        assert!(parse_date("2005-04-08T00:13:13+02:00") == expected);
+        assert!(parse_date("2005-04-08 00:13:13 +0200") == expected);
+        assert!(parse_date("Fri, 8 Apr 2005 00:13:13 +0200") == expected);
+        assert!(parse_date("Fri Apr 8 00:13:13 2005 +0200") == expected);
+        assert!(parse_date("2005-04-07T22:13:13Z") == Some((1112911993, 0)));
+        assert!(parse_date("yesterday-ish").is_none());
+        assert!(sanitize(" <Name>, ") == "Name");
+        assert!(parse_name_email("A U Thor <a@example.com>") == Some(("A U Thor".to_owned(), "a@example.com".to_owned())));
+        assert!(parse_name_email("nobody").is_none());
+    }
+}
@@ -0,0 +1,242 @@
+use crate::lib::objects::git_config::{expand_path, xdg_config_path};
+use crate::lib::objects::git_repository::{repo_path, GitRepository, RepositoryError};
+use crate::lib::parsing::wildmatch::{wildmatch, WM_CASEFOLD, WM_PATHNAME};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+///One line of an ignore file
+#[derive(Clone, Debug, PartialEq)]
+pub struct IgnorePattern {
+    ///The line as written, for reporting which rule matched
+    pub text: String,
+    ///File the pattern came from, relative to the worktree for `.gitignore` files
+    pub source: String,
+    pub line: usize,
+    ///A leading `!` re-includes paths an earlier pattern excluded
+    pub negated: bool,
+    glob: String,
+    dir_only: bool,
+    ///Patterns with a slash match the whole path below their directory, others only the last name
+    anchored: bool,
+    ///Directory holding the ignore file, with a trailing slash unless it is the top level
+    base: String,
+}
+
+impl IgnorePattern {
+    ///Parse one line, skipping blanks and comments the way git does
+    pub fn parse(line: &str, base: &str, source: &str, line_number: usize) -> Option<IgnorePattern> {
+        if line.is_empty() || line.starts_with('#') {
+            return None;
+        }
+        let text = trim_trailing_spaces(line);
+        let (negated, mut glob) = match text.strip_prefix('!') {
+            Some(rest) => (true, rest),
+            None => (false, text),
+        };
+        let dir_only = glob.ends_with('/');
+        if dir_only {
+            glob = &glob[..glob.len() - 1];
+        }
+        let anchored = glob.contains('/');
+        let glob = glob.strip_prefix('/').unwrap_or(glob);
+        if glob.is_empty() {
+            return None;
+        }
+        Some(IgnorePattern {
+            text: text.to_owned(),
+            source: source.to_owned(),
+            line: line_number,
+            negated,
+            glob: glob.to_owned(),
+            dir_only,
+            anchored,
+            base: base.to_owned(),
+        })
+    }
+
+    ///Whether the pattern applies to a path relative to the worktree
+    pub fn matches(&self, path: &str, is_dir: bool, ignore_case: bool) -> bool {
+        if self.dir_only && !is_dir {
+            return false;
+        }
+        let flags = if ignore_case { WM_CASEFOLD } else { 0 };
+        if !self.anchored {
+            let name = path.rsplit('/').next().unwrap_or(path);
+            return wildmatch(&self.glob, name, flags);
+        }
+        match path.strip_prefix(&self.base) {
+            Some(relative) => wildmatch(&self.glob, relative, flags | WM_PATHNAME),
+            None => false,
+        }
+    }
+}
+
+///Spaces at the end of a line are dropped unless escaped with a backslash
+fn trim_trailing_spaces(line: &str) -> &str {
+    let trimmed = line.trim_end_matches(' ');
+    if trimmed.len() < line.len() && trimmed.ends_with('\\') {
+        let escapes = trimmed.len() - trimmed.trim_end_matches('\\').len();
+        if escapes % 2 == 1 {
+            return &line[..trimmed.len() + 1];
+        }
+    }
+    trimmed
+}
+
+fn parse_patterns(text: &str, base: &str, source: &str) -> Vec<IgnorePattern> {
+    text.lines()
+        .enumerate()
+        .filter_map(|(number, line)| IgnorePattern::parse(line, base, source, number + 1))
+        .collect()
+}
+
+///Git's ignore rules for one worktree: `.gitignore` files found along the way, then
+///`info/exclude`, then `core.excludesFile`
+pub struct IgnoreRules {
+    worktree: PathBuf,
+    ignore_case: bool,
+    global: Vec<Vec<IgnorePattern>>,
+    ///Name of the file read from each directory, normally `.gitignore`
+    per_directory_name: Option<String>,
+    ///Patterns of each directory's file, read the first time a path below it is checked
+    per_directory: HashMap<String, Vec<IgnorePattern>>,
+}
+
+impl IgnoreRules {
+    pub fn for_repository(repo: &GitRepository) -> Result<IgnoreRules, RepositoryError> {
+        let worktree = repo.worktree()?.to_owned();
+        let config = repo.config();
+        let mut rules = IgnoreRules::new(&worktree);
+        rules.set_per_directory(".gitignore");
+        rules.ignore_case = matches!(config.get_bool("core.ignorecase"), Ok(Some(true)));
+
+        let exclude = repo_path(repo, "info/exclude");
+        let source = exclude
+            .strip_prefix(&worktree)
+            .unwrap_or(&exclude)
+            .to_string_lossy()
+            .into_owned();
+        rules.add_file(&exclude, &source);
+        let excludes_file = match config.get("core.excludesfile") {
+            Some(path) => Some(expand_path(path)),
+            None => xdg_config_path("ignore"),
+        };
+        if let Some(path) = excludes_file {
+            rules.add_file(&path, &path.to_string_lossy());
+        }
+        Ok(rules)
+    }
+
+    ///Rules that ignore nothing until files or patterns are added
+    pub fn new(worktree: &Path) -> IgnoreRules {
+        IgnoreRules {
+            worktree: worktree.to_owned(),
+            ignore_case: false,
+            global: Vec::new(),
+            per_directory_name: None,
+            per_directory: HashMap::new(),
+        }
+    }
+
+    ///Read patterns from a file of this name in every directory, taking precedence over global files
+    pub fn set_per_directory(&mut self, name: &str) {
+        self.per_directory_name = Some(name.to_owned());
+        self.per_directory.clear();
+    }
+
+    ///Add a file of patterns that applies to the whole worktree, after all those already added
+    pub fn add_file(&mut self, path: &Path, source: &str) {
+        if let Ok(text) = std::fs::read_to_string(path) {
+            self.global.push(parse_patterns(&text, "", source));
+        }
+    }
+
+    ///Add patterns given directly, such as `--exclude` options, ahead of every file
+    pub fn add_patterns<S: AsRef<str>>(&mut self, patterns: &[S]) {
+        let patterns = patterns
+            .iter()
+            .filter_map(|pattern| IgnorePattern::parse(pattern.as_ref(), "", "", 0))
+            .collect();
+        self.global.insert(0, patterns);
+    }
+
+    pub fn is_ignored(&mut self, path: &str, is_dir: bool) -> bool {
+        matches!(self.matching(path, is_dir), Some(pattern) if !pattern.negated)
+    }
+
+    ///The pattern deciding whether a path is ignored, which may be a negated one. Once a
+    ///directory is excluded nothing below it can be re-included, so that pattern wins
+    pub fn matching(&mut self, path: &str, is_dir: bool) -> Option<&IgnorePattern> {
+        let path = path.trim_end_matches('/');
+        let dirs: Vec<&str> = path.match_indices('/').map(|(end, _)| &path[..end]).collect();
+        for dir in &dirs {
+            if self.last_match(dir, true).is_some_and(|pattern| !pattern.negated) {
+                return self.last_match(dir, true);
+            }
+        }
+        self.last_match(path, is_dir)
+    }
+
+    ///Check the deepest `.gitignore` first, the last matching line of a file wins
+    fn last_match(&mut self, path: &str, is_dir: bool) -> Option<&IgnorePattern> {
+        let mut bases = vec![String::new()];
+        bases.extend(path.match_indices('/').map(|(end, _)| format!("{}/", &path[..end])));
+        for base in &bases {
+            self.load_directory(base);
+        }
+        let ignore_case = self.ignore_case;
+        let loaded = &self.per_directory;
+        let per_directory = bases.iter().rev().map(move |base| &loaded[base]);
+        per_directory
+            .chain(self.global.iter())
+            .find_map(|patterns| patterns.iter().rev().find(|pattern| pattern.matches(path, is_dir, ignore_case)))
+    }
+
+    fn load_directory(&mut self, base: &str) {
+        if self.per_directory.contains_key(base) {
+            return;
+        }
+        let patterns = match &self.per_directory_name {
+            Some(name) => {
+                let source = format!("{}{}", base, name);
+                match std::fs::read_to_string(self.worktree.join(&source)) {
+                    Ok(text) => parse_patterns(&text, base, &source),
+                    Err(_) => Vec::new(),
+                }
+            }
+            None => Vec::new(),
+        };
+        self.per_directory.insert(base.to_owned(), patterns);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lib::get_test_dir;
+
+    #[test]
+    fn follows_gitignore_rules() {
+        let test_dir = get_test_dir("follows_gitignore_rules");
+        let _ = std::fs::remove_dir_all(&test_dir);
+        std::fs::create_dir_all(test_dir.join("src").join("gen")).expect("Unable to create test directory");
+        std::fs::write(test_dir.join(".gitignore"), "*.log\n!keep.log\n/build\ntmp/\nsrc/**/*.o\nbuild-\\ \n").unwrap();
+        std::fs::write(test_dir.join("src").join(".gitignore"), "gen/\n!*.log\n").unwrap();
+
+        let mut rules = IgnoreRules::new(&test_dir);
+        assert!(!rules.is_ignored("debug.log", false));
+        rules.set_per_directory(".gitignore");
+        assert!(rules.is_ignored("debug.log", false));
+        assert!(!rules.is_ignored("keep.log", false));
+        assert!(rules.is_ignored("build", true));
+        assert!(!rules.is_ignored("src/build", true), "Leading slash anchors to the top");
+        assert!(rules.is_ignored("a/tmp", true));
+        assert!(!rules.is_ignored("a/tmp", false), "Trailing slash only matches directories");
+        assert!(rules.is_ignored("src/x/y/z.o", false));
+        assert!(rules.is_ignored("build- ", false), "Escaped trailing space is kept");
+        assert!(!rules.is_ignored("src/debug.log", false), "Deeper .gitignore wins");
+        assert!(rules.is_ignored("src/gen/keep.log", false), "Files in excluded directories cannot be re-included");
+        let pattern = rules.matching("src/gen/file", false).unwrap();
+        assert!(pattern.source == "src/.gitignore" && pattern.line == 1 && pattern.text == "gen/");
+    }
+}
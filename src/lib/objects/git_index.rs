@@ -0,0 +1,946 @@
+use crate::lib::objects::git_hash::ObjectFormat;
+use crate::lib::objects::git_lock::LockFile;
+use crate::lib::objects::git_repository::{repo_path, GitRepository};
+use crate::lib::parsing::encoding::{bytes_to_hex, raw_object_id};
+use std::cmp::Ordering;
+use std::collections::HashSet;
+use std::fmt::{Display, Formatter};
+use std::path::{Path, PathBuf};
+
+const INDEX_SIGNATURE: &[u8; 4] = b"DIRC";
+///Flag bit telling git to trust the entry without looking at the worktree file
+const FLAG_ASSUME_VALID: u16 = 0x8000;
+const FLAG_EXTENDED: u16 = 0x4000;
+const FLAG_STAGE_MASK: u16 = 0x3000;
+const FLAG_STAGE_SHIFT: u16 = 12;
+///Paths this long or longer store 0xFFF and rely on the NUL terminator
+const FLAG_NAME_MASK: u16 = 0x0FFF;
+const EXTENDED_SKIP_WORKTREE: u16 = 0x4000;
+const EXTENDED_INTENT_TO_ADD: u16 = 0x2000;
+
+const EXT_CACHE_TREE: &[u8; 4] = b"TREE";
+const EXT_SPLIT_INDEX: &[u8; 4] = b"link";
+///Offsets for loading entries on several threads, wrong as soon as the index is rewritten
+const EXT_ENTRY_OFFSETS: [&[u8; 4]; 2] = [b"EOIE", b"IEOT"];
+///Caches of worktree state that git rebuilds when missing but could trust wrongly once entries change
+const EXT_WORKTREE_CACHES: [&[u8; 4]; 2] = [b"UNTR", b"FSMN"];
+
+///File system details git records to notice when a worktree file changed without hashing it
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct StatData {
+    pub ctime: (u32, u32),
+    pub mtime: (u32, u32),
+    pub dev: u32,
+    pub ino: u32,
+    pub uid: u32,
+    pub gid: u32,
+    pub size: u32,
+}
+
+impl StatData {
+    ///Capture stat data the way git does, truncating every field to 32 bits
+    #[cfg(unix)]
+    pub fn from_metadata(meta: &std::fs::Metadata) -> StatData {
+        use std::os::unix::fs::MetadataExt;
+        StatData {
+            ctime: (meta.ctime() as u32, meta.ctime_nsec() as u32),
+            mtime: (meta.mtime() as u32, meta.mtime_nsec() as u32),
+            dev: meta.dev() as u32,
+            ino: meta.ino() as u32,
+            uid: meta.uid(),
+            gid: meta.gid(),
+            size: meta.size() as u32,
+        }
+    }
+
+    #[cfg(not(unix))]
+    pub fn from_metadata(meta: &std::fs::Metadata) -> StatData {
+        let since_epoch = |time: std::io::Result<std::time::SystemTime>| {
+            time.ok()
+                .and_then(|time| time.duration_since(std::time::UNIX_EPOCH).ok())
+                .map(|duration| (duration.as_secs() as u32, duration.subsec_nanos()))
+                .unwrap_or_default()
+        };
+        StatData {
+            ctime: since_epoch(meta.created()),
+            mtime: since_epoch(meta.modified()),
+            size: meta.len() as u32,
+            ..StatData::default()
+        }
+    }
+}
+
+///One staged file: its blob id, mode and merge stage along with cached stat data
+#[derive(Clone, Debug, PartialEq)]
+pub struct IndexEntry {
+    pub stat: StatData,
+    pub mode: u32,
+    pub sha: String,
+    pub stage: u8,
+    pub assume_valid: bool,
+    ///Sparse checkout leaves the worktree file out, needs index version 3
+    pub skip_worktree: bool,
+    ///Recorded by `add -N`, the path is tracked but its content not yet staged
+    pub intent_to_add: bool,
+    pub path: String,
+}
+
+impl IndexEntry {
+    pub fn new(path: &str, sha: &str, mode: u32, stat: StatData) -> IndexEntry {
+        IndexEntry {
+            stat,
+            mode,
+            sha: sha.to_owned(),
+            stage: 0,
+            assume_valid: false,
+            skip_worktree: false,
+            intent_to_add: false,
+            path: path.to_owned(),
+        }
+    }
+
+    ///Index order: path bytes first, then merge stage
+    fn compare(&self, path: &str, stage: u8) -> Ordering {
+        self.path.as_bytes().cmp(path.as_bytes()).then(self.stage.cmp(&stage))
+    }
+
+    ///Whether the file may have changed in the same instant the index was written, so that
+    ///matching stat data proves nothing and the content has to be compared
+    pub fn is_racy(&self, index_time: Option<(u32, u32)>) -> bool {
+        index_time.is_some_and(|time| time <= self.stat.mtime)
+    }
+
+    fn is_extended(&self) -> bool {
+        self.skip_worktree || self.intent_to_add
+    }
+}
+
+///Tree ids remembered for directories whose entries have not changed since the last write-tree
+#[derive(Clone, Debug, PartialEq)]
+pub struct CacheTree {
+    ///Directory name relative to the parent, empty for the root
+    pub name: String,
+    ///Number of index entries below this directory, negative once invalidated
+    pub entry_count: i32,
+    pub sha: Option<String>,
+    pub children: Vec<CacheTree>,
+}
+
+impl CacheTree {
+    ///Whether the stored tree id still matches the index
+    pub fn is_valid(&self) -> bool {
+        self.entry_count >= 0 && self.sha.is_some()
+    }
+
+    ///The node for a directory such as "dir/sub", or "" for the root, if it is still valid
+    pub fn find(&self, dir: &str) -> Option<&CacheTree> {
+        let mut node = self;
+        for name in dir.split('/').filter(|name| !name.is_empty()) {
+            node = node.children.iter().find(|child| child.name == name)?;
+        }
+        Some(node).filter(|node| node.is_valid())
+    }
+
+    ///Forget the tree ids of every directory containing the path
+    pub fn invalidate(&mut self, path: &str) {
+        self.entry_count = -1;
+        self.sha = None;
+        if let Some((dir, rest)) = path.split_once('/') {
+            if let Some(child) = self.children.iter_mut().find(|child| child.name == dir) {
+                child.invalidate(rest);
+            }
+        }
+    }
+
+    fn parse(data: &[u8], format: ObjectFormat) -> Result<CacheTree, IndexError> {
+        let mut reader = Reader { data, pos: 0 };
+        let tree = CacheTree::read(&mut reader, format)?;
+        Ok(tree)
+    }
+
+    fn read(reader: &mut Reader, format: ObjectFormat) -> Result<CacheTree, IndexError> {
+        let bad_tree = || IndexError::BadExtension("TREE".to_owned());
+        let name = String::from_utf8_lossy(reader.until(0)?).into_owned();
+        let entry_count = std::str::from_utf8(reader.until(b' ')?)
+            .ok()
+            .and_then(|count| count.parse::<i32>().ok())
+            .ok_or_else(bad_tree)?;
+        let child_count = std::str::from_utf8(reader.until(b'\n')?)
+            .ok()
+            .and_then(|count| count.parse::<usize>().ok())
+            .ok_or_else(bad_tree)?;
+        let sha = if entry_count >= 0 {
+            Some(bytes_to_hex(reader.take(format.raw_len())?))
+        } else {
+            None
+        };
+        let children = (0..child_count)
+            .map(|_| CacheTree::read(reader, format))
+            .collect::<Result<Vec<CacheTree>, IndexError>>()?;
+        Ok(CacheTree {
+            name,
+            entry_count,
+            sha,
+            children,
+        })
+    }
+
+    fn write(&self, bytes: &mut Vec<u8>, format: ObjectFormat) -> Result<(), IndexError> {
+        bytes.extend_from_slice(self.name.as_bytes());
+        bytes.push(0);
+        bytes.extend_from_slice(format!("{} {}\n", self.entry_count, self.children.len()).as_bytes());
+        if let (true, Some(sha)) = (self.entry_count >= 0, &self.sha) {
+            let raw = raw_object_id(sha, format).ok_or_else(|| IndexError::BadObjectId(sha.clone(), "cache tree".to_owned()))?;
+            bytes.extend_from_slice(&raw);
+        }
+        for child in &self.children {
+            child.write(bytes, format)?;
+        }
+        Ok(())
+    }
+}
+
+///An optional extension kept byte for byte so other git tools still find it
+#[derive(Clone, Debug, PartialEq)]
+struct IndexExtension {
+    signature: [u8; 4],
+    data: Vec<u8>,
+}
+
+///The `link` extension of a split index: the shared base file and how this file changes it
+#[derive(Clone, Debug, PartialEq)]
+struct SplitLink {
+    base: String,
+    deleted: Vec<usize>,
+    replaced: Vec<usize>,
+}
+
+impl SplitLink {
+    fn parse(data: &[u8], format: ObjectFormat) -> Result<SplitLink, IndexError> {
+        let mut reader = Reader { data, pos: 0 };
+        let base = bytes_to_hex(reader.take(format.raw_len())?);
+        let mut link = SplitLink {
+            base,
+            deleted: Vec::new(),
+            replaced: Vec::new(),
+        };
+        if reader.pos < data.len() {
+            link.deleted = read_ewah(&mut reader)?;
+            link.replaced = read_ewah(&mut reader)?;
+        }
+        Ok(link)
+    }
+}
+
+///The staging area, kept sorted the way git expects to find it on disk
+#[derive(Clone, Debug, PartialEq)]
+pub struct GitIndex {
+    version: u32,
+    entries: Vec<IndexEntry>,
+    cache_tree: Option<CacheTree>,
+    extensions: Vec<IndexExtension>,
+    split: Option<SplitLink>,
+    ///Modification time of the file this index was loaded from, to spot racily clean entries
+    timestamp: Option<(u32, u32)>,
+    ///Entries that were racily clean when loaded and have not been staged or refreshed since
+    racy: HashSet<String>,
+}
+
+impl Default for GitIndex {
+    fn default() -> Self {
+        GitIndex::new()
+    }
+}
+
+impl GitIndex {
+    pub fn new() -> GitIndex {
+        GitIndex {
+            version: 2,
+            entries: Vec::new(),
+            cache_tree: None,
+            extensions: Vec::new(),
+            split: None,
+            timestamp: None,
+            racy: HashSet::new(),
+        }
+    }
+
+    ///Read the repository's index, a missing file is an empty index in the configured version
+    pub fn load(repo: &GitRepository) -> Result<GitIndex, IndexError> {
+        let path = index_path(repo);
+        let bytes = match std::fs::read(&path) {
+            Ok(bytes) => bytes,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => {
+                let mut index = GitIndex::new();
+                index.version = default_version(repo);
+                return Ok(index);
+            }
+            Err(err) => return Err(err.into()),
+        };
+        let format = repo.object_format();
+        let mut index = GitIndex::parse(&bytes, format)?;
+        index.timestamp = std::fs::metadata(&path).ok().map(|meta| StatData::from_metadata(&meta).mtime);
+        if let Some(link) = index.split.take() {
+            if link.base != format.null_id() {
+                let shared_bytes = std::fs::read(repo_path(repo, format!("sharedindex.{}", link.base)))?;
+                let shared = GitIndex::parse(&shared_bytes, format)?;
+                if bytes_to_hex(&shared_bytes[shared_bytes.len() - format.raw_len()..]) != link.base {
+                    return Err(IndexError::BadSplitIndex(link.base));
+                }
+                index.merge_shared(shared, link)?;
+            }
+        }
+        index.racy = index
+            .entries
+            .iter()
+            .filter(|entry| entry.is_racy(index.timestamp))
+            .map(|entry| entry.path.clone())
+            .collect();
+        Ok(index)
+    }
+
+    ///Write the index through `index.lock` so other readers only ever see a complete file.
+    ///A split index is written back whole, which git reads just as well
+    pub fn save(&self, repo: &GitRepository) -> Result<(), IndexError> {
+        self.save_locked(repo, GitIndex::lock(repo)?)
+    }
+
+    ///Take `index.lock` before loading, so that no other process writes the index in between
+    pub(crate) fn lock(repo: &GitRepository) -> Result<LockFile, IndexError> {
+        Ok(LockFile::acquire(index_path(repo))?)
+    }
+
+    ///Write the index through a lock taken with `lock`
+    pub(crate) fn save_locked(&self, repo: &GitRepository, mut lock: LockFile) -> Result<(), IndexError> {
+        lock.write_all(&self.to_bytes(repo.object_format())?)?;
+        lock.commit()?;
+        Ok(())
+    }
+
+    ///When the loaded index file was last written, `None` for one not read from disk
+    pub fn timestamp(&self) -> Option<(u32, u32)> {
+        self.timestamp
+    }
+
+    pub fn version(&self) -> u32 {
+        self.version
+    }
+
+    ///Choose the file format for the next save: 2 and 3 pad entries, 4 prefix compresses paths
+    pub fn set_version(&mut self, version: u32) -> Result<(), IndexError> {
+        if !(2..=4).contains(&version) {
+            return Err(IndexError::UnsupportedVersion(version));
+        }
+        self.version = version;
+        Ok(())
+    }
+
+    pub fn entries(&self) -> &[IndexEntry] {
+        &self.entries
+    }
+
+    pub fn cache_tree(&self) -> Option<&CacheTree> {
+        self.cache_tree.as_ref()
+    }
+
+    ///Replace the cached tree ids, normally right after write-tree computed them
+    pub fn set_cache_tree(&mut self, cache_tree: Option<CacheTree>) {
+        self.cache_tree = cache_tree;
+    }
+
+    ///Look up a path at the given merge stage, 0 for normal entries
+    pub fn get(&self, path: &str, stage: u8) -> Option<&IndexEntry> {
+        self.search(path, stage).ok().map(|pos| &self.entries[pos])
+    }
+
+    ///Insert an entry in sorted position, replacing any entry for the same path and stage
+    pub fn add(&mut self, entry: IndexEntry) {
+        self.entries_changed(&entry.path);
+        self.racy.remove(&entry.path);
+        self.insert(entry);
+    }
+
+    ///Stage a file the way `add` does: conflict stages of the path go away, as do entries a
+    ///file and a directory of the same name would collide with
+    pub fn add_replacing(&mut self, entry: IndexEntry) {
+        let path = entry.path.clone();
+        let below = format!("{}/", path);
+        self.entries.retain(|existing| {
+            existing.path != path
+                && !existing.path.starts_with(&below)
+                && !(path.starts_with(&existing.path) && path.as_bytes().get(existing.path.len()) == Some(&b'/'))
+        });
+        self.add(entry);
+    }
+
+    ///Record fresh stat data for an entry whose content is unchanged, leaving cached trees valid
+    pub fn refresh_stat(&mut self, path: &str, stage: u8, stat: StatData) {
+        if let Ok(position) = self.search(path, stage) {
+            self.entries[position].stat = stat;
+            self.racy.remove(path);
+        }
+    }
+
+    ///Drop every stage of a path, returning whether anything was removed
+    pub fn remove(&mut self, path: &str) -> bool {
+        let before = self.entries.len();
+        self.entries.retain(|entry| entry.path != path);
+        let removed = before != self.entries.len();
+        if removed {
+            self.entries_changed(path);
+        }
+        removed
+    }
+
+    fn insert(&mut self, entry: IndexEntry) {
+        match self.search(&entry.path, entry.stage) {
+            Ok(pos) => self.entries[pos] = entry,
+            Err(pos) => self.entries.insert(pos, entry),
+        }
+    }
+
+    fn search(&self, path: &str, stage: u8) -> Result<usize, usize> {
+        self.entries.binary_search_by(|entry| entry.compare(path, stage))
+    }
+
+    fn entries_changed(&mut self, path: &str) {
+        if let Some(tree) = self.cache_tree.as_mut() {
+            tree.invalidate(path);
+        }
+        self.extensions
+            .retain(|extension| !EXT_WORKTREE_CACHES.contains(&&extension.signature));
+    }
+
+    ///Apply this split index on top of its shared base: drop deleted entries, swap in
+    ///replacements, which come first and without names, then add the remaining new entries
+    fn merge_shared(&mut self, shared: GitIndex, link: SplitLink) -> Result<(), IndexError> {
+        let mut own_entries = std::mem::take(&mut self.entries).into_iter();
+        for (pos, base_entry) in shared.entries.into_iter().enumerate() {
+            if link.deleted.binary_search(&pos).is_ok() {
+                continue;
+            }
+            if link.replaced.binary_search(&pos).is_ok() {
+                let mut replacement = own_entries.next().ok_or_else(|| IndexError::BadSplitIndex(link.base.clone()))?;
+                replacement.path = base_entry.path;
+                self.entries.push(replacement);
+            } else {
+                self.entries.push(base_entry);
+            }
+        }
+        for entry in own_entries {
+            self.insert(entry);
+        }
+        Ok(())
+    }
+
+    pub fn parse(bytes: &[u8], format: ObjectFormat) -> Result<GitIndex, IndexError> {
+        let raw_len = format.raw_len();
+        if bytes.len() < 12 + raw_len {
+            return Err(IndexError::Truncated());
+        }
+        let (body, checksum) = bytes.split_at(bytes.len() - raw_len);
+        //index.skipHash writes zeros instead of a checksum
+        if checksum.iter().any(|byte| *byte != 0) && format.digest(body) != checksum {
+            return Err(IndexError::BadChecksum());
+        }
+
+        let mut reader = Reader { data: body, pos: 0 };
+        if reader.take(4)? != INDEX_SIGNATURE {
+            return Err(IndexError::BadSignature());
+        }
+        let mut index = GitIndex::new();
+        index.set_version(reader.u32()?)?;
+        let count = reader.u32()? as usize;
+        index.entries.reserve(count);
+        for _ in 0..count {
+            let start = reader.pos;
+            let previous_path = index.entries.last().map_or("", |entry| entry.path.as_str());
+            let entry = read_entry(&mut reader, format, index.version, previous_path.as_bytes())?;
+            if index.version < 4 {
+                //Entries are NUL padded to a multiple of eight bytes
+                let used = reader.pos - start;
+                reader.take((8 - used % 8) % 8)?;
+            }
+            index.entries.push(entry);
+        }
+
+        while reader.pos < body.len() {
+            let signature = reader.take(4)?;
+            let size = reader.u32()? as usize;
+            let data = reader.take(size)?;
+            match signature {
+                signature if signature == EXT_CACHE_TREE => index.cache_tree = Some(CacheTree::parse(data, format)?),
+                signature if signature == EXT_SPLIT_INDEX => index.split = Some(SplitLink::parse(data, format)?),
+                signature if EXT_ENTRY_OFFSETS.iter().any(|offsets| signature == *offsets) => {}
+                //Optional extensions start with an uppercase letter and may be kept without understanding them
+                signature if signature[0].is_ascii_uppercase() => index.extensions.push(IndexExtension {
+                    signature: [signature[0], signature[1], signature[2], signature[3]],
+                    data: data.to_vec(),
+                }),
+                _ => return Err(IndexError::UnknownExtension(String::from_utf8_lossy(signature).into_owned())),
+            }
+        }
+
+        Ok(index)
+    }
+
+    ///Serialize the index, failing on an object id that does not fit the format before any
+    ///byte reaches the disk
+    pub fn to_bytes(&self, format: ObjectFormat) -> Result<Vec<u8>, IndexError> {
+        //Extended flags only exist from version 3 on, so git upgrades a version 2 index that needs them
+        let version = if self.version == 2 && self.entries.iter().any(IndexEntry::is_extended) {
+            3
+        } else {
+            self.version
+        };
+        let mut bytes = Vec::new();
+        bytes.extend_from_slice(INDEX_SIGNATURE);
+        bytes.extend_from_slice(&version.to_be_bytes());
+        bytes.extend_from_slice(&(self.entries.len() as u32).to_be_bytes());
+        let mut previous_path = "";
+        for entry in &self.entries {
+            let start = bytes.len();
+            //Like git, a racily clean entry loses its size so that a later write of the index,
+            //which makes it look settled, cannot hide a change made in the same instant
+            if self.racy.contains(&entry.path) {
+                let mut smudged = entry.clone();
+                smudged.stat.size = 0;
+                write_entry(&mut bytes, &smudged, format)?;
+            } else {
+                write_entry(&mut bytes, entry, format)?;
+            }
+            if version < 4 {
+                bytes.extend_from_slice(entry.path.as_bytes());
+                let used = bytes.len() - start;
+                //Always at least one NUL to terminate the path
+                bytes.extend(std::iter::repeat_n(0, 8 - used % 8));
+            } else {
+                let common = previous_path
+                    .bytes()
+                    .zip(entry.path.bytes())
+                    .take_while(|(previous, current)| previous == current)
+                    .count();
+                write_varint(&mut bytes, (previous_path.len() - common) as u64);
+                bytes.extend_from_slice(&entry.path.as_bytes()[common..]);
+                bytes.push(0);
+            }
+            previous_path = &entry.path;
+        }
+
+        if let Some(tree) = &self.cache_tree {
+            let mut data = Vec::new();
+            tree.write(&mut data, format)?;
+            write_extension(&mut bytes, EXT_CACHE_TREE, &data);
+        }
+        for extension in &self.extensions {
+            write_extension(&mut bytes, &extension.signature, &extension.data);
+        }
+        let checksum = format.digest(&bytes);
+        bytes.extend_from_slice(&checksum);
+        Ok(bytes)
+    }
+}
+
+///Where the index lives, GIT_INDEX_FILE overrides the per worktree default
+pub(crate) fn index_path(repo: &GitRepository) -> PathBuf {
+    match std::env::var_os("GIT_INDEX_FILE") {
+        Some(path) if !path.is_empty() => Path::new(&path).to_path_buf(),
+        _ => repo_path(repo, "index"),
+    }
+}
+
+///Version for a brand new index: GIT_INDEX_VERSION, then index.version, then feature.manyFiles
+fn default_version(repo: &GitRepository) -> u32 {
+    let from_env = std::env::var("GIT_INDEX_VERSION").ok().and_then(|version| version.parse::<i64>().ok());
+    let config = repo.config();
+    let version = from_env.or_else(|| config.get_int("index.version").ok().flatten());
+    match version {
+        Some(version) if (2..=4).contains(&version) => version as u32,
+        _ if config.get_bool("feature.manyfiles").ok().flatten() == Some(true) => 4,
+        _ => 2,
+    }
+}
+
+struct Reader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, len: usize) -> Result<&'a [u8], IndexError> {
+        if len > self.data.len() - self.pos {
+            return Err(IndexError::Truncated());
+        }
+        let slice = &self.data[self.pos..self.pos + len];
+        self.pos += len;
+        Ok(slice)
+    }
+
+    ///Bytes up to a terminator, consuming the terminator too
+    fn until(&mut self, terminator: u8) -> Result<&'a [u8], IndexError> {
+        let rest = &self.data[self.pos..];
+        let end = rest.iter().position(|byte| *byte == terminator).ok_or_else(IndexError::Truncated)?;
+        self.pos += end + 1;
+        Ok(&rest[..end])
+    }
+
+    fn u16(&mut self) -> Result<u16, IndexError> {
+        let bytes = self.take(2)?;
+        Ok(u16::from_be_bytes([bytes[0], bytes[1]]))
+    }
+
+    fn u32(&mut self) -> Result<u32, IndexError> {
+        let bytes = self.take(4)?;
+        Ok(u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+    }
+
+    fn u64(&mut self) -> Result<u64, IndexError> {
+        let high = self.u32()? as u64;
+        let low = self.u32()? as u64;
+        Ok(high << 32 | low)
+    }
+
+    ///Git's offset varint: seven bits per byte, high bit set on all but the last
+    fn varint(&mut self) -> Result<u64, IndexError> {
+        let mut byte = self.take(1)?[0];
+        let mut value = (byte & 0x7f) as u64;
+        while byte & 0x80 != 0 {
+            byte = self.take(1)?[0];
+            value = ((value + 1) << 7) | (byte & 0x7f) as u64;
+        }
+        Ok(value)
+    }
+}
+
+fn write_varint(bytes: &mut Vec<u8>, mut value: u64) {
+    let mut encoded = vec![(value & 0x7f) as u8];
+    value >>= 7;
+    while value != 0 {
+        value -= 1;
+        encoded.push(0x80 | (value & 0x7f) as u8);
+        value >>= 7;
+    }
+    bytes.extend(encoded.iter().rev());
+}
+
+///Positions of the set bits in a run length compressed EWAH bitmap
+fn read_ewah(reader: &mut Reader) -> Result<Vec<usize>, IndexError> {
+    let bit_count = reader.u32()? as usize;
+    let word_count = reader.u32()? as usize;
+    //The counts come from the file, so check them against what is left before allocating
+    if word_count > (reader.data.len() - reader.pos) / 8 {
+        return Err(IndexError::Truncated());
+    }
+    let words = (0..word_count).map(|_| reader.u64()).collect::<Result<Vec<u64>, IndexError>>()?;
+    let _last_marker = reader.u32()?;
+    let corrupt = || IndexError::BadExtension("link".to_owned());
+    let bit_limit = bit_count.div_ceil(64) * 64;
+
+    let mut bits = Vec::new();
+    let mut position = 0;
+    let mut words = words.into_iter();
+    //Each marker word holds a run of identical words followed by a count of literal words
+    while let Some(marker) = words.next() {
+        let run_bit = marker & 1 != 0;
+        let run_words = ((marker >> 1) & 0xffff_ffff) as usize;
+        let literal_words = (marker >> 33) as usize;
+        if run_words > (bit_limit - position) / 64 || literal_words > words.len() {
+            return Err(corrupt());
+        }
+        if run_bit {
+            bits.extend(position..position + run_words * 64);
+        }
+        position += run_words * 64;
+        for _ in 0..literal_words {
+            let literal = words.next().ok_or_else(corrupt)?;
+            if position >= bit_limit {
+                return Err(corrupt());
+            }
+            bits.extend((0..64).filter(|bit| literal >> bit & 1 != 0).map(|bit| position + bit));
+            position += 64;
+        }
+    }
+    Ok(bits)
+}
+
+fn read_entry(reader: &mut Reader, format: ObjectFormat, version: u32, previous_path: &[u8]) -> Result<IndexEntry, IndexError> {
+    let stat_fields = (0..10).map(|_| reader.u32()).collect::<Result<Vec<u32>, IndexError>>()?;
+    let stat = StatData {
+        ctime: (stat_fields[0], stat_fields[1]),
+        mtime: (stat_fields[2], stat_fields[3]),
+        dev: stat_fields[4],
+        ino: stat_fields[5],
+        uid: stat_fields[7],
+        gid: stat_fields[8],
+        size: stat_fields[9],
+    };
+    let mode = stat_fields[6];
+    let sha = bytes_to_hex(reader.take(format.raw_len())?);
+    let flags = reader.u16()?;
+    let extended = if flags & FLAG_EXTENDED != 0 { reader.u16()? } else { 0 };
+
+    let path = if version >= 4 {
+        //Only the part that differs from the previous path is stored
+        let strip = reader.varint()? as usize;
+        if strip > previous_path.len() {
+            return Err(IndexError::BadPath(String::from_utf8_lossy(previous_path).into_owned()));
+        }
+        let suffix = reader.until(0)?;
+        let mut path = previous_path[..previous_path.len() - strip].to_vec();
+        path.extend_from_slice(suffix);
+        String::from_utf8_lossy(&path).into_owned()
+    } else {
+        let name_len = (flags & FLAG_NAME_MASK) as usize;
+        let name = if name_len < FLAG_NAME_MASK as usize {
+            let name = reader.take(name_len)?;
+            if reader.take(1)? != [0] {
+                return Err(IndexError::BadPath(String::from_utf8_lossy(name).into_owned()));
+            }
+            name
+        } else {
+            reader.until(0)?
+        };
+        String::from_utf8_lossy(name).into_owned()
+    };
+    Ok(IndexEntry {
+        stat,
+        mode,
+        sha,
+        stage: ((flags & FLAG_STAGE_MASK) >> FLAG_STAGE_SHIFT) as u8,
+        assume_valid: flags & FLAG_ASSUME_VALID != 0,
+        skip_worktree: extended & EXTENDED_SKIP_WORKTREE != 0,
+        intent_to_add: extended & EXTENDED_INTENT_TO_ADD != 0,
+        path,
+    })
+}
+
+fn write_entry(bytes: &mut Vec<u8>, entry: &IndexEntry, format: ObjectFormat) -> Result<(), IndexError> {
+    let sha = raw_object_id(&entry.sha, format).ok_or_else(|| IndexError::BadObjectId(entry.sha.clone(), entry.path.clone()))?;
+    let stat = &entry.stat;
+    for field in [
+        stat.ctime.0,
+        stat.ctime.1,
+        stat.mtime.0,
+        stat.mtime.1,
+        stat.dev,
+        stat.ino,
+        entry.mode,
+        stat.uid,
+        stat.gid,
+        stat.size,
+    ] {
+        bytes.extend_from_slice(&field.to_be_bytes());
+    }
+    bytes.extend_from_slice(&sha);
+    let mut flags = (entry.path.len().min(FLAG_NAME_MASK as usize) as u16) | ((entry.stage as u16 & 0x3) << FLAG_STAGE_SHIFT);
+    if entry.assume_valid {
+        flags |= FLAG_ASSUME_VALID;
+    }
+    if entry.is_extended() {
+        flags |= FLAG_EXTENDED;
+    }
+    bytes.extend_from_slice(&flags.to_be_bytes());
+    if entry.is_extended() {
+        let mut extended = 0;
+        if entry.skip_worktree {
+            extended |= EXTENDED_SKIP_WORKTREE;
+        }
+        if entry.intent_to_add {
+            extended |= EXTENDED_INTENT_TO_ADD;
+        }
+        bytes.extend_from_slice(&extended.to_be_bytes());
+    }
+    Ok(())
+}
+
+fn write_extension(bytes: &mut Vec<u8>, signature: &[u8; 4], data: &[u8]) {
+    bytes.extend_from_slice(signature);
+    bytes.extend_from_slice(&(data.len() as u32).to_be_bytes());
+    bytes.extend_from_slice(data);
+}
+
+#[derive(Debug)]
+pub enum IndexError {
+    Io(std::io::Error),
+    Truncated(),
+    BadSignature(),
+    BadChecksum(),
+    UnsupportedVersion(u32),
+    UnknownExtension(String),
+    BadExtension(String),
+    BadSplitIndex(String),
+    BadPath(String),
+    BadObjectId(String, String),
+}
+
+impl Display for IndexError {
+    fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
+        match self {
+            IndexError::Io(err) => {write!(f, "Failed to access index: {}", err)},
+            IndexError::Truncated() => {write!(f, "index file is truncated")},
+            IndexError::BadSignature() => {write!(f, "bad signature in index file")},
+            IndexError::BadChecksum() => {write!(f, "index file corrupt: checksum mismatch")},
+            IndexError::UnsupportedVersion(version) => {write!(f, "index file version {} is not supported", version)},
+            IndexError::UnknownExtension(name) => {write!(f, "index uses {} extension, which we do not understand", name)},
+            IndexError::BadExtension(name) => {write!(f, "index {} extension is corrupt", name)},
+            IndexError::BadSplitIndex(base) => {write!(f, "broken split index, shared index {} does not match", base)},
+            IndexError::BadPath(path) => {write!(f, "invalid path '{}' in index", path)},
+            IndexError::BadObjectId(sha, path) => {write!(f, "invalid object id '{}' for '{}' in index", sha, path)},
+        }
+    }
+}
+
+impl From<std::io::Error> for IndexError {
+    fn from(err: std::io::Error) -> Self {
+        IndexError::Io(err)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lib::get_test_dir;
+    use crate::lib::objects::git_config::ConfigFile;
+    use std::path::PathBuf;
+
+    fn test_source(name: &str) -> PathBuf {
+        std::env::current_dir()
+            .unwrap()
+            .join(["src", "test", name].iter().collect::<PathBuf>())
+    }
+
+    fn entry_paths(index: &GitIndex) -> Vec<&str> {
+        index.entries().iter().map(|entry| entry.path.as_str()).collect()
+    }
+
+    #[test]
+    fn reads_git_index() {
+        let bytes = std::fs::read(test_source("index_v2")).expect("Failed to read test index");
+        let index = GitIndex::parse(&bytes, ObjectFormat::Sha1).expect("Failed to parse index");
+        let paths = entry_paths(&index);
+        assert!(paths == ["a.txt", "dir/b.txt", "link", "run.sh"], "Paths were {:?}", paths);
+        let link = index.get("link", 0).unwrap();
+        assert!(link.mode == 0o120000 && link.sha == "8d14cbf983b3fad683171c9418998d9f68340823");
+        assert!(index.get("run.sh", 0).unwrap().mode == 0o100755);
+        assert!(index.to_bytes(ObjectFormat::Sha1).unwrap() == bytes, "Round trip should be byte identical");
+
+        let mut corrupt = bytes.clone();
+        corrupt[20] ^= 1;
+        assert!(matches!(GitIndex::parse(&corrupt, ObjectFormat::Sha1), Err(IndexError::BadChecksum())));
+    }
+
+    #[test]
+    fn reads_version_4_with_extensions() {
+        let bytes = std::fs::read(test_source("index_v4")).expect("Failed to read test index");
+        let mut index = GitIndex::parse(&bytes, ObjectFormat::Sha1).expect("Failed to parse index");
+        assert!(index.version() == 4);
+        let paths = entry_paths(&index);
+        assert!(paths == ["a.txt", "dir/b.txt", "dir/sub/c.txt", "dir/sub/d.txt"], "Paths were {:?}", paths);
+        assert!(index.get("dir/b.txt", 0).unwrap().skip_worktree);
+        assert!(index.to_bytes(ObjectFormat::Sha1).unwrap() == bytes, "Round trip should be byte identical");
+
+        let tree = index.cache_tree().expect("TREE extension missing");
+        let dir = tree.find("dir").expect("dir should be cached");
+        assert!(dir.sha.as_deref() == Some("84ac3912465890e091717d778bee05d6638a019c") && dir.entry_count == 3);
+
+        index.add(IndexEntry::new("dir/sub/e.txt", &ObjectFormat::Sha1.null_id(), 0o100644, StatData::default()));
+        let tree = index.cache_tree().unwrap();
+        assert!(tree.find("dir/sub").is_none() && tree.find("").is_none(), "Changed directories should be invalidated");
+        assert!(index.extensions.is_empty(), "Untracked cache should be dropped once entries change");
+
+        index.set_version(2).unwrap();
+        let reread = GitIndex::parse(&index.to_bytes(ObjectFormat::Sha1).unwrap(), ObjectFormat::Sha1).unwrap();
+        assert!(reread.version() == 3, "Skip worktree entries need version 3");
+        assert!(reread.entries() == index.entries());
+    }
+
+    #[test]
+    fn reads_split_index() {
+        let test_dir = get_test_dir("reads_split_index");
+        let _ = std::fs::remove_dir_all(&test_dir);
+        std::fs::create_dir_all(test_dir.join(".git")).expect("Unable to create test directory");
+        for file in std::fs::read_dir(test_source("split_index")).unwrap() {
+            let file = file.unwrap();
+            std::fs::copy(file.path(), test_dir.join(".git").join(file.file_name())).unwrap();
+        }
+        let repo = GitRepository::new(test_dir.clone(), test_dir.join(".git"), ConfigFile::new());
+
+        let index = GitIndex::load(&repo).expect("Failed to load split index");
+        let paths = entry_paths(&index);
+        assert!(paths == ["a.txt", "b.txt", "c.txt", "e.txt", "f.txt"], "Paths were {:?}", paths);
+        assert!(index.get("b.txt", 0).unwrap().sha == "223b7836fb19fdf64ba2d3cd6173c6a283141f78");
+
+        index.save(&repo).expect("Failed to save index");
+        let unsplit = GitIndex::parse(&std::fs::read(test_dir.join(".git").join("index")).unwrap(), ObjectFormat::Sha1).unwrap();
+        assert!(unsplit.entries() == index.entries());
+    }
+
+    #[test]
+    fn saves_sorted_entries() {
+        let test_dir = get_test_dir("saves_sorted_entries");
+        let _ = std::fs::remove_dir_all(&test_dir);
+        std::fs::create_dir_all(test_dir.join(".git")).expect("Unable to create test directory");
+        let repo = GitRepository::new(test_dir.clone(), test_dir.join(".git"), ConfigFile::new());
+
+        let mut index = GitIndex::new();
+        let sha = ObjectFormat::Sha1.null_id();
+        for path in ["b", "a/c", "a-b", "a"] {
+            index.add(IndexEntry::new(path, &sha, 0o100644, StatData::default()));
+        }
+        index.add(IndexEntry::new("a", &sha, 0o100755, StatData::default()));
+        index.save(&repo).expect("Failed to save index");
+        assert!(!test_dir.join(".git").join("index.lock").exists());
+
+        let loaded = GitIndex::load(&repo).expect("Failed to load index");
+        let paths = entry_paths(&loaded);
+        assert!(paths == ["a", "a-b", "a/c", "b"], "Paths were {:?}", paths);
+        assert!(loaded.get("a", 0).unwrap().mode == 0o100755);
+    }
+
+    #[test]
+    fn refuses_to_save_malformed_ids() {
+        let test_dir = get_test_dir("refuses_to_save_malformed_ids");
+        let _ = std::fs::remove_dir_all(&test_dir);
+        std::fs::create_dir_all(test_dir.join(".git")).expect("Unable to create test directory");
+        let repo = GitRepository::new(test_dir.clone(), test_dir.join(".git"), ConfigFile::new());
+        let null_id = ObjectFormat::Sha1.null_id();
+        let mut index = GitIndex::new();
+        index.add(IndexEntry::new("a", &null_id, 0o100644, StatData::default()));
+        index.save(&repo).expect("Failed to save index");
+        let saved = std::fs::read(test_dir.join(".git").join("index")).unwrap();
+
+        for sha in [&null_id[..38], "zz", "é0", &ObjectFormat::Sha256.null_id()] {
+            let mut bad = index.clone();
+            bad.add(IndexEntry::new("b", sha, 0o100644, StatData::default()));
+            assert!(matches!(bad.save(&repo), Err(IndexError::BadObjectId(_, _))), "Saved id {}", sha);
+            assert!(std::fs::read(test_dir.join(".git").join("index")).unwrap() == saved);
+            assert!(!test_dir.join(".git").join("index.lock").exists());
+        }
+    }
+
+    #[test]
+    fn reads_ewah_bitmaps_defensively() {
+        let bitmap = |bit_count: u32, words: &[u64], word_count: u32| {
+            let mut bytes = Vec::new();
+            bytes.extend_from_slice(&bit_count.to_be_bytes());
+            bytes.extend_from_slice(&word_count.to_be_bytes());
+            for word in words {
+                bytes.extend_from_slice(&word.to_be_bytes());
+            }
+            bytes.extend_from_slice(&0u32.to_be_bytes());
+            bytes
+        };
+        //One run of a set word, then one literal word with bits 0 and 2 set
+        let marker = 1 | 1 << 1 | 1 << 33;
+        let bytes = bitmap(70, &[marker, 0b101], 2);
+        let bits = read_ewah(&mut Reader { data: &bytes, pos: 0 }).expect("Error reading bitmap");
+        assert!(bits.len() == 66 && bits[63] == 63 && bits[64..] == [64, 66], "Bits were {:?}", &bits[60..]);
+
+        let bytes = bitmap(70, &[1 | 0xffff_ffff << 1], 1);
+        assert!(matches!(read_ewah(&mut Reader { data: &bytes, pos: 0 }), Err(IndexError::BadExtension(_))), "Runs past the bit count");
+        let bytes = bitmap(70, &[1 << 34], 1);
+        assert!(matches!(read_ewah(&mut Reader { data: &bytes, pos: 0 }), Err(IndexError::BadExtension(_))), "Literals past the words");
+        let bytes = bitmap(70, &[marker], u32::MAX);
+        assert!(matches!(read_ewah(&mut Reader { data: &bytes, pos: 0 }), Err(IndexError::Truncated())), "Word count past the data");
+    }
+}
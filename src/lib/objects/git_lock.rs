@@ -0,0 +1,89 @@
+use std::fs::{File, OpenOptions};
+use std::io::{ErrorKind, Write};
+use std::path::{Path, PathBuf};
+
+///Exclusive `<path>.lock` file that replaces its target on commit and is removed if dropped first
+pub(crate) struct LockFile {
+    target: PathBuf,
+    lock_path: PathBuf,
+    file: Option<File>,
+}
+
+impl LockFile {
+    ///Create the lock file, failing the way git does when another process already holds it
+    pub fn acquire<P: AsRef<Path>>(target: P) -> Result<LockFile, std::io::Error> {
+        let target = target.as_ref().to_path_buf();
+        let mut lock_name = target.as_os_str().to_owned();
+        lock_name.push(".lock");
+        let lock_path = PathBuf::from(lock_name);
+        let file = OpenOptions::new().write(true).create_new(true).open(&lock_path).map_err(|err| {
+            if err.kind() == ErrorKind::AlreadyExists {
+                std::io::Error::new(
+                    ErrorKind::AlreadyExists,
+                    format!(
+                        "Unable to create '{}': File exists. Another git process seems to be running in this repository",
+                        lock_path.display()
+                    ),
+                )
+            } else {
+                err
+            }
+        })?;
+        Ok(LockFile {
+            target,
+            lock_path,
+            file: Some(file),
+        })
+    }
+
+    pub fn write_all(&mut self, bytes: &[u8]) -> Result<(), std::io::Error> {
+        match self.file.as_mut() {
+            Some(file) => file.write_all(bytes),
+            None => Err(std::io::Error::other("lock file already released")),
+        }
+    }
+
+    ///Flush the new content and rename it over the target
+    pub fn commit(mut self) -> Result<(), std::io::Error> {
+        if let Some(file) = self.file.take() {
+            file.sync_all()?;
+        }
+        if let Err(err) = std::fs::rename(&self.lock_path, &self.target) {
+            let _ = std::fs::remove_file(&self.lock_path);
+            return Err(err);
+        }
+        Ok(())
+    }
+}
+
+impl Drop for LockFile {
+    fn drop(&mut self) {
+        if self.file.take().is_some() {
+            let _ = std::fs::remove_file(&self.lock_path);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lib::get_test_dir;
+
+    #[test]
+    fn lock_is_exclusive() {
+        let test_dir = get_test_dir("lock_is_exclusive");
+        std::fs::create_dir_all(&test_dir).expect("Unable to create test directory");
+        let target = test_dir.join("file");
+        let _ = std::fs::remove_file(test_dir.join("file.lock"));
+
+        let mut lock = LockFile::acquire(&target).expect("Failed to take lock");
+        assert!(LockFile::acquire(&target).is_err(), "Second lock should fail");
+        lock.write_all(b"new").unwrap();
+        lock.commit().expect("Failed to commit lock");
+        assert!(std::fs::read(&target).unwrap() == b"new");
+
+        let dropped = LockFile::acquire(&target).expect("Lock should be free after commit");
+        drop(dropped);
+        assert!(!test_dir.join("file.lock").exists(), "Dropped lock should be removed");
+    }
+}
@@ -1,5 +1,7 @@
-use crate::lib::objects::git_repository::GitRepository;
-use crate::lib::parsing::encoding::{object_file_format,hash_bytes_as_string};
+use crate::lib::objects::git_repository::{GitRepository, repo_path};
+use crate::lib::objects::git_pack::read_packed_object;
+use crate::lib::objects::git_workdir::read_worktree_file;
+use crate::lib::parsing::encoding::{object_file_format,hash_bytes_as_string,compress_bytes};
 use crate::lib::parsing::decoding::{bytes_to_object,ObjectParseError,read_repo_file,GitNameFormat, find_object};
 use std::{fmt::{Display,Formatter}, path::Path, str::FromStr, rc::Rc};
 
@@ -11,13 +13,13 @@ pub enum ObjectType {
     Tag,
 }
 
-impl ToString for ObjectType {
-    fn to_string(&self) -> String {
+impl Display for ObjectType {
+    fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
         match self {
-            ObjectType::Commit => "Commit".to_owned(),
-            ObjectType::Tag => "Tag".to_owned(),
-            ObjectType::Tree => "Tree".to_owned(),
-            ObjectType::Blob => "Blob".to_owned(),
+            ObjectType::Commit => write!(f, "commit"),
+            ObjectType::Tag => write!(f, "tag"),
+            ObjectType::Tree => write!(f, "tree"),
+            ObjectType::Blob => write!(f, "blob"),
         }
     }
 }
@@ -62,53 +64,52 @@ impl GitObject {
         }
     }
 
+    ///Content is kept in its serialized form, trees and commits are parsed on demand
     pub fn serialize(&self) -> &[u8] {
-        match self.kind {
-            ObjectType::Blob => {
-                &self.content },
-            _ => {
-                panic!("Placeholder until I have all implementations done")
-            }
-        }
-        
+        &self.content
     }
 
-    pub fn deserialize(&mut self, data: Vec<u8>) -> () {
-        match self.kind {
-            ObjectType::Blob => {
-                self.content = data;
-            },
-            _ => {
-                panic!("Placeholder until I have all implementations done")
-            }
-        }
+    #[allow(dead_code)]
+    pub fn deserialize(&mut self, data: Vec<u8>) {
+        self.content = data;
     }
 
     pub fn kind(&self) -> &ObjectType {
         &self.kind
     }
 
+    #[allow(dead_code)]
     pub fn repo(&self) -> &Rc<GitRepository> {
         &self.repo
     }
 
+    #[allow(dead_code)]
     pub fn content(&self) -> &[u8] {
         &self.content
     }
 
-    ///Write this object to the repo
-    pub fn write_to_repo(&self) -> Result<(), ObjectError> {
-        
-        let formatted_content = object_file_format(&self);
-        let hash = hash_bytes_as_string(&formatted_content);
+    ///Write this object to the repo as a compressed loose object, returning its id
+    pub fn write_to_repo(&self) -> Result<String, ObjectError> {
+        let formatted_content = object_file_format(self);
+        let hash = hash_bytes_as_string(&formatted_content, self.repo.object_format());
         let target = object_file_location(&self.repo, &hash);
-        std::fs::write(target, formatted_content)?;
-        Ok(())  
+        if target.exists() {
+            return Ok(hash);
+        }
+        if let Some(fan_out_dir) = target.parent() {
+            std::fs::create_dir_all(fan_out_dir)?;
+        }
+        //Write next to the final name and rename so readers never see a partial object
+        let temp = target.with_extension("tmp");
+        std::fs::write(&temp, compress_bytes(&formatted_content)?)?;
+        std::fs::rename(&temp, &target)?;
+        Ok(hash)
     }
 
-    ///Create blob object from unarchived file
+    ///Create blob object from unarchived file, storing a symlink's target rather than following it
     pub fn from_external_file<P: AsRef<Path>>(file: P, repo: &Rc<GitRepository>) -> Result<GitObject, ObjectError> {
-        let contents = std::fs::read(file.as_ref())?;
+        let meta = std::fs::symlink_metadata(file.as_ref())?;
+        let contents = read_worktree_file(file.as_ref(), &meta)?;
         Ok(
             GitObject{
             repo: Rc::clone(repo),
@@ -118,11 +119,16 @@ impl GitObject {
     )
     }
 
-    ///Create reference to a file inside the repo objects folder 
+    ///Read an object from the repo, either a loose file in the objects folder or a packfile entry
     pub fn from_internal_file(sha: &str, repo: &Rc<GitRepository>) -> Result<GitObject, ObjectError> {
         let target = object_file_location(repo, sha);
+        if !target.is_file() {
+            if let Some((kind, content)) = read_packed_object(repo, sha)? {
+                return Ok(GitObject::new(kind, content, repo));
+            }
+        }
         let contents = read_repo_file(target)?;
-        let object = bytes_to_object(&contents, &repo)?;
+        let object = bytes_to_object(&contents, repo)?;
         Ok(object)
     }
 
@@ -132,20 +138,22 @@ impl GitObject {
     }
 
     pub fn get_hash(&self) -> String {
-        let formatted_content = object_file_format(&self);
-        hash_bytes_as_string(&formatted_content)
+        let formatted_content = object_file_format(self);
+        hash_bytes_as_string(&formatted_content, self.repo.object_format())
     }
     
 }
 
 fn object_file_location(repo: &GitRepository, sha: &str) -> std::path::PathBuf {
-    repo.gitdir().join("objects").join(&sha[..2]).join(&sha[2..])
+    repo_path(repo, "objects").join(&sha[..2]).join(&sha[2..])
 }
 
 #[derive(Debug)]
 pub enum ObjectError {
     FileIo(std::io::Error),
     FileParse(ObjectParseError),
+    WrongType(String, &'static str),
+    BadObjectId(String),
 }
 
 impl Display for ObjectError {
@@ -153,6 +161,8 @@ impl Display for ObjectError {
         match self {
             ObjectError::FileIo(io) => {write!(f, "Failed to access file: {}", io)},
             ObjectError::FileParse(parse_err) => {write!(f, "Unable to parse file: {}",parse_err)},
+            ObjectError::WrongType(name, expected) => {write!(f, "{} is not a valid '{}' object", name, expected)},
+            ObjectError::BadObjectId(sha) => {write!(f, "'{}' is not a valid object id", sha)},
         }
     }
 }
@@ -173,24 +183,26 @@ impl From<ObjectParseError> for ObjectError {
 mod tests {
     use super::*;
     use crate::lib::get_test_dir;
+    use crate::lib::objects::git_config::ConfigFile;
     use crate::lib::objects::git_repository::GitRepository;
     use std::rc::Rc;
     use std::path::PathBuf;
 
     use super::GitObject;
     #[test]
+    #[allow(unused_variables, clippy::assertions_on_constants, clippy::expect_fun_call)]
     fn read_object_from_bytes() {
         let test_dir = get_test_dir("read_object_from_bytes");
         let test_repo = Rc::new(
             GitRepository::new(
             test_dir.clone(),
             test_dir.clone().join(".git"),
-            configparser::ini::Ini::new(),
+            ConfigFile::new(),
         ));
-        let file_dir = test_dir.join(r".git\objects\05");
+        let file_dir = test_dir.join([".git", "objects", "05"].iter().collect::<PathBuf>());
         let src_path = std::env::current_dir()
             .unwrap()
-            .join(r"src\test\05\f01ab76171493c8ab7dc46d0abdbc94ed85372");
+            .join(["src", "test", "05", "f01ab76171493c8ab7dc46d0abdbc94ed85372"].iter().collect::<PathBuf>());
         let mut src = std::fs::File::open(&src_path).expect(
             format!(
                 "Error opening test file source at {}",
@@ -220,13 +232,13 @@ mod tests {
         let test_file = test_dir
             .join(".git")
             .join("objects")
-            .join("44")
-            .join("94953d947add7f87c652ad6bdf7243cc945041");
+            .join("f7")
+            .join("04b93e1eb2c92ed45dd0403887f6869c776c8f");
         let test_repo = Rc::new(
             GitRepository::new(
             PathBuf::new(),
             test_dir.join(".git"),
-            configparser::ini::Ini::new(),
+            ConfigFile::new(),
         ));
         let test_obj = GitObject::new(ObjectType::Blob, "Not real content".as_bytes().to_owned(), &test_repo);
 
@@ -238,9 +250,11 @@ mod tests {
             std::fs::remove_file(&test_file).expect("Failed to remove test file");
         }
 
-        test_obj.write_to_repo().expect("Error writing hashed object");
+        let hash = test_obj.write_to_repo().expect("Error writing hashed object");
 
         assert!(test_file.exists());
+        let read_back = GitObject::from_internal_file(&hash, &test_repo).expect("Error reading object back");
+        assert!(read_back.serialize() == b"Not real content");
     }
 
     #[test]
@@ -249,13 +263,28 @@ mod tests {
             GitRepository::new(
             PathBuf::new(),
             PathBuf::new(),
-            configparser::ini::Ini::new(),
+            ConfigFile::new(),
         ));
         let test_obj = GitObject::new(ObjectType::Blob, "Not real content".as_bytes().to_owned(), &test_repo);
         let file_content = object_file_format(&test_obj);
-        let hash_str = hash_bytes_as_string(&file_content);
-        println!("object content is {}", String::from_utf8_lossy(test_obj.content()));
-        assert!(hash_str == "4494953d947add7f87c652ad6bdf7243cc945041", "Hash was {}", hash_str);
+        let hash_str = hash_bytes_as_string(&file_content, test_repo.object_format());
+        println!("object content is {}", String::from_utf8_lossy(test_obj.serialize()));
+        assert!(hash_str == "f704b93e1eb2c92ed45dd0403887f6869c776c8f", "Hash was {}", hash_str);
+    }
+
+    #[test]
+    fn hash_a_sha256_object() {
+        let mut config = ConfigFile::new();
+        config.set("core.repositoryformatversion", "1").unwrap();
+        config.set("extensions.objectformat", "sha256").unwrap();
+        let test_repo = Rc::new(GitRepository::new(PathBuf::new(), PathBuf::new(), config));
+        let test_obj = GitObject::new(ObjectType::Blob, "Not real content".as_bytes().to_owned(), &test_repo);
+        let hash_str = test_obj.get_hash();
+        assert!(
+            hash_str == "a1d8cdefec7e71f4ebfcea47ce73108b4bb0d49ffcc85537036c08fdc96716a7",
+            "Hash was {}",
+            hash_str
+        );
     }
 }
 
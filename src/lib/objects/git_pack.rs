@@ -0,0 +1,363 @@
+use crate::lib::objects::git_hash::ObjectFormat;
+use crate::lib::objects::git_object::ObjectType;
+use crate::lib::objects::git_repository::{repo_path, GitRepository};
+use crate::lib::parsing::encoding::{bytes_to_hex, raw_object_id};
+use flate2::read::ZlibDecoder;
+use std::convert::TryInto;
+use std::ffi::OsStr;
+use std::fs::File;
+use std::io::{BufReader, ErrorKind, Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+
+const IDX_MAGIC: [u8; 4] = [0xff, b't', b'O', b'c'];
+
+///Version 2 pack index: the sorted object ids of one packfile and where each starts in it
+pub(crate) struct PackIndex {
+    pack_path: PathBuf,
+    format: ObjectFormat,
+    fanout: Vec<u32>,
+    ids: Vec<u8>,
+    offsets: Vec<u64>,
+}
+
+impl PackIndex {
+    ///Load an `.idx` file whose object ids are in the given format
+    pub(crate) fn load(idx_path: &Path, format: ObjectFormat) -> Result<PackIndex, std::io::Error> {
+        let data = std::fs::read(idx_path)?;
+        let invalid = |msg: &str| {
+            std::io::Error::new(
+                ErrorKind::InvalidData,
+                format!("{}: {}", idx_path.to_string_lossy(), msg),
+            )
+        };
+        let read_u32 = |at: usize| -> Result<u32, std::io::Error> {
+            data.get(at..at + 4)
+                .map(|bytes| u32::from_be_bytes(bytes.try_into().unwrap()))
+                .ok_or_else(|| invalid("truncated pack index"))
+        };
+
+        if data.len() < 8 || data[..4] != IDX_MAGIC || read_u32(4)? != 2 {
+            return Err(invalid("only version 2 pack indexes are supported"));
+        }
+        let fanout = (0..256)
+            .map(|i| read_u32(8 + i * 4))
+            .collect::<Result<Vec<u32>, std::io::Error>>()?;
+        //Each bucket ends where the next starts, so positions never run past the last count
+        if fanout.windows(2).any(|pair| pair[0] > pair[1]) {
+            return Err(invalid("pack index fanout table is not sorted"));
+        }
+        let count = fanout[255] as usize;
+        let raw_len = format.raw_len();
+        let ids_start = 8 + 256 * 4;
+        let offsets_start = ids_start + count * raw_len + count * 4;
+        let large_start = offsets_start + count * 4;
+        let ids = data
+            .get(ids_start..ids_start + count * raw_len)
+            .ok_or_else(|| invalid("truncated pack index"))?
+            .to_owned();
+
+        let mut offsets = Vec::with_capacity(count);
+        for i in 0..count {
+            let offset = read_u32(offsets_start + i * 4)?;
+            if offset & 0x8000_0000 == 0 {
+                offsets.push(u64::from(offset));
+            } else {
+                let at = large_start + (offset & 0x7fff_ffff) as usize * 8;
+                let large = data
+                    .get(at..at + 8)
+                    .ok_or_else(|| invalid("truncated large offset table"))?;
+                offsets.push(u64::from_be_bytes(large.try_into().unwrap()));
+            }
+        }
+
+        Ok(PackIndex {
+            pack_path: idx_path.with_extension("pack"),
+            format,
+            fanout,
+            ids,
+            offsets,
+        })
+    }
+
+    fn id_at(&self, i: usize) -> &[u8] {
+        let raw_len = self.format.raw_len();
+        &self.ids[i * raw_len..(i + 1) * raw_len]
+    }
+
+    ///Range of positions whose ids start with the given byte
+    fn fanout_range(&self, first_byte: u8) -> (usize, usize) {
+        let start = if first_byte == 0 { 0 } else { self.fanout[first_byte as usize - 1] as usize };
+        (start, self.fanout[first_byte as usize] as usize)
+    }
+
+    ///Offset of the object in the packfile, if this pack has it
+    pub(crate) fn find(&self, raw_id: &[u8]) -> Option<u64> {
+        let (mut low, mut high) = self.fanout_range(*raw_id.first()?);
+        while low < high {
+            let mid = (low + high) / 2;
+            match self.id_at(mid).cmp(raw_id) {
+                std::cmp::Ordering::Equal => return Some(self.offsets[mid]),
+                std::cmp::Ordering::Less => low = mid + 1,
+                std::cmp::Ordering::Greater => high = mid,
+            }
+        }
+        None
+    }
+
+    ///All ids in this pack starting with the hex prefix
+    pub(crate) fn ids_with_prefix(&self, prefix: &str) -> Vec<String> {
+        let first_byte = match u8::from_str_radix(&prefix[..2], 16) {
+            Ok(byte) => byte,
+            Err(_) => return Vec::new(),
+        };
+        let (low, high) = self.fanout_range(first_byte);
+        (low..high)
+            .map(|i| bytes_to_hex(self.id_at(i)))
+            .filter(|id| id.starts_with(prefix))
+            .collect()
+    }
+}
+
+///Every pack index in the repository's objects/pack directory, read from disk. Use
+///`GitRepository::pack_indexes`, which keeps them once read
+pub(crate) fn load_pack_indexes(repo: &GitRepository) -> Result<Vec<PackIndex>, std::io::Error> {
+    let pack_dir = repo_path(repo, "objects").join("pack");
+    if !pack_dir.is_dir() {
+        return Ok(Vec::new());
+    }
+    let mut indexes = Vec::new();
+    for entry in std::fs::read_dir(pack_dir)? {
+        let path = entry?.path();
+        if path.extension() == Some(OsStr::new("idx")) && path.with_extension("pack").is_file() {
+            indexes.push(PackIndex::load(&path, repo.object_format())?);
+        }
+    }
+    Ok(indexes)
+}
+
+///Read an object out of whichever pack contains it
+pub(crate) fn read_packed_object(repo: &GitRepository, sha: &str) -> Result<Option<(ObjectType, Vec<u8>)>, std::io::Error> {
+    let raw_id = match raw_object_id(sha, repo.object_format()) {
+        Some(raw_id) => raw_id,
+        None => return Ok(None),
+    };
+    for index in repo.pack_indexes()?.iter() {
+        if let Some(offset) = index.find(&raw_id) {
+            let mut pack = BufReader::new(File::open(&index.pack_path)?);
+            check_pack_header(&mut pack)?;
+            return read_entry(repo, index, &mut pack, offset).map(Some);
+        }
+    }
+    Ok(None)
+}
+
+///Object ids in any pack starting with the hex prefix
+pub(crate) fn abbreviated_in_packs(repo: &GitRepository, prefix: &str) -> Result<Vec<String>, std::io::Error> {
+    let mut matches = Vec::new();
+    for index in repo.pack_indexes()?.iter() {
+        matches.extend(index.ids_with_prefix(prefix));
+    }
+    Ok(matches)
+}
+
+fn check_pack_header(pack: &mut BufReader<File>) -> Result<(), std::io::Error> {
+    let mut header = [0u8; 8];
+    pack.read_exact(&mut header)?;
+    let version = u32::from_be_bytes(header[4..8].try_into().unwrap());
+    if &header[..4] != b"PACK" || !(version == 2 || version == 3) {
+        return Err(std::io::Error::new(ErrorKind::InvalidData, "not a version 2 or 3 packfile"));
+    }
+    Ok(())
+}
+
+fn read_byte(pack: &mut BufReader<File>) -> Result<u8, std::io::Error> {
+    let mut byte = [0u8; 1];
+    pack.read_exact(&mut byte)?;
+    Ok(byte[0])
+}
+
+///Read the entry at the given offset, resolving deltas against their bases
+fn read_entry(repo: &GitRepository, index: &PackIndex, pack: &mut BufReader<File>, offset: u64) -> Result<(ObjectType, Vec<u8>), std::io::Error> {
+    pack.seek(SeekFrom::Start(offset))?;
+    let mut byte = read_byte(pack)?;
+    let kind = (byte >> 4) & 0x7;
+    let mut size = u64::from(byte & 0x0f);
+    let mut shift = 4;
+    while byte & 0x80 != 0 {
+        if shift > 57 {
+            return Err(corrupt_entry("pack entry size overflows"));
+        }
+        byte = read_byte(pack)?;
+        size |= u64::from(byte & 0x7f) << shift;
+        shift += 7;
+    }
+
+    match kind {
+        1..=4 => {
+            let kind = match kind {
+                1 => ObjectType::Commit,
+                2 => ObjectType::Tree,
+                3 => ObjectType::Blob,
+                _ => ObjectType::Tag,
+            };
+            Ok((kind, inflate(pack, size)?))
+        }
+        6 => {
+            byte = read_byte(pack)?;
+            let mut distance = u64::from(byte & 0x7f);
+            while byte & 0x80 != 0 {
+                byte = read_byte(pack)?;
+                let next = distance
+                    .checked_add(1)
+                    .filter(|next| next.leading_zeros() >= 7)
+                    .ok_or_else(|| corrupt_entry("delta base offset overflows"))?;
+                distance = (next << 7) | u64::from(byte & 0x7f);
+            }
+            //The base comes earlier in the pack, a distance of 0 would be the entry itself
+            let base_offset = offset
+                .checked_sub(distance)
+                .filter(|_| distance > 0)
+                .ok_or_else(|| corrupt_entry("delta base offset is out of bounds"))?;
+            let delta = inflate(pack, size)?;
+            let (kind, base) = read_entry(repo, index, pack, base_offset)?;
+            Ok((kind, apply_delta(&base, &delta)?))
+        }
+        7 => {
+            let mut base_id = vec![0u8; index.format.raw_len()];
+            pack.read_exact(&mut base_id)?;
+            let delta = inflate(pack, size)?;
+            let (kind, base) = match index.find(&base_id) {
+                Some(base_offset) => read_entry(repo, index, pack, base_offset)?,
+                None => read_packed_object(repo, &bytes_to_hex(&base_id))?.ok_or_else(|| {
+                    std::io::Error::new(ErrorKind::NotFound, "delta base object is missing")
+                })?,
+            };
+            Ok((kind, apply_delta(&base, &delta)?))
+        }
+        _ => Err(corrupt_entry(&format!("unknown pack entry type {}", kind))),
+    }
+}
+
+fn corrupt_entry(msg: &str) -> std::io::Error {
+    std::io::Error::new(ErrorKind::InvalidData, msg)
+}
+
+///Inflate an entry of the given size. The size comes from the pack, so the buffer grows with
+///the data actually inflated rather than being allocated up front
+fn inflate(pack: &mut BufReader<File>, size: u64) -> Result<Vec<u8>, std::io::Error> {
+    let mut data = Vec::new();
+    ZlibDecoder::new(pack).take(size).read_to_end(&mut data)?;
+    if data.len() as u64 != size {
+        return Err(corrupt_entry("pack entry is shorter than its header says"));
+    }
+    Ok(data)
+}
+
+///Rebuild an object from its base and a git delta (copy and insert instructions)
+pub(crate) fn apply_delta(base: &[u8], delta: &[u8]) -> Result<Vec<u8>, std::io::Error> {
+    let corrupt = || std::io::Error::new(ErrorKind::InvalidData, "corrupt delta");
+    let mut pos = 0;
+    let read_size = |pos: &mut usize| -> Result<usize, std::io::Error> {
+        let mut size = 0;
+        let mut shift = 0;
+        loop {
+            let byte = *delta.get(*pos).ok_or_else(corrupt)?;
+            if shift > usize::BITS - 7 {
+                return Err(corrupt());
+            }
+            *pos += 1;
+            size |= ((byte & 0x7f) as usize) << shift;
+            shift += 7;
+            if byte & 0x80 == 0 {
+                return Ok(size);
+            }
+        }
+    };
+    let base_size = read_size(&mut pos)?;
+    let result_size = read_size(&mut pos)?;
+    if base_size != base.len() {
+        return Err(corrupt());
+    }
+
+    //The sizes come from the delta, so only its own length and the base bound the first allocation
+    let mut result = Vec::with_capacity(result_size.min(base.len() + delta.len()));
+    while pos < delta.len() {
+        let op = delta[pos];
+        pos += 1;
+        if op & 0x80 != 0 {
+            let mut offset = 0usize;
+            let mut length = 0usize;
+            for bit in 0..4 {
+                if op & (1 << bit) != 0 {
+                    offset |= (*delta.get(pos).ok_or_else(corrupt)? as usize) << (bit * 8);
+                    pos += 1;
+                }
+            }
+            for bit in 0..3 {
+                if op & (0x10 << bit) != 0 {
+                    length |= (*delta.get(pos).ok_or_else(corrupt)? as usize) << (bit * 8);
+                    pos += 1;
+                }
+            }
+            if length == 0 {
+                length = 0x10000;
+            }
+            result.extend_from_slice(base.get(offset..offset + length).ok_or_else(corrupt)?);
+        } else if op != 0 {
+            let insert = delta.get(pos..pos + op as usize).ok_or_else(corrupt)?;
+            result.extend_from_slice(insert);
+            pos += op as usize;
+        } else {
+            return Err(corrupt());
+        }
+    }
+    if result.len() != result_size {
+        return Err(corrupt());
+    }
+    Ok(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{apply_delta, PackIndex, IDX_MAGIC};
+    use crate::lib::get_test_dir;
+    use crate::lib::objects::git_hash::ObjectFormat;
+    use std::io::ErrorKind;
+
+    #[test]
+    fn applies_copy_and_insert() {
+        let base = b"hello world";
+        //base size 11, result size 11, copy 6 bytes from 0, insert "there"
+        let delta = [11, 11, 0x90, 6, 5, b't', b'h', b'e', b'r', b'e'];
+        let result = apply_delta(base, &delta).expect("Error applying delta");
+        assert!(result == b"hello there", "was {}", String::from_utf8_lossy(&result));
+    }
+
+    #[test]
+    fn rejects_oversized_delta_sizes() {
+        //Eleven continuation bytes shift the size past the width of usize
+        let mut delta = vec![0xff; 11];
+        delta.extend_from_slice(&[0x01, 0x00]);
+        let err = apply_delta(b"", &delta).expect_err("Delta with an overflowing size was applied");
+        assert!(err.kind() == ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn rejects_unsorted_fanout() {
+        let test_dir = get_test_dir("rejects_unsorted_fanout");
+        let _ = std::fs::remove_dir_all(&test_dir);
+        std::fs::create_dir_all(&test_dir).unwrap();
+        let mut data = IDX_MAGIC.to_vec();
+        data.extend_from_slice(&2u32.to_be_bytes());
+        for i in 0..256u32 {
+            //Bucket 0x10 claims more objects than every bucket after it
+            let count: u32 = if i == 0x10 { 5 } else { 0 };
+            data.extend_from_slice(&count.to_be_bytes());
+        }
+        let idx_path = test_dir.join("pack-bad.idx");
+        std::fs::write(&idx_path, data).unwrap();
+
+        let err = PackIndex::load(&idx_path, ObjectFormat::Sha1).err().expect("Unsorted fanout was accepted");
+        assert!(err.kind() == ErrorKind::InvalidData, "was {}", err);
+    }
+}
@@ -0,0 +1,421 @@
+use crate::lib::objects::git_attributes::{is_valid_attr_name, AttrValue};
+use crate::lib::parsing::wildmatch::{wildmatch, WM_CASEFOLD, WM_PATHNAME};
+use std::fmt::{Display, Formatter};
+
+///Magic words changing how one pathspec matches, given as `:(glob,icase)path` or in the short
+///forms `:/path` and `:!path`
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+struct Magic {
+    ///Wildcards do not cross `/`, except for `**` as a whole component
+    glob: bool,
+    icase: bool,
+    ///Wildcard characters stand for themselves
+    literal: bool,
+    ///Paths matching this item are taken out of what the others select
+    exclude: bool,
+    ///Resolve against the top of the worktree rather than the current directory
+    top: bool,
+}
+
+///One pathspec argument resolved against the current directory
+#[derive(Clone, Debug, PartialEq)]
+struct PathspecItem {
+    original: String,
+    ///Path relative to the top of the worktree, empty for the whole tree
+    path: String,
+    magic: Magic,
+    ///Length of the leading part of `path` without wildcards
+    nowildcard_len: usize,
+    attrs: Vec<(String, AttrValue)>,
+}
+
+impl PathspecItem {
+    fn parse(arg: &str, prefix: &str, global: &GlobalMagic) -> Result<PathspecItem, PathspecError> {
+        let mut magic = Magic::default();
+        let mut attrs = Vec::new();
+        let mut rest = arg;
+        if global.literal {
+            magic.literal = true;
+        } else if let Some(long) = arg.strip_prefix(":(") {
+            let end = long.find(')').ok_or_else(|| PathspecError::MissingParen(arg.to_owned()))?;
+            for word in long[..end].split(',').filter(|word| !word.is_empty()) {
+                match word {
+                    "glob" => magic.glob = true,
+                    "icase" => magic.icase = true,
+                    "literal" => magic.literal = true,
+                    "exclude" => magic.exclude = true,
+                    "top" => magic.top = true,
+                    _ => match word.strip_prefix("attr:") {
+                        Some(_) if !attrs.is_empty() => return Err(PathspecError::InvalidAttr("Only one 'attr:' specification is allowed.".to_owned())),
+                        Some(spec) => attrs = parse_attr_spec(spec)?,
+                        None => return Err(PathspecError::InvalidMagic(word.to_owned(), arg.to_owned())),
+                    },
+                }
+            }
+            rest = &long[end + 1..];
+        } else if let Some(short) = arg.strip_prefix(':') {
+            rest = short;
+            for (at, ch) in short.char_indices() {
+                match ch {
+                    '/' => magic.top = true,
+                    '!' | '^' => magic.exclude = true,
+                    ':' => {
+                        rest = &short[at + 1..];
+                        break;
+                    }
+                    _ => {
+                        rest = &short[at..];
+                        break;
+                    }
+                }
+                rest = &short[at + 1..];
+            }
+        }
+        if magic.glob && magic.literal {
+            return Err(PathspecError::IncompatibleMagic(arg.to_owned()));
+        }
+        if !magic.glob && !magic.literal {
+            magic.literal = global.noglob;
+            magic.glob = global.glob;
+        }
+        magic.icase |= global.icase;
+
+        let base = if magic.top { "" } else { prefix };
+        let path = normalize(base, rest).map_err(|_| PathspecError::OutsideRepository(arg.to_owned()))?;
+        //Like git, the directories of the prefix are matched literally even if their names hold wildcards
+        let prefix_len = kept_prefix_len(base, rest).min(path.len());
+        let nowildcard_len = match magic.literal {
+            true => path.len(),
+            false => path[prefix_len..]
+                .find(['*', '?', '[', '\\'])
+                .map_or(path.len(), |at| prefix_len + at),
+        };
+        Ok(PathspecItem {
+            original: arg.to_owned(),
+            path,
+            magic,
+            nowildcard_len,
+            attrs,
+        })
+    }
+
+    fn has_wildcard(&self) -> bool {
+        self.nowildcard_len < self.path.len()
+    }
+
+    fn same(&self, a: &[u8], b: &[u8]) -> bool {
+        if self.magic.icase {
+            a.eq_ignore_ascii_case(b)
+        } else {
+            a == b
+        }
+    }
+
+    ///The path itself, or a wildcard match of it
+    fn names(&self, path: &str) -> bool {
+        if self.same(self.path.as_bytes(), path.as_bytes()) {
+            return true;
+        }
+        let mut flags = 0;
+        if self.magic.glob {
+            flags |= WM_PATHNAME;
+        }
+        if self.magic.icase {
+            flags |= WM_CASEFOLD;
+        }
+        let literal = self.nowildcard_len;
+        self.has_wildcard()
+            && path.len() >= literal
+            && self.same(&self.path.as_bytes()[..literal], &path.as_bytes()[..literal])
+            && wildmatch(&self.path[literal..], &path[literal..], flags)
+    }
+
+    fn matches_path(&self, path: &str) -> bool {
+        let spec = self.path.as_bytes();
+        if spec.is_empty() || self.names(path) {
+            return true;
+        }
+        //A directory selects everything below it
+        let leading_dir = spec.ends_with(b"/") || path.as_bytes().get(spec.len()) == Some(&b'/');
+        leading_dir && path.len() >= spec.len() && self.same(&path.as_bytes()[..spec.len()], spec)
+    }
+
+    fn matches<F: FnMut(&str, &str) -> AttrValue>(&self, path: &str, attr: &mut F) -> bool {
+        self.matches_path(path) && self.attrs.iter().all(|(name, wanted)| attr(path, name) == *wanted)
+    }
+
+    ///Whether paths below `dir`, which ends in `/`, could match
+    fn may_match_under(&self, dir: &str) -> bool {
+        let literal = &self.path.as_bytes()[..self.nowildcard_len];
+        let common = literal.len().min(dir.len());
+        if !self.same(&literal[..common], &dir.as_bytes()[..common]) {
+            return false;
+        }
+        self.has_wildcard() || literal.len() >= dir.len() || literal.is_empty() || literal.ends_with(b"/") || dir.as_bytes()[literal.len()] == b'/'
+    }
+}
+
+///`attr:` magic lists attributes to be set (`name`), unset (`-name`), unspecified (`!name`) or
+///given a value (`name=value`)
+fn parse_attr_spec(spec: &str) -> Result<Vec<(String, AttrValue)>, PathspecError> {
+    let mut attrs = Vec::new();
+    for word in spec.split(' ').filter(|word| !word.is_empty()) {
+        let (name, value) = if let Some(name) = word.strip_prefix('-') {
+            (name, AttrValue::Unset)
+        } else if let Some(name) = word.strip_prefix('!') {
+            (name, AttrValue::Unspecified)
+        } else if let Some((name, value)) = word.split_once('=') {
+            (name, AttrValue::Value(value.to_owned()))
+        } else {
+            (word, AttrValue::Set)
+        };
+        if !is_valid_attr_name(name) {
+            return Err(PathspecError::InvalidAttr(format!("invalid attribute name {}", name)));
+        }
+        attrs.push((name.to_owned(), value));
+    }
+    if attrs.is_empty() {
+        return Err(PathspecError::InvalidAttr("attr spec must not be empty".to_owned()));
+    }
+    Ok(attrs)
+}
+
+///Magic applied to every pathspec through git's environment switches
+#[derive(Default)]
+struct GlobalMagic {
+    ///GIT_LITERAL_PATHSPECS: no magic and no wildcards at all
+    literal: bool,
+    glob: bool,
+    noglob: bool,
+    icase: bool,
+}
+
+impl GlobalMagic {
+    fn from_env() -> GlobalMagic {
+        let flag = |name: &str| std::env::var(name).is_ok_and(|value| matches!(value.to_ascii_lowercase().as_str(), "1" | "true" | "yes" | "on"));
+        GlobalMagic {
+            literal: flag("GIT_LITERAL_PATHSPECS"),
+            glob: flag("GIT_GLOB_PATHSPECS"),
+            noglob: flag("GIT_NOGLOB_PATHSPECS"),
+            icase: flag("GIT_ICASE_PATHSPECS"),
+        }
+    }
+}
+
+///Paths given on the command line to limit a command, matched the way git does: by default
+///exact paths, leading directories, or globs where `*` also crosses `/`, changed by magic
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Pathspec {
+    items: Vec<PathspecItem>,
+}
+
+impl Pathspec {
+    ///Resolve arguments given in the directory `prefix` (relative to the top, ending in `/`)
+    pub fn parse<S: AsRef<str>>(args: &[S], prefix: &str) -> Result<Pathspec, PathspecError> {
+        let global = GlobalMagic::from_env();
+        let mut items = args
+            .iter()
+            .map(|arg| PathspecItem::parse(arg.as_ref(), prefix, &global))
+            .collect::<Result<Vec<PathspecItem>, PathspecError>>()?;
+        //Exclusions alone take away from what no pathspec would select, the current directory
+        if !items.is_empty() && items.iter().all(|item| item.magic.exclude) {
+            items.push(PathspecItem::parse(".", prefix, &GlobalMagic::default())?);
+        }
+        Ok(Pathspec { items })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.items.is_empty()
+    }
+
+    fn positive(&self) -> impl Iterator<Item = &PathspecItem> {
+        self.items.iter().filter(|item| !item.magic.exclude)
+    }
+
+    ///Whether a path relative to the top of the worktree is selected, `attr` looking up attributes
+    ///for `attr:` magic as (path, name). No pathspec selects everything
+    pub fn matches<F: FnMut(&str, &str) -> AttrValue>(&self, path: &str, mut attr: F) -> bool {
+        let included = self.items.is_empty() || self.positive().any(|item| item.matches(path, &mut attr));
+        included && !self.items.iter().any(|item| item.magic.exclude && item.matches(path, &mut attr))
+    }
+
+    ///Whether a tree walk needs to look inside directory `dir` to find selected paths
+    pub fn may_match_under(&self, dir: &str) -> bool {
+        let dir = format!("{}/", dir.trim_end_matches('/'));
+        self.items.is_empty() || self.positive().any(|item| item.may_match_under(&dir))
+    }
+
+    ///Whether an argument names this path itself rather than a directory or glob covering it
+    pub fn names(&self, path: &str) -> bool {
+        self.positive().any(|item| item.same(item.path.as_bytes(), path.as_bytes()))
+    }
+
+    ///For a path selected only because an argument names a directory above it, that argument.
+    ///Commands like `rm` want `-r` before acting on such paths
+    pub fn matching_directory(&self, path: &str) -> Option<&str> {
+        if self.positive().any(|item| item.names(path)) {
+            return None;
+        }
+        self.positive()
+            .find(|item| item.matches_path(path))
+            .map(|item| item.original.as_str())
+    }
+
+    ///The arguments that select none of the given paths, for "did not match any file" errors.
+    ///Exclusions never count as unmatched
+    pub fn unmatched<'a, I: IntoIterator<Item = &'a str>, F: FnMut(&str, &str) -> AttrValue>(&self, paths: I, mut attr: F) -> Vec<&str> {
+        let positive: Vec<&PathspecItem> = self.positive().collect();
+        let mut matched = vec![false; positive.len()];
+        for path in paths {
+            for (item, seen) in positive.iter().zip(matched.iter_mut()) {
+                *seen = *seen || item.matches(path, &mut attr);
+            }
+        }
+        positive
+            .iter()
+            .zip(matched)
+            .filter(|(_, seen)| !seen)
+            .map(|(item, _)| item.original.as_str())
+            .collect()
+    }
+}
+
+///Join an argument to the prefix and resolve `.` and `..`, refusing to leave the worktree
+pub(crate) fn normalize(prefix: &str, arg: &str) -> Result<String, PathspecError> {
+    let mut parts: Vec<&str> = Vec::new();
+    let joined = format!("{}{}", prefix, arg);
+    for part in joined.split('/') {
+        match part {
+            "" | "." => {}
+            ".." => {
+                if parts.pop().is_none() {
+                    return Err(PathspecError::OutsideRepository(arg.to_owned()));
+                }
+            }
+            _ => parts.push(part),
+        }
+    }
+    let mut path = parts.join("/");
+    if arg.ends_with('/') && !path.is_empty() {
+        path.push('/');
+    }
+    Ok(path)
+}
+
+///How many bytes of `prefix` still lead the path once `arg` is joined to it, as `..` can climb out of it
+fn kept_prefix_len(prefix: &str, arg: &str) -> usize {
+    let dirs: Vec<&str> = prefix.split('/').filter(|dir| !dir.is_empty()).collect();
+    let mut depth = dirs.len();
+    let mut kept = depth;
+    for part in arg.split('/') {
+        match part {
+            "" | "." => {}
+            ".." => {
+                depth = depth.saturating_sub(1);
+                kept = kept.min(depth);
+            }
+            _ => depth += 1,
+        }
+    }
+    dirs[..kept].iter().map(|dir| dir.len() + 1).sum()
+}
+
+///Show a path relative to the top of the worktree as seen from the directory `prefix`
+pub fn relative_path(path: &str, prefix: &str) -> String {
+    let mut common = 0;
+    for (end, _) in prefix.match_indices('/') {
+        if path.starts_with(&prefix[..=end]) {
+            common = end + 1;
+        } else {
+            break;
+        }
+    }
+    let ups = prefix[common..].matches('/').count();
+    format!("{}{}", "../".repeat(ups), &path[common..])
+}
+
+#[derive(Debug)]
+pub enum PathspecError {
+    OutsideRepository(String),
+    MissingParen(String),
+    InvalidMagic(String, String),
+    IncompatibleMagic(String),
+    InvalidAttr(String),
+}
+
+impl Display for PathspecError {
+    fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
+        match self {
+            PathspecError::OutsideRepository(path) => {write!(f, "{}: '{}' is outside repository", path, path)},
+            PathspecError::MissingParen(arg) => {write!(f, "Missing ')' at the end of pathspec magic in '{}'", arg)},
+            PathspecError::InvalidMagic(word, arg) => {write!(f, "Invalid pathspec magic '{}' in '{}'", word, arg)},
+            PathspecError::IncompatibleMagic(arg) => {write!(f, "{}: 'literal' and 'glob' are incompatible", arg)},
+            PathspecError::InvalidAttr(message) => {write!(f, "{}", message)},
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn no_attrs(_: &str, _: &str) -> AttrValue {
+        AttrValue::Unspecified
+    }
+
+    #[test]
+    fn matches_default_pathspecs() {
+        let spec = Pathspec::parse(&["src", "*.md", "../top.txt"], "docs/").unwrap();
+        assert!(spec.matches("docs/src/main.rs", no_attrs));
+        assert!(!spec.matches("docs/srcs", no_attrs));
+        assert!(spec.matches("docs/guide/intro.md", no_attrs), "Stars cross directories");
+        assert!(spec.matches("top.txt", no_attrs));
+        assert!(!spec.matches("readme.md", no_attrs));
+        assert!(spec.unmatched(["docs/src/a"], no_attrs) == ["*.md", "../top.txt"]);
+        assert!(Pathspec::parse(&["../.."], "docs/").is_err());
+        assert!(Pathspec::parse(&["."], "").unwrap().matches("anything", no_attrs));
+
+        assert!(relative_path("docs/src/a", "docs/") == "src/a");
+        assert!(relative_path("top.txt", "docs/guide/") == "../../top.txt");
+        assert!(relative_path("docs/b", "docs/guide/") == "../b");
+
+        let bracket_dir = Pathspec::parse(&["*.c", "../[ab]/x"], "[ab]/").unwrap();
+        assert!(bracket_dir.matches("[ab]/main.c", no_attrs) && !bracket_dir.matches("a/main.c", no_attrs), "The prefix is literal");
+        assert!(bracket_dir.matches("a/x", no_attrs), "A pathspec that climbs out of the prefix keeps its wildcards");
+    }
+
+    #[test]
+    fn matches_magic_pathspecs() {
+        let glob = Pathspec::parse(&[":(glob)src/*.c", ":(glob)**/test/*"], "").unwrap();
+        assert!(glob.matches("src/a.c", no_attrs) && !glob.matches("src/sub/a.c", no_attrs), "Glob stars stop at slashes");
+        assert!(glob.matches("test/x", no_attrs) && glob.matches("a/b/test/x", no_attrs));
+        assert!(glob.may_match_under("docs/test"), "Leading stars can match anywhere");
+        let src = Pathspec::parse(&[":(glob)src/*.c"], "").unwrap();
+        assert!(src.may_match_under("src") && !src.may_match_under("lib") && !src.may_match_under("srcs"));
+        let spec = Pathspec::parse(&[":(icase)README", ":(literal)a*", ":/top.c"], "sub/").unwrap();
+        assert!(spec.matches("sub/readme", no_attrs) && spec.matches("sub/a*", no_attrs) && !spec.matches("sub/ab", no_attrs));
+        assert!(spec.matches("top.c", no_attrs) && !spec.matches("sub/top.c", no_attrs));
+        assert!(!spec.may_match_under("docs") && spec.may_match_under("sub"));
+
+        let exclude = Pathspec::parse(&["src", ":!src/gen", ":^*.o"], "").unwrap();
+        assert!(exclude.matches("src/a.c", no_attrs) && !exclude.matches("src/gen/a.c", no_attrs) && !exclude.matches("src/a.o", no_attrs));
+        assert!(exclude.unmatched(["src/a.c"], no_attrs).is_empty(), "Exclusions are never unmatched");
+        let only_exclude = Pathspec::parse(&[":(exclude)gen"], "sub/").unwrap();
+        assert!(only_exclude.matches("sub/a", no_attrs) && !only_exclude.matches("sub/gen/a", no_attrs) && !only_exclude.matches("top", no_attrs));
+
+        let attr = Pathspec::parse(&[":(attr:text -binary eol=lf)"], "").unwrap();
+        let lookup = |path: &str, name: &str| match (path, name) {
+            ("a.txt", "text") => AttrValue::Set,
+            ("a.txt", "binary") => AttrValue::Unset,
+            (_, "eol") => AttrValue::Value("lf".to_owned()),
+            _ => AttrValue::Unspecified,
+        };
+        assert!(attr.matches("a.txt", lookup) && !attr.matches("b.txt", lookup));
+        assert!(attr.unmatched(["b.txt"], lookup) == [":(attr:text -binary eol=lf)"] && attr.unmatched(["a.txt"], lookup).is_empty());
+
+        assert!(matches!(Pathspec::parse(&[":(glob"], ""), Err(PathspecError::MissingParen(_))));
+        assert!(matches!(Pathspec::parse(&[":(nope)x"], ""), Err(PathspecError::InvalidMagic(..))));
+        assert!(matches!(Pathspec::parse(&[":(glob,literal)x"], ""), Err(PathspecError::IncompatibleMagic(_))));
+        assert!(matches!(Pathspec::parse(&[":(attr:)x"], ""), Err(PathspecError::InvalidAttr(_))));
+    }
+}
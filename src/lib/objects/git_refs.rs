@@ -0,0 +1,226 @@
+use crate::lib::objects::git_ident::Signature;
+use crate::lib::objects::git_lock::LockFile;
+use crate::lib::objects::git_repository::{GitRepository, repo_path};
+use std::io::{ErrorKind, Write};
+
+///The contents of a ref file: either an object id or a pointer to another ref
+#[derive(Debug, PartialEq)]
+pub(crate) enum RefValue {
+    Direct(String),
+    Symbolic(String),
+}
+
+///Read a single ref (loose first, then packed-refs) without following symbolic refs
+pub(crate) fn read_ref(repo: &GitRepository, name: &str) -> Result<Option<RefValue>, std::io::Error> {
+    let path = repo_path(repo, name);
+    if path.is_file() {
+        let contents = std::fs::read_to_string(&path)?;
+        let contents = contents.trim_end();
+        if let Some(target) = contents.strip_prefix("ref: ") {
+            return Ok(Some(RefValue::Symbolic(target.to_owned())));
+        }
+        return Ok(Some(RefValue::Direct(contents.to_owned())));
+    }
+    Ok(read_packed_refs(repo)?
+        .into_iter()
+        .find(|(ref_name, _)| ref_name == name)
+        .map(|(_, sha)| RefValue::Direct(sha)))
+}
+
+///Follow a ref through any symbolic refs to the object id it names
+pub(crate) fn resolve_ref(repo: &GitRepository, name: &str) -> Result<Option<String>, std::io::Error> {
+    let mut name = name.to_owned();
+    //Same limit git uses to stop symbolic ref loops
+    for _ in 0..5 {
+        match read_ref(repo, &name)? {
+            Some(RefValue::Direct(sha)) => return Ok(Some(sha)),
+            Some(RefValue::Symbolic(target)) => name = target,
+            None => return Ok(None),
+        }
+    }
+    Err(std::io::Error::new(
+        ErrorKind::InvalidData,
+        format!("symbolic ref loop at {}", name),
+    ))
+}
+
+///Write a ref file, creating the directories leading up to it
+pub(crate) fn write_ref(repo: &GitRepository, name: &str, value: &RefValue) -> Result<(), std::io::Error> {
+    let path = repo_path(repo, name);
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir)?;
+    }
+    let contents = match value {
+        RefValue::Direct(sha) => format!("{}\n", sha),
+        RefValue::Symbolic(target) => format!("ref: {}\n", target),
+    };
+    std::fs::write(path, contents)
+}
+
+///Move a ref, or the branch a symbolic ref points at, to a new object while holding its lock.
+///`old` is the value the caller based the update on, None when the ref did not exist yet; the
+///update fails if someone else moved the ref since. Reflog entries are written for the ref and
+///for the symbolic ref it was reached through
+pub(crate) fn update_ref(
+    repo: &GitRepository,
+    name: &str,
+    new: &str,
+    old: Option<&str>,
+    committer: &Signature,
+    message: &str,
+) -> Result<(), std::io::Error> {
+    let mut target = name.to_owned();
+    for _ in 0..5 {
+        match read_ref(repo, &target)? {
+            Some(RefValue::Symbolic(next)) => target = next,
+            _ => break,
+        }
+    }
+    let path = repo_path(repo, &target);
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir)?;
+    }
+    let mut lock = LockFile::acquire(path)?;
+    let current = resolve_ref(repo, &target)?;
+    if current.as_deref() != old {
+        return Err(std::io::Error::other(format!(
+            "cannot lock ref '{}': is at {} but expected {}",
+            name,
+            current.unwrap_or_default(),
+            old.unwrap_or_default()
+        )));
+    }
+    lock.write_all(format!("{}\n", new).as_bytes())?;
+    lock.commit()?;
+
+    let null_id = repo.object_format().null_id();
+    let old = old.map_or(null_id, str::to_owned);
+    append_reflog(repo, &target, &old, new, committer, message)?;
+    if target != name {
+        append_reflog(repo, name, &old, new, committer, message)?;
+    }
+    Ok(())
+}
+
+///Point HEAD itself at a branch or, detached, at a commit, logging the move in HEAD's reflog
+pub(crate) fn set_head(repo: &GitRepository, value: &RefValue, committer: &Signature, message: &str) -> Result<(), std::io::Error> {
+    let mut lock = LockFile::acquire(repo_path(repo, "HEAD"))?;
+    let old = resolve_ref(repo, "HEAD")?;
+    let (contents, new) = match value {
+        RefValue::Direct(sha) => (format!("{}\n", sha), Some(sha.clone())),
+        RefValue::Symbolic(target) => (format!("ref: {}\n", target), resolve_ref(repo, target)?),
+    };
+    lock.write_all(contents.as_bytes())?;
+    lock.commit()?;
+
+    let null_id = repo.object_format().null_id();
+    match new {
+        Some(new) => append_reflog(repo, "HEAD", old.as_deref().unwrap_or(&null_id), &new, committer, message),
+        None => Ok(()),
+    }
+}
+
+///Add a line to a ref's log if core.logAllRefUpdates asks for it or the log already exists
+fn append_reflog(repo: &GitRepository, name: &str, old: &str, new: &str, committer: &Signature, message: &str) -> Result<(), std::io::Error> {
+    let path = repo_path(repo, format!("logs/{}", name));
+    let config = repo.config();
+    let log_all = match config.get("core.logallrefupdates") {
+        Some(value) if value.eq_ignore_ascii_case("always") => true,
+        _ => match config.get_bool("core.logallrefupdates") {
+            Ok(Some(enabled)) => enabled && is_logged_by_default(name),
+            _ => !repo.is_bare() && is_logged_by_default(name),
+        },
+    };
+    if !log_all && !path.is_file() {
+        return Ok(());
+    }
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir)?;
+    }
+    let mut log = std::fs::OpenOptions::new().create(true).append(true).open(path)?;
+    let message = message.replace('\n', " ");
+    log.write_all(format!("{} {} {}\t{}\n", old, new, committer, message.trim_end()).as_bytes())
+}
+
+fn is_logged_by_default(name: &str) -> bool {
+    name == "HEAD" || ["refs/heads/", "refs/remotes/", "refs/notes/"].iter().any(|prefix| name.starts_with(prefix))
+}
+
+///All (name, object id) pairs in the packed-refs file, skipping peeled tag lines
+pub(crate) fn read_packed_refs(repo: &GitRepository) -> Result<Vec<(String, String)>, std::io::Error> {
+    let path = repo_path(repo, "packed-refs");
+    if !path.is_file() {
+        return Ok(Vec::new());
+    }
+    let contents = std::fs::read_to_string(path)?;
+    Ok(contents
+        .lines()
+        .filter(|line| !line.starts_with('#') && !line.starts_with('^'))
+        .filter_map(|line| {
+            let (sha, name) = line.split_once(' ')?;
+            Some((name.to_owned(), sha.to_owned()))
+        })
+        .collect())
+}
+
+///Check a full ref name (e.g. refs/heads/main) against git's check-ref-format rules
+pub(crate) fn is_valid_ref_name(name: &str) -> bool {
+    if name.is_empty() || name == "@" || name.starts_with('/') || name.ends_with('/') || name.ends_with('.') {
+        return false;
+    }
+    if name.contains("..") || name.contains("@{") || name.contains("//") {
+        return false;
+    }
+    let bad_char = |c: char| c.is_ascii_control() || " ~^:?*[\\".contains(c);
+    if name.chars().any(bad_char) {
+        return false;
+    }
+    name.split('/')
+        .all(|component| !component.starts_with('.') && !component.ends_with(".lock"))
+}
+
+///Check a short branch name by checking the ref it would create
+pub(crate) fn is_valid_branch_name(name: &str) -> bool {
+    !name.starts_with('-') && name != "HEAD" && is_valid_ref_name(&format!("refs/heads/{}", name))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lib::get_test_dir;
+    use crate::lib::objects::git_config::ConfigFile;
+
+    #[test]
+    fn validates_ref_names() {
+        assert!(is_valid_ref_name("refs/heads/main"));
+        assert!(is_valid_ref_name("refs/heads/feature/one"));
+        assert!(!is_valid_ref_name("refs/heads/.hidden"));
+        assert!(!is_valid_ref_name("refs/heads/a..b"));
+        assert!(!is_valid_ref_name("refs/heads/topic.lock"));
+        assert!(!is_valid_ref_name("refs/heads/with space"));
+        assert!(!is_valid_ref_name("refs/heads/trailing/"));
+        assert!(!is_valid_branch_name("-dash"));
+        assert!(!is_valid_branch_name("HEAD"));
+    }
+
+    #[test]
+    fn resolves_symbolic_and_packed_refs() {
+        let test_dir = get_test_dir("resolves_symbolic_and_packed_refs");
+        if test_dir.exists() {
+            std::fs::remove_dir_all(&test_dir).expect("unable to clean directory");
+        }
+        let repo = GitRepository::new(test_dir.clone(), test_dir.join(".git"), ConfigFile::new());
+        let sha = "4494953d947add7f87c652ad6bdf7243cc945041";
+        std::fs::create_dir_all(test_dir.join(".git")).unwrap();
+        std::fs::write(
+            test_dir.join(".git").join("packed-refs"),
+            format!("# pack-refs with: peeled\n{} refs/heads/main\n", sha),
+        )
+        .unwrap();
+        write_ref(&repo, "HEAD", &RefValue::Symbolic("refs/heads/main".to_owned())).unwrap();
+
+        assert!(resolve_ref(&repo, "HEAD").unwrap() == Some(sha.to_owned()));
+        assert!(read_ref(&repo, "HEAD").unwrap() == Some(RefValue::Symbolic("refs/heads/main".to_owned())));
+        assert!(resolve_ref(&repo, "refs/heads/other").unwrap().is_none());
+    }
+}
@@ -1,16 +1,23 @@
 use crate::lib::clean_unc;
-use configparser::ini::Ini;
+use crate::lib::objects::git_hash::ObjectFormat;
+use crate::lib::objects::git_config::{expand_path, parse_bool, ConfigError, ConfigFile, ConfigScope, GitConfig};
+use crate::lib::objects::git_pack::{load_pack_indexes, PackIndex};
+use std::cell::RefCell;
 use std::io::ErrorKind;
 use std::path::{Path, PathBuf};
 use std::default::Default;
 use std::fmt::{Display,Formatter};
 use std::convert::From;
+use std::rc::Rc;
 
 #[derive(Default)]
 pub struct GitRepository {
-    worktree: PathBuf,
+    worktree: Option<PathBuf>,
     gitdir: PathBuf,
-    config: Ini,
+    commondir: PathBuf,
+    config: GitConfig,
+    object_format: ObjectFormat,
+    packs: RefCell<Option<Rc<Vec<PackIndex>>>>,
 }
 
 #[derive(Debug)]
@@ -18,10 +25,14 @@ pub enum RepositoryError {
     FailedToCreateDirctory(std::io::Error),
     DirectoryDoesNotExist(String),
     NoGitDirectory(),
-    ConfigLoadFail(String),
+    ConfigLoadFail(ConfigError),
     MissingConfig(),
     UnsupportedVersion(i64),
     VersionNotFound(),
+    NoWorktree(),
+    InvalidGitFile(String),
+    UnsupportedExtension(String),
+    UnsafeDirectory(String),
 }
 
 impl From<std::io::Error> for RepositoryError {
@@ -30,6 +41,12 @@ impl From<std::io::Error> for RepositoryError {
     }
 }
 
+impl From<ConfigError> for RepositoryError {
+    fn from(config_err: ConfigError) -> Self {
+        RepositoryError::ConfigLoadFail(config_err)
+    }
+}
+
 impl Display for RepositoryError {
     fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
         match self {
@@ -38,79 +55,290 @@ impl Display for RepositoryError {
             RepositoryError::NoGitDirectory() => {write!(f, "Git directory not found")},
             RepositoryError::ConfigLoadFail(msg) => {write!(f, "Failed to load config: {}",msg)},
             RepositoryError::MissingConfig() => {write!(f, "Config file not found")},
-            RepositoryError::UnsupportedVersion(num) => {write!(f, "Unsuppored version: {}, only 1.0 supported", num)},
+            RepositoryError::UnsupportedVersion(num) => {write!(f, "Unsupported repository format version: {}, only 0 and 1 are supported", num)},
             RepositoryError::VersionNotFound() => {write!(f, "Unable to find key for repository version in config")},
+            RepositoryError::NoWorktree() => {write!(f, "This operation must be run in a work tree")},
+            RepositoryError::InvalidGitFile(path) => {write!(f, "Invalid gitfile format: {}", path)},
+            RepositoryError::UnsupportedExtension(ext) => {write!(f, "Unsupported repository extension: {}", ext)},
+            RepositoryError::UnsafeDirectory(path) => {
+                write!(f, "detected dubious ownership in repository at '{}'\n\
+                    To add an exception for this directory, call:\n\n\
+                    \tgit config --global --add safe.directory {}", path, path)
+            },
 
         }
     }
 }
 
 impl GitRepository {
-    pub fn new(worktree: PathBuf, gitdir: PathBuf, config: Ini) -> GitRepository {
+    pub fn new(worktree: PathBuf, gitdir: PathBuf, config: ConfigFile) -> GitRepository {
         GitRepository {
-            worktree,
+            worktree: Some(worktree),
+            commondir: gitdir.clone(),
+            object_format: ObjectFormat::from_config(&config),
+            config: GitConfig::from_files(&[(ConfigScope::Local, &gitdir.join("config"), &config)]),
             gitdir,
-            config,
+            packs: RefCell::default(),
+        }
+    }
+
+    ///Create a repository that only has a git directory
+    pub fn new_bare(gitdir: PathBuf, config: ConfigFile) -> GitRepository {
+        GitRepository {
+            worktree: None,
+            commondir: gitdir.clone(),
+            object_format: ObjectFormat::from_config(&config),
+            config: GitConfig::from_files(&[(ConfigScope::Local, &gitdir.join("config"), &config)]),
+            gitdir,
+            packs: RefCell::default(),
         }
     }
 
-    pub fn worktree(&self) -> &Path {
-        self.worktree.as_ref()
+    ///Returns the worktree, or an error for bare repositories
+    pub fn worktree(&self) -> Result<&Path, RepositoryError> {
+        self.worktree.as_deref().ok_or(RepositoryError::NoWorktree())
+    }
+
+    ///Where a directory sits in the worktree as a `/` separated prefix ending in `/`,
+    ///empty at the top or when the directory is outside the worktree
+    pub fn prefix_of<P: AsRef<Path>>(&self, dir: P) -> Result<String, RepositoryError> {
+        let worktree = clean_unc(self.worktree()?.canonicalize()?);
+        let dir = clean_unc(dir.as_ref().canonicalize()?);
+        let prefix = match dir.strip_prefix(&worktree) {
+            Ok(relative) => relative
+                .components()
+                .map(|part| format!("{}/", part.as_os_str().to_string_lossy()))
+                .collect(),
+            Err(_) => String::new(),
+        };
+        Ok(prefix)
+    }
+
+    pub fn is_bare(&self) -> bool {
+        self.worktree.is_none()
     }
 
+    ///The git directory of this worktree, holding HEAD and the index
     pub fn gitdir(&self) -> &Path {
         self.gitdir.as_ref()
     }
 
-    pub fn config(&self) -> &Ini {
+    ///The git directory shared by all worktrees, holding objects, refs and config
+    pub fn commondir(&self) -> &Path {
+        self.commondir.as_ref()
+    }
+
+    ///Whether this is a worktree added with `worktree add` rather than the main one
+    pub fn is_linked_worktree(&self) -> bool {
+        self.gitdir != self.commondir
+    }
+
+    pub fn config(&self) -> &GitConfig {
         &self.config
     }
 
+    ///The hash algorithm naming this repository's objects
+    pub fn object_format(&self) -> ObjectFormat {
+        self.object_format
+    }
+
+    ///The indexes of the packs in objects/pack, read the first time an object is looked up there
+    pub(crate) fn pack_indexes(&self) -> Result<Rc<Vec<PackIndex>>, std::io::Error> {
+        if let Some(packs) = self.packs.borrow().as_ref() {
+            return Ok(Rc::clone(packs));
+        }
+        let packs = Rc::new(load_pack_indexes(self)?);
+        *self.packs.borrow_mut() = Some(Rc::clone(&packs));
+        Ok(packs)
+    }
+
+    ///Open the repository whose worktree is at the given path, or the bare repository whose git directory it is
     pub fn at_path<P: Into<PathBuf>>(path: P, force: bool) -> Result<GitRepository, RepositoryError> {
-        let worktree: PathBuf = path.into();
-        let gitdir = worktree.join(".git");
-        let mut config = Ini::new();
-        let config_path = gitdir.join("config");
+        let (worktree, gitdir) = split_repo_dir(path.into())?;
+        if !force {
+            check_ownership(worktree.as_deref(), &gitdir)?;
+        }
+        GitRepository::open(gitdir, worktree, None, force)
+    }
+
+    ///Returns a repo object pointing to a .git folder found anywhere along the given path,
+    ///honoring the GIT_DIR family of environment variables
+    pub fn along_path<P: AsRef<Path>>(path: P, force: bool) -> Result<GitRepository, RepositoryError>{
+        GitRepository::discover(path, &DiscoveryOptions::from_env(), force)
+    }
+
+    ///Finds the repository for the given path using explicit discovery options
+    pub fn discover<P: AsRef<Path>>(path: P, options: &DiscoveryOptions, force: bool) -> Result<GitRepository, RepositoryError> {
+        let path = path.as_ref();
+        let work_tree = options.work_tree.as_ref().map(|tree| path.join(tree));
+        if let Some(git_dir) = &options.git_dir {
+            let mut gitdir = path.join(git_dir);
+            if gitdir.is_file() {
+                gitdir = read_gitfile(&gitdir)?;
+            }
+            if !force && !is_git_dir(&gitdir) {
+                return Err(RepositoryError::NoGitDirectory());
+            }
+            //With an explicit git dir and no other hints, the current directory is the worktree.
+            //Naming the repository outright vouches for it, so ownership is not checked
+            let repo = GitRepository::open(gitdir, Some(path.to_owned()), work_tree, force)?;
+            return Ok(repo);
+        }
+        let repo_dir = find_repo_dir(path, options)?;
+        let (worktree, gitdir) = split_repo_dir(repo_dir)?;
+        if !force {
+            check_ownership(worktree.as_deref(), &gitdir)?;
+        }
+        GitRepository::open(gitdir, worktree, work_tree, force)
+    }
+
+    ///Load and validate the repository configuration. An explicit worktree overrides both
+    ///the discovered one and core.bare/core.worktree, which linked worktrees ignore
+    fn open(gitdir: PathBuf, worktree: Option<PathBuf>, explicit_worktree: Option<PathBuf>, force: bool) -> Result<GitRepository, RepositoryError> {
+        let commondir = read_commondir(&gitdir)?;
+        let mut local = ConfigFile::new();
+        let config_path = commondir.join("config");
 
         if !force && !gitdir.exists() {
             return Err(RepositoryError::NoGitDirectory());
         }
-
         if config_path.is_file() {
-            if let Err(msg) = config.load(config_path.to_str().unwrap()) {
-                return Err(RepositoryError::ConfigLoadFail(msg));
-            }
+            local = ConfigFile::load(&config_path)?;
         } else if !force {
             return Err(RepositoryError::MissingConfig());
         }
 
         if !force {
-            if let Ok(Some(ver)) = config.getint("core", "repositoryformatversion") {
-                if ver != 0 {
-                    return Err(RepositoryError::UnsupportedVersion(ver));
-                }
-            } else {
-                return Err(RepositoryError::VersionNotFound());
+            match local.get_int("core.repositoryformatversion")? {
+                Some(0) => (),
+                Some(1) => check_extensions(&local)?,
+                Some(ver) => return Err(RepositoryError::UnsupportedVersion(ver)),
+                None => return Err(RepositoryError::VersionNotFound()),
             }
         }
 
+        let worktree_config_path = gitdir.join("config.worktree");
+        let worktree_config = if extension_enabled(&local, "worktreeconfig") && worktree_config_path.is_file() {
+            Some(ConfigFile::load(&worktree_config_path)?)
+        } else {
+            None
+        };
+        let mut files = vec![(ConfigScope::Local, config_path.as_path(), &local)];
+        if let Some(file) = &worktree_config {
+            files.push((ConfigScope::Worktree, worktree_config_path.as_path(), file));
+        }
+        let config = GitConfig::for_repository(&gitdir, &files)?;
+        //Only the repository's own files decide whether it is bare or where its worktree is
+        let repo_settings = config.in_scopes(&[ConfigScope::Local, ConfigScope::Worktree]);
+
+        let worktree = if explicit_worktree.is_some() {
+            explicit_worktree
+        } else if commondir != gitdir {
+            worktree.or_else(|| linked_worktree_path(&gitdir))
+        } else if let Some(core_worktree) = repo_settings.get("core.worktree") {
+            Some(gitdir.join(core_worktree))
+        } else {
+            match repo_settings.get_bool("core.bare") {
+                Ok(Some(true)) => None,
+                _ => worktree,
+            }
+        };
+
         Ok(GitRepository {
             worktree,
             gitdir,
+            commondir,
+            object_format: ObjectFormat::from_config(&local),
             config,
+            packs: RefCell::default(),
         })
     }
 
-    ///Returns a repo object pointing to a .git folder found anywhere along the given path
-    pub fn along_path<P: AsRef<Path>>(path: P, force: bool) -> Result<GitRepository, RepositoryError>{
-        let git_dir_folder = find_repo_dir(path.as_ref())?;
-        GitRepository::at_path(git_dir_folder, force)
+} //impl GitRepo
+
+///Repository extensions (lowercased, as config keys are case insensitive) that wyag understands
+const KNOWN_EXTENSIONS: [&str; 4] = ["noop", "objectformat", "preciousobjects", "worktreeconfig"];
+
+///Object formats allowed for extensions.objectFormat
+const OBJECT_FORMATS: [&str; 2] = ["sha1", "sha256"];
+
+///Refuse version 1 repositories that rely on extensions wyag does not implement
+fn check_extensions(config: &ConfigFile) -> Result<(), RepositoryError> {
+    for (key, value) in config.entries() {
+        let name = match key.strip_prefix("extensions.") {
+            Some(name) => name,
+            None => continue,
+        };
+        if !KNOWN_EXTENSIONS.contains(&name) {
+            return Err(RepositoryError::UnsupportedExtension(name.to_owned()));
+        }
+        if name == "objectformat" {
+            let format = value.unwrap_or_default().to_lowercase();
+            if !OBJECT_FORMATS.contains(&format.as_str()) {
+                return Err(RepositoryError::UnsupportedExtension(format!("objectformat={}", format)));
+            }
+        }
     }
+    Ok(())
+}
 
-} //impl GitRepo
+///Whether a boolean extension is switched on. Extensions only count in version 1 repositories
+pub(crate) fn extension_enabled(config: &ConfigFile, name: &str) -> bool {
+    matches!(config.get_int("core.repositoryformatversion"), Ok(Some(1)))
+        && matches!(config.get_bool(&format!("extensions.{}", name)), Ok(Some(true)))
+}
 
+///Paths shared between worktrees, with the exceptions that stay per worktree.
+///The longest matching prefix decides, anything unlisted belongs to the worktree
+const COMMON_PATHS: [(&str, bool); 24] = [
+    ("branches", true),
+    ("common", true),
+    ("config", true),
+    ("gc.pid", true),
+    ("hooks", true),
+    ("info", true),
+    ("info/sparse-checkout", false),
+    ("logs", true),
+    ("logs/HEAD", false),
+    ("logs/refs/bisect", false),
+    ("logs/refs/rewritten", false),
+    ("logs/refs/worktree", false),
+    ("lost-found", true),
+    ("objects", true),
+    ("packed-refs", true),
+    ("refs", true),
+    ("refs/bisect", false),
+    ("refs/rewritten", false),
+    ("refs/worktree", false),
+    ("remotes", true),
+    ("rr-cache", true),
+    ("shallow", true),
+    ("svn", true),
+    ("worktrees", true),
+];
+
+///Path of a file in the repository, inside the common or per-worktree git directory as appropriate
 pub(crate) fn repo_path<P: AsRef<Path>>(repo: &GitRepository, path: P) -> PathBuf {
-    repo.gitdir().join(path)
+    let path = path.as_ref();
+    if is_common_path(path) {
+        repo.commondir().join(path)
+    } else {
+        repo.gitdir().join(path)
+    }
+}
+
+fn is_common_path(path: &Path) -> bool {
+    let mut shared = false;
+    let mut longest = 0;
+    for (prefix, is_shared) in COMMON_PATHS.iter() {
+        let prefix_path: PathBuf = prefix.split('/').collect();
+        let depth = prefix_path.components().count();
+        if path.starts_with(&prefix_path) && depth > longest {
+            longest = depth;
+            shared = *is_shared;
+        }
+    }
+    shared
 }
 
 pub(crate) fn repo_dir<P: AsRef<Path>>(
@@ -138,13 +366,14 @@ pub(crate) fn repo_file<P: AsRef<Path>>(
     path: P,
     mkdir: bool,
 ) -> Result<PathBuf, std::io::Error> {
-    let path = repo_path(repo, path);
+    let rel_path = path.as_ref();
+    let path = repo_path(repo, rel_path);
     if path.is_file() {
         Ok(path)
     } else if mkdir {
-        let mut dir = path.clone();
-        dir.pop();
-        repo_dir(repo, &dir, mkdir)?;
+        if let Some(dir) = rel_path.parent() {
+            repo_dir(repo, dir, mkdir)?;
+        }
         std::fs::File::create(&path)?;
         Ok(path)
     } else {
@@ -155,34 +384,208 @@ pub(crate) fn repo_file<P: AsRef<Path>>(
     }
 }
 
-///Return the path of the folder containing the .git along the given path
-fn find_repo_dir<P: Into<PathBuf>>(path: P) -> Result<PathBuf, RepositoryError> {
-    let path = path.into().canonicalize()?;
-    let path = clean_unc(path);
-    let git_dir = path.join(".git");
+///Checks whether the directory has the layout of a git directory (HEAD, objects and refs).
+///A linked worktree's directory keeps only HEAD, so objects and refs are looked for in its commondir
+pub(crate) fn is_git_dir(path: &Path) -> bool {
+    if !path.join("HEAD").is_file() {
+        return false;
+    }
+    match read_commondir(path) {
+        Ok(common) => common.join("objects").is_dir() && common.join("refs").is_dir(),
+        Err(_) => false,
+    }
+}
+
+///Split a directory found by discovery into its worktree (if any) and git directory,
+///following a `.git` gitfile to the directory it names
+fn split_repo_dir(path: PathBuf) -> Result<(Option<PathBuf>, PathBuf), RepositoryError> {
+    let dot_git = path.join(".git");
+    if dot_git.is_file() {
+        let gitdir = read_gitfile(&dot_git)?;
+        Ok((Some(path), gitdir))
+    } else if !dot_git.exists() && is_git_dir(&path) {
+        Ok((None, path))
+    } else {
+        Ok((Some(path), dot_git))
+    }
+}
 
-    if git_dir.exists() {
-        Ok(path)
+///Read a `gitdir: <path>` file, resolving the path relative to the file's directory
+pub(crate) fn read_gitfile(path: &Path) -> Result<PathBuf, RepositoryError> {
+    let invalid = || RepositoryError::InvalidGitFile(path.to_string_lossy().into_owned());
+    let contents = std::fs::read_to_string(path).map_err(|_| invalid())?;
+    let target = contents
+        .trim_end_matches(['\n', '\r'])
+        .strip_prefix("gitdir: ")
+        .ok_or_else(invalid)?;
+    let base = path.parent().unwrap_or_else(|| Path::new(""));
+    let gitdir = base.join(target);
+    if gitdir.is_dir() {
+        Ok(gitdir)
+    } else {
+        Err(RepositoryError::DirectoryDoesNotExist(gitdir.to_string_lossy().into_owned()))
+    }
+}
+
+///The common git directory named in `<gitdir>/commondir`, or the git directory itself
+fn read_commondir(gitdir: &Path) -> Result<PathBuf, RepositoryError> {
+    let commondir_file = gitdir.join("commondir");
+    if commondir_file.is_file() {
+        let contents = std::fs::read_to_string(&commondir_file)?;
+        Ok(clean_unc(gitdir.join(contents.trim_end()).canonicalize()?))
+    } else {
+        Ok(gitdir.to_owned())
+    }
+}
+
+///The worktree recorded in a linked worktree's `gitdir` file (which points at its `.git` file)
+pub(crate) fn linked_worktree_path(admin_dir: &Path) -> Option<PathBuf> {
+    let contents = std::fs::read_to_string(admin_dir.join("gitdir")).ok()?;
+    let dot_git = admin_dir.join(contents.trim_end());
+    dot_git.parent().map(Path::to_owned)
+}
+
+///Settings that change how a repository is found, normally read from the environment
+#[derive(Default)]
+pub struct DiscoveryOptions {
+    pub git_dir: Option<PathBuf>,
+    pub work_tree: Option<PathBuf>,
+    pub ceiling_dirs: Vec<PathBuf>,
+    pub across_filesystem: bool,
+}
+
+impl DiscoveryOptions {
+    ///Read GIT_DIR, GIT_WORK_TREE, GIT_CEILING_DIRECTORIES and GIT_DISCOVERY_ACROSS_FILESYSTEM
+    pub fn from_env() -> DiscoveryOptions {
+        let non_empty = |name: &str| std::env::var_os(name).filter(|val| !val.is_empty());
+        let ceiling_dirs = non_empty("GIT_CEILING_DIRECTORIES")
+            .map(|dirs| {
+                std::env::split_paths(&dirs)
+                    .filter(|dir| dir.is_absolute())
+                    .collect()
+            })
+            .unwrap_or_default();
+        let across_filesystem = non_empty("GIT_DISCOVERY_ACROSS_FILESYSTEM")
+            .and_then(|val| parse_bool(Some(&val.to_string_lossy())))
+            .unwrap_or(false);
+        DiscoveryOptions {
+            git_dir: non_empty("GIT_DIR").map(PathBuf::from),
+            work_tree: non_empty("GIT_WORK_TREE").map(PathBuf::from),
+            ceiling_dirs,
+            across_filesystem,
+        }
+    }
+}
+
+///Return the folder containing the .git (or the bare git directory) along the given path,
+///never stepping into a ceiling directory or, unless allowed, onto another filesystem
+fn find_repo_dir<P: Into<PathBuf>>(path: P, options: &DiscoveryOptions) -> Result<PathBuf, RepositoryError> {
+    let path = clean_unc(path.into().canonicalize()?);
+    let ceilings: Vec<PathBuf> = options
+        .ceiling_dirs
+        .iter()
+        .filter_map(|dir| dir.canonicalize().ok())
+        .map(clean_unc)
+        .collect();
+    let start_device = device_id(&path);
+    let mut current = path.as_path();
+
+    loop {
+        if current.join(".git").exists() || is_git_dir(current) {
+            return Ok(current.to_owned());
+        }
+        let parent = current.parent().ok_or(RepositoryError::NoGitDirectory())?;
+        if ceilings.iter().any(|ceiling| ceiling == parent) {
+            return Err(RepositoryError::NoGitDirectory());
+        }
+        if !options.across_filesystem && device_id(parent) != start_device {
+            return Err(RepositoryError::NoGitDirectory());
+        }
+        current = parent;
+    }
+}
+
+///Refuse a repository owned by another user unless system or global safe.directory lists
+///it, so their config and hooks never run on our behalf
+fn check_ownership(worktree: Option<&Path>, gitdir: &Path) -> Result<(), RepositoryError> {
+    if worktree.is_none_or(is_owned_by_current_user) && is_owned_by_current_user(gitdir) {
+        return Ok(());
+    }
+    let dir = worktree.unwrap_or(gitdir);
+    let dir = dir.canonicalize().map(clean_unc).unwrap_or_else(|_| dir.to_owned());
+    let protected = GitConfig::without_repository()?.in_scopes(&[ConfigScope::System, ConfigScope::Global]);
+    if is_safe_directory(&dir, &protected.get_all("safe.directory")) {
+        Ok(())
     } else {
-        if let Some(parent) = path.parent() {
-            find_repo_dir(parent.to_str().unwrap())
+        Err(RepositoryError::UnsafeDirectory(dir.to_string_lossy().into_owned()))
+    }
+}
+
+///Check a directory against safe.directory values in order: `*` allows everything, a
+///trailing `/*` allows everything below a path, and an empty value clears earlier entries
+fn is_safe_directory(dir: &Path, values: &[Option<&str>]) -> bool {
+    let mut safe = false;
+    for value in values.iter().map(|value| value.unwrap_or("")) {
+        if value.is_empty() {
+            safe = false;
+        } else if value == "*" {
+            safe = true;
+        } else if let Some(prefix) = value.strip_suffix("/*") {
+            safe |= dir.starts_with(expand_path(prefix));
         } else {
-            Err(RepositoryError::NoGitDirectory())
+            let allowed = expand_path(value);
+            let allowed = allowed.canonicalize().map(clean_unc).unwrap_or(allowed);
+            safe |= allowed == dir;
         }
     }
+    safe
+}
+
+#[cfg(unix)]
+fn is_owned_by_current_user(path: &Path) -> bool {
+    use std::os::unix::fs::MetadataExt;
+    let owner = match std::fs::metadata(path) {
+        Ok(meta) => meta.uid(),
+        Err(_) => return true,
+    };
+    let mut uid = unsafe { libc::geteuid() };
+    //Like git, root running through sudo acts for the user who ran sudo
+    if uid == 0 {
+        if let Some(sudo_uid) = std::env::var("SUDO_UID").ok().and_then(|id| id.parse().ok()) {
+            uid = sudo_uid;
+        }
+    }
+    owner == uid
+}
+
+#[cfg(not(unix))]
+fn is_owned_by_current_user(_path: &Path) -> bool {
+    true
+}
+
+#[cfg(unix)]
+fn device_id(path: &Path) -> Option<u64> {
+    use std::os::unix::fs::MetadataExt;
+    std::fs::metadata(path).ok().map(|meta| meta.dev())
+}
+
+#[cfg(not(unix))]
+fn device_id(_path: &Path) -> Option<u64> {
+    None
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::lib::commands::{init, InitOptions};
     use crate::lib::get_test_dir;
-    use configparser::ini::Ini;
+    use crate::lib::objects::git_config::ConfigFile;
     use std::path::{Path, PathBuf};
 
     fn init_test_repo(temp_dir: &str) -> GitRepository {
         let worktree = get_test_dir(temp_dir);
         let gitdir = worktree.join(".git");
-        let config = Ini::new();
+        let config = ConfigFile::new();
         GitRepository::new(worktree, gitdir, config)
     }
 
@@ -215,6 +618,7 @@ mod tests {
     }
 
     #[test]
+    #[allow(clippy::expect_fun_call)]
     fn create_repo_file() {
         let test_repo = init_test_repo("create_repo_file");
         let rel_path = ["objects", "tags", "test"].iter().collect::<PathBuf>();
@@ -238,6 +642,28 @@ mod tests {
     }
 
     #[test]
+    fn open_bare_repo() {
+        let test_dir = get_test_dir("open_bare_repo");
+        if test_dir.exists() {
+            std::fs::remove_dir_all(&test_dir).expect("unable to clean directory");
+        }
+        let options = InitOptions {
+            bare: true,
+            ..InitOptions::default()
+        };
+        init(&test_dir, &options).expect("Error initializing bare repo");
+
+        let repo = GitRepository::at_path(&test_dir, false).expect("Error opening bare repo");
+        assert!(repo.is_bare());
+        assert!(repo.gitdir() == test_dir);
+        match repo.worktree() {
+            Err(RepositoryError::NoWorktree()) => (),
+            _ => panic!("bare repo should not have a worktree"),
+        }
+    }
+
+    #[test]
+    #[allow(clippy::assertions_on_constants)]
     fn find_repo_in_path() {
         let test_dir = get_test_dir("find_repo_in_path");
         let deep_dir = test_dir.join(["A", "B", "C"].iter().collect::<PathBuf>());
@@ -249,7 +675,7 @@ mod tests {
             std::fs::create_dir(&git_dir).expect("Problem creating git directory");
         }
 
-        match find_repo_dir(deep_dir.to_str().unwrap()) {
+        match find_repo_dir(deep_dir.to_str().unwrap(), &DiscoveryOptions::default()) {
             //There is weird case stuff here that only works on Windows, that's why there is the to_lower
             Ok(repo_dir) => assert!(
                 repo_dir.to_str().unwrap().to_lowercase()
@@ -262,4 +688,168 @@ mod tests {
             Err(error) => assert!(false, "Problem finding repo: {}", error),
         }
     }
+
+    #[test]
+    fn discovery_stops_at_ceiling() {
+        let test_dir = get_test_dir("discovery_stops_at_ceiling");
+        let deep_dir = test_dir.join(["A", "B"].iter().collect::<PathBuf>());
+        if test_dir.exists() {
+            std::fs::remove_dir_all(&test_dir).expect("unable to clean directory");
+        }
+        std::fs::create_dir_all(&deep_dir).expect("Problem creating directory structure");
+        init(&test_dir, &InitOptions::default()).expect("Error initializing repo");
+
+        let mut options = DiscoveryOptions::default();
+        assert!(GitRepository::discover(&deep_dir, &options, false).is_ok());
+
+        options.ceiling_dirs = vec![test_dir.join("A")];
+        assert!(GitRepository::discover(&deep_dir, &options, false).is_err());
+        //The ceiling only stops the walk upwards, it can still be a repository itself
+        assert!(GitRepository::discover(&test_dir, &options, false).is_ok());
+    }
+
+    #[test]
+    fn discover_bare_and_explicit_git_dir() {
+        let test_dir = get_test_dir("discover_bare_and_explicit_git_dir");
+        let bare_dir = test_dir.join("repo.git");
+        let work_dir = test_dir.join("work");
+        if test_dir.exists() {
+            std::fs::remove_dir_all(&test_dir).expect("unable to clean directory");
+        }
+        let options = InitOptions {
+            bare: true,
+            ..InitOptions::default()
+        };
+        init(&bare_dir, &options).expect("Error initializing bare repo");
+        std::fs::create_dir_all(bare_dir.join("refs").join("heads").join("nested")).unwrap();
+        std::fs::create_dir_all(&work_dir).unwrap();
+
+        let nested = bare_dir.join(["refs", "heads", "nested"].iter().collect::<PathBuf>());
+        let repo = GitRepository::discover(&nested, &DiscoveryOptions::default(), false)
+            .expect("Error discovering bare repo");
+        assert!(repo.is_bare());
+
+        let options = DiscoveryOptions {
+            git_dir: Some(bare_dir.clone()),
+            work_tree: Some(work_dir.clone()),
+            ..DiscoveryOptions::default()
+        };
+        let repo = GitRepository::discover(&test_dir, &options, false).expect("Error opening GIT_DIR");
+        assert!(repo.gitdir() == bare_dir);
+        assert!(repo.worktree().expect("work tree should be set") == work_dir);
+
+        //A linked worktree's git directory holds HEAD, its objects and refs live in the commondir
+        let admin_dir = bare_dir.join("worktrees").join("linked");
+        std::fs::create_dir_all(&admin_dir).unwrap();
+        std::fs::write(admin_dir.join("HEAD"), "ref: refs/heads/linked\n").unwrap();
+        std::fs::write(admin_dir.join("commondir"), "../..\n").unwrap();
+        let options = DiscoveryOptions {
+            git_dir: Some(admin_dir.clone()),
+            ..DiscoveryOptions::default()
+        };
+        let repo = GitRepository::discover(&work_dir, &options, false).expect("Error opening linked worktree GIT_DIR");
+        assert!(repo.gitdir() == admin_dir && repo.is_linked_worktree());
+        assert!(repo.commondir() == bare_dir.canonicalize().unwrap());
+        assert!(repo.worktree().expect("work tree should be set") == work_dir);
+    }
+
+    #[test]
+    fn routes_common_and_worktree_paths() {
+        assert!(is_common_path(Path::new("objects")));
+        assert!(is_common_path(&["refs", "heads", "main"].iter().collect::<PathBuf>()));
+        assert!(is_common_path(Path::new("config")));
+        assert!(!is_common_path(Path::new("HEAD")));
+        assert!(!is_common_path(Path::new("index")));
+        assert!(!is_common_path(&["logs", "HEAD"].iter().collect::<PathBuf>()));
+        assert!(!is_common_path(&["refs", "bisect", "bad"].iter().collect::<PathBuf>()));
+        assert!(!is_common_path(Path::new("config.worktree")));
+    }
+
+    #[test]
+    fn validate_format_version_and_extensions() {
+        let test_dir = get_test_dir("validate_format_version_and_extensions");
+        if test_dir.exists() {
+            std::fs::remove_dir_all(&test_dir).expect("unable to clean directory");
+        }
+        init(&test_dir, &InitOptions::default()).expect("Error initializing repo");
+        let config_path = test_dir.join(".git").join("config");
+        let write_config = |extra: &str| {
+            std::fs::write(&config_path, format!("[core]\nbare = false\n{}", extra)).unwrap();
+        };
+
+        write_config("repositoryformatversion = 1\n[extensions]\nnoop = true\npreciousObjects = true\nobjectFormat = sha1\n");
+        assert!(GitRepository::at_path(&test_dir, false).is_ok());
+
+        write_config("repositoryformatversion = 1\n[extensions]\nfancyNewThing = true\n");
+        match GitRepository::at_path(&test_dir, false) {
+            Err(RepositoryError::UnsupportedExtension(name)) => assert!(name == "fancynewthing", "was {}", name),
+            _ => panic!("unknown extension should be refused"),
+        }
+
+        //Version 0 repositories ignore extensions entirely
+        write_config("repositoryformatversion = 0\n[extensions]\nfancyNewThing = true\n");
+        assert!(GitRepository::at_path(&test_dir, false).is_ok());
+
+        write_config("repositoryformatversion = 2\n");
+        assert!(GitRepository::at_path(&test_dir, false).is_err());
+
+        write_config("repositoryformatversion = 1\n[extensions]\nworktreeConfig = true\n");
+        std::fs::write(test_dir.join(".git").join("config.worktree"), "[core]\nbare = true\n").unwrap();
+        let repo = GitRepository::at_path(&test_dir, false).expect("Error opening repo");
+        assert!(repo.is_bare());
+    }
+
+    #[test]
+    fn checks_safe_directories() {
+        let test_dir = get_test_dir("checks_safe_directories");
+        std::fs::create_dir_all(test_dir.join("repo")).expect("Error creating directory");
+        let repo_dir = test_dir.join("repo").canonicalize().unwrap();
+        let repo_str = repo_dir.to_string_lossy().into_owned();
+        let parent_glob = format!("{}/*", test_dir.canonicalize().unwrap().to_string_lossy());
+
+        assert!(!is_safe_directory(&repo_dir, &[]));
+        assert!(is_safe_directory(&repo_dir, &[Some("*")]));
+        assert!(is_safe_directory(&repo_dir, &[Some(&repo_str)]));
+        assert!(is_safe_directory(&repo_dir, &[Some(&parent_glob)]));
+        assert!(!is_safe_directory(&repo_dir, &[Some("*"), Some("")]));
+        assert!(!is_safe_directory(&repo_dir, &[Some("/somewhere/else")]));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn detects_foreign_owner() {
+        use std::os::unix::fs::{chown, MetadataExt};
+        let test_dir = get_test_dir("detects_foreign_owner");
+        if test_dir.exists() {
+            std::fs::remove_dir_all(&test_dir).expect("unable to clean directory");
+        }
+        init(&test_dir, &InitOptions::default()).expect("Error initializing repo");
+        let git_dir = test_dir.join(".git");
+        assert!(is_owned_by_current_user(&test_dir));
+        //Handing the repository to another user only works when running as root
+        if unsafe { libc::geteuid() } != 0 || std::env::var("SUDO_UID").is_ok() {
+            eprintln!("skipping detects_foreign_owner: changing owners needs root");
+            return;
+        }
+        let owner = std::fs::metadata(&test_dir).unwrap().uid();
+        let git_dir_owner = std::fs::metadata(&git_dir).unwrap().uid();
+        chown(&test_dir, Some(65534), None).expect("Error changing owner");
+        chown(&git_dir, Some(65534), None).expect("Error changing owner");
+        let foreign = !is_owned_by_current_user(&test_dir);
+        let discovered = GitRepository::at_path(&test_dir, false);
+        let options = DiscoveryOptions {
+            git_dir: Some(git_dir.clone()),
+            ..DiscoveryOptions::default()
+        };
+        let explicit = GitRepository::discover(&test_dir, &options, false);
+        chown(&test_dir, Some(owner), None).expect("Error restoring owner");
+        chown(&git_dir, Some(git_dir_owner), None).expect("Error restoring owner");
+
+        assert!(foreign);
+        match discovered {
+            Err(RepositoryError::UnsafeDirectory(path)) => assert!(path.ends_with("detects_foreign_owner"), "path was {}", path),
+            _ => panic!("a repository owned by someone else should be refused"),
+        }
+        assert!(explicit.is_ok(), "an explicit git dir is trusted");
+    }
 }
@@ -0,0 +1,597 @@
+use crate::lib::objects::git_attributes::AttrSource;
+use crate::lib::objects::git_commit::Commit;
+use crate::lib::objects::git_convert::{ConvertError, Converter};
+use crate::lib::objects::git_ignore::IgnoreRules;
+use crate::lib::objects::git_index::{GitIndex, IndexEntry, IndexError, StatData};
+use crate::lib::objects::git_object::{GitObject, ObjectError};
+use crate::lib::objects::git_pathspec::Pathspec;
+use crate::lib::objects::git_refs::{read_ref, resolve_ref, RefValue};
+use crate::lib::objects::git_repository::{repo_path, GitRepository, RepositoryError};
+use crate::lib::objects::git_tree::{flatten_tree_matching, peel_to_tree};
+use crate::lib::objects::git_workdir::{mode_from_metadata, stat_matches, untracked_paths, worktree_change, StatOptions, UntrackedPath, WorktreeChange, MODE_GITLINK, MODE_SYMLINK};
+use std::collections::{HashMap, HashSet};
+use std::fmt::{Display, Formatter};
+use std::rc::Rc;
+
+///Mode and object id of a path in HEAD or the index
+pub type Blob = (u32, String);
+
+///Which untracked files to look for
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum UntrackedMode {
+    No,
+    ///Directories with nothing tracked below them are shown as a whole
+    Normal,
+    All,
+}
+
+///What a status scan should look for
+#[derive(Clone, Copy, Debug)]
+pub struct ScanOptions {
+    pub untracked: UntrackedMode,
+    pub ignored: bool,
+    pub renames: bool,
+}
+
+///A path that differs between HEAD, the index and the worktree
+#[derive(Clone, Debug, PartialEq)]
+pub struct StatusEntry {
+    pub path: String,
+    ///Letter for HEAD against the index (`A`, `M`, `D`, `R`, `T`), a space when unchanged
+    pub staged: char,
+    ///Letter for the index against the worktree (`M`, `D`, `T`, or `A` for intent-to-add)
+    pub unstaged: char,
+    pub head: Option<Blob>,
+    pub index: Option<Blob>,
+    ///Mode of the file on disk, 0 when it is missing
+    pub worktree_mode: u32,
+    ///Source path and similarity percentage of a staged rename
+    pub renamed_from: Option<(String, u32)>,
+    ///Entries at merge stages 1 to 3 when the path is unmerged
+    pub stages: Option<[Option<Blob>; 3]>,
+}
+
+impl StatusEntry {
+    fn new(path: &str) -> StatusEntry {
+        StatusEntry {
+            path: path.to_owned(),
+            staged: ' ',
+            unstaged: ' ',
+            head: None,
+            index: None,
+            worktree_mode: 0,
+            renamed_from: None,
+            stages: None,
+        }
+    }
+
+    ///Two-letter code git uses for unmerged paths, such as `UU` or `DU`
+    pub fn unmerged_code(&self) -> Option<&'static str> {
+        let stages = self.stages.as_ref()?;
+        Some(match (stages[0].is_some(), stages[1].is_some(), stages[2].is_some()) {
+            (true, false, false) => "DD",
+            (false, true, false) => "AU",
+            (true, true, false) => "UD",
+            (false, false, true) => "UA",
+            (true, false, true) => "DU",
+            (false, true, true) => "AA",
+            _ => "UU",
+        })
+    }
+}
+
+///Where HEAD is and how it relates to its upstream
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct BranchStatus {
+    ///Commit HEAD points at, None before the first commit
+    pub head: Option<String>,
+    ///Short branch name, None when detached
+    pub branch: Option<String>,
+    ///Short name of the configured upstream, such as `origin/main`
+    pub upstream: Option<String>,
+    ///Commits only on the branch and only on the upstream, None when the upstream is gone
+    pub ahead_behind: Option<(usize, usize)>,
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct RepoStatus {
+    pub branch: BranchStatus,
+    ///Changed and unmerged paths in path order
+    pub entries: Vec<StatusEntry>,
+    ///Untracked paths, directories ending in `/`
+    pub untracked: Vec<String>,
+    pub ignored: Vec<String>,
+    ///Whether a merge is waiting to be committed
+    pub merging: bool,
+    ///Warnings from converting worktree files to compare them, for the command to show
+    pub warnings: Vec<String>,
+}
+
+///Compare HEAD, the index and the worktree for the paths a pathspec selects
+pub(crate) fn repo_status(repo: &Rc<GitRepository>, pathspec: &Pathspec, options: &ScanOptions) -> Result<RepoStatus, StatusError> {
+    let worktree = repo.worktree()?;
+    //Like git, refreshing stat data is best effort: it only happens when the index lock can be
+    //taken before loading, and not at all with GIT_OPTIONAL_LOCKS=0
+    let lock = if std::env::var("GIT_OPTIONAL_LOCKS").map_or(true, |value| value != "0") {
+        GitIndex::lock(repo).ok()
+    } else {
+        None
+    };
+    let mut index = GitIndex::load(repo)?;
+    let scan_start = now_seconds();
+    let branch = branch_status(repo)?;
+    let mut convert = Converter::for_index(repo, &index, AttrSource::WorktreeFirst)?;
+    let head_entries: HashMap<String, Blob> = match &branch.head {
+        Some(head) => flatten_tree_matching(repo, &peel_to_tree(repo, head)?, pathspec, |path, name| convert.attribute(path, name))?
+            .into_iter()
+            .map(|entry| (entry.name, (entry.mode, entry.sha)))
+            .collect(),
+        None => HashMap::new(),
+    };
+
+    let mut entries: Vec<StatusEntry> = Vec::new();
+    let mut unmerged: HashMap<String, [Option<Blob>; 3]> = HashMap::new();
+    let stat_options = StatOptions::from_repo(repo);
+    let mut refreshed: Vec<(String, StatData)> = Vec::new();
+    for entry in index.entries() {
+        if !pathspec.matches(&entry.path, |path, name| convert.attribute(path, name)) {
+            continue;
+        }
+        if entry.stage != 0 {
+            unmerged.entry(entry.path.clone()).or_default()[entry.stage as usize - 1] = Some((entry.mode, entry.sha.clone()));
+            continue;
+        }
+        let mut status = StatusEntry::new(&entry.path);
+        status.head = head_entries.get(&entry.path).cloned();
+        if !entry.intent_to_add {
+            status.index = Some((entry.mode, entry.sha.clone()));
+        }
+        status.staged = staged_letter(status.head.as_ref(), status.index.as_ref());
+        if !entry.skip_worktree {
+            let (letter, mode, fresh) = unstaged_change(repo, entry, stat_options, index.timestamp(), &mut convert)?;
+            status.unstaged = letter;
+            status.worktree_mode = mode;
+            //Files changed as recently as this scan could change again unseen within the same
+            //timestamp, so they keep stale stat data and are hashed again next time
+            if let Some(stat) = fresh.filter(|stat| stat.mtime.0 < scan_start) {
+                refreshed.push((entry.path.clone(), stat));
+            }
+        } else {
+            status.worktree_mode = entry.mode;
+        }
+        if status.staged != ' ' || status.unstaged != ' ' {
+            entries.push(status);
+        }
+    }
+    if let Some(lock) = lock.filter(|_| !refreshed.is_empty()) {
+        for (path, stat) in refreshed {
+            index.refresh_stat(&path, 0, stat);
+        }
+        let _ = index.save_locked(repo, lock);
+    }
+    for (path, stages) in unmerged {
+        let mut status = StatusEntry::new(&path);
+        status.head = head_entries.get(&path).cloned();
+        status.worktree_mode = std::fs::symlink_metadata(worktree.join(&path))
+            .map(|meta| mode_from_metadata(&meta, None, stat_options.filemode))
+            .unwrap_or(0);
+        status.stages = Some(stages);
+        entries.push(status);
+    }
+    let tracked: HashSet<&str> = index.entries().iter().map(|entry| entry.path.as_str()).collect();
+    for (path, blob) in &head_entries {
+        if !tracked.contains(path.as_str()) && pathspec.matches(path, |path, name| convert.attribute(path, name)) {
+            let mut status = StatusEntry::new(path);
+            status.head = Some(blob.clone());
+            status.staged = 'D';
+            entries.push(status);
+        }
+    }
+    if options.renames {
+        detect_renames(repo, &mut entries)?;
+    }
+    entries.sort_by(|a, b| a.path.as_bytes().cmp(b.path.as_bytes()));
+
+    let mut untracked = Vec::new();
+    let mut ignored = Vec::new();
+    //Ignored files are only looked for along with untracked ones
+    if options.untracked != UntrackedMode::No {
+        let mut rules = IgnoreRules::for_repository(repo)?;
+        let tracked_dirs: HashSet<&str> = index
+            .entries()
+            .iter()
+            .flat_map(|entry| entry.path.match_indices('/').map(move |(end, _)| &entry.path[..=end]))
+            .collect();
+        let found_paths: Vec<UntrackedPath> = untracked_paths(worktree, &index, &mut rules, options.ignored)?
+            .into_iter()
+            .filter(|found| pathspec.matches(&found.path, |path, name| convert.attribute(path, name)))
+            .collect();
+        //Ignored files are shown by directory only where nothing else untracked lives
+        let with_untracked: HashSet<&str> = found_paths
+            .iter()
+            .filter(|found| !found.ignored)
+            .flat_map(|found| found.path.match_indices('/').map(move |(end, _)| &found.path[..end]))
+            .collect();
+        for found in &found_paths {
+            let path = match options.untracked {
+                UntrackedMode::All => found.path.clone(),
+                _ if found.ignored => collapse(&found.path, &tracked_dirs, |dir| !with_untracked.contains(dir) || rules.is_ignored(dir, true)),
+                _ => collapse(&found.path, &tracked_dirs, |_| true),
+            };
+            let list = if found.ignored { &mut ignored } else { &mut untracked };
+            if list.last() != Some(&path) {
+                list.push(path);
+            }
+        }
+    }
+
+    Ok(RepoStatus {
+        branch,
+        entries,
+        untracked,
+        ignored,
+        merging: repo_path(repo, "MERGE_HEAD").is_file(),
+        warnings: convert.warnings,
+    })
+}
+
+fn staged_letter(head: Option<&Blob>, index: Option<&Blob>) -> char {
+    match (head, index) {
+        (None, None) => ' ',
+        (None, Some(_)) => 'A',
+        (Some(_), None) => 'D',
+        (Some(head), Some(index)) if head == index => ' ',
+        (Some(head), Some(index)) if (head.0 == MODE_SYMLINK) != (index.0 == MODE_SYMLINK) => 'T',
+        _ => 'M',
+    }
+}
+
+///Letter and on-disk mode for a tracked file compared with its index entry, along with stat
+///data to record when the content is unchanged but the entry's stat data is stale or racy
+fn unstaged_change(
+    repo: &GitRepository,
+    entry: &IndexEntry,
+    options: StatOptions,
+    index_time: Option<(u32, u32)>,
+    convert: &mut Converter,
+) -> Result<(char, u32, Option<StatData>), StatusError> {
+    let worktree = repo.worktree()?;
+    let change = worktree_change(worktree, entry, repo.object_format(), options, index_time, convert)?;
+    if change == Some(WorktreeChange::Deleted) {
+        return Ok(('D', 0, None));
+    }
+    let meta = std::fs::symlink_metadata(worktree.join(&entry.path))?;
+    let mode = mode_from_metadata(&meta, Some(entry.mode), options.filemode);
+    let stat = StatData::from_metadata(&meta);
+    let fresh = match change {
+        None if entry.mode != MODE_GITLINK && !entry.intent_to_add => {
+            Some(stat).filter(|stat| !stat_matches(&entry.stat, stat, options) || entry.is_racy(index_time))
+        }
+        _ => None,
+    };
+    let letter = match change {
+        _ if entry.intent_to_add => 'A',
+        Some(WorktreeChange::TypeChanged) => 'T',
+        Some(_) => 'M',
+        None => ' ',
+    };
+    Ok((letter, mode, fresh))
+}
+
+fn now_seconds() -> u32 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_secs() as u32)
+        .unwrap_or(0)
+}
+
+///The outermost directory above an untracked path that holds nothing tracked and that
+///`accept` agrees to show whole, or the path itself
+fn collapse<F: FnMut(&str) -> bool>(path: &str, tracked_dirs: &HashSet<&str>, mut accept: F) -> String {
+    for (end, _) in path.match_indices('/') {
+        let dir = &path[..=end];
+        if end + 1 < path.len() && !tracked_dirs.contains(dir) && accept(&dir[..end]) {
+            return dir.to_owned();
+        }
+    }
+    path.to_owned()
+}
+
+///Pair staged deletions with staged additions of the same or similar content
+fn detect_renames(repo: &Rc<GitRepository>, entries: &mut Vec<StatusEntry>) -> Result<(), StatusError> {
+    let is_deleted = |entry: &StatusEntry| entry.staged == 'D' && entry.unstaged == ' ' && entry.stages.is_none();
+    let is_added = |entry: &StatusEntry| entry.staged == 'A' && entry.stages.is_none();
+    let deleted: Vec<usize> = (0..entries.len()).filter(|&pos| is_deleted(&entries[pos])).collect();
+    let added: Vec<usize> = (0..entries.len()).filter(|&pos| is_added(&entries[pos])).collect();
+    if deleted.is_empty() || added.is_empty() {
+        return Ok(());
+    }
+    let mut contents: HashMap<String, Vec<u8>> = HashMap::new();
+    let mut read = |sha: &str| -> Result<Vec<u8>, ObjectError> {
+        if !contents.contains_key(sha) {
+            let object = GitObject::from_internal_file(sha, repo)?;
+            contents.insert(sha.to_owned(), object.serialize().to_vec());
+        }
+        Ok(contents[sha].clone())
+    };
+
+    let mut pairs: Vec<(usize, usize, u32)> = Vec::new();
+    let mut used = HashSet::new();
+    for &target in &added {
+        let (new_mode, new_sha) = entries[target].index.clone().unwrap_or_default();
+        let mut best: Option<(usize, u32)> = None;
+        for &source in deleted.iter().filter(|source| !used.contains(*source)) {
+            let (old_mode, old_sha) = entries[source].head.clone().unwrap_or_default();
+            if (old_mode == MODE_SYMLINK) != (new_mode == MODE_SYMLINK) {
+                continue;
+            }
+            let score = if old_sha == new_sha { 100 } else { similarity(&read(&old_sha)?, &read(&new_sha)?) };
+            if score >= 50 && best.is_none_or(|(_, best_score)| score > best_score) {
+                best = Some((source, score));
+            }
+        }
+        if let Some((source, score)) = best {
+            used.insert(source);
+            pairs.push((source, target, score));
+        }
+    }
+    for (source, target, score) in &pairs {
+        entries[*target].staged = 'R';
+        entries[*target].head = entries[*source].head.clone();
+        entries[*target].renamed_from = Some((entries[*source].path.clone(), *score));
+    }
+    let mut position = 0;
+    entries.retain(|_| {
+        position += 1;
+        !used.contains(&(position - 1))
+    });
+    Ok(())
+}
+
+///Percentage of content two blobs share, counted in lines (or 64 byte pieces of long lines)
+///relative to the larger one, the way git estimates rename similarity
+fn similarity(old: &[u8], new: &[u8]) -> u32 {
+    let larger = old.len().max(new.len());
+    if larger == 0 {
+        return 100;
+    }
+    let chunks = |content: &[u8]| {
+        let mut counts: HashMap<Vec<u8>, usize> = HashMap::new();
+        let mut start = 0;
+        for pos in 0..content.len() {
+            if content[pos] == b'\n' || pos + 1 - start == 64 {
+                *counts.entry(content[start..=pos].to_vec()).or_default() += pos + 1 - start;
+                start = pos + 1;
+            }
+        }
+        if start < content.len() {
+            *counts.entry(content[start..].to_vec()).or_default() += content.len() - start;
+        }
+        counts
+    };
+    let old_chunks = chunks(old);
+    let copied: usize = chunks(new)
+        .iter()
+        .map(|(chunk, size)| old_chunks.get(chunk).map_or(0, |old_size| (*old_size).min(*size)))
+        .sum();
+    (copied * 100 / larger) as u32
+}
+
+///Where HEAD points and how far it is from the branch's upstream
+pub(crate) fn branch_status(repo: &Rc<GitRepository>) -> Result<BranchStatus, StatusError> {
+    let mut status = BranchStatus {
+        head: resolve_ref(repo, "HEAD")?,
+        ..BranchStatus::default()
+    };
+    let branch = match read_ref(repo, "HEAD")? {
+        Some(RefValue::Symbolic(target)) => target,
+        _ => return Ok(status),
+    };
+    let name = branch.strip_prefix("refs/heads/").unwrap_or(&branch).to_owned();
+    let config = repo.config();
+    let remote = config.get(&format!("branch.{}.remote", name)).map(str::to_owned);
+    let merge = config.get(&format!("branch.{}.merge", name)).map(str::to_owned);
+    status.branch = Some(name);
+    let (remote, merge) = match (remote, merge) {
+        (Some(remote), Some(merge)) => (remote, merge),
+        _ => return Ok(status),
+    };
+    let upstream_ref = match merge.strip_prefix("refs/heads/") {
+        _ if remote == "." => merge.clone(),
+        Some(upstream_branch) => format!("refs/remotes/{}/{}", remote, upstream_branch),
+        None => merge.clone(),
+    };
+    status.upstream = Some(
+        upstream_ref
+            .strip_prefix("refs/remotes/")
+            .or_else(|| upstream_ref.strip_prefix("refs/heads/"))
+            .unwrap_or(&upstream_ref)
+            .to_owned(),
+    );
+    if let (Some(local), Some(upstream)) = (&status.head, resolve_ref(repo, &upstream_ref)?) {
+        let local_history = ancestors(repo, local)?;
+        let upstream_history = ancestors(repo, &upstream)?;
+        status.ahead_behind = Some((
+            local_history.difference(&upstream_history).count(),
+            upstream_history.difference(&local_history).count(),
+        ));
+    }
+    Ok(status)
+}
+
+///A commit and everything reachable from it
+fn ancestors(repo: &Rc<GitRepository>, start: &str) -> Result<HashSet<String>, ObjectError> {
+    let mut seen = HashSet::new();
+    let mut pending = vec![start.to_owned()];
+    while let Some(sha) = pending.pop() {
+        if !seen.insert(sha.clone()) {
+            continue;
+        }
+        let commit = Commit::parse(GitObject::from_internal_file(&sha, repo)?.serialize())?;
+        pending.extend(commit.parents().into_iter().map(str::to_owned));
+    }
+    Ok(seen)
+}
+
+#[derive(Debug)]
+pub enum StatusError {
+    Repo(RepositoryError),
+    Index(IndexError),
+    Object(ObjectError),
+    Io(std::io::Error),
+    Convert(ConvertError),
+}
+
+impl Display for StatusError {
+    fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
+        match self {
+            StatusError::Repo(err) => {write!(f, "{}", err)},
+            StatusError::Index(err) => {write!(f, "{}", err)},
+            StatusError::Object(err) => {write!(f, "{}", err)},
+            StatusError::Io(err) => {write!(f, "{}", err)},
+            StatusError::Convert(err) => {write!(f, "{}", err)},
+        }
+    }
+}
+
+impl From<RepositoryError> for StatusError {
+    fn from(repo_err: RepositoryError) -> Self {
+        StatusError::Repo(repo_err)
+    }
+}
+
+impl From<IndexError> for StatusError {
+    fn from(index_err: IndexError) -> Self {
+        StatusError::Index(index_err)
+    }
+}
+
+impl From<ObjectError> for StatusError {
+    fn from(obj_err: ObjectError) -> Self {
+        StatusError::Object(obj_err)
+    }
+}
+
+impl From<std::io::Error> for StatusError {
+    fn from(io_err: std::io::Error) -> Self {
+        StatusError::Io(io_err)
+    }
+}
+
+impl From<ConvertError> for StatusError {
+    fn from(convert_err: ConvertError) -> Self {
+        StatusError::Convert(convert_err)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lib::{configured_test_repo, test_repo};
+    use crate::lib::objects::git_workdir::hash_blob;
+    use std::time::{Duration, SystemTime};
+
+    #[test]
+    fn estimates_similarity() {
+        let old = b"line1\nline2\nline3\nline4\nchanged\n";
+        let new = b"line1\nline2\nline3\nline4\nagain\n";
+        assert!(similarity(old, new) == 75, "Similarity was {}", similarity(old, new));
+        assert!(similarity(old, old) == 100);
+        assert!(similarity(b"a\n", b"b\n") == 0);
+        let tracked: HashSet<&str> = ["dir/"].iter().copied().collect();
+        assert!(collapse("dir/sub/file", &tracked, |_| true) == "dir/sub/");
+        assert!(collapse("dir/file", &tracked, |_| true) == "dir/file");
+    }
+
+    #[test]
+    fn refreshes_stale_and_racy_entries() {
+        let (test_dir, repo) = test_repo("refreshes_stale_and_racy_entries");
+        let format = repo.object_format();
+        let file = test_dir.join("a.txt");
+        std::fs::write(&file, "one\n").unwrap();
+        let hour_ago = SystemTime::now() - Duration::from_secs(3600);
+        std::fs::File::options().write(true).open(&file).unwrap().set_modified(hour_ago).unwrap();
+        let meta = std::fs::metadata(&file).unwrap();
+        let mut entry = IndexEntry::new("a.txt", &hash_blob(b"one\n", format), 0o100644, StatData::from_metadata(&meta));
+
+        //Same stat data, but the file changed in the instant the index was written
+        entry.sha = hash_blob(b"two\n", format);
+        let options = StatOptions::from_repo(&repo);
+        let mut convert = Converter::for_index(&repo, &GitIndex::new(), AttrSource::WorktreeFirst).unwrap();
+        assert!(worktree_change(&test_dir, &entry, format, options, None, &mut convert).unwrap().is_none(), "Matching stat data is trusted");
+        let racy = worktree_change(&test_dir, &entry, format, options, Some(entry.stat.mtime), &mut convert).unwrap();
+        assert!(racy == Some(WorktreeChange::Modified), "Racy entry was {:?}", racy);
+
+        entry.sha = hash_blob(b"one\n", format);
+        entry.stat = StatData::default();
+        let mut index = GitIndex::new();
+        index.add(entry);
+        index.save(&repo).expect("Error writing index");
+        let status = repo_status(&repo, &Pathspec::default(), &ScanOptions { untracked: UntrackedMode::No, ignored: false, renames: false }).unwrap();
+        assert!(status.entries.iter().all(|entry| entry.unstaged == ' '), "Unchanged content is clean");
+        let refreshed = GitIndex::load(&repo).unwrap();
+        assert!(refreshed.get("a.txt", 0).unwrap().stat == StatData::from_metadata(&meta), "Stat data was not refreshed");
+    }
+
+    #[test]
+    fn smudges_racy_entries_when_saving() {
+        let (test_dir, repo) = configured_test_repo("smudges_racy_entries_when_saving", &[("core.trustctime", "false")]);
+        let file = test_dir.join("a.txt");
+        let index_file = test_dir.join(".git").join("index");
+        let set_time = |path: &std::path::Path, time: SystemTime| std::fs::File::options().write(true).open(path).unwrap().set_modified(time).unwrap();
+        let tick = SystemTime::now() - Duration::from_secs(3600);
+
+        //Staged and written to the index within one timestamp
+        std::fs::write(&file, "one\n").unwrap();
+        set_time(&file, tick);
+        let meta = std::fs::metadata(&file).unwrap();
+        let mut index = GitIndex::new();
+        index.add(IndexEntry::new("a.txt", &hash_blob(b"one\n", repo.object_format()), 0o100644, StatData::from_metadata(&meta)));
+        index.save(&repo).expect("Error writing index");
+        set_time(&index_file, tick);
+
+        //Edited within the same timestamp, then the index is written again later
+        std::fs::write(&file, "two\n").unwrap();
+        set_time(&file, tick);
+        GitIndex::load(&repo).unwrap().save(&repo).expect("Error writing index");
+        let index = GitIndex::load(&repo).unwrap();
+        assert!(!index.get("a.txt", 0).unwrap().is_racy(index.timestamp()), "The rewritten index looks settled");
+        assert!(index.get("a.txt", 0).unwrap().stat.size == 0, "Racy entry was not smudged");
+
+        let status = repo_status(&repo, &Pathspec::default(), &ScanOptions { untracked: UntrackedMode::No, ignored: false, renames: false }).unwrap();
+        assert!(status.entries.iter().any(|entry| entry.path == "a.txt" && entry.unstaged == 'M'), "Status was {:?}", status.entries);
+    }
+
+    #[test]
+    fn skips_refresh_while_index_is_locked() {
+        let (test_dir, repo) = test_repo("skips_refresh_while_index_is_locked");
+        std::fs::write(test_dir.join("a.txt"), "one\n").unwrap();
+        let mut index = GitIndex::new();
+        index.add(IndexEntry::new("a.txt", &hash_blob(b"one\n", repo.object_format()), 0o100644, StatData::default()));
+        index.save(&repo).expect("Error writing index");
+
+        let lock = test_dir.join(".git").join("index.lock");
+        std::fs::write(&lock, "busy").unwrap();
+        let status = repo_status(&repo, &Pathspec::default(), &ScanOptions { untracked: UntrackedMode::No, ignored: false, renames: false }).unwrap();
+        assert!(status.entries.iter().all(|entry| entry.unstaged == ' '), "Status was {:?}", status.entries);
+        assert!(std::fs::read(&lock).unwrap() == b"busy", "Another process's lock was touched");
+        assert!(GitIndex::load(&repo).unwrap().get("a.txt", 0).unwrap().stat == StatData::default(), "Index was written while locked");
+    }
+
+    #[test]
+    fn collects_conversion_warnings() {
+        let (test_dir, repo) = configured_test_repo("collects_conversion_warnings", &[("core.autocrlf", "true"), ("core.safecrlf", "true")]);
+        std::fs::write(test_dir.join("a.txt"), "a\nb\n").unwrap();
+        let mut index = GitIndex::new();
+        index.add(IndexEntry::new("a.txt", &hash_blob(b"a\n", repo.object_format()), 0o100644, StatData::default()));
+        index.save(&repo).expect("Error writing index");
+
+        //Comparing only warns, even where adding would fail
+        let status = repo_status(&repo, &Pathspec::default(), &ScanOptions { untracked: UntrackedMode::No, ignored: false, renames: false }).unwrap();
+        assert!(status.entries.iter().any(|entry| entry.path == "a.txt" && entry.unstaged == 'M'), "Status was {:?}", status.entries);
+        assert!(
+            status.warnings == ["in the working copy of 'a.txt', LF will be replaced by CRLF the next time Git touches it"],
+            "Warnings were {:?}", status.warnings
+        );
+    }
+}
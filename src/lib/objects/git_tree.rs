@@ -0,0 +1,216 @@
+use crate::lib::objects::git_attributes::AttrValue;
+use crate::lib::objects::git_commit::Commit;
+use crate::lib::objects::git_index::{CacheTree, GitIndex, IndexEntry};
+use crate::lib::objects::git_object::{GitObject, ObjectError, ObjectType};
+use crate::lib::objects::git_pathspec::Pathspec;
+use crate::lib::objects::git_repository::GitRepository;
+use crate::lib::parsing::decoding::bytes_to_tree;
+use crate::lib::parsing::encoding::tree_to_bytes;
+use std::rc::Rc;
+
+///Mode git records for subtree entries
+pub const MODE_TREE: u32 = 0o040000;
+
+///One line of a tree object: a named blob, subtree, symlink or submodule commit
+#[derive(Clone, Debug, PartialEq)]
+pub struct TreeEntry {
+    pub mode: u32,
+    pub name: String,
+    pub sha: String,
+}
+
+impl TreeEntry {
+    pub fn is_tree(&self) -> bool {
+        self.mode == MODE_TREE
+    }
+
+    ///Object type this entry points at, as printed by ls-tree
+    pub fn kind_name(&self) -> &'static str {
+        if self.is_tree() {
+            "tree"
+        } else if self.mode == 0o160000 {
+            "commit"
+        } else {
+            "blob"
+        }
+    }
+}
+
+///Follow tags and commits down to the tree they describe
+pub(crate) fn peel_to_tree(repo: &Rc<GitRepository>, sha: &str) -> Result<String, ObjectError> {
+    let mut sha = sha.to_owned();
+    loop {
+        let object = GitObject::from_internal_file(&sha, repo)?;
+        let next = match object.kind() {
+            ObjectType::Tree => return Ok(sha),
+            ObjectType::Commit => Commit::parse(object.serialize())?.tree().map(str::to_owned),
+            ObjectType::Tag => Commit::parse(object.serialize())?.header("object").map(str::to_owned),
+            ObjectType::Blob => None,
+        };
+        sha = next.ok_or_else(|| ObjectError::WrongType(sha.clone(), "tree"))?;
+    }
+}
+
+///Follow tags down to the commit they name
+pub(crate) fn peel_to_commit(repo: &Rc<GitRepository>, sha: &str) -> Result<String, ObjectError> {
+    let mut sha = sha.to_owned();
+    loop {
+        let object = GitObject::from_internal_file(&sha, repo)?;
+        let next = match object.kind() {
+            ObjectType::Commit => return Ok(sha),
+            ObjectType::Tag => Commit::parse(object.serialize())?.header("object").map(str::to_owned),
+            _ => None,
+        };
+        sha = next.ok_or_else(|| ObjectError::WrongType(sha.clone(), "commit"))?;
+    }
+}
+
+///Every blob, symlink and submodule below a tree, named by full path and sorted in index order
+pub(crate) fn flatten_tree(repo: &Rc<GitRepository>, tree: &str) -> Result<Vec<TreeEntry>, ObjectError> {
+    flatten_tree_matching(repo, tree, &Pathspec::default(), |_, _| AttrValue::Unspecified)
+}
+
+///The entries of `flatten_tree` a pathspec selects, without reading subtrees it rules out.
+///`attr` looks up attributes for `attr:` magic
+pub(crate) fn flatten_tree_matching<F: FnMut(&str, &str) -> AttrValue>(repo: &Rc<GitRepository>, tree: &str, pathspec: &Pathspec, mut attr: F) -> Result<Vec<TreeEntry>, ObjectError> {
+    let mut entries = Vec::new();
+    flatten_into(repo, tree, "", pathspec, &mut attr, &mut entries)?;
+    entries.sort_by(|a, b| a.name.as_bytes().cmp(b.name.as_bytes()));
+    Ok(entries)
+}
+
+fn flatten_into<F: FnMut(&str, &str) -> AttrValue>(
+    repo: &Rc<GitRepository>,
+    tree: &str,
+    prefix: &str,
+    pathspec: &Pathspec,
+    attr: &mut F,
+    entries: &mut Vec<TreeEntry>,
+) -> Result<(), ObjectError> {
+    let object = GitObject::from_internal_file(tree, repo)?;
+    for entry in bytes_to_tree(object.serialize(), repo.object_format())? {
+        let path = format!("{}{}", prefix, entry.name);
+        if entry.is_tree() {
+            if pathspec.may_match_under(&path) {
+                flatten_into(repo, &entry.sha, &format!("{}/", path), pathspec, attr, entries)?;
+            }
+        } else if pathspec.matches(&path, &mut *attr) {
+            entries.push(TreeEntry { name: path, ..entry });
+        }
+    }
+    Ok(())
+}
+
+///Write the index's merged entries as tree objects and return the root tree id. Directories
+///the cache tree still holds are not rewritten, and the cache tree is refreshed with every id
+pub(crate) fn write_index_tree(repo: &Rc<GitRepository>, index: &mut GitIndex) -> Result<String, ObjectError> {
+    let entries: Vec<&IndexEntry> = index.entries().iter().filter(|entry| entry.stage == 0).collect();
+    let (cache_tree, _) = build_tree(repo, &entries, "", index.cache_tree())?;
+    let sha = cache_tree.sha.clone().unwrap_or_default();
+    index.set_cache_tree(Some(cache_tree));
+    Ok(sha)
+}
+
+///Write the tree for the entries below `base`, returning its cache tree node and whether the
+///tree has any entries. Intent-to-add entries are left out and keep the node invalid
+fn build_tree(repo: &Rc<GitRepository>, entries: &[&IndexEntry], base: &str, old: Option<&CacheTree>) -> Result<(CacheTree, bool), ObjectError> {
+    if let Some(old) = old.filter(|old| old.is_valid() && old.entry_count as usize == entries.len()) {
+        return Ok((old.clone(), true));
+    }
+    let mut tree_entries = Vec::new();
+    let mut children = Vec::new();
+    let mut complete = true;
+    let mut pos = 0;
+    while pos < entries.len() {
+        let entry = entries[pos];
+        let relative = &entry.path[base.len()..];
+        if let Some((dir, _)) = relative.split_once('/') {
+            let dir_base = format!("{}{}/", base, dir);
+            let end = pos + entries[pos..].iter().take_while(|entry| entry.path.starts_with(&dir_base)).count();
+            let old_child = old.and_then(|old| old.children.iter().find(|child| child.name == dir));
+            let (mut child, has_entries) = build_tree(repo, &entries[pos..end], &dir_base, old_child)?;
+            child.name = dir.to_owned();
+            complete &= child.is_valid();
+            //A directory holding only intent-to-add entries has no tree yet
+            if has_entries {
+                tree_entries.push(TreeEntry {
+                    mode: MODE_TREE,
+                    name: dir.to_owned(),
+                    sha: child.sha.clone().unwrap_or_default(),
+                });
+            }
+            children.push(child);
+            pos = end;
+        } else {
+            if entry.intent_to_add {
+                complete = false;
+            } else {
+                tree_entries.push(TreeEntry {
+                    mode: entry.mode,
+                    name: relative.to_owned(),
+                    sha: entry.sha.clone(),
+                });
+            }
+            pos += 1;
+        }
+    }
+    let sha = GitObject::new(ObjectType::Tree, tree_to_bytes(&tree_entries, repo.object_format())?, repo).write_to_repo()?;
+    let node = CacheTree {
+        name: String::new(),
+        entry_count: if complete { entries.len() as i32 } else { -1 },
+        sha: Some(sha),
+        children,
+    };
+    Ok((node, !tree_entries.is_empty()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::write_index_tree;
+    use crate::lib::get_test_dir;
+    use crate::lib::objects::git_config::ConfigFile;
+    use crate::lib::objects::git_hash::ObjectFormat;
+    use crate::lib::objects::git_index::{GitIndex, IndexEntry, StatData};
+    use crate::lib::objects::git_repository::GitRepository;
+    use crate::lib::parsing::decoding::bytes_to_tree;
+    use crate::lib::parsing::encoding::hex_to_bytes;
+    use std::rc::Rc;
+
+    #[test]
+    fn parse_sha256_tree() {
+        let sha = "a1d8cdefec7e71f4ebfcea47ce73108b4bb0d49ffcc85537036c08fdc96716a7";
+        let mut bytes = b"100644 file.txt\0".to_vec();
+        bytes.extend(hex_to_bytes(sha).unwrap());
+        bytes.extend(b"40000 dir\0".iter());
+        bytes.extend(hex_to_bytes(sha).unwrap());
+
+        let parsed = bytes_to_tree(&bytes, ObjectFormat::Sha256).expect("Error parsing tree");
+        assert!(parsed.len() == 2);
+        assert!(parsed[0].name == "file.txt" && parsed[0].sha == sha && !parsed[0].is_tree());
+        assert!(parsed[1].is_tree() && parsed[1].kind_name() == "tree");
+        assert!(bytes_to_tree(&bytes, ObjectFormat::Sha1).is_err());
+    }
+
+    #[test]
+    fn writes_index_as_trees() {
+        let test_dir = get_test_dir("writes_index_as_trees");
+        let _ = std::fs::remove_dir_all(&test_dir);
+        let repo = Rc::new(GitRepository::new(test_dir.clone(), test_dir.join(".git"), ConfigFile::new()));
+        let blob = "78981922613b2afb6025042ff6bd878ac1994e85";
+        let mut index = GitIndex::new();
+        for path in ["a.txt", "a/a"] {
+            index.add(IndexEntry::new(path, blob, 0o100644, StatData::default()));
+        }
+        let root = write_index_tree(&repo, &mut index).expect("Error writing tree");
+        assert!(root == "be66b2b30841fbf6a9cd072d244fd93ea35c91a1", "Root tree was {}", root);
+        let cache_tree = index.cache_tree().expect("Cache tree was not stored");
+        assert!(cache_tree.entry_count == 2);
+        assert!(cache_tree.find("a").and_then(|node| node.sha.as_deref()) == Some("aaff74984cccd156a469afa7d9ab10e4777beb24"));
+
+        let mut pending = IndexEntry::new("a/b", "e69de29bb2d1d6434b8b29ae775ad8c2e48c5391", 0o100644, StatData::default());
+        pending.intent_to_add = true;
+        index.add(pending);
+        assert!(write_index_tree(&repo, &mut index).unwrap() == root, "Intent-to-add entries are left out");
+        assert!(index.cache_tree().unwrap().find("a").is_none(), "Trees with intent-to-add entries stay invalid");
+    }
+}
@@ -0,0 +1,294 @@
+use crate::lib::objects::git_convert::{ConvertError, Converter};
+use crate::lib::objects::git_hash::ObjectFormat;
+use crate::lib::objects::git_ignore::IgnoreRules;
+use crate::lib::objects::git_index::{GitIndex, IndexEntry, StatData};
+use crate::lib::objects::git_repository::GitRepository;
+use crate::lib::parsing::encoding::hash_bytes_as_string;
+use std::collections::HashSet;
+use std::fs::Metadata;
+use std::io::ErrorKind;
+use std::path::Path;
+
+pub(crate) const MODE_FILE: u32 = 0o100644;
+pub(crate) const MODE_EXECUTABLE: u32 = 0o100755;
+pub(crate) const MODE_SYMLINK: u32 = 0o120000;
+pub(crate) const MODE_GITLINK: u32 = 0o160000;
+
+///Config deciding how far the file system's stat data is trusted when comparing against the index
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct StatOptions {
+    ///core.fileMode: whether the executable bit on disk means anything
+    pub filemode: bool,
+    ///core.trustCtime: tools that touch inodes make ctime unreliable
+    pub trust_ctime: bool,
+    ///core.checkStat=minimal: only look at whole seconds of mtime and the size
+    pub minimal: bool,
+}
+
+impl StatOptions {
+    pub fn from_repo(repo: &GitRepository) -> StatOptions {
+        let config = repo.config();
+        let flag = |name: &str| !matches!(config.get_bool(name), Ok(Some(false)));
+        StatOptions {
+            filemode: flag("core.filemode"),
+            trust_ctime: flag("core.trustctime"),
+            minimal: config.get("core.checkstat") == Some("minimal"),
+        }
+    }
+}
+
+///How a tracked file on disk differs from its index entry
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) enum WorktreeChange {
+    Deleted,
+    TypeChanged,
+    Modified,
+}
+
+///Mode git would record for a file, keeping the old mode's executable bit when it cannot be trusted
+pub(crate) fn mode_from_metadata(meta: &Metadata, old_mode: Option<u32>, filemode: bool) -> u32 {
+    let file_type = meta.file_type();
+    if file_type.is_symlink() {
+        MODE_SYMLINK
+    } else if file_type.is_dir() {
+        MODE_GITLINK
+    } else if !filemode {
+        match old_mode {
+            Some(mode) if mode == MODE_EXECUTABLE || mode == MODE_FILE => mode,
+            _ => MODE_FILE,
+        }
+    } else if is_executable(meta) {
+        MODE_EXECUTABLE
+    } else {
+        MODE_FILE
+    }
+}
+
+#[cfg(unix)]
+fn is_executable(meta: &Metadata) -> bool {
+    use std::os::unix::fs::PermissionsExt;
+    meta.permissions().mode() & 0o100 != 0
+}
+
+#[cfg(not(unix))]
+fn is_executable(_meta: &Metadata) -> bool {
+    false
+}
+
+///Content of a worktree file as git stores it: the file's bytes, or a symlink's target
+pub(crate) fn read_worktree_file(path: &Path, meta: &Metadata) -> Result<Vec<u8>, std::io::Error> {
+    if meta.file_type().is_symlink() {
+        let target = std::fs::read_link(path)?;
+        Ok(target.to_string_lossy().replace('\\', "/").into_bytes())
+    } else {
+        std::fs::read(path)
+    }
+}
+
+///Id the content would get as a blob, without writing it
+pub(crate) fn hash_blob(content: &[u8], format: ObjectFormat) -> String {
+    let header = format!("blob {}\0", content.len());
+    hash_bytes_as_string(&[header.as_bytes(), content].concat(), format)
+}
+
+///Whether recorded stat data still describes the file, in which case its content is assumed unchanged
+pub(crate) fn stat_matches(recorded: &StatData, current: &StatData, options: StatOptions) -> bool {
+    if options.minimal {
+        return recorded.mtime.0 == current.mtime.0 && recorded.size == current.size;
+    }
+    recorded.mtime == current.mtime
+        && (!options.trust_ctime || recorded.ctime == current.ctime)
+        && recorded.size == current.size
+        && recorded.ino == current.ino
+        && recorded.dev == current.dev
+        && recorded.uid == current.uid
+        && recorded.gid == current.gid
+}
+
+///Compare a tracked file with its entry, hashing only when the stat data has changed or the
+///entry is racily clean relative to `index_time`, the index file's modification time. The file
+///is converted as `add` would before hashing, round trip problems only giving warnings
+pub(crate) fn worktree_change(
+    worktree: &Path,
+    entry: &IndexEntry,
+    format: ObjectFormat,
+    options: StatOptions,
+    index_time: Option<(u32, u32)>,
+    convert: &mut Converter,
+) -> Result<Option<WorktreeChange>, ConvertError> {
+    let path = worktree.join(&entry.path);
+    let meta = match std::fs::symlink_metadata(&path) {
+        Ok(meta) => meta,
+        Err(err) if err.kind() == ErrorKind::NotFound || err.kind() == ErrorKind::NotADirectory => {
+            return Ok(Some(WorktreeChange::Deleted))
+        }
+        Err(err) => return Err(err.into()),
+    };
+    let file_type = meta.file_type();
+    let same_type = match entry.mode {
+        MODE_SYMLINK => file_type.is_symlink(),
+        MODE_GITLINK => file_type.is_dir(),
+        _ => file_type.is_file(),
+    };
+    if !same_type {
+        return Ok(Some(WorktreeChange::TypeChanged));
+    }
+    if entry.mode == MODE_GITLINK {
+        return Ok(None);
+    }
+    if mode_from_metadata(&meta, Some(entry.mode), options.filemode) != entry.mode {
+        return Ok(Some(WorktreeChange::Modified));
+    }
+    //A size of zero on anything but the empty blob is an entry smudged as racily clean
+    let smudged = entry.stat.size == 0 && entry.sha != hash_blob(b"", format);
+    if !smudged && stat_matches(&entry.stat, &StatData::from_metadata(&meta), options) && !entry.is_racy(index_time) {
+        return Ok(None);
+    }
+    let mut content = read_worktree_file(&path, &meta)?;
+    if entry.mode != MODE_SYMLINK {
+        content = convert.clean_compared(&entry.path, content)?;
+    }
+    if hash_blob(&content, format) == entry.sha {
+        Ok(None)
+    } else {
+        Ok(Some(WorktreeChange::Modified))
+    }
+}
+
+///Write content to the worktree the way the mode says: a regular or executable file, a symlink
+///(or a file holding the target when `symlinks` is off) or an empty directory for a submodule.
+///Anything in the way is replaced. Returns the stat data to record in the index
+pub(crate) fn write_worktree_file(worktree: &Path, path: &str, mode: u32, content: &[u8], symlinks: bool) -> Result<StatData, std::io::Error> {
+    let file = worktree.join(path);
+    //Files standing where a leading directory belongs go first
+    for (end, _) in path.match_indices('/') {
+        let dir = worktree.join(&path[..end]);
+        match std::fs::symlink_metadata(&dir) {
+            Ok(meta) if !meta.is_dir() => std::fs::remove_file(&dir)?,
+            Ok(_) => {}
+            Err(_) => break,
+        }
+    }
+    if let Some(parent) = file.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    match std::fs::symlink_metadata(&file) {
+        Ok(meta) if meta.is_dir() && mode == MODE_GITLINK => {}
+        Ok(meta) if meta.is_dir() => std::fs::remove_dir_all(&file)?,
+        Ok(_) => std::fs::remove_file(&file)?,
+        Err(_) => {}
+    }
+    match mode {
+        MODE_GITLINK => std::fs::create_dir_all(&file)?,
+        MODE_SYMLINK if symlinks => create_symlink(&String::from_utf8_lossy(content), &file)?,
+        _ => write_file(&file, content, mode == MODE_EXECUTABLE)?,
+    }
+    Ok(StatData::from_metadata(&std::fs::symlink_metadata(&file)?))
+}
+
+#[cfg(unix)]
+fn create_symlink(target: &str, link: &Path) -> Result<(), std::io::Error> {
+    std::os::unix::fs::symlink(target, link)
+}
+
+#[cfg(not(unix))]
+fn create_symlink(target: &str, link: &Path) -> Result<(), std::io::Error> {
+    std::fs::write(link, target)
+}
+
+///Create the file with the permissions git asks for, leaving the umask to trim them
+#[cfg(unix)]
+fn write_file(file: &Path, content: &[u8], executable: bool) -> Result<(), std::io::Error> {
+    use std::io::Write;
+    use std::os::unix::fs::OpenOptionsExt;
+    let permissions = if executable { 0o777 } else { 0o666 };
+    let mut handle = std::fs::OpenOptions::new().write(true).create_new(true).mode(permissions).open(file)?;
+    handle.write_all(content)
+}
+
+#[cfg(not(unix))]
+fn write_file(file: &Path, content: &[u8], _executable: bool) -> Result<(), std::io::Error> {
+    std::fs::write(file, content)
+}
+
+///Delete a file and any directories it leaves empty, short of the worktree itself
+pub(crate) fn remove_worktree_file(worktree: &Path, path: &str) -> Result<(), std::io::Error> {
+    let file = worktree.join(path);
+    match std::fs::remove_file(&file) {
+        Ok(()) => {}
+        Err(err) if err.kind() == ErrorKind::NotFound => return Ok(()),
+        Err(err) => return Err(err),
+    }
+    let mut dir = file.parent();
+    while let Some(current) = dir.filter(|current| *current != worktree) {
+        if std::fs::remove_dir(current).is_err() {
+            break;
+        }
+        dir = current.parent();
+    }
+    Ok(())
+}
+
+///A worktree path the index does not track
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) struct UntrackedPath {
+    ///Relative to the top of the worktree, with a trailing slash for nested repositories
+    pub path: String,
+    pub ignored: bool,
+}
+
+///Walk the worktree for files missing from the index, sorted in index order. Ignored files
+///are only reported, and ignored directories only entered, when `with_ignored` is set
+pub(crate) fn untracked_paths(
+    worktree: &Path,
+    index: &GitIndex,
+    ignore: &mut IgnoreRules,
+    with_ignored: bool,
+) -> Result<Vec<UntrackedPath>, std::io::Error> {
+    let tracked: HashSet<&str> = index.entries().iter().map(|entry| entry.path.as_str()).collect();
+    let mut found = Vec::new();
+    walk_untracked(worktree, "", false, &tracked, ignore, with_ignored, &mut found)?;
+    found.sort_by(|a, b| a.path.as_bytes().cmp(b.path.as_bytes()));
+    Ok(found)
+}
+
+fn walk_untracked(
+    worktree: &Path,
+    dir: &str,
+    dir_ignored: bool,
+    tracked: &HashSet<&str>,
+    ignore: &mut IgnoreRules,
+    with_ignored: bool,
+    found: &mut Vec<UntrackedPath>,
+) -> Result<(), std::io::Error> {
+    for entry in std::fs::read_dir(worktree.join(dir))? {
+        let entry = entry?;
+        let name = entry.file_name().to_string_lossy().into_owned();
+        if name == ".git" {
+            continue;
+        }
+        let path = format!("{}{}", dir, name);
+        let file_type = entry.file_type()?;
+        if file_type.is_dir() {
+            if tracked.contains(path.as_str()) {
+                continue;
+            }
+            let ignored = dir_ignored || ignore.is_ignored(&path, true);
+            if ignored && !with_ignored {
+                continue;
+            }
+            //Nested repositories are reported as a whole rather than entered
+            if entry.path().join(".git").exists() {
+                found.push(UntrackedPath { path: format!("{}/", path), ignored });
+            } else {
+                walk_untracked(worktree, &format!("{}/", path), ignored, tracked, ignore, with_ignored, found)?;
+            }
+        } else if !tracked.contains(path.as_str()) {
+            let ignored = dir_ignored || ignore.is_ignored(&path, false);
+            if !ignored || with_ignored {
+                found.push(UntrackedPath { path, ignored });
+            }
+        }
+    }
+    Ok(())
+}
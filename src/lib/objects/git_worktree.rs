@@ -0,0 +1,135 @@
+use crate::lib::clean_unc;
+use crate::lib::objects::git_refs::{resolve_ref, RefValue};
+use crate::lib::objects::git_repository::{linked_worktree_path, repo_path, GitRepository};
+use std::path::{Path, PathBuf};
+
+///A worktree attached to a repository, as reported by `worktree list`
+pub(crate) struct WorktreeInfo {
+    pub path: PathBuf,
+    ///The per-worktree git directory under `worktrees/`, None for the main worktree
+    pub admin_dir: Option<PathBuf>,
+    pub head: Option<String>,
+    pub branch: Option<String>,
+    pub bare: bool,
+    pub locked: Option<String>,
+    pub prunable: Option<String>,
+}
+
+///The main worktree followed by every linked worktree registered in the common directory
+pub(crate) fn list_worktrees(repo: &GitRepository) -> Result<Vec<WorktreeInfo>, std::io::Error> {
+    let bare = matches!(repo.config().get_bool("core.bare"), Ok(Some(true)));
+    let main_path = if repo.is_linked_worktree() || repo.is_bare() {
+        main_worktree_path(repo.commondir(), bare)
+    } else {
+        repo.worktree().map(Path::to_owned).unwrap_or_default()
+    };
+    let (head, branch) = read_head_at(repo, repo.commondir())?;
+    let mut worktrees = vec![WorktreeInfo {
+        path: main_path,
+        admin_dir: None,
+        head,
+        branch,
+        bare,
+        locked: None,
+        prunable: None,
+    }];
+
+    let admin_root = repo_path(repo, "worktrees");
+    if !admin_root.is_dir() {
+        return Ok(worktrees);
+    }
+    let mut admin_dirs: Vec<PathBuf> = std::fs::read_dir(admin_root)?
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.path())
+        .filter(|path| path.is_dir())
+        .collect();
+    admin_dirs.sort();
+    for admin_dir in admin_dirs {
+        let (head, branch) = read_head_at(repo, &admin_dir)?;
+        let locked = std::fs::read_to_string(admin_dir.join("locked")).ok();
+        worktrees.push(WorktreeInfo {
+            path: linked_worktree_path(&admin_dir).unwrap_or_default(),
+            prunable: prune_reason(&admin_dir),
+            admin_dir: Some(admin_dir),
+            head,
+            branch,
+            bare: false,
+            locked: locked.map(|reason| reason.trim_end().to_owned()),
+        });
+    }
+    Ok(worktrees)
+}
+
+///Why a linked worktree's administrative files can be pruned, if they can
+pub(crate) fn prune_reason(admin_dir: &Path) -> Option<String> {
+    if admin_dir.join("locked").exists() {
+        return None;
+    }
+    if !admin_dir.join("gitdir").is_file() {
+        return Some("gitdir file does not exist".to_owned());
+    }
+    match linked_worktree_path(admin_dir) {
+        Some(path) if path.join(".git").exists() => None,
+        _ => Some("gitdir file points to non-existent location".to_owned()),
+    }
+}
+
+///Pick an unused name under `worktrees/` based on the last component of the worktree path
+pub(crate) fn unique_admin_dir(repo: &GitRepository, worktree: &Path) -> PathBuf {
+    let base: String = worktree
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_else(|| "worktree".to_owned())
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() || "-_.".contains(c) { c } else { '-' })
+        .collect();
+    let admin_root = repo_path(repo, "worktrees");
+    let mut candidate = admin_root.join(&base);
+    let mut counter = 1;
+    while candidate.exists() {
+        candidate = admin_root.join(format!("{}{}", base, counter));
+        counter += 1;
+    }
+    candidate
+}
+
+///Write the files linking a new worktree and its administrative directory together
+pub(crate) fn link_worktree(worktree: &Path, admin_dir: &Path, head: &RefValue) -> Result<(), std::io::Error> {
+    std::fs::create_dir_all(worktree)?;
+    std::fs::create_dir_all(admin_dir)?;
+    let worktree = clean_unc(worktree.canonicalize()?);
+    let admin_dir = clean_unc(admin_dir.canonicalize()?);
+    let dot_git = worktree.join(".git");
+    std::fs::write(admin_dir.join("gitdir"), format!("{}\n", dot_git.to_string_lossy()))?;
+    std::fs::write(admin_dir.join("commondir"), "../..\n")?;
+    let head = match head {
+        RefValue::Direct(sha) => format!("{}\n", sha),
+        RefValue::Symbolic(target) => format!("ref: {}\n", target),
+    };
+    std::fs::write(admin_dir.join("HEAD"), head)?;
+    std::fs::write(dot_git, format!("gitdir: {}\n", admin_dir.to_string_lossy()))
+}
+
+///The branch and commit HEAD holds in the given git directory
+fn read_head_at(repo: &GitRepository, dir: &Path) -> Result<(Option<String>, Option<String>), std::io::Error> {
+    let contents = match std::fs::read_to_string(dir.join("HEAD")) {
+        Ok(contents) => contents,
+        Err(_) => return Ok((None, None)),
+    };
+    let contents = contents.trim_end();
+    if let Some(target) = contents.strip_prefix("ref: ") {
+        Ok((resolve_ref(repo, target)?, Some(target.to_owned())))
+    } else {
+        Ok((Some(contents.to_owned()), None))
+    }
+}
+
+fn main_worktree_path(commondir: &Path, bare: bool) -> PathBuf {
+    if bare {
+        return commondir.to_owned();
+    }
+    match commondir.file_name() {
+        Some(name) if name == ".git" => commondir.parent().map(Path::to_owned).unwrap_or_default(),
+        _ => commondir.to_owned(),
+    }
+}
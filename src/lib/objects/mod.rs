@@ -1,2 +1,20 @@
+pub mod git_attributes;
+pub(crate) mod git_checkout;
+pub mod git_commit;
+pub mod git_config;
+pub(crate) mod git_convert;
+pub(crate) mod git_filter;
+pub(crate) mod git_hash;
+pub mod git_ident;
+pub mod git_ignore;
+pub mod git_index;
+pub(crate) mod git_lock;
 pub(crate) mod git_object;
-pub(crate) mod git_repository;
+pub(crate) mod git_pack;
+pub mod git_pathspec;
+pub(crate) mod git_refs;
+pub mod git_repository;
+pub mod git_status;
+pub(crate) mod git_tree;
+pub(crate) mod git_workdir;
+pub(crate) mod git_worktree;
@@ -1,8 +1,13 @@
 
 use crate::lib::objects::git_object::{GitObject,ObjectType};
-use crate::lib::objects::git_repository::{GitRepository};
+use crate::lib::objects::git_repository::{GitRepository, repo_path};
+use crate::lib::objects::git_hash::ObjectFormat;
+use crate::lib::objects::git_pack::abbreviated_in_packs;
+use crate::lib::objects::git_refs::{is_valid_ref_name, resolve_ref};
+use crate::lib::objects::git_tree::TreeEntry;
+use crate::lib::parsing::encoding::bytes_to_hex;
 use flate2::read::ZlibDecoder;
-use std::io::Read;
+use std::io::{ErrorKind, Read};
 use std::path::Path;
 use std::fmt;
 use std::rc::Rc;
@@ -26,15 +31,108 @@ pub enum GitNameFormat {
     Placeholder,
 }
 
-///Finds an object using the given name format and returns the full sha name
-pub(crate) fn find_object<'a>(repo: &GitRepository, name: &str, fmt: &GitNameFormat, follow: bool) -> Result<String,std::io::Error> {
-    Ok(String::default())
+///Finds an object using the given name format and returns the full sha name.
+///Full hashes are taken as-is, then refs are tried in git's order, then abbreviated hashes
+pub(crate) fn find_object(repo: &GitRepository, name: &str, _fmt: &GitNameFormat, _follow: bool) -> Result<String,std::io::Error> {
+    let is_hex = !name.is_empty() && name.chars().all(|c| c.is_ascii_hexdigit());
+    if repo.object_format().is_object_id(name) {
+        return Ok(name.to_lowercase());
+    }
+
+    let name = if name == "@" { "HEAD" } else { name };
+    let ref_candidates = [
+        name.to_owned(),
+        format!("refs/{}", name),
+        format!("refs/tags/{}", name),
+        format!("refs/heads/{}", name),
+        format!("refs/remotes/{}", name),
+        format!("refs/remotes/{}/HEAD", name),
+    ];
+    for candidate in ref_candidates.iter() {
+        if !is_valid_ref_name(candidate) && candidate != "HEAD" {
+            continue;
+        }
+        if let Some(sha) = resolve_ref(repo, candidate)? {
+            return Ok(sha);
+        }
+    }
+
+    if is_hex && name.len() >= 4 {
+        let matches = find_abbreviated(repo, &name.to_lowercase())?;
+        match matches.len() {
+            1 => return Ok(matches[0].clone()),
+            0 => (),
+            _ => {
+                return Err(std::io::Error::new(
+                    ErrorKind::InvalidInput,
+                    format!("short object ID {} is ambiguous", name),
+                ))
+            }
+        }
+    }
+    Err(std::io::Error::new(
+        ErrorKind::NotFound,
+        format!("Not a valid object name {}", name),
+    ))
+}
+
+///Shortest prefix of an object id, at least seven characters, that names no other object
+pub(crate) fn abbreviate(repo: &GitRepository, sha: &str) -> Result<String, std::io::Error> {
+    for len in 7..sha.len() {
+        if find_abbreviated(repo, &sha[..len])?.len() <= 1 {
+            return Ok(sha[..len].to_owned());
+        }
+    }
+    Ok(sha.to_owned())
+}
+
+///All object ids, loose or packed, starting with the given hex prefix
+fn find_abbreviated(repo: &GitRepository, prefix: &str) -> Result<Vec<String>, std::io::Error> {
+    let fan_out_dir = repo_path(repo, "objects").join(&prefix[..2]);
+    let mut matches = Vec::new();
+    if fan_out_dir.is_dir() {
+        for entry in std::fs::read_dir(fan_out_dir)? {
+            let rest = entry?.file_name().to_string_lossy().into_owned();
+            if rest.starts_with(&prefix[2..]) {
+                matches.push(format!("{}{}", &prefix[..2], rest));
+            }
+        }
+    }
+    for sha in abbreviated_in_packs(repo, prefix)? {
+        if !matches.contains(&sha) {
+            matches.push(sha);
+        }
+    }
+    Ok(matches)
+}
+
+///Parse the content of a tree object into its entries
+pub fn bytes_to_tree(bytes: &[u8], format: ObjectFormat) -> Result<Vec<TreeEntry>, ObjectParseError> {
+    let mut entries = Vec::new();
+    let mut rest = bytes;
+    while !rest.is_empty() {
+        let end_mode = rest.iter().position(|b| *b == b' ').ok_or(ObjectParseError::MalformedTree())?;
+        let end_name = rest.iter().position(|b| *b == 0).ok_or(ObjectParseError::MalformedTree())?;
+        let end_sha = end_name + 1 + format.raw_len();
+        if end_mode > end_name || end_sha > rest.len() {
+            return Err(ObjectParseError::MalformedTree());
+        }
+        let mode = std::str::from_utf8(&rest[..end_mode]).map_err(|_| ObjectParseError::MalformedTree())?;
+        let mode = u32::from_str_radix(mode, 8).map_err(|_| ObjectParseError::MalformedTree())?;
+        entries.push(TreeEntry {
+            mode,
+            name: String::from_utf8_lossy(&rest[end_mode + 1..end_name]).into_owned(),
+            sha: bytes_to_hex(&rest[end_name + 1..end_sha]),
+        });
+        rest = &rest[end_sha..];
+    }
+    Ok(entries)
 }
 
 struct ObjectData {
     length: String,
     obj_type: String,
-    content: String,
+    content: Vec<u8>,
 }
 
 fn parse_bytes (bytes: &[u8]) -> Result<ObjectData, ObjectParseError> {
@@ -45,16 +143,14 @@ fn parse_bytes (bytes: &[u8]) -> Result<ObjectData, ObjectParseError> {
         if let Some(end_obj_size) = bytes[end_object_type..].iter().position(is_ascii_null) {
             let end_obj_size = end_obj_size + end_object_type;
             let obj_type = &bytes[..end_object_type];
-            println!("end type {}, end size {}", end_object_type, end_obj_size);
             let length = &bytes[(end_object_type + 1)..end_obj_size];
             let content = &bytes[(end_obj_size + 1)..];
             let obj_type = String::from_utf8_lossy(obj_type);
             let length = String::from_utf8_lossy(length);
-            let content = String::from_utf8_lossy(content);
             Ok(ObjectData {
                 length: (*length).to_owned(),
                 obj_type: (*obj_type).to_owned(),
-                content: (*content).to_owned(),
+                content: content.to_owned(),
             })
         } else {
             Err(ObjectParseError::SizeNotFound())
@@ -71,12 +167,9 @@ fn validate_object(
     let obj_size: usize = data.length.parse()?;
     if obj_size == data.content.len() {
         if let Ok(obj_type) = data.obj_type.parse::<ObjectType>() {
-            Ok(GitObject::new(obj_type, data.content.as_bytes().to_owned(), repo))
+            Ok(GitObject::new(obj_type, data.content, repo))
         } else {
-            Err(ObjectParseError::ObjectTypeNotRecognized(format!(
-                "{}",
-                data.obj_type
-            )))
+            Err(ObjectParseError::ObjectTypeNotRecognized(data.obj_type))
         }
     } else {
         Err(ObjectParseError::ObjectWrongSize())
@@ -91,6 +184,8 @@ pub enum ObjectParseError {
     ObjectTypeNotRecognized(String),
     SizeNotFound(),
     TypeNotFound(),
+    MalformedTree(),
+    MalformedCommit(),
 }
 
 impl std::fmt::Display for ObjectParseError {
@@ -114,6 +209,12 @@ impl std::fmt::Display for ObjectParseError {
             ObjectParseError::TypeNotFound() => {
                 write!(f, "Ascii space not found at the end of object type")
             }
+            ObjectParseError::MalformedTree() => {
+                write!(f, "Tree entry is not in the form '<mode> <name>\\0<id>'")
+            }
+            ObjectParseError::MalformedCommit() => {
+                write!(f, "Commit header line is not in the form '<key> <value>'")
+            }
         }
     }
 }
@@ -147,6 +248,6 @@ mod tests {
             "data length {}",
             data.length
         );
-        assert!(data.content == "tree");
+        assert!(data.content == b"tree");
     }
 }
@@ -1,33 +1,93 @@
-use crate::lib::objects::git_object::{GitObject};
-use sha1::{Digest, Sha1};
+use crate::lib::objects::git_hash::ObjectFormat;
+use crate::lib::objects::git_object::{GitObject, ObjectError};
+use crate::lib::objects::git_tree::TreeEntry;
+use flate2::write::ZlibEncoder;
+use flate2::Compression;
+use std::io::Write;
 
 ///Pack object info into the git object style
 pub fn object_file_format(object: &GitObject) -> Vec<u8> {
     let data = object.serialize();
     let type_string = object.kind().to_string();
-    let type_bytes = type_string.as_bytes();
     let len_string = data.len().to_string();
-    let len_bytes = len_string.as_bytes();
-    let null: u32 = 0x00;
-    let null = null.to_be_bytes();
-    let result = [type_bytes, b" ", len_bytes, &null, &data].concat();
-    result
+    [type_string.as_bytes(), b" ", len_string.as_bytes(), b"\0", data].concat()
 }
 
-///Return the SHA1 hash as a string of hex characters
-pub fn hash_bytes_as_string(content: &[u8]) -> String {
-    let mut hasher = Sha1::new();
-    hasher.update(&content);
-    let result = hasher.finalize();
-    byte_array_to_string(result.as_slice())
+///Return the hash of the content as a string of hex characters
+pub fn hash_bytes_as_string(content: &[u8], format: ObjectFormat) -> String {
+    bytes_to_hex(&format.digest(content))
+}
+
+///Compress bytes the way loose objects are stored
+pub fn compress_bytes(content: &[u8]) -> Result<Vec<u8>, std::io::Error> {
+    let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+    encoder.write_all(content)?;
+    encoder.finish()
+}
+
+///Pack tree entries, already in tree order, into the content of a tree object
+pub fn tree_to_bytes(entries: &[TreeEntry], format: ObjectFormat) -> Result<Vec<u8>, ObjectError> {
+    let mut bytes = Vec::new();
+    for entry in entries {
+        let raw = raw_object_id(&entry.sha, format).ok_or_else(|| ObjectError::BadObjectId(entry.sha.clone()))?;
+        bytes.extend(format!("{:o} {}\0", entry.mode, entry.name).as_bytes());
+        bytes.extend(raw);
+    }
+    Ok(bytes)
 }
 
 ///Turn an array of bytes into a string of hex characters
-fn byte_array_to_string(arr: &[u8]) -> String {
-    let hash_iter = arr.iter().map(|v| format!("{:x}", v));
-    let mut hash_str = "".to_owned();
-    for val in hash_iter {
-        hash_str.push_str(val.as_ref());
+pub fn bytes_to_hex(arr: &[u8]) -> String {
+    arr.iter().map(|v| format!("{:02x}", v)).collect()
+}
+
+///Turn a string of hex characters into bytes, `None` unless every character pairs up as hex
+pub fn hex_to_bytes(hex: &str) -> Option<Vec<u8>> {
+    let digit = |c: u8| (c as char).to_digit(16).map(|d| d as u8);
+    if !hex.len().is_multiple_of(2) {
+        return None;
     }
-    hash_str
-}
\ No newline at end of file
+    hex.as_bytes()
+        .chunks(2)
+        .map(|pair| Some(digit(pair[0])? << 4 | digit(pair[1])?))
+        .collect()
+}
+
+///The raw form of a full object id, `None` when it is not hex or not as long as the format's ids
+pub fn raw_object_id(hex: &str, format: ObjectFormat) -> Option<Vec<u8>> {
+    hex_to_bytes(hex).filter(|raw| raw.len() == format.raw_len())
+}
+
+///Quote a path the way git prints it: unchanged when plain, otherwise in double quotes with
+///C escapes. Bytes above 0x7f are escaped too unless `quote_high` is off (core.quotePath=false)
+pub fn quote_path(path: &str, quote_high: bool) -> String {
+    let needs_quoting = |byte: u8| byte < 0x20 || byte == b'"' || byte == b'\\' || byte == 0x7f || (quote_high && byte >= 0x80);
+    if !path.bytes().any(needs_quoting) {
+        return path.to_owned();
+    }
+    let mut quoted = String::from("\"");
+    let mut plain = Vec::new();
+    for byte in path.bytes() {
+        if !needs_quoting(byte) {
+            plain.push(byte);
+            continue;
+        }
+        quoted.push_str(&String::from_utf8_lossy(&plain));
+        plain.clear();
+        match byte {
+            0x07 => quoted.push_str("\\a"),
+            0x08 => quoted.push_str("\\b"),
+            b'\t' => quoted.push_str("\\t"),
+            b'\n' => quoted.push_str("\\n"),
+            0x0b => quoted.push_str("\\v"),
+            0x0c => quoted.push_str("\\f"),
+            b'\r' => quoted.push_str("\\r"),
+            b'"' => quoted.push_str("\\\""),
+            b'\\' => quoted.push_str("\\\\"),
+            _ => quoted.push_str(&format!("\\{:03o}", byte)),
+        }
+    }
+    quoted.push_str(&String::from_utf8_lossy(&plain));
+    quoted.push('"');
+    quoted
+}
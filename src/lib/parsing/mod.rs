@@ -1,2 +1,3 @@
 pub(crate) mod decoding;
-pub(crate) mod encoding;
\ No newline at end of file
+pub(crate) mod encoding;
+pub(crate) mod wildmatch;
\ No newline at end of file
@@ -0,0 +1,250 @@
+///Compare letters without regard to case
+pub(crate) const WM_CASEFOLD: u32 = 1;
+///Wildcards do not match `/`, except for `**` as a whole path component
+pub(crate) const WM_PATHNAME: u32 = 2;
+
+#[derive(Debug, PartialEq)]
+enum MatchResult {
+    Match,
+    NoMatch,
+    AbortAll,
+    AbortToStarStar,
+}
+
+///Match text against a shell glob the way git does for pathspecs, ignore files and includeIf
+pub(crate) fn wildmatch(pattern: &str, text: &str, flags: u32) -> bool {
+    dowild(pattern.as_bytes(), text.as_bytes(), flags) == MatchResult::Match
+}
+
+fn is_glob_special(byte: u8) -> bool {
+    matches!(byte, b'*' | b'?' | b'[' | b'\\')
+}
+
+///Port of git's dowild. Positions past the end read as 0, standing in for C's terminator
+fn dowild(pattern: &[u8], text: &[u8], flags: u32) -> MatchResult {
+    let casefold = flags & WM_CASEFOLD != 0;
+    let pathname = flags & WM_PATHNAME != 0;
+    let at = |bytes: &[u8], i: usize| bytes.get(i).copied().unwrap_or(0);
+    let fold = |byte: u8| if casefold { byte.to_ascii_lowercase() } else { byte };
+
+    let mut p = 0;
+    let mut t = 0;
+    while p < pattern.len() {
+        let mut p_ch = fold(pattern[p]);
+        let mut t_ch = fold(at(text, t));
+        if t >= text.len() && p_ch != b'*' {
+            return MatchResult::AbortAll;
+        }
+        match p_ch {
+            b'?' => {
+                if pathname && t_ch == b'/' {
+                    return MatchResult::NoMatch;
+                }
+            }
+            b'*' => {
+                p += 1;
+                let match_slash = if at(pattern, p) == b'*' {
+                    let starts_component = p < 2 || pattern[p - 2] == b'/';
+                    while at(pattern, p) == b'*' {
+                        p += 1;
+                    }
+                    let next = at(pattern, p);
+                    let ends_component = next == 0 || next == b'/' || (next == b'\\' && at(pattern, p + 1) == b'/');
+                    if !pathname {
+                        true
+                    } else if starts_component && ends_component {
+                        if next == b'/' && dowild(&pattern[p + 1..], &text[t..], flags) == MatchResult::Match {
+                            return MatchResult::Match;
+                        }
+                        true
+                    } else {
+                        false
+                    }
+                } else {
+                    !pathname
+                };
+
+                if p >= pattern.len() {
+                    //Trailing "**" matches everything, trailing "*" only the rest of this component
+                    if !match_slash && text[t..].contains(&b'/') {
+                        return MatchResult::NoMatch;
+                    }
+                    return MatchResult::Match;
+                } else if !match_slash && pattern[p] == b'/' {
+                    //A single star followed by a slash matches the rest of one directory name
+                    match text[t..].iter().position(|byte| *byte == b'/') {
+                        Some(offset) => t += offset,
+                        None => return MatchResult::NoMatch,
+                    }
+                } else {
+                    loop {
+                        if t >= text.len() {
+                            break;
+                        }
+                        //Skip ahead to where the literal after the star could start
+                        if !is_glob_special(pattern[p]) {
+                            let literal = fold(pattern[p]);
+                            while t < text.len() && (match_slash || text[t] != b'/') && fold(text[t]) != literal {
+                                t += 1;
+                            }
+                            if fold(at(text, t)) != literal {
+                                return MatchResult::NoMatch;
+                            }
+                            t_ch = literal;
+                        }
+                        let matched = dowild(&pattern[p..], &text[t..], flags);
+                        if matched != MatchResult::NoMatch {
+                            if !match_slash || matched != MatchResult::AbortToStarStar {
+                                return matched;
+                            }
+                        } else if !match_slash && t_ch == b'/' {
+                            return MatchResult::AbortToStarStar;
+                        }
+                        t += 1;
+                        t_ch = fold(at(text, t));
+                    }
+                    return MatchResult::AbortAll;
+                }
+            }
+            b'[' => {
+                p += 1;
+                p_ch = at(pattern, p);
+                if p_ch == b'^' {
+                    p_ch = b'!';
+                }
+                let negated = p_ch == b'!';
+                if negated {
+                    p += 1;
+                    p_ch = at(pattern, p);
+                }
+                let mut prev_ch = 0;
+                let mut matched = false;
+                loop {
+                    if p_ch == 0 {
+                        return MatchResult::AbortAll;
+                    }
+                    if p_ch == b'\\' {
+                        p += 1;
+                        p_ch = at(pattern, p);
+                        if p_ch == 0 {
+                            return MatchResult::AbortAll;
+                        }
+                        if t_ch == p_ch {
+                            matched = true;
+                        }
+                    } else if p_ch == b'-' && prev_ch != 0 && at(pattern, p + 1) != 0 && at(pattern, p + 1) != b']' {
+                        p += 1;
+                        p_ch = at(pattern, p);
+                        if p_ch == b'\\' {
+                            p += 1;
+                            p_ch = at(pattern, p);
+                            if p_ch == 0 {
+                                return MatchResult::AbortAll;
+                            }
+                        }
+                        if t_ch <= p_ch && t_ch >= prev_ch {
+                            matched = true;
+                        } else if casefold && t_ch.is_ascii_lowercase() {
+                            let upper = t_ch.to_ascii_uppercase();
+                            if upper <= p_ch && upper >= prev_ch {
+                                matched = true;
+                            }
+                        }
+                        p_ch = 0;
+                    } else if p_ch == b'[' && at(pattern, p + 1) == b':' {
+                        let class_start = p + 2;
+                        let mut end = class_start;
+                        while at(pattern, end) != 0 && at(pattern, end) != b']' {
+                            end += 1;
+                        }
+                        if at(pattern, end) == 0 {
+                            return MatchResult::AbortAll;
+                        }
+                        if end < class_start + 1 || pattern[end - 1] != b':' {
+                            //No closing ":]", so the '[' is just part of the set
+                            if t_ch == b'[' {
+                                matched = true;
+                            }
+                        } else {
+                            let class = &pattern[class_start..end - 1];
+                            let in_class = match class {
+                                b"alnum" => t_ch.is_ascii_alphanumeric(),
+                                b"alpha" => t_ch.is_ascii_alphabetic(),
+                                b"blank" => t_ch == b' ' || t_ch == b'\t',
+                                b"cntrl" => t_ch.is_ascii_control(),
+                                b"digit" => t_ch.is_ascii_digit(),
+                                b"graph" => t_ch.is_ascii_graphic(),
+                                b"lower" => t_ch.is_ascii_lowercase(),
+                                b"print" => t_ch.is_ascii_graphic() || t_ch == b' ',
+                                b"punct" => t_ch.is_ascii_punctuation(),
+                                b"space" => t_ch.is_ascii_whitespace(),
+                                b"upper" => t_ch.is_ascii_uppercase() || (casefold && t_ch.is_ascii_lowercase()),
+                                b"xdigit" => t_ch.is_ascii_hexdigit(),
+                                _ => return MatchResult::AbortAll,
+                            };
+                            if in_class {
+                                matched = true;
+                            }
+                            p = end;
+                            p_ch = 0;
+                        }
+                    } else if t_ch == p_ch {
+                        matched = true;
+                    }
+                    prev_ch = p_ch;
+                    p += 1;
+                    p_ch = at(pattern, p);
+                    if p_ch == b']' {
+                        break;
+                    }
+                }
+                if matched == negated || (pathname && t_ch == b'/') {
+                    return MatchResult::NoMatch;
+                }
+            }
+            _ => {
+                if p_ch == b'\\' {
+                    p += 1;
+                    p_ch = at(pattern, p);
+                }
+                if t_ch != p_ch {
+                    return MatchResult::NoMatch;
+                }
+            }
+        }
+        p += 1;
+        t += 1;
+    }
+    if t < text.len() {
+        MatchResult::NoMatch
+    } else {
+        MatchResult::Match
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn matches_like_git() {
+        assert!(wildmatch("foo", "foo", 0));
+        assert!(!wildmatch("foo", "bar", 0));
+        assert!(wildmatch("*.c", "dir/file.c", 0));
+        assert!(!wildmatch("*.c", "dir/file.c", WM_PATHNAME));
+        assert!(wildmatch("**/*.c", "dir/sub/file.c", WM_PATHNAME));
+        assert!(wildmatch("**/file.c", "file.c", WM_PATHNAME));
+        assert!(wildmatch("dir/**", "dir/a/b", WM_PATHNAME));
+        assert!(wildmatch("a/**/b", "a/b", WM_PATHNAME));
+        assert!(wildmatch("a/**/b", "a/x/y/b", WM_PATHNAME));
+        assert!(!wildmatch("a/*/b", "a/x/y/b", WM_PATHNAME));
+        assert!(wildmatch("a/*/b", "a/x/b", WM_PATHNAME));
+        assert!(wildmatch("[a-c]at", "bat", 0));
+        assert!(!wildmatch("[!a-c]at", "bat", 0));
+        assert!(wildmatch("[[:digit:]]x", "7x", 0));
+        assert!(wildmatch("\\*", "*", 0));
+        assert!(!wildmatch("\\*", "a", 0));
+        assert!(wildmatch("FOO/*.TXT", "foo/bar.txt", WM_CASEFOLD | WM_PATHNAME));
+        assert!(!wildmatch("foo?bar", "foo/bar", WM_PATHNAME));
+    }
+}
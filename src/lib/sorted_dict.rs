@@ -1,6 +1,5 @@
-use std::{cell::RefCell, hash::Hash, ptr::null};
-use std::collections::{HashMap,LinkedList};
-use std::ptr::null_mut;
+use std::{cell::RefCell, hash::Hash};
+use std::collections::HashMap;
 use std::rc::{Rc, Weak};
 
 ///Dictionary that remembers the order that keys were added in
@@ -33,13 +32,13 @@ impl <K: Hash + Eq + PartialEq + Clone ,V> OrderedDictionary<K,V>{
 
     pub fn remove(&mut self, key: &K) -> Option<V> {
         let node = self.secondary_dict
-        .get(&key)?
+        .get(key)?
         .upgrade()
         .expect("Node dropped unexpectedly");
 
         self.ordered_keys.remove(node);
-        self.secondary_dict.remove(&key);
-        self.primary_dict.remove(&key)
+        self.secondary_dict.remove(key);
+        self.primary_dict.remove(key)
     }
 
     pub fn get(&self, key: &K) -> Option<&V> {
@@ -116,7 +115,7 @@ impl <K> NodeList<K> {
                     KeyNode {
                         next: None,
                         prev: None,
-                        val: val,
+                        val,
                     }
                 )
             );
@@ -125,7 +124,7 @@ impl <K> NodeList<K> {
         }
     }
 
-    pub fn remove(&mut self, node: StrongNode<K>) -> () {
+    pub fn remove(&mut self, node: StrongNode<K>) {
         let next_node = node.borrow_mut().next.clone();
         let prev_node = node.borrow_mut().prev.clone();
         
@@ -150,7 +149,7 @@ impl <K> NodeList<K> {
                     KeyNode {
                         next: None,
                         prev: Some(Rc::downgrade(&node)),
-                        val: val,
+                        val,
                     }
                 )
             );
@@ -166,6 +165,7 @@ mod tests {
     use super::OrderedDictionary;
 
     #[test]
+    #[allow(suspicious_double_ref_op, clippy::clone_on_copy)]
     fn creates_ordered_iterator() {
         let pairs = [("test1",9), ("test2", 8), ("test3", 0)];
         let mut test_dict: OrderedDictionary<&str, i32> = OrderedDictionary::new();
@@ -1,12 +1,51 @@
+//The path is spelled out so `lib` is not mistaken for a library crate root
+#[path = "lib/mod.rs"]
 pub mod lib;
-use clap::{App, Arg};
-use lib::commands::{init,cat_file};
+use clap::{App, Arg, ArgGroup, ArgMatches};
+use lib::commands::{init,cat_file,config,ls_files,check_ignore,check_attr,add,rm,write_tree,commit_tree,commit,status,checkout,switch,restore,reset,worktree_add,worktree_list,worktree_prune,worktree_remove,AddOptions,CheckAttrOptions,CheckIgnoreOptions,CheckoutOptions,CommandError,CommitOptions,ConfigAction,ConfigOptions,InitOptions,LsFilesOptions,MessageSource,ResetMode,RestoreOptions,RmOptions,StatusFormat,StatusOptions,SwitchOptions,WorktreeAddOptions};
+use lib::objects::git_config::quote_parameter;
+use lib::objects::git_repository::GitRepository;
+use lib::objects::git_status::UntrackedMode;
+use std::path::PathBuf;
+use std::rc::Rc;
 
 fn make_parser() -> App<'static, 'static> {
      App::new("wyag")
         .version("0.1")
         .author("Jonathan Taylor")
         .about("Learner's git implementation (not fully featured)")
+        .arg(
+            Arg::with_name("C")
+                .short("C")
+                .takes_value(true)
+                .value_name("PATH")
+                .multiple(true)
+                .number_of_values(1)
+                .help("Run as if started in PATH"),
+        )
+        .arg(
+            Arg::with_name("config")
+                .short("c")
+                .takes_value(true)
+                .value_name("NAME=VALUE")
+                .multiple(true)
+                .number_of_values(1)
+                .help("Set a configuration value for this command only"),
+        )
+        .arg(
+            Arg::with_name("git-dir")
+                .long("git-dir")
+                .takes_value(true)
+                .value_name("PATH")
+                .help("Path to the repository (same as setting GIT_DIR)"),
+        )
+        .arg(
+            Arg::with_name("work-tree")
+                .long("work-tree")
+                .takes_value(true)
+                .value_name("PATH")
+                .help("Path to the working tree (same as setting GIT_WORK_TREE)"),
+        )
         .subcommand(
             App::new("init").arg(
                 Arg::with_name("path")
@@ -15,6 +54,30 @@ fn make_parser() -> App<'static, 'static> {
                     .default_value(".")
                     .help("Directory in which to initialize new repo")
                     .required(true),
+            ).arg(
+                Arg::with_name("bare")
+                    .long("bare")
+                    .help("Create a bare repository without a worktree"),
+            ).arg(
+                Arg::with_name("initial-branch")
+                    .long("initial-branch")
+                    .short("b")
+                    .takes_value(true)
+                    .value_name("BRANCH")
+                    .help("Name of the branch HEAD points to"),
+            ).arg(
+                Arg::with_name("object-format")
+                    .long("object-format")
+                    .takes_value(true)
+                    .value_name("FORMAT")
+                    .possible_values(&["sha1", "sha256"])
+                    .help("Hash algorithm used to name objects"),
+            ).arg(
+                Arg::with_name("template")
+                    .long("template")
+                    .takes_value(true)
+                    .value_name("TEMPLATE_DIR")
+                    .help("Directory whose contents are copied into the new git directory"),
             ),
         )
         .subcommand(
@@ -32,14 +95,365 @@ fn make_parser() -> App<'static, 'static> {
             .help("The object to display")
             .required(true)
         ))
+        .subcommand(
+            App::new("config")
+                .about("Get and set repository or global options")
+                .args(&[
+                    Arg::with_name("get").long("get").help("Get the value for a key"),
+                    Arg::with_name("get-all").long("get-all").help("Get all values for a multi-valued key"),
+                    Arg::with_name("get-regexp").long("get-regexp").help("Get values for keys matching a regex"),
+                    Arg::with_name("list").long("list").short("l").help("List all variables with their values"),
+                    Arg::with_name("add").long("add").help("Add a new value without replacing existing ones"),
+                    Arg::with_name("unset").long("unset").help("Remove a key"),
+                    Arg::with_name("unset-all").long("unset-all").help("Remove every value of a key"),
+                    Arg::with_name("rename-section").long("rename-section").help("Rename a section"),
+                    Arg::with_name("remove-section").long("remove-section").help("Remove a section"),
+                ])
+                .group(ArgGroup::with_name("action").args(&[
+                    "get", "get-all", "get-regexp", "list", "add", "unset", "unset-all", "rename-section", "remove-section",
+                ]))
+                .arg(Arg::with_name("global").long("global").help("Use the global config file"))
+                .arg(Arg::with_name("local").long("local").help("Use the repository config file"))
+                .arg(
+                    Arg::with_name("file")
+                        .long("file")
+                        .short("f")
+                        .takes_value(true)
+                        .value_name("FILE")
+                        .help("Use the given config file"),
+                )
+                .group(ArgGroup::with_name("location").args(&["global", "local", "file"]))
+                .arg(
+                    Arg::with_name("type")
+                        .long("type")
+                        .takes_value(true)
+                        .possible_values(&["bool", "int", "path", "color"])
+                        .help("Check and convert values to this type"),
+                )
+                .arg(Arg::with_name("show-origin").long("show-origin").help("Show where each value came from"))
+                .arg(Arg::with_name("name").index(1).value_name("NAME"))
+                .arg(Arg::with_name("value").index(2).value_name("VALUE"))
+                .arg(Arg::with_name("value-pattern").index(3).value_name("VALUE_PATTERN")),
+        )
+        .subcommand(
+            App::new("ls-files")
+                .about("Show information about files in the index and the working tree")
+                .arg(Arg::with_name("cached").long("cached").short("c").help("Show cached files (the default)"))
+                .arg(Arg::with_name("stage").long("stage").short("s").help("Show mode, object id and stage of staged files"))
+                .arg(Arg::with_name("deleted").long("deleted").short("d").help("Show deleted files"))
+                .arg(Arg::with_name("modified").long("modified").short("m").help("Show modified files"))
+                .arg(Arg::with_name("others").long("others").short("o").help("Show untracked files"))
+                .arg(Arg::with_name("ignored").long("ignored").short("i").help("Show only ignored files"))
+                .arg(Arg::with_name("exclude-standard").long("exclude-standard").help("Use the standard git exclusions"))
+                .arg(Arg::with_name("unmerged").long("unmerged").short("u").help("Show unmerged files, implies --stage"))
+                .arg(Arg::with_name("z").short("z").help("Terminate paths with NUL and do not quote them"))
+                .arg(Arg::with_name("pathspec").index(1).multiple(true).value_name("PATHSPEC")),
+        )
+        .subcommand(
+            App::new("check-ignore")
+                .about("Debug gitignore / exclude files")
+                .arg(Arg::with_name("verbose").long("verbose").short("v").help("Show the exclude file, line and pattern that matched"))
+                .arg(Arg::with_name("non-matching").long("non-matching").short("n").help("Also show paths that match no pattern"))
+                .arg(Arg::with_name("no-index").long("no-index").help("Check tracked files as well"))
+                .arg(Arg::with_name("stdin").long("stdin").help("Read paths from standard input, one per line"))
+                .arg(Arg::with_name("pathname").index(1).multiple(true).conflicts_with("stdin").value_name("PATHNAME")),
+        )
+        .subcommand(
+            App::new("check-attr")
+                .about("Display gitattributes information")
+                .arg(Arg::with_name("all").long("all").short("a").help("List all attributes set on the paths"))
+                .arg(Arg::with_name("cached").long("cached").help("Only read .gitattributes from the index"))
+                .arg(Arg::with_name("stdin").long("stdin").help("Read paths from standard input, one per line"))
+                .arg(Arg::with_name("args").index(1).multiple(true).value_name("ATTR|PATHNAME"))
+                .arg(
+                    Arg::with_name("pathname")
+                        .index(2)
+                        .multiple(true)
+                        .last(true)
+                        .value_name("PATHNAME")
+                        .help("Paths to check, making all earlier arguments attribute names"),
+                ),
+        )
+        .subcommand(
+            App::new("add")
+                .about("Add file contents to the index")
+                .arg(Arg::with_name("all").long("all").short("A").help("Also stage untracked and deleted files in the whole tree"))
+                .arg(Arg::with_name("update").long("update").short("u").help("Only stage changes to tracked files"))
+                .arg(Arg::with_name("force").long("force").short("f").help("Allow adding ignored files"))
+                .arg(Arg::with_name("dry-run").long("dry-run").short("n").help("Show what would be added without adding it"))
+                .arg(Arg::with_name("intent-to-add").long("intent-to-add").short("N").help("Record only that the paths will be added later"))
+                .arg(Arg::with_name("verbose").long("verbose").short("v").help("Show each path as it is staged"))
+                .group(ArgGroup::with_name("mode").args(&["all", "update"]))
+                .arg(Arg::with_name("pathspec").index(1).multiple(true).value_name("PATHSPEC")),
+        )
+        .subcommand(
+            App::new("rm")
+                .about("Remove files from the working tree and from the index")
+                .arg(Arg::with_name("cached").long("cached").help("Only remove from the index"))
+                .arg(Arg::with_name("r").short("r").help("Allow recursive removal when a directory is given"))
+                .arg(Arg::with_name("force").long("force").short("f").help("Remove even with staged or local changes"))
+                .arg(Arg::with_name("dry-run").long("dry-run").short("n").help("Show what would be removed without removing it"))
+                .arg(Arg::with_name("quiet").long("quiet").short("q").help("Do not list removed files"))
+                .arg(Arg::with_name("pathspec").index(1).multiple(true).value_name("PATHSPEC")),
+        )
+        .subcommand(
+            App::new("write-tree")
+                .about("Create a tree object from the index")
+                .arg(
+                    Arg::with_name("prefix")
+                        .long("prefix")
+                        .takes_value(true)
+                        .value_name("PREFIX")
+                        .help("Write the tree object for a subdirectory"),
+                ),
+        )
+        .subcommand(
+            App::new("commit-tree")
+                .about("Create a new commit object")
+                .arg(Arg::with_name("tree").index(1).value_name("TREE").required(true))
+                .arg(
+                    Arg::with_name("parent")
+                        .short("p")
+                        .takes_value(true)
+                        .value_name("PARENT")
+                        .multiple(true)
+                        .number_of_values(1)
+                        .help("Id of a parent commit"),
+                )
+                .arg(
+                    Arg::with_name("message")
+                        .short("m")
+                        .takes_value(true)
+                        .value_name("MESSAGE")
+                        .multiple(true)
+                        .number_of_values(1)
+                        .help("A paragraph of the commit message"),
+                )
+                .arg(
+                    Arg::with_name("file")
+                        .short("F")
+                        .takes_value(true)
+                        .value_name("FILE")
+                        .multiple(true)
+                        .number_of_values(1)
+                        .help("Read a paragraph of the commit message from a file"),
+                ),
+        )
+        .subcommand(
+            App::new("commit")
+                .about("Record changes to the repository")
+                .arg(
+                    Arg::with_name("message")
+                        .long("message")
+                        .short("m")
+                        .takes_value(true)
+                        .value_name("MESSAGE")
+                        .multiple(true)
+                        .number_of_values(1)
+                        .help("A paragraph of the commit message"),
+                )
+                .arg(
+                    Arg::with_name("file")
+                        .long("file")
+                        .short("F")
+                        .takes_value(true)
+                        .value_name("FILE")
+                        .multiple(true)
+                        .number_of_values(1)
+                        .help("Read a paragraph of the commit message from a file"),
+                )
+                .arg(Arg::with_name("all").long("all").short("a").help("Stage modified and deleted files first"))
+                .arg(Arg::with_name("amend").long("amend").help("Replace the tip of the current branch"))
+                .arg(Arg::with_name("allow-empty").long("allow-empty").help("Allow a commit with the same tree as its parent"))
+                .arg(
+                    Arg::with_name("author")
+                        .long("author")
+                        .takes_value(true)
+                        .value_name("AUTHOR")
+                        .help("Override the author as 'Name <email>'"),
+                )
+                .arg(
+                    Arg::with_name("date")
+                        .long("date")
+                        .takes_value(true)
+                        .value_name("DATE")
+                        .help("Override the author date"),
+                ),
+        )
+        .subcommand(
+            App::new("status")
+                .about("Show the working tree status")
+                .arg(Arg::with_name("short").long("short").short("s").help("Give the output in the short format"))
+                .arg(Arg::with_name("branch").long("branch").short("b").help("Show branch and tracking info in short formats"))
+                .arg(
+                    Arg::with_name("porcelain")
+                        .long("porcelain")
+                        .takes_value(true)
+                        .min_values(0)
+                        .require_equals(true)
+                        .possible_values(&["v1", "v2"])
+                        .value_name("VERSION")
+                        .help("Give the output in a stable format for scripts"),
+                )
+                .arg(Arg::with_name("z").short("z").help("Terminate entries with NUL and do not quote paths"))
+                .arg(
+                    Arg::with_name("untracked-files")
+                        .long("untracked-files")
+                        .takes_value(true)
+                        .min_values(0)
+                        .require_equals(true)
+                        .possible_values(&["no", "normal", "all"])
+                        .value_name("MODE")
+                        .help("Show untracked files: no, normal or all (the default without a mode)"),
+                )
+                //Short form takes its mode attached, as in -uno
+                .arg(
+                    Arg::with_name("u")
+                        .short("u")
+                        .takes_value(true)
+                        .min_values(0)
+                        .max_values(1)
+                        .possible_values(&["no", "normal", "all"])
+                        .value_name("MODE")
+                        .conflicts_with("untracked-files")
+                        .help("Same as --untracked-files"),
+                )
+                .arg(Arg::with_name("ignored").long("ignored").help("Show ignored files as well"))
+                .arg(Arg::with_name("pathspec").index(1).multiple(true).value_name("PATHSPEC")),
+        )
+        .subcommand(
+            App::new("checkout")
+                .about("Switch branches or restore working tree files")
+                .arg(Arg::with_name("force").long("force").short("f").help("Throw away local changes and untracked files in the way"))
+                .arg(Arg::with_name("detach").long("detach").help("Detach HEAD at the commit even when given a branch"))
+                .arg(Arg::with_name("target").index(1).value_name("BRANCH|COMMIT|TREE-ISH"))
+                .arg(
+                    Arg::with_name("pathspec")
+                        .index(2)
+                        .multiple(true)
+                        .last(true)
+                        .value_name("PATHSPEC")
+                        .help("Paths to copy out of the index, or out of the tree-ish when one is given"),
+                ),
+        )
+        .subcommand(
+            App::new("switch")
+                .about("Switch branches")
+                .arg(Arg::with_name("create").long("create").short("c").takes_value(true).value_name("NEW_BRANCH").help("Create a branch at the start point and switch to it"))
+                .arg(
+                    Arg::with_name("force-create")
+                        .long("force-create")
+                        .short("C")
+                        .takes_value(true)
+                        .value_name("NEW_BRANCH")
+                        .conflicts_with("create")
+                        .help("Like --create, resetting the branch if it already exists"),
+                )
+                .arg(Arg::with_name("detach").long("detach").short("d").help("Switch to a commit without a branch"))
+                .arg(Arg::with_name("discard-changes").long("discard-changes").visible_alias("force").short("f").help("Throw away local changes and untracked files in the way"))
+                .arg(Arg::with_name("target").index(1).value_name("BRANCH|START_POINT")),
+        )
+        .subcommand(
+            App::new("restore")
+                .about("Restore working tree files")
+                .arg(Arg::with_name("source").long("source").short("s").takes_value(true).value_name("TREE").help("Take the content from this tree-ish instead of the index or HEAD"))
+                .arg(Arg::with_name("staged").long("staged").short("S").help("Restore the index, from HEAD unless a source is given"))
+                .arg(Arg::with_name("worktree").long("worktree").short("W").help("Restore the working tree, the default"))
+                .arg(Arg::with_name("pathspec").index(1).multiple(true).required(true).value_name("PATHSPEC")),
+        )
+        .subcommand(
+            App::new("reset")
+                .about("Reset current HEAD to the specified state")
+                .arg(Arg::with_name("soft").long("soft").help("Only move HEAD"))
+                .arg(Arg::with_name("mixed").long("mixed").help("Move HEAD and reset the index, the default"))
+                .arg(Arg::with_name("hard").long("hard").help("Move HEAD and reset the index and working tree"))
+                .arg(Arg::with_name("keep").long("keep").help("Like --hard, refusing to touch files with local changes"))
+                .arg(Arg::with_name("merge").long("merge").help("Like --hard, keeping changes that are not staged"))
+                .group(ArgGroup::with_name("mode").args(&["soft", "mixed", "hard", "keep", "merge"]))
+                .arg(Arg::with_name("target").index(1).value_name("COMMIT|TREE-ISH"))
+                .arg(
+                    Arg::with_name("pathspec")
+                        .index(2)
+                        .multiple(true)
+                        .last(true)
+                        .value_name("PATHSPEC")
+                        .help("Reset only the index entries of these paths"),
+                ),
+        )
+        .subcommand(
+            App::new("worktree")
+                .about("Manage multiple working trees")
+                .subcommand(
+                    App::new("add")
+                        .arg(Arg::with_name("path").index(1).value_name("PATH").required(true))
+                        .arg(Arg::with_name("commit-ish").index(2).value_name("COMMIT-ISH"))
+                        .arg(
+                            Arg::with_name("new-branch")
+                                .short("b")
+                                .takes_value(true)
+                                .value_name("NEW_BRANCH")
+                                .help("Create a new branch for the worktree"),
+                        )
+                        .arg(Arg::with_name("detach").long("detach").help("Detach HEAD in the new worktree"))
+                        .arg(Arg::with_name("force").long("force").short("f").help("Check out a branch used by another worktree"))
+                        .arg(Arg::with_name("no-checkout").long("no-checkout").help("Do not populate the new worktree")),
+                )
+                .subcommand(
+                    App::new("list")
+                        .arg(Arg::with_name("porcelain").long("porcelain").help("Machine readable output")),
+                )
+                .subcommand(
+                    App::new("remove")
+                        .arg(Arg::with_name("worktree").index(1).value_name("WORKTREE").required(true))
+                        .arg(Arg::with_name("force").long("force").short("f").help("Remove even with changes or when locked")),
+                )
+                .subcommand(
+                    App::new("prune")
+                        .arg(Arg::with_name("dry-run").long("dry-run").short("n").help("Only report what would be removed"))
+                        .arg(Arg::with_name("verbose").long("verbose").short("v").help("Report all removals")),
+                ),
+        )
+}
+
+///Apply the options that change where the repository is looked for before running a command
+fn apply_global_options(matches: &ArgMatches) -> Result<(), std::io::Error> {
+    if let Some(dirs) = matches.values_of("C") {
+        for dir in dirs.filter(|dir| !dir.is_empty()) {
+            std::env::set_current_dir(dir)?;
+        }
+    }
+    let cwd = std::env::current_dir()?;
+    if let Some(git_dir) = matches.value_of("git-dir") {
+        std::env::set_var("GIT_DIR", cwd.join(git_dir));
+    }
+    if let Some(work_tree) = matches.value_of("work-tree") {
+        std::env::set_var("GIT_WORK_TREE", cwd.join(work_tree));
+    }
+    //Like git, hand -c settings to config loading (and any child process) through the environment
+    if let Some(settings) = matches.values_of("config") {
+        let mut parameters: Vec<String> = std::env::var("GIT_CONFIG_PARAMETERS").into_iter().collect();
+        parameters.extend(settings.map(quote_parameter));
+        std::env::set_var("GIT_CONFIG_PARAMETERS", parameters.join(" "));
+    }
+    Ok(())
 }
 
 fn main() {
     let matches = make_parser().get_matches();
+    if let Err(err) = apply_global_options(&matches) {
+        println!("Error: {}", err);
+        return;
+    }
     if matches.is_present("init") {
         let sub_matches = matches.subcommand_matches("init").unwrap();
         if let Some(path) = sub_matches.value_of("path") {
-            match init(path) {
+            let options = InitOptions {
+                bare: sub_matches.is_present("bare"),
+                initial_branch: sub_matches.value_of("initial-branch").map(str::to_owned),
+                template: sub_matches.value_of("template").map(PathBuf::from),
+                object_format: sub_matches.value_of("object-format").and_then(|name| name.parse().ok()),
+            };
+            match init(path, &options) {
                 Ok(_) => (),
                 Err(err) => println!("Error initializing: {:?}", err),
             }
@@ -61,6 +475,330 @@ fn main() {
         } else {
             println!("No value given for type");
         }
+    } else if let Some(sub_matches) = matches.subcommand_matches("config") {
+        if let Err(err) = run_config(sub_matches) {
+            println!("Error: {:?}", err);
+        }
+    } else if let Some(sub_matches) = matches.subcommand_matches("ls-files") {
+        if let Err(err) = run_ls_files(sub_matches) {
+            println!("Error: {:?}", err);
+        }
+    } else if let Some(sub_matches) = matches.subcommand_matches("check-ignore") {
+        if let Err(err) = run_check_ignore(sub_matches) {
+            println!("Error: {:?}", err);
+        }
+    } else if let Some(sub_matches) = matches.subcommand_matches("check-attr") {
+        if let Err(err) = run_check_attr(sub_matches) {
+            println!("Error: {:?}", err);
+        }
+    } else if let Some(sub_matches) = matches.subcommand_matches("add") {
+        if let Err(err) = run_add(sub_matches) {
+            println!("Error: {:?}", err);
+        }
+    } else if let Some(sub_matches) = matches.subcommand_matches("rm") {
+        if let Err(err) = run_rm(sub_matches) {
+            println!("Error: {:?}", err);
+        }
+    } else if let Some(sub_matches) = matches.subcommand_matches("write-tree") {
+        if let Err(err) = run_write_tree(sub_matches) {
+            println!("Error: {:?}", err);
+        }
+    } else if let Some(sub_matches) = matches.subcommand_matches("commit-tree") {
+        if let Err(err) = run_commit_tree(sub_matches) {
+            println!("Error: {:?}", err);
+        }
+    } else if let Some(sub_matches) = matches.subcommand_matches("commit") {
+        if let Err(err) = run_commit(sub_matches) {
+            println!("Error: {:?}", err);
+        }
+    } else if let Some(sub_matches) = matches.subcommand_matches("status") {
+        if let Err(err) = run_status(sub_matches) {
+            println!("Error: {:?}", err);
+        }
+    } else if let Some(sub_matches) = matches.subcommand_matches("checkout") {
+        if let Err(err) = run_checkout(sub_matches) {
+            println!("Error: {:?}", err);
+        }
+    } else if let Some(sub_matches) = matches.subcommand_matches("switch") {
+        if let Err(err) = run_switch(sub_matches) {
+            println!("Error: {:?}", err);
+        }
+    } else if let Some(sub_matches) = matches.subcommand_matches("restore") {
+        if let Err(err) = run_restore(sub_matches) {
+            println!("Error: {:?}", err);
+        }
+    } else if let Some(sub_matches) = matches.subcommand_matches("reset") {
+        if let Err(err) = run_reset(sub_matches) {
+            println!("Error: {:?}", err);
+        }
+    } else if let Some(sub_matches) = matches.subcommand_matches("worktree") {
+        if let Err(err) = run_worktree(sub_matches) {
+            println!("Error: {:?}", err);
+        }
+    }
+}
+
+fn run_config(matches: &ArgMatches) -> Result<(), CommandError> {
+    let options = ConfigOptions {
+        file: matches.value_of("file").map(PathBuf::from),
+        global: matches.is_present("global"),
+        local: matches.is_present("local"),
+        value_type: matches.value_of("type").and_then(|name| name.parse().ok()),
+        show_origin: matches.is_present("show-origin"),
+    };
+    let arg = |name: &str| matches.value_of(name).map(str::to_owned);
+    let pattern = arg("value-pattern").or_else(|| arg("value"));
+    let action = match (arg("name"), arg("value"), arg("value-pattern")) {
+        (None, None, None) if matches.is_present("list") => Some(ConfigAction::List),
+        (Some(name), _, None) if matches.is_present("get") => Some(ConfigAction::Get { name, value_pattern: pattern }),
+        (Some(name), _, None) if matches.is_present("get-all") => Some(ConfigAction::GetAll { name, value_pattern: pattern }),
+        (Some(name_pattern), _, None) if matches.is_present("get-regexp") => {
+            Some(ConfigAction::GetRegexp { name_pattern, value_pattern: pattern })
+        }
+        (Some(name), Some(value), None) if matches.is_present("add") => Some(ConfigAction::Add { name, value }),
+        (Some(name), _, None) if matches.is_present("unset") => Some(ConfigAction::Unset { name, value_pattern: pattern }),
+        (Some(name), _, None) if matches.is_present("unset-all") => Some(ConfigAction::UnsetAll { name, value_pattern: pattern }),
+        (Some(old_name), Some(new_name), None) if matches.is_present("rename-section") => {
+            Some(ConfigAction::RenameSection { old_name, new_name })
+        }
+        (Some(name), None, None) if matches.is_present("remove-section") => Some(ConfigAction::RemoveSection { name }),
+        _ if matches.is_present("action") => None,
+        (Some(name), None, None) => Some(ConfigAction::Get { name, value_pattern: None }),
+        (Some(name), Some(value), None) => Some(ConfigAction::Set { name, value }),
+        _ => None,
+    };
+    match action {
+        Some(action) => config(&action, &options),
+        None => {
+            println!("error: wrong number of arguments\n{}", matches.usage());
+            Ok(())
+        }
+    }
+}
+
+fn run_ls_files(matches: &ArgMatches) -> Result<(), CommandError> {
+    let repo = Rc::new(GitRepository::along_path(std::env::current_dir()?, false)?);
+    let options = LsFilesOptions {
+        cached: matches.is_present("cached"),
+        stage: matches.is_present("stage"),
+        deleted: matches.is_present("deleted"),
+        modified: matches.is_present("modified"),
+        others: matches.is_present("others"),
+        ignored: matches.is_present("ignored"),
+        exclude_standard: matches.is_present("exclude-standard"),
+        unmerged: matches.is_present("unmerged"),
+        null_terminated: matches.is_present("z"),
+    };
+    let pathspecs: Vec<&str> = matches.values_of("pathspec").map(|values| values.collect()).unwrap_or_default();
+    ls_files(&repo, &pathspecs, &options)
+}
+
+fn run_check_ignore(matches: &ArgMatches) -> Result<(), CommandError> {
+    let repo = GitRepository::along_path(std::env::current_dir()?, false)?;
+    let options = CheckIgnoreOptions {
+        verbose: matches.is_present("verbose"),
+        non_matching: matches.is_present("non-matching"),
+        no_index: matches.is_present("no-index"),
+    };
+    let input: Vec<String> = if matches.is_present("stdin") {
+        std::io::stdin().lines().collect::<Result<_, _>>()?
+    } else {
+        matches.values_of("pathname").map(|values| values.map(str::to_owned).collect()).unwrap_or_default()
+    };
+    let paths: Vec<&str> = input.iter().map(String::as_str).collect();
+    check_ignore(&repo, &paths, &options)
+}
+
+fn run_check_attr(matches: &ArgMatches) -> Result<(), CommandError> {
+    let repo = Rc::new(GitRepository::along_path(std::env::current_dir()?, false)?);
+    let options = CheckAttrOptions {
+        all: matches.is_present("all"),
+        cached: matches.is_present("cached"),
+    };
+    let args: Vec<&str> = matches.values_of("args").map(|values| values.collect()).unwrap_or_default();
+    //Without `--`, the first argument is the attribute unless --all or --stdin decide otherwise
+    let (attrs, mut paths): (Vec<&str>, Vec<String>) = match matches.values_of("pathname") {
+        Some(paths) => (args, paths.map(str::to_owned).collect()),
+        None if options.all => (Vec::new(), args.iter().map(|arg| arg.to_string()).collect()),
+        None if matches.is_present("stdin") => (args, Vec::new()),
+        None => (args.iter().take(1).copied().collect(), args.iter().skip(1).map(|arg| arg.to_string()).collect()),
+    };
+    if matches.is_present("stdin") {
+        paths.extend(std::io::stdin().lines().collect::<Result<Vec<String>, _>>()?);
+    }
+    let paths: Vec<&str> = paths.iter().map(String::as_str).collect();
+    check_attr(&repo, &attrs, &paths, &options)
+}
+
+fn run_add(matches: &ArgMatches) -> Result<(), CommandError> {
+    let repo = Rc::new(GitRepository::along_path(std::env::current_dir()?, false)?);
+    let options = AddOptions {
+        all: matches.is_present("all"),
+        update: matches.is_present("update"),
+        force: matches.is_present("force"),
+        dry_run: matches.is_present("dry-run"),
+        intent_to_add: matches.is_present("intent-to-add"),
+        verbose: matches.is_present("verbose"),
+    };
+    let pathspecs: Vec<&str> = matches.values_of("pathspec").map(|values| values.collect()).unwrap_or_default();
+    add(&repo, &pathspecs, &options)
+}
+
+fn run_rm(matches: &ArgMatches) -> Result<(), CommandError> {
+    let repo = Rc::new(GitRepository::along_path(std::env::current_dir()?, false)?);
+    let options = RmOptions {
+        cached: matches.is_present("cached"),
+        recursive: matches.is_present("r"),
+        force: matches.is_present("force"),
+        dry_run: matches.is_present("dry-run"),
+        quiet: matches.is_present("quiet"),
+    };
+    let pathspecs: Vec<&str> = matches.values_of("pathspec").map(|values| values.collect()).unwrap_or_default();
+    rm(&repo, &pathspecs, &options)
+}
+
+fn run_write_tree(matches: &ArgMatches) -> Result<(), CommandError> {
+    let repo = Rc::new(GitRepository::along_path(std::env::current_dir()?, false)?);
+    write_tree(&repo, matches.value_of("prefix"))
+}
+
+///-m and -F paragraphs in the order they were given
+fn message_sources(matches: &ArgMatches) -> Vec<MessageSource> {
+    let mut sources: Vec<(usize, MessageSource)> = Vec::new();
+    for (name, make) in [("message", MessageSource::Text as fn(String) -> MessageSource), ("file", MessageSource::File)] {
+        if let (Some(indices), Some(values)) = (matches.indices_of(name), matches.values_of(name)) {
+            sources.extend(indices.zip(values.map(|value| make(value.to_owned()))));
+        }
+    }
+    sources.sort_by_key(|(position, _)| *position);
+    sources.into_iter().map(|(_, source)| source).collect()
+}
+
+fn run_commit_tree(matches: &ArgMatches) -> Result<(), CommandError> {
+    let repo = Rc::new(GitRepository::along_path(std::env::current_dir()?, false)?);
+    let parents: Vec<&str> = matches.values_of("parent").map(|values| values.collect()).unwrap_or_default();
+    commit_tree(&repo, matches.value_of("tree").unwrap_or_default(), &parents, &message_sources(matches))
+}
+
+fn run_commit(matches: &ArgMatches) -> Result<(), CommandError> {
+    let repo = Rc::new(GitRepository::along_path(std::env::current_dir()?, false)?);
+    let options = CommitOptions {
+        message: message_sources(matches),
+        all: matches.is_present("all"),
+        amend: matches.is_present("amend"),
+        allow_empty: matches.is_present("allow-empty"),
+        author: matches.value_of("author").map(str::to_owned),
+        date: matches.value_of("date").map(str::to_owned),
+    };
+    commit(&repo, &options)
+}
+
+fn run_status(matches: &ArgMatches) -> Result<(), CommandError> {
+    let repo = Rc::new(GitRepository::along_path(std::env::current_dir()?, false)?);
+    let format = if matches.is_present("porcelain") {
+        match matches.value_of("porcelain") {
+            Some("v2") => StatusFormat::PorcelainV2,
+            _ => StatusFormat::PorcelainV1,
+        }
+    } else if matches.is_present("short") {
+        StatusFormat::Short
+    } else {
+        StatusFormat::Long
+    };
+    let untracked_arg = if matches.is_present("u") { "u" } else { "untracked-files" };
+    let untracked = if matches.is_present(untracked_arg) {
+        match matches.value_of(untracked_arg) {
+            Some("no") => Some(UntrackedMode::No),
+            Some("normal") => Some(UntrackedMode::Normal),
+            _ => Some(UntrackedMode::All),
+        }
+    } else {
+        None
+    };
+    let options = StatusOptions {
+        format,
+        branch: matches.is_present("branch"),
+        null_terminated: matches.is_present("z"),
+        untracked,
+        ignored: matches.is_present("ignored"),
+    };
+    let pathspecs: Vec<&str> = matches.values_of("pathspec").map(|values| values.collect()).unwrap_or_default();
+    status(&repo, &pathspecs, &options)
+}
+
+fn run_checkout(matches: &ArgMatches) -> Result<(), CommandError> {
+    let repo = Rc::new(GitRepository::along_path(std::env::current_dir()?, false)?);
+    let options = CheckoutOptions {
+        force: matches.is_present("force"),
+        detach: matches.is_present("detach"),
+    };
+    let pathspecs: Vec<&str> = matches.values_of("pathspec").map(|values| values.collect()).unwrap_or_default();
+    checkout(&repo, matches.value_of("target"), &pathspecs, &options)
+}
+
+fn run_switch(matches: &ArgMatches) -> Result<(), CommandError> {
+    let repo = Rc::new(GitRepository::along_path(std::env::current_dir()?, false)?);
+    let options = SwitchOptions {
+        create: matches.value_of("create").map(str::to_owned),
+        force_create: matches.value_of("force-create").map(str::to_owned),
+        detach: matches.is_present("detach"),
+        discard_changes: matches.is_present("discard-changes"),
+    };
+    switch(&repo, matches.value_of("target"), &options)
+}
+
+fn run_restore(matches: &ArgMatches) -> Result<(), CommandError> {
+    let repo = Rc::new(GitRepository::along_path(std::env::current_dir()?, false)?);
+    let options = RestoreOptions {
+        source: matches.value_of("source").map(str::to_owned),
+        staged: matches.is_present("staged"),
+        worktree: matches.is_present("worktree"),
+    };
+    let pathspecs: Vec<&str> = matches.values_of("pathspec").map(|values| values.collect()).unwrap_or_default();
+    restore(&repo, &pathspecs, &options)
+}
+
+fn run_reset(matches: &ArgMatches) -> Result<(), CommandError> {
+    let repo = Rc::new(GitRepository::along_path(std::env::current_dir()?, false)?);
+    let mode = [
+        ("soft", ResetMode::Soft),
+        ("mixed", ResetMode::Mixed),
+        ("hard", ResetMode::Hard),
+        ("keep", ResetMode::Keep),
+        ("merge", ResetMode::Merge),
+    ]
+    .iter()
+    .find(|(flag, _)| matches.is_present(flag))
+    .map(|(_, mode)| *mode);
+    let pathspecs: Vec<&str> = matches.values_of("pathspec").map(|values| values.collect()).unwrap_or_default();
+    reset(&repo, matches.value_of("target"), &pathspecs, mode)
+}
+
+fn run_worktree(matches: &ArgMatches) -> Result<(), CommandError> {
+    let repo = GitRepository::along_path(std::env::current_dir()?, false)?;
+    match matches.subcommand() {
+        ("add", Some(sub_matches)) => {
+            let options = WorktreeAddOptions {
+                new_branch: sub_matches.value_of("new-branch").map(str::to_owned),
+                detach: sub_matches.is_present("detach"),
+                force: sub_matches.is_present("force"),
+                no_checkout: sub_matches.is_present("no-checkout"),
+            };
+            let path = sub_matches.value_of("path").unwrap_or_default();
+            worktree_add(&repo, path, sub_matches.value_of("commit-ish"), &options)
+        }
+        ("list", Some(sub_matches)) => worktree_list(&repo, sub_matches.is_present("porcelain")),
+        ("remove", Some(sub_matches)) => {
+            let path = sub_matches.value_of("worktree").unwrap_or_default();
+            worktree_remove(&repo, path, sub_matches.is_present("force"))
+        }
+        ("prune", Some(sub_matches)) => {
+            worktree_prune(&repo, sub_matches.is_present("dry-run"), sub_matches.is_present("verbose"))
+        }
+        _ => {
+            println!("{}", matches.usage());
+            Ok(())
+        }
     }
 }
 
@@ -68,9 +806,9 @@ fn main() {
 mod tests {
 
     use super::*;
-    use std::path::PathBuf;
 
     #[test]
+    #[allow(clippy::assertions_on_constants, clippy::redundant_pattern_matching)]
     fn parse_init() {
         let parser = make_parser();
         let res_path = ["C:\\", "Users", "username", "repos", "repo_name"]
@@ -90,4 +828,18 @@ mod tests {
             assert!(false)
         }
     }
+
+    #[test]
+    fn parse_global_options() {
+        let parser = make_parser();
+        let args = ["wyag", "-C", "first", "-C", "second", "-c", "core.bare=true", "--git-dir", "repo.git", "cat-file", "blob", "HEAD"];
+
+        let matches = parser.get_matches_from(args.iter());
+
+        let dirs: Vec<&str> = matches.values_of("C").unwrap().collect();
+        assert!(dirs == ["first", "second"], "dirs were {:?}", dirs);
+        assert!(matches.value_of("git-dir") == Some("repo.git"));
+        assert!(matches.value_of("config") == Some("core.bare=true"));
+        assert!(matches.is_present("cat-file"));
+    }
 }